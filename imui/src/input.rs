@@ -13,6 +13,23 @@ pub enum Input {
 
 pub type InputFlags = enumflags2::BitFlags<Input>;
 
+/// Non-character keys that edit text. Typed characters are sent separately; see `Ui::type_char`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EditKey {
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+
+    /// Enter and Escape both give up keyboard focus.
+    Enter,
+    Escape,
+}
+
 /// Finite state machine for clicking.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ClickFSM {
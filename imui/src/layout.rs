@@ -15,6 +15,9 @@ pub struct Layout {
     pub center_y: bool,
 
     pub new_layer: bool,
+
+    /// The layer to put the control and its children on, instead of the one its parent is on. Overrides `new_layer`.
+    pub layer: Option<Layer>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,6 +54,7 @@ impl Default for Layout {
             center_x: false,
             center_y: false,
             new_layer: false,
+            layer: None,
         }
     }
 }
@@ -74,7 +78,14 @@ impl Position {
     }
 }
 
-pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, renderer: &mut R, mut layer: Layer) {
+pub(crate) fn compute<R: Render>(
+    pool: &mut Pool,
+    key: &Key,
+    space_rect: Rect,
+    renderer: &mut R,
+    mut layer: Layer,
+    clip: Option<Rect>,
+) {
     let control = pool.get(key).unwrap();
     let num_siblings = key.parent // not actually num siblings, because it includes self
         .as_ref()
@@ -87,12 +98,26 @@ pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, r
         .flatten()
         .unwrap_or(space_rect);
 
-    if control.layout.new_layer {
-        layer += 1;
-    }
+    let parent_layer = layer;
+    layer = match control.layout.layer {
+        Some(layer) => layer,
+        None if control.layout.new_layer => layer.saturating_add(1),
+        None => layer,
+    };
+
+    // Popups on a higher layer aren't hidden by any scroll area they are in.
+    let clip = if layer > parent_layer { None } else { clip };
 
     let mut recommendation = match &control.widget {
-        Widget::Text(text) => renderer.measure_text(text),
+        Widget::Text { text, overflow: TextOverflow::Wrap, .. } => {
+            // Wrap to the width the text will end up, so that it is tall enough for every line.
+            let max_width = match &control.layout.width {
+                Dimension::Range(range) => range.end().min(space_rect.width()),
+                Dimension::Fill => space_rect.width(),
+            };
+            renderer.measure_text(text, Some(max_width))
+        }
+        Widget::Text { text, .. } => renderer.measure_text(text, None),
         _ => space_rect.size,
     };
 
@@ -112,12 +137,31 @@ pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, r
         ),
     };
 
+    // Scroll areas and canvases hide any part of their children that is outside of them.
+    let is_scroll_area = matches!(control.widget, Widget::ScrollArea { .. });
+    let child_clip = if is_scroll_area || matches!(control.widget, Widget::Canvas) {
+        Some(clip.map_or(rect, |clip| clip.intersection(&rect).unwrap_or_else(Rect::zero)))
+    } else {
+        clip
+    };
+
     let children = control.children.clone();
     match children.len() {
+        _ if is_scroll_area => {
+            // Children can be as tall as they like, rather than being squashed into the area.
+            let space = Rect {
+                origin: rect.origin,
+                size: Size::new(rect.width(), f32::INFINITY),
+            };
+
+            for child in &children {
+                compute(pool, child, space, renderer, layer, child_clip);
+            }
+        }
         0 => {}
         1 => {
             // Single child gets all the space its parent has.
-            compute(pool, &children[0], rect, renderer, layer);
+            compute(pool, &children[0], rect, renderer, layer, child_clip);
 
             let calc = &pool[&children[0]].region.rect;
             let control = pool.get(key).unwrap();
@@ -129,7 +173,7 @@ pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, r
         _ => match control.layout.direction {
             Dir::BackFront => {
                 for child in &children {
-                    compute(pool, child, rect.clone(), renderer, layer);
+                    compute(pool, child, rect.clone(), renderer, layer, child_clip);
                 }
             }
             Dir::LeftRight { wrap } => {
@@ -149,6 +193,7 @@ pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, r
                             },
                             renderer,
                             layer,
+                            child_clip,
                         );
 
                         // Absolutely-positioned children don't take up space in their parent.
@@ -200,6 +245,7 @@ pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, r
                             },
                             renderer,
                             layer,
+                            child_clip,
                         );
 
                         // Absolutely-positioned children don't take up space in their parent.
@@ -243,6 +289,7 @@ pub(crate) fn compute<R: Render>(pool: &mut Pool, key: &Key, space_rect: Rect, r
     control.region = Region {
         rect,
         layer,
+        clip,
     };
 }
 
@@ -3,10 +3,11 @@ pub mod input;
 mod render;
 mod key;
 
+use std::ops::{Range, RangeInclusive};
 use std::time::{Duration, Instant};
 
 pub use layout::Layout;
-use input::{ClickFSM, Input, InputFlags};
+use input::{ClickFSM, EditKey, Input, InputFlags};
 use layout::{Dimension, Position};
 pub use render::Render;
 pub use key::UniqueKey;
@@ -23,6 +24,25 @@ type Pool = std::collections::HashMap<Key, Control>;
 pub type Layer = u8;
 pub const LAYER_DEFAULT: Layer = 100;
 
+const SCROLLBAR_WIDTH: f32 = 12.0;
+
+/// How long the mouse has to rest on a control before its tooltip appears.
+const TOOLTIP_DELAY: Duration = Duration::from_millis(500);
+const TOOLTIP_PADDING: f32 = 6.0;
+
+/// How soon after one click another has to follow to make a double-click.
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+
+/// How often to redraw while something is animating, such as an indeterminate progress bar.
+const ANIMATION_FRAME: Duration = Duration::from_millis(16);
+/// How long an indeterminate progress bar takes to sweep across once.
+const PROGRESS_SWEEP: Duration = Duration::from_millis(1500);
+
+/// How close to the right edge of a block a drag has to start to resize it rather than move it.
+const BLOCK_END_HANDLE: f32 = 6.0;
+/// How far the mouse can move between pressing and releasing and still count as a click on a canvas.
+const CANVAS_CLICK_SLOP: f32 = 3.0;
+
 /// A UI tree.
 pub struct Ui {
     /// Control pool/arena. Holds the control tree in a flat format.
@@ -49,6 +69,24 @@ pub struct Ui {
     active_layer: Layer,
 
     input_highlight: Option<Rect>,
+
+    /// The control that keyboard input is sent to, if any.
+    focus: Option<Key>,
+
+    /// Keyboard input waiting to be handled by the focused control during the next update.
+    text_events: Vec<TextEvent>,
+
+    /// The [selectable](Text::selectable) text with part of it selected, and the byte range that is selected.
+    text_selection: Option<(Key, Range<usize>)>,
+
+    /// Whether a tooltip was drawn by the most recent `render`.
+    tooltip_visible: bool,
+
+    /// If the most recent `render` drew something animated, when it should be drawn again.
+    next_animation_frame: Option<Instant>,
+
+    /// When this UI was created, which animations are timed from.
+    epoch: Instant,
 }
 
 /// Interface for adding controls to the UI tree.
@@ -74,6 +112,10 @@ pub struct Key {
 pub struct Region {
     pub rect: Rect,
     pub layer: Layer,
+
+    /// If this control is inside a scroll area, the part of the screen outside of which it is hidden and can't be
+    /// interacted with.
+    pub clip: Option<Rect>,
 }
 
 /// A UI element.
@@ -112,26 +154,108 @@ pub struct Control {
     drag: Option<Drag>,
     unhandled_drag_end: bool,
     drag_trigger_update: bool,
+    /// The drag that just ended, until the control takes it.
+    ended_drag: Option<Drag>,
+
+    /// Whether Tab can move keyboard focus to this control.
+    focusable: bool,
+
+    /// Text shown near the mouse after it rests on this control for a moment.
+    tooltip: Option<String>,
+
+    /// When the mouse moved onto this control, if it is still over it.
+    hovered_since: Option<Instant>,
+
+    /// When this control was last clicked, unless that click finished a double-click.
+    clicked_at: Option<Instant>,
+    /// Whether the latest click followed another quickly enough to make a double-click.
+    is_double_click: bool,
 }
 
 #[derive(Debug)]
+enum TextEvent {
+    Char(char),
+    Key(EditKey),
+}
+
+#[derive(Debug, Clone)]
 struct Drag {
     start_position: layout::Position,
+    start_rect: Rect,
     start_mouse_pos: Point,
     current_mouse_pos: Point,
 }
 
+/// Part of a block that is being dragged. See [UiFrame::block].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockHandle {
+    /// Moves the block.
+    Body,
+    /// The right edge, which resizes the block.
+    End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockDrag {
+    pub handle: BlockHandle,
+    /// Where the mouse was pressed, relative to the top-left corner of the block.
+    pub start: Point,
+    /// How far the mouse has moved since the drag started.
+    pub delta: Vector,
+    /// Whether the mouse button was released, so this is the last update for the drag.
+    pub is_finished: bool,
+}
+
 /// A widget is the 'type' of a control. They are effectively bags of style properties intended to inform the
 /// renderer how a particular control should look.
 #[derive(Debug)]
 enum Widget {
     Group,
-    Text(String),
+    Text {
+        text: String,
+        overflow: TextOverflow,
+        is_selectable: bool,
+    },
     Button { texture: &'static str, texture_pressed: &'static str },
     ToggleButton(bool),
     Modal {
         size: Size,
     },
+    TextInput {
+        text: String,
+        /// Byte index into `text`.
+        cursor: usize,
+        is_focused: bool,
+    },
+    Slider {
+        /// Position of the handle along the track, from 0.0 to 1.0.
+        fraction: f32,
+        is_focused: bool,
+    },
+    ScrollArea {
+        /// How far the children are scrolled up by.
+        offset: f32,
+    },
+    Scrollbar {
+        thumb: Rect,
+        grab: Option<Grab>,
+    },
+    ProgressBar {
+        /// From 0.0 to 1.0, or None if it isn't known how far along the operation is.
+        fraction: Option<f32>,
+    },
+    Canvas,
+    Block {
+        color: [f32; 4],
+    },
+}
+
+/// What part of a scrollbar is being dragged.
+#[derive(Debug, Clone, Copy)]
+enum Grab {
+    Thumb { start_offset: f32 },
+    /// The track above or below the thumb was clicked, which scrolls by a page once.
+    Track,
 }
 
 impl Ui {
@@ -149,6 +273,12 @@ impl Ui {
             most_recent_update: Instant::now(),
             active_layer: LAYER_DEFAULT,
             input_highlight: None,
+            focus: None,
+            text_events: Vec::new(),
+            text_selection: None,
+            tooltip_visible: false,
+            next_animation_frame: None,
+            epoch: Instant::now(),
         };
 
         // Create omnipresent root node.
@@ -175,10 +305,18 @@ impl Ui {
 
         self.end_frame();
 
-        // Relayout.
-        layout::compute(&mut self.pool, &Key::root(), self.screen.clone(), renderer, LAYER_DEFAULT);
+        self.relayout(renderer);
 
-        // Set the active layer to the highest layer of any control.
+        // Which characters are selected depends on where they were laid out.
+        self.update_text_selection(renderer);
+    }
+
+    /// Lays out every control, then works out which layer gets input from where they ended up.
+    fn relayout<R: Render>(&mut self, renderer: &mut R) {
+        layout::compute(&mut self.pool, &Key::root(), self.screen.clone(), renderer, LAYER_DEFAULT, None);
+
+        // Set the active layer to the highest layer of any control, whether it is a popup or was put there with
+        // UiFrame::layer or on_layer.
         self.active_layer = 0;
         for (_, ctrl) in &self.pool {
             if ctrl.region.layer > self.active_layer {
@@ -198,7 +336,7 @@ impl Ui {
 
     pub fn resize<R: Render>(&mut self, screen: Rect, renderer: &mut R) {
         self.screen = screen;
-        layout::compute(&mut self.pool, &Key::root(), self.screen.clone(), renderer, LAYER_DEFAULT);
+        self.relayout(renderer);
     }
 
     #[must_use = "if true is returned, call update"]
@@ -208,11 +346,12 @@ impl Ui {
         let active_layer = self.active_layer;
 
         self.iter_mut_depth_first(&Key::root(), &mut |ctrl: &mut Control| {
-            let is_hit = !captured && ctrl.region.layer == active_layer && ctrl.region.rect.contains(pos);
+            let is_hit = !captured && ctrl.region.layer == active_layer && ctrl.region.contains(pos);
             let was_hit = ctrl.inputs_active.contains(Input::MouseOver);
 
             if is_hit != was_hit {
                 ctrl.inputs_active.toggle(Input::MouseOver);
+                ctrl.hovered_since = if is_hit { Some(Instant::now()) } else { None };
 
                 if ctrl.inputs_trigger_update.contains(Input::MouseOver) {
                     needs_update = true;
@@ -233,6 +372,11 @@ impl Ui {
 
         self.mouse_pos = pos;
 
+        // The tooltip follows the mouse, and disappears once it leaves the control.
+        if self.tooltip_visible {
+            needs_update = true;
+        }
+
         needs_update
     }
 
@@ -258,20 +402,22 @@ impl Ui {
             }
 
             // XXX this should not be in this method
-            if ctrl.drag_trigger_update {
+            if ctrl.drag_trigger_update && flag.contains(Input::LeftMouseDown) {
                 if to_set {
                     if ctrl.drag.is_none() {
                         ctrl.drag = Some(Drag {
                             start_mouse_pos: self.mouse_pos,
                             current_mouse_pos: self.mouse_pos,
                             start_position: ctrl.layout.position.clone(),
+                            start_rect: ctrl.region.rect,
                         });
                         ctrl.unhandled_drag_end = false;
+                        ctrl.ended_drag = None;
                         needs_update = true;
                     }
                 } else {
                     if ctrl.drag.is_some() {
-                        ctrl.drag = None;
+                        ctrl.ended_drag = ctrl.drag.take();
                         ctrl.unhandled_drag_end = true;
                         needs_update = true;
                     }
@@ -284,7 +430,230 @@ impl Ui {
 
     #[must_use = "if true is returned, call update"]
     pub fn set_left_mouse(&mut self, is_down: bool) -> bool {
-        self.set_input_flag_on_controls_if(is_down, Input::LeftMouseDown.into(), Input::MouseOver.into())
+        let mut needs_update =
+            is_down && (self.blur_unless_hovered() | self.deselect_unless_hovered() | self.dismiss_tooltip());
+
+        if self.set_input_flag_on_controls_if(is_down, Input::LeftMouseDown.into(), Input::MouseOver.into()) {
+            needs_update = true;
+        }
+
+        needs_update
+    }
+
+    #[must_use = "if true is returned, call update"]
+    pub fn set_right_mouse(&mut self, is_down: bool) -> bool {
+        let mut needs_update = is_down && (self.blur_unless_hovered() | self.dismiss_tooltip());
+
+        if self.set_input_flag_on_controls_if(is_down, Input::RightMouseDown.into(), Input::MouseOver.into()) {
+            needs_update = true;
+        }
+
+        needs_update
+    }
+
+    /// Clicking anywhere other than the focused control (or its children) takes focus away from it. Returns whether
+    /// focus was taken away.
+    fn blur_unless_hovered(&mut self) -> bool {
+        match &self.focus {
+            Some(focus) if !self.is_hovered_within(focus) => {
+                self.focus = None;
+                self.text_events.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Clicking anywhere other than the text with a selection deselects it. Returns whether it was deselected.
+    fn deselect_unless_hovered(&mut self) -> bool {
+        match &self.text_selection {
+            Some((key, _)) if !self.is_hovered_within(key) => {
+                self.text_selection = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Clicking hides any tooltip until the mouse next moves onto a control. Returns whether one was visible.
+    fn dismiss_tooltip(&mut self) -> bool {
+        for ctrl in self.pool.values_mut() {
+            ctrl.hovered_since = None;
+        }
+
+        std::mem::replace(&mut self.tooltip_visible, false)
+    }
+
+    /// The control under the mouse that has a tooltip, if any.
+    fn hovered_tooltip(&self) -> Option<(String, Instant)> {
+        let mut hovered = None;
+
+        // Depth-first, so the innermost control under the mouse wins.
+        self.iter_depth_first(&Key::root(), &mut |ctrl| {
+            if hovered.is_some() || ctrl.region.layer != self.active_layer {
+                return;
+            }
+
+            if let (Some(tooltip), Some(since)) = (&ctrl.tooltip, ctrl.hovered_since) {
+                hovered = Some((tooltip.clone(), since));
+            }
+        });
+
+        hovered
+    }
+
+    /// When the UI next needs rendering again even if there is no input before then, if ever. This is the case when
+    /// something is animating, or when a tooltip that isn't shown yet is due to appear.
+    pub fn redraw_deadline(&self) -> Option<Instant> {
+        let animation = self.next_animation_frame;
+        let tooltip = if self.tooltip_visible {
+            None
+        } else {
+            self.hovered_tooltip().map(|(_, since)| since + TOOLTIP_DELAY)
+        };
+
+        match (animation, tooltip) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The control being dragged with the mouse, such as a slider or a draglist element, if any. Changes made during
+    /// a drag can be treated as one edit. If the drag started over nested controls, this is the innermost one.
+    pub fn dragging(&self) -> Option<&Key> {
+        self.pool
+            .iter()
+            .filter(|(_, ctrl)| ctrl.drag.is_some())
+            .map(|(key, _)| key)
+            .max_by_key(|key| std::iter::successors(Some(*key), |key| key.parent.as_deref()).count())
+    }
+
+    /// Scrolls the scroll area under the mouse, if any. `delta` is in the same direction as the mouse wheel, so
+    /// positive values scroll up.
+    #[must_use = "if true is returned, call update"]
+    pub fn scroll(&mut self, delta: Vector) -> bool {
+        let pos = self.mouse_pos;
+        let active_layer = self.active_layer;
+        let mut handled = false;
+
+        // Depth-first, so the innermost scroll area under the mouse gets the scroll.
+        self.iter_mut_depth_first(&Key::root(), &mut |ctrl: &mut Control| {
+            if handled || ctrl.region.layer != active_layer || !ctrl.region.contains(pos) {
+                return;
+            }
+
+            if let Widget::ScrollArea { offset } = &mut ctrl.widget {
+                // The maximum is applied by `UiFrame::scroll_area`, which knows how tall the content is.
+                *offset = (*offset - delta.y).max(0.0);
+                handled = true;
+            }
+        });
+
+        handled
+    }
+
+    /// Moves keyboard focus to the next focusable control in tree order, or the previous one if `reverse` is set.
+    /// Only controls on the active layer can be focused, so an open modal keeps focus inside it.
+    #[must_use = "if true is returned, call update"]
+    pub fn focus_next(&mut self, reverse: bool) -> bool {
+        let active_layer = self.active_layer;
+        let mut focusable = Vec::new();
+
+        self.iter_breadth_first(&Key::root(), &mut |ctrl| {
+            if ctrl.focusable && ctrl.region.layer == active_layer {
+                focusable.push(ctrl.key.clone());
+            }
+        });
+
+        if focusable.is_empty() {
+            return false;
+        }
+
+        let len = focusable.len();
+        let current = self.focus.as_ref().and_then(|focus| focusable.iter().position(|key| key == focus));
+        let next = match (current, reverse) {
+            (Some(i), false) => (i + 1) % len,
+            (Some(i), true) => (i + len - 1) % len,
+            (None, false) => 0,
+            (None, true) => len - 1,
+        };
+
+        self.focus = Some(focusable.swap_remove(next));
+        self.text_events.clear();
+        true
+    }
+
+    /// Whether a control, such as a text input, is taking keyboard input. If so, key presses should not also be used
+    /// as shortcuts.
+    pub fn has_focus(&self) -> bool {
+        self.focus.is_some()
+    }
+
+    /// The part of a [selectable](Text::selectable) text that the user has selected, if any, e.g. to copy.
+    pub fn selected_text(&self) -> Option<&str> {
+        let (key, range) = self.text_selection.as_ref()?;
+        match &self.pool.get(key)?.widget {
+            Widget::Text { text, .. } => text.get(range.clone()),
+            _ => None,
+        }
+    }
+
+    /// Selects the characters between where the mouse started and where it is now, if it is being dragged over a
+    /// selectable text.
+    fn update_text_selection<R: Render>(&mut self, renderer: &mut R) {
+        for (key, ctrl) in &self.pool {
+            if let (Widget::Text { text, is_selectable: true, .. }, Some(drag)) = (&ctrl.widget, &ctrl.drag) {
+                let left = ctrl.region.rect.min_x();
+                let start = text_index_at(renderer, text, drag.start_mouse_pos.x - left);
+                let end = text_index_at(renderer, text, drag.current_mouse_pos.x - left);
+
+                // Just clicking selects nothing.
+                self.text_selection = if start == end {
+                    None
+                } else {
+                    Some((key.clone(), start.min(end)..start.max(end)))
+                };
+                return;
+            }
+        }
+    }
+
+    /// The text input being typed into, if any.
+    pub fn editing_text(&self) -> Option<&Key> {
+        let focus = self.focus.as_ref()?;
+        match self.pool.get(focus).map(|ctrl| &ctrl.widget) {
+            Some(Widget::TextInput { .. }) => Some(focus),
+            _ => None,
+        }
+    }
+
+    /// Types a character into the focused control. Control characters are ignored; use `press_key` for those.
+    #[must_use = "if true is returned, call update"]
+    pub fn type_char(&mut self, ch: char) -> bool {
+        if self.focus.is_none() || ch.is_control() {
+            return false;
+        }
+
+        self.text_events.push(TextEvent::Char(ch));
+        true
+    }
+
+    #[must_use = "if true is returned, call update"]
+    pub fn press_key(&mut self, key: EditKey) -> bool {
+        if self.focus.is_none() {
+            return false;
+        }
+
+        self.text_events.push(TextEvent::Key(key));
+        true
+    }
+
+    /// Whether the mouse is over a control or any of its children.
+    fn is_hovered_within(&self, key: &Key) -> bool {
+        self.pool.get(key).is_some_and(|ctrl| {
+            ctrl.inputs_active.contains(Input::MouseOver)
+                || ctrl.children.iter().any(|child| self.is_hovered_within(child))
+        })
     }
 
     /// Iterate through a tree and its children, depth-first AKA post-order.
@@ -322,6 +691,15 @@ impl Ui {
     }
 
     pub fn render<R: Render>(&mut self, renderer: &mut R) {
+        let focus = self.focus.as_ref();
+        let mut is_animating = false;
+
+        // Repeats from 0.0 to 1.0 every PROGRESS_SWEEP.
+        let sweep_phase = {
+            let sweep = PROGRESS_SWEEP.as_secs_f32();
+            (self.epoch.elapsed().as_secs_f32() % sweep) / sweep
+        };
+
         self.iter_breadth_first(&Key::root(), &mut |ctrl| {
             let Control { widget, region, layout, .. } = ctrl;
 
@@ -343,7 +721,23 @@ impl Ui {
 
             match widget {
                 Widget::Group => {}
-                Widget::Text(text) => renderer.render_text(&region, text),
+                Widget::Text { text, overflow: TextOverflow::Ellipsis, .. } => {
+                    let width = region.rect.width();
+                    match ellipsize(text, width, |text| renderer.measure_text(text, None).width) {
+                        Some(shortened) => renderer.render_text(&region, &shortened),
+                        None => renderer.render_text(&region, text),
+                    }
+                }
+                Widget::Text { text, .. } => {
+                    match &self.text_selection {
+                        Some((key, range)) if *key == ctrl.key && text.get(range.clone()).is_some() => {
+                            renderer.render_text_selection(&region, text, range.clone());
+                        }
+                        _ => {}
+                    }
+
+                    renderer.render_text(&region, text)
+                }
                 Widget::Button { texture, texture_pressed } => {
                     let tex = if ctrl.left_click.is_press() {
                         texture_pressed
@@ -355,12 +749,54 @@ impl Ui {
                 },
                 Widget::ToggleButton(v) => renderer.render_toggle_button(&region, ctrl.left_click.is_press(), *v),
                 Widget::Modal { .. } => renderer.render_window(&region),
+                Widget::TextInput { text, cursor, is_focused } => {
+                    renderer.render_text_input(&region, text, if *is_focused { Some(*cursor) } else { None })
+                },
+                Widget::Slider { fraction, is_focused } => renderer.render_slider(&region, *fraction, *is_focused),
+                Widget::ScrollArea { .. } => {}
+                Widget::Scrollbar { thumb, grab } => {
+                    renderer.render_scrollbar(&region, thumb, matches!(grab, Some(Grab::Thumb { .. })))
+                },
+                Widget::ProgressBar { fraction } => {
+                    if fraction.is_none() {
+                        is_animating = true;
+                    }
+
+                    renderer.render_progress_bar(&region, *fraction, sweep_phase)
+                },
+                Widget::Canvas => {}
+                Widget::Block { color } => renderer.render_block(&region, *color),
+            }
+
+            if ctrl.focusable && focus == Some(&ctrl.key) {
+                renderer.render_focus_ring(&region);
             }
         });
 
         if let Some(rect) = &self.input_highlight {
             renderer.render_input_highlight(rect);
         }
+
+        self.next_animation_frame = if is_animating { Some(Instant::now() + ANIMATION_FRAME) } else { None };
+        self.tooltip_visible = false;
+
+        if let Some((text, since)) = self.hovered_tooltip() {
+            if since.elapsed() >= TOOLTIP_DELAY {
+                let size = renderer.measure_text(&text, None) + Size::new(TOOLTIP_PADDING, TOOLTIP_PADDING) * 2.0;
+
+                // Below and to the right of the cursor, but kept on-screen.
+                let mut origin = self.mouse_pos + Vector::new(12.0, 16.0);
+                origin.x = origin.x.min(self.screen.max_x() - size.width).max(0.0);
+                origin.y = origin.y.min(self.screen.max_y() - size.height).max(0.0);
+
+                renderer.render_tooltip(&Region {
+                    rect: Rect::new(origin, size),
+                    layer: Layer::MAX,
+                    clip: None,
+                }, &text);
+                self.tooltip_visible = true;
+            }
+        }
     }
 
     fn begin_frame(&mut self) {
@@ -382,6 +818,19 @@ impl Ui {
         let frame_no = self.frame_no;
         self.forget_old_children(&Key::root());
         self.pool.retain(|_, control| !control.is_old(frame_no));
+
+        // The focused control may have been removed, and any input it didn't handle is stale now.
+        if let Some(focus) = &self.focus {
+            if !self.pool.contains_key(focus) {
+                self.focus = None;
+            }
+        }
+        if let Some((key, _)) = &self.text_selection {
+            if !self.pool.contains_key(key) {
+                self.text_selection = None;
+            }
+        }
+        self.text_events.clear();
     }
 
     fn key(&self, user: UserKey) -> Key {
@@ -445,6 +894,9 @@ impl Ui {
         if let Some(ctrl) = self.pool.get_mut(&key) {
             ctrl.touch(frame_no);
             ctrl.accept_widget(widget);
+            ctrl.focusable = false;
+            ctrl.tooltip = None;
+            ctrl.layout.layer = None;
         } else {
             self.pool.insert(key.clone(), Control::new(frame_no, key.clone(), widget));
         }
@@ -538,6 +990,19 @@ impl UiFrame<'_> {
         self.ui.end_control();
     }
 
+    /// Create a group of controls on `layer`, rather than on the layer of the controls around it. Higher layers are
+    /// drawn over lower ones, and only the highest layer in use gets mouse and keyboard input, so anything above
+    /// [LAYER_DEFAULT] blocks input to the rest of the UI like a [modal](UiFrame::modal) does. Modals, menus and
+    /// dropdowns inside the group open one layer above it.
+    pub fn layer<K: UniqueKey, F: FnOnce(&mut Self)>(&mut self, key: K, layer: Layer, f: F) {
+        let key = self.ui.key(key.key());
+
+        self.ui.begin_control(key, Widget::Group);
+        self.current_mut().layout.layer = Some(layer);
+        f(self);
+        self.ui.end_control();
+    }
+
     pub fn known_size<K: UniqueKey, F: FnOnce(&mut Self)>(&mut self, key: K, width: f32, height: f32, f: F) {
         let key = self.ui.key(key.key());
 
@@ -633,7 +1098,11 @@ impl UiFrame<'_> {
 
     /// Create a simple block of text.
     pub fn text<'a, K: UniqueKey, S: Into<String>>(&'a mut self, key: K, string: S) -> Text<'a> {
-        self.ui.begin_control(self.ui.key(key.key()), Widget::Text(string.into()));
+        self.ui.begin_control(self.ui.key(key.key()), Widget::Text {
+            text: string.into(),
+            overflow: TextOverflow::Clip,
+            is_selectable: false,
+        });
         self.ui.end_control();
 
         Text {
@@ -649,16 +1118,18 @@ impl UiFrame<'_> {
     pub fn toggle_button<'a, K: UniqueKey, S: Into<String>>(&'a mut self, key: K, label: S, state: &mut bool) -> Button<'a> {
         let key = self.ui.key(key.key());
 
-        self.ui.begin_control(key, Widget::ToggleButton(*state));
+        self.ui.begin_control(key.clone(), Widget::ToggleButton(*state));
         self.text(0, label).center_x().center_y();
         self.ui.end_control();
 
+        let is_activated = self.take_activation(&key);
         let ctrl = self.current_mut();
 
         ctrl.layout.width = Dimension::Range(100.0..=100.0);
         ctrl.layout.height = Dimension::Range(36.0..=36.0);
+        ctrl.focusable = true;
 
-        let is_click = ctrl.advance_left_click().is_click();
+        let is_click = ctrl.advance_left_click().is_click() || is_activated;
 
         if is_click {
             *state = !*state;
@@ -679,114 +1150,757 @@ impl UiFrame<'_> {
     ) -> Button<'a> {
         let key = self.ui.key(key.key());
 
-        self.ui.begin_control(key, Widget::Button { texture, texture_pressed });
+        self.ui.begin_control(key.clone(), Widget::Button { texture, texture_pressed });
         self.text(0, label).center_x().center_y();
         self.ui.end_control();
 
+        let is_activated = self.take_activation(&key);
         let ctrl = self.current_mut();
 
         ctrl.layout.width = Dimension::Range(100.0..=100.0);
         ctrl.layout.height = Dimension::Range(36.0..=36.0);
+        ctrl.focusable = true;
 
         Button {
-            is_click: ctrl.advance_left_click().is_click(),
+            is_click: ctrl.advance_left_click().is_click() || is_activated,
             ctrl,
         }
     }
 
-    pub fn tabs<K, V, I>(&mut self, key: K, value: &mut V, tabs: I) -> bool
-    where
-        K: UniqueKey,
-        V: UniqueKey + PartialEq,
-        I: Iterator<Item = (V, String)>,
-    {
+    /// Whether `key` has keyboard focus and Enter or Space was pressed. Escape takes focus away from it.
+    fn take_activation(&mut self, key: &Key) -> bool {
+        if self.ui.focus.as_ref() != Some(key) {
+            return false;
+        }
+
+        let mut is_activated = false;
+
+        for event in self.ui.text_events.drain(..) {
+            match event {
+                TextEvent::Key(EditKey::Enter) | TextEvent::Char(' ') => is_activated = true,
+                TextEvent::Key(EditKey::Escape) => self.ui.focus = None,
+                _ => {}
+            }
+        }
+
+        is_activated
+    }
+
+    /// A vertical list of controls that fills the remaining space in its parent. Anything that doesn't fit is hidden,
+    /// and can be scrolled to with the mouse wheel.
+    pub fn scroll_area<K: UniqueKey, F: FnOnce(&mut Self)>(&mut self, key: K, f: F) {
+        self.scroll_area_impl(key, false, f)
+    }
+
+    /// Like [UiFrame::scroll_area], with a [scrollbar](UiFrame::scrollbar) along its right edge.
+    pub fn scroll_area_with_scrollbar<K: UniqueKey, F: FnOnce(&mut Self)>(&mut self, key: K, f: F) {
+        self.scroll_area_impl(key, true, f)
+    }
+
+    fn scroll_area_impl<K: UniqueKey, F: FnOnce(&mut Self)>(&mut self, key: K, has_scrollbar: bool, f: F) {
         let key = self.ui.key(key.key());
 
-        self.ui.begin_control(key, Widget::Group);
+        self.ui.begin_control(key.clone(), Widget::ScrollArea { offset: 0.0 });
 
-        let ctrl = self.current_mut();
-        ctrl.layout.direction = layout::Dir::LeftRight { wrap: false };
+        let inner_key = self.ui.key(UserKey(0));
 
-        let mut changed = false;
+        // Use the previous layout to limit the offset, so the content can't be scrolled out of view entirely.
+        let content_height = match self.ui.pool.get(&inner_key) {
+            Some(inner) => {
+                let top = inner.region.rect.min_y();
+                let mut bottom = top;
+                self.ui.iter_depth_first(&inner_key, &mut |ctrl| {
+                    // Groups can be as tall as the space they're given, so only measure what's inside them.
+                    let max_y = ctrl.region.rect.max_y();
+                    if !matches!(ctrl.widget, Widget::Group) && max_y.is_finite() && max_y > bottom {
+                        bottom = max_y;
+                    }
+                });
+                bottom - top
+            }
+            None => 0.0,
+        };
 
-        for (i, (v, label)) in tabs.enumerate() {
-            let texture;
-            let texture_pressed;
-            if *value == v {
-                texture = "tab_selected";
-                texture_pressed = "tab_selected";
-            } else {
-                texture = "tab";
-                texture_pressed = "tab_pressed";
-            };
+        let ctrl = self.current_mut();
+        let area = ctrl.region.rect;
+        let max_offset = (content_height - area.height()).max(0.0);
+        let mut offset = match &mut ctrl.widget {
+            Widget::ScrollArea { offset } => {
+                *offset = offset.min(max_offset);
+                *offset
+            }
+            _ => unreachable!(),
+        };
 
-            if self.custom_button(i, label, texture, texture_pressed).with_width(200.0).clicked() {
-                *value = v;
-                changed = true;
+        if has_scrollbar {
+            if self.scrollbar(UserKey(1), &mut offset, content_height, area.height()) {
+                if let Widget::ScrollArea { offset: area_offset } = &mut self.ui.pool.get_mut(&key).unwrap().widget {
+                    *area_offset = offset;
+                }
             }
+
+            let ctrl = self.current_mut();
+            ctrl.layout.position = Position::Relative(Point::new(area.width() - SCROLLBAR_WIDTH, 0.0));
+            ctrl.layout.height = Dimension::Range(area.height()..=area.height());
         }
 
+        self.ui.begin_control(inner_key, Widget::Group);
+        let ctrl = self.current_mut();
+        ctrl.layout.direction = layout::Dir::TopBottom { wrap: false };
+        ctrl.layout.position = Position::Relative(Point::new(0.0, -offset));
+        if has_scrollbar {
+            let width = (area.width() - SCROLLBAR_WIDTH).max(0.0);
+            ctrl.layout.width = Dimension::Range(0.0..=width);
+        }
+        f(self);
         self.ui.end_control();
-        changed
-    }
-
-    pub fn hdraglist<K, V, F>(&mut self, key: K, vec: &mut Vec<V>, draw: F) -> bool
-    where
-        K: UniqueKey,
-        F: FnMut(&mut Self, &mut V)
-    {
-        self.draglist(layout::Dir::LeftRight { wrap: false }, key, vec, draw)
-    }
 
-    pub fn vdraglist<K, V, F>(&mut self, key: K, vec: &mut Vec<V>, draw: F) -> bool
-    where
-        K: UniqueKey,
-        F: FnMut(&mut Self, &mut V)
-    {
-        self.draglist(layout::Dir::TopBottom { wrap: false }, key, vec, draw)
+        self.ui.end_control();
     }
 
-    fn draglist<K, V, F>(&mut self, dir: layout::Dir, key: K, vec: &mut Vec<V>, mut draw: F) -> bool
+    /// A vertical scrollbar for content `content_height` tall, shown in a viewport `viewport_height` tall and scrolled
+    /// down by `offset`. Drag the thumb to scroll, or click above or below it to scroll by a page. Returns whether
+    /// `offset` changed.
+    pub fn scrollbar<K>(&mut self, key: K, offset: &mut f32, content_height: f32, viewport_height: f32) -> bool
     where
         K: UniqueKey,
-        F: FnMut(&mut Self, &mut V)
     {
-        /// The distance a dropped control must be from a drag target (see drag_targets below) to actually get
-        /// inserted there.
-        const MIN_DISTANCE_FROM_DRAG_TARGET: f32 = 9999.0;
+        /// So the thumb is still easy to grab when the content is very tall.
+        const MIN_THUMB_HEIGHT: f32 = 20.0;
 
         let key = self.ui.key(key.key());
-        self.ui.begin_control(key.clone(), Widget::Group);
-
-        let ctrl = self.current_mut();
-        ctrl.layout.direction = dir;
 
-        // drag_targets is a list of (position, idx) where:
-        //   - position is the point before or after each control
-        //   - idx is the index into vec where a dropped element should be inserted
-        //
-        // So for something that looks like below, drag_targets would be the points noted by the arrows:
-        //
-        //     [item 0]   [item 1]   [item 2]   [item 3]
-        //    ^        ^ ^        ^ ^        ^ ^        ^
-        //    |        | |        | |        | |        |
-        //    0        1 1        2 2        3 3        4
-        //
-        // These points are the places that will 'accept' the newly-dragged element. Note that the element currently
-        // being dragged will not be included in drag_targets.
-        let mut drag_targets = Vec::with_capacity(vec.len() * 2);
+        let prev_offset = *offset;
+        let max_offset = (content_height - viewport_height).max(0.0);
 
-        // The index of the element that we are dragging, if any.
-        let mut dragging = None;
+        self.ui.begin_control(key, Widget::Scrollbar {
+            thumb: Rect::zero(),
+            grab: None,
+        });
+        self.ui.end_control();
 
-        // Whether to actually move `dragging` or not (i.e. has the drag ended).
-        let mut do_move = false;
+        let ctrl = self.current_mut();
 
-        for (idx, element) in vec.iter_mut().enumerate() {
-            let key = self.ui.key(idx.key());
-            self.ui.begin_control(key.clone(), Widget::Group);
+        ctrl.layout.width = Dimension::Range(SCROLLBAR_WIDTH..=SCROLLBAR_WIDTH);
+        ctrl.drag_trigger_update = true;
+        ctrl.inputs_trigger_update |= Input::MouseOver | Input::LeftMouseDown;
+        ctrl.unhandled_drag_end = false;
 
-            let group = self.current_mut();
+        let track = ctrl.region.rect;
+        let thumb_height = if content_height > viewport_height {
+            (viewport_height / content_height * track.height()).max(MIN_THUMB_HEIGHT).min(track.height())
+        } else {
+            track.height()
+        };
+        let thumb_travel = track.height() - thumb_height;
+        let thumb_y = |offset: f32| {
+            if max_offset > 0.0 {
+                track.min_y() + offset / max_offset * thumb_travel
+            } else {
+                track.min_y()
+            }
+        };
+
+        let mut grab = match &ctrl.widget {
+            Widget::Scrollbar { grab, .. } => *grab,
+            _ => None,
+        };
+
+        match (&ctrl.drag, grab) {
+            // Just pressed.
+            (Some(drag), None) => {
+                let top = thumb_y(*offset);
+
+                if drag.start_mouse_pos.y < top {
+                    *offset -= viewport_height;
+                    grab = Some(Grab::Track);
+                } else if drag.start_mouse_pos.y > top + thumb_height {
+                    *offset += viewport_height;
+                    grab = Some(Grab::Track);
+                } else {
+                    grab = Some(Grab::Thumb { start_offset: *offset });
+                }
+            }
+            (Some(drag), Some(Grab::Thumb { start_offset })) => {
+                if thumb_travel > 0.0 {
+                    let delta = drag.current_mouse_pos.y - drag.start_mouse_pos.y;
+                    *offset = start_offset + delta / thumb_travel * max_offset;
+                }
+            }
+            (Some(_), Some(Grab::Track)) => {}
+            (None, _) => grab = None,
+        }
+
+        *offset = offset.clamp(0.0, max_offset);
+
+        ctrl.widget = Widget::Scrollbar {
+            thumb: Rect::new(Point::new(track.min_x(), thumb_y(*offset)), Size::new(track.width(), thumb_height)),
+            grab,
+        };
+
+        *offset != prev_offset
+    }
+
+    /// A single-line box for editing `buf`. Clicking it gives it keyboard focus; pressing Enter or Escape, or clicking
+    /// elsewhere, takes focus away again.
+    pub fn text_input<'a, K: UniqueKey>(&'a mut self, key: K, buf: &mut String) -> TextInputResponse<'a> {
+        let key = self.ui.key(key.key());
+
+        let mut is_focused = self.ui.focus.as_ref() == Some(&key);
+        let mut changed = false;
+
+        // The cursor is kept from the previous frame, but `buf` may have been changed by something else since then.
+        let mut cursor = match self.ui.pool.get(&key).map(|ctrl| &ctrl.widget) {
+            Some(Widget::TextInput { cursor, .. }) => (*cursor).min(buf.len()),
+            _ => buf.len(),
+        };
+        while !buf.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+
+        if is_focused {
+            for event in self.ui.text_events.drain(..) {
+                match event {
+                    TextEvent::Char(ch) => {
+                        buf.insert(cursor, ch);
+                        cursor += ch.len_utf8();
+                        changed = true;
+                    }
+                    TextEvent::Key(EditKey::Backspace) => {
+                        if let Some(ch) = buf[..cursor].chars().next_back() {
+                            cursor -= ch.len_utf8();
+                            buf.remove(cursor);
+                            changed = true;
+                        }
+                    }
+                    TextEvent::Key(EditKey::Delete) => {
+                        if cursor < buf.len() {
+                            buf.remove(cursor);
+                            changed = true;
+                        }
+                    }
+                    TextEvent::Key(EditKey::Left) => {
+                        if let Some(ch) = buf[..cursor].chars().next_back() {
+                            cursor -= ch.len_utf8();
+                        }
+                    }
+                    TextEvent::Key(EditKey::Right) => {
+                        if let Some(ch) = buf[cursor..].chars().next() {
+                            cursor += ch.len_utf8();
+                        }
+                    }
+                    TextEvent::Key(EditKey::Home) => cursor = 0,
+                    TextEvent::Key(EditKey::End) => cursor = buf.len(),
+                    TextEvent::Key(EditKey::Up) | TextEvent::Key(EditKey::Down) => {}
+                    TextEvent::Key(EditKey::Enter) | TextEvent::Key(EditKey::Escape) => {
+                        is_focused = false;
+                        break;
+                    }
+                }
+            }
+
+            if !is_focused {
+                self.ui.focus = None;
+            }
+        }
+
+        self.ui.begin_control(key.clone(), Widget::TextInput {
+            text: buf.clone(),
+            cursor,
+            is_focused,
+        });
+        self.ui.end_control();
+
+        let ctrl = self.current_mut();
+
+        ctrl.layout.width = Dimension::Range(200.0..=200.0);
+        ctrl.layout.height = Dimension::Range(36.0..=36.0);
+        ctrl.focusable = true;
+
+        if ctrl.advance_left_click().is_click() && !is_focused {
+            ctrl.widget = Widget::TextInput {
+                text: buf.clone(),
+                cursor: buf.len(),
+                is_focused: true,
+            };
+            self.ui.focus = Some(key);
+        }
+
+        TextInputResponse {
+            ctrl: self.current_mut(),
+            changed,
+        }
+    }
+
+    /// Gives keyboard focus to the control `key` in the current container, e.g. to start typing into a text input as
+    /// soon as it appears. Call this before the control itself, so it is drawn focused.
+    pub fn focus<K: UniqueKey>(&mut self, key: K) {
+        self.ui.focus = Some(self.ui.key(key.key()));
+        self.ui.text_events.clear();
+    }
+
+    /// A bar that fills up as an operation progresses. `fraction` is how far along it is, from 0.0 to 1.0; pass `None`
+    /// if that isn't known, and the bar will animate instead.
+    pub fn progress_bar<K: UniqueKey, F: Into<Option<f32>>>(&mut self, key: K, fraction: F) {
+        let key = self.ui.key(key.key());
+
+        self.ui.begin_control(key, Widget::ProgressBar {
+            fraction: fraction.into().map(|fraction| fraction.clamp(0.0, 1.0)),
+        });
+        self.ui.end_control();
+
+        let ctrl = self.current_mut();
+        ctrl.layout.width = Dimension::Range(200.0..=200.0);
+        ctrl.layout.height = Dimension::Range(12.0..=12.0);
+    }
+
+    /// A `width` by `height` area for drawing with [UiFrame::block] and [UiFrame::rectangle], which are placed
+    /// relative to its top-left corner and hidden outside of it. Returns where empty space was clicked, if it was,
+    /// relative to the top-left corner too.
+    pub fn canvas<K, F>(&mut self, key: K, width: f32, height: f32, f: F) -> Option<Point>
+    where
+        K: UniqueKey,
+        F: FnOnce(&mut Self),
+    {
+        let key = self.ui.key(key.key());
+
+        self.ui.begin_control(key, Widget::Canvas);
+        f(self);
+        self.ui.end_control();
+
+        let ctrl = self.current_mut();
+
+        ctrl.layout.width = Dimension::Range(width..=width);
+        ctrl.layout.height = Dimension::Range(height..=height);
+        ctrl.layout.direction = layout::Dir::BackFront;
+
+        // Clicks are found by dragging, so that pressing on a block then releasing over empty space isn't a click.
+        ctrl.drag_trigger_update = true;
+        ctrl.inputs_trigger_update |= Input::MouseOver | Input::LeftMouseDown;
+        ctrl.unhandled_drag_end = false;
+
+        let origin = ctrl.region.rect.origin;
+        let click = ctrl.ended_drag
+            .take()
+            .filter(|drag| (drag.current_mouse_pos - drag.start_mouse_pos).length() <= CANVAS_CLICK_SLOP)
+            .map(|drag| drag.start_mouse_pos)?;
+
+        // Only empty space counts; blocks handle their own clicks.
+        let pool = &self.ui.pool;
+        let is_on_block = self.current().children.iter().any(|child| {
+            let child = &pool[child];
+            child.drag_trigger_update && child.region.contains(click)
+        });
+
+        if is_on_block {
+            None
+        } else {
+            Some(click - origin.to_vector())
+        }
+    }
+
+    /// A rectangle in a [canvas](UiFrame::canvas), which can be moved or resized by dragging it. Dragging near its
+    /// right edge resizes it. The block doesn't move by itself; instead, while it is being dragged, this returns how
+    /// far so the caller can move it, or show where it will end up.
+    pub fn block<K: UniqueKey>(&mut self, key: K, rect: Rect, color: [f32; 4]) -> Option<BlockDrag> {
+        self.rectangle(key, rect, color);
+
+        let ctrl = self.current_mut();
+
+        ctrl.drag_trigger_update = true;
+        ctrl.inputs_trigger_update |= Input::MouseOver | Input::LeftMouseDown;
+        ctrl.unhandled_drag_end = false;
+
+        let (drag, is_finished) = match ctrl.ended_drag.take() {
+            Some(drag) => (drag, true),
+            None => (ctrl.drag.clone()?, false),
+        };
+
+        // Small blocks can still be moved from their left part.
+        let handle_width = BLOCK_END_HANDLE.min(drag.start_rect.width() / 3.0);
+        let handle = if drag.start_mouse_pos.x >= drag.start_rect.max_x() - handle_width {
+            BlockHandle::End
+        } else {
+            BlockHandle::Body
+        };
+
+        Some(BlockDrag {
+            handle,
+            start: drag.start_mouse_pos - drag.start_rect.origin.to_vector(),
+            delta: drag.current_mouse_pos - drag.start_mouse_pos,
+            is_finished,
+        })
+    }
+
+    /// A rectangle in a [canvas](UiFrame::canvas) that can't be interacted with, e.g. for grid lines.
+    pub fn rectangle<K: UniqueKey>(&mut self, key: K, rect: Rect, color: [f32; 4]) {
+        let key = self.ui.key(key.key());
+
+        self.ui.begin_control(key, Widget::Block { color });
+        self.ui.end_control();
+
+        let ctrl = self.current_mut();
+
+        ctrl.layout.position = Position::Relative(rect.origin);
+        ctrl.layout.width = Dimension::Range(rect.width()..=rect.width());
+        ctrl.layout.height = Dimension::Range(rect.height()..=rect.height());
+    }
+
+    /// Gives the previous control a tooltip, shown near the mouse after it rests on the control for a moment.
+    pub fn tooltip(&mut self, text: &str) {
+        self.current_mut().tooltip = Some(text.to_owned());
+    }
+
+    /// A menu for the previous control, which opens at the mouse when that control is right-clicked. Returns the index
+    /// of the item chosen, if any. Clicking elsewhere closes the menu without choosing anything.
+    pub fn context_menu<K: UniqueKey>(&mut self, key: K, items: &[&str]) -> Option<usize> {
+        let key = self.ui.key(key.key());
+
+        // The menu stays open while one of its items has focus, too.
+        let mut is_open = self.ui.focus.as_ref().is_some_and(|focus| focus.is_within(&key));
+        let mut chosen = None;
+
+        if self.current_mut().advance_right_click().is_click() {
+            is_open = true;
+        }
+
+        if self.ui.focus.as_ref() == Some(&key) {
+            for event in self.ui.text_events.drain(..) {
+                if let TextEvent::Key(EditKey::Escape) = event {
+                    is_open = false;
+                    break;
+                }
+            }
+        }
+
+        if is_open {
+            let mouse_pos = self.ui.mouse_pos;
+
+            self.ui.begin_control(key.clone(), Widget::Modal {
+                size: Size::new(150.0, items.len() as f32 * 36.0),
+            });
+
+            let ctrl = self.current_mut();
+            ctrl.layout.direction = layout::Dir::TopBottom { wrap: false };
+            ctrl.layout.width = Dimension::Range(150.0..=150.0);
+            ctrl.layout.height = Dimension::Range(items.len() as f32 * 36.0..=items.len() as f32 * 36.0);
+            ctrl.layout.new_layer = true;
+
+            if let Position::Relative(..) = ctrl.layout.position {
+                // Open where the click was, and stay there.
+                ctrl.layout.position = Position::Absolute(mouse_pos);
+            }
+
+            for (i, item) in items.iter().enumerate() {
+                if self.button(i, *item).with_width(150.0).clicked() {
+                    chosen = Some(i);
+                    is_open = false;
+                }
+            }
+
+            self.ui.end_control();
+        }
+
+        let has_focus = self.ui.focus.as_ref().is_some_and(|focus| focus.is_within(&key));
+        if is_open && !has_focus {
+            self.ui.focus = Some(key);
+        } else if !is_open && has_focus {
+            self.ui.focus = None;
+        }
+
+        chosen
+    }
+
+    /// A horizontal slider for choosing a value in `range`. Clicking or dragging along the track sets the value; once
+    /// clicked, the arrow keys nudge it by 1% of the range. A value outside `range` is drawn at the nearest end and only
+    /// clamped once it is changed. Returns whether the value changed.
+    pub fn slider<K: UniqueKey>(&mut self, key: K, value: &mut f32, range: RangeInclusive<f32>) -> bool {
+        let key = self.ui.key(key.key());
+
+        let (min, max) = (*range.start(), *range.end());
+        let prev_value = *value;
+        let mut is_focused = self.ui.focus.as_ref() == Some(&key);
+
+        // Only input clamps the value, so an out-of-range value that nobody touched is left as it is.
+        let mut edited = false;
+
+        if is_focused {
+            let step = (max - min) / 100.0;
+
+            for event in self.ui.text_events.drain(..) {
+                match event {
+                    TextEvent::Key(EditKey::Left) => {
+                        *value -= step;
+                        edited = true;
+                    }
+                    TextEvent::Key(EditKey::Right) => {
+                        *value += step;
+                        edited = true;
+                    }
+                    TextEvent::Key(EditKey::Home) => {
+                        *value = min;
+                        edited = true;
+                    }
+                    TextEvent::Key(EditKey::End) => {
+                        *value = max;
+                        edited = true;
+                    }
+                    TextEvent::Key(EditKey::Enter) | TextEvent::Key(EditKey::Escape) => {
+                        is_focused = false;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.ui.begin_control(key.clone(), Widget::Slider {
+            fraction: 0.0,
+            is_focused,
+        });
+        self.ui.end_control();
+
+        let ctrl = self.current_mut();
+
+        ctrl.layout.width = Dimension::Range(200.0..=200.0);
+        ctrl.layout.height = Dimension::Range(36.0..=36.0);
+
+        ctrl.focusable = true;
+
+        // A drag starts as soon as the mouse is pressed on the track, so this handles clicking to set the value too.
+        ctrl.drag_trigger_update = true;
+        ctrl.inputs_trigger_update |= Input::MouseOver | Input::LeftMouseDown;
+        ctrl.unhandled_drag_end = false;
+
+        if let Some(drag) = ctrl.drag.as_ref() {
+            let rect = &ctrl.region.rect;
+
+            if rect.width() > 0.0 {
+                let t = (drag.current_mouse_pos.x - rect.min_x()) / rect.width();
+                *value = min + t.clamp(0.0, 1.0) * (max - min);
+                edited = true;
+            }
+
+            is_focused = true;
+        }
+
+        if edited {
+            *value = value.clamp(min, max);
+        }
+
+        ctrl.widget = Widget::Slider {
+            fraction: if max > min { ((*value - min) / (max - min)).clamp(0.0, 1.0) } else { 0.0 },
+            is_focused,
+        };
+
+        if is_focused {
+            self.ui.focus = Some(key);
+        } else if self.ui.focus.as_ref() == Some(&key) {
+            self.ui.focus = None;
+        }
+
+        *value != prev_value
+    }
+
+    /// A button showing the selected item, which when clicked opens a list of all the items to choose from. While the
+    /// list is open, the up and down arrow keys change the selection too. Returns whether the selection changed.
+    pub fn dropdown<K, I>(&mut self, key: K, selected: &mut usize, items: I) -> bool
+    where
+        K: UniqueKey,
+        I: Iterator<Item = String>,
+    {
+        /// The list is scrollable if it would be taller than this.
+        const MAX_LIST_HEIGHT: f32 = 300.0;
+
+        let key = self.ui.key(key.key());
+        let items: Vec<String> = items.collect();
+
+        let prev_selected = *selected;
+
+        // The list stays open while one of its items has focus, too.
+        let mut is_open = self.ui.focus.as_ref().is_some_and(|focus| focus.is_within(&key));
+
+        if self.ui.focus.as_ref() == Some(&key) {
+            for event in self.ui.text_events.drain(..) {
+                match event {
+                    TextEvent::Key(EditKey::Up) => *selected = selected.saturating_sub(1),
+                    TextEvent::Key(EditKey::Down) => {
+                        if *selected + 1 < items.len() {
+                            *selected += 1;
+                        }
+                    }
+                    TextEvent::Key(EditKey::Enter) | TextEvent::Key(EditKey::Escape) => {
+                        is_open = false;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let label = items.get(*selected).cloned().unwrap_or_default();
+
+        self.ui.begin_control(key.clone(), Widget::Button {
+            texture: "button",
+            texture_pressed: "button_pressed",
+        });
+        self.text(0, label).center_x().center_y();
+
+        let ctrl = self.ui.pool.get_mut(&key).unwrap();
+        ctrl.layout.width = Dimension::Range(200.0..=200.0);
+        ctrl.layout.height = Dimension::Range(36.0..=36.0);
+        ctrl.focusable = true;
+
+        if ctrl.advance_left_click().is_click() {
+            is_open = !is_open;
+        }
+
+        let rect = ctrl.region.rect;
+
+        if is_open {
+            // Appear below the button, above everything else.
+            self.ui.begin_control(self.ui.key(UserKey(1)), Widget::Group);
+            let ctrl = self.current_mut();
+            let height = (items.len() as f32 * 36.0).min(MAX_LIST_HEIGHT);
+            ctrl.layout.position = Position::Absolute(Point::new(rect.min_x(), rect.max_y()));
+            ctrl.layout.width = Dimension::Range(rect.width()..=rect.width());
+            ctrl.layout.height = Dimension::Range(height..=height);
+            ctrl.layout.new_layer = true;
+
+            self.scroll_area(0, |ui| {
+                for (i, item) in items.iter().enumerate() {
+                    let (texture, texture_pressed) = if i == *selected {
+                        ("tab_selected", "tab_selected")
+                    } else {
+                        ("button", "button_pressed")
+                    };
+
+                    if ui.custom_button(i, item, texture, texture_pressed).with_width(rect.width()).clicked() {
+                        *selected = i;
+                        is_open = false;
+                    }
+                }
+            });
+
+            self.ui.end_control();
+        }
+
+        self.ui.end_control();
+
+        let has_focus = self.ui.focus.as_ref().is_some_and(|focus| focus.is_within(&key));
+        if is_open && !has_focus {
+            self.ui.focus = Some(key);
+        } else if !is_open && has_focus {
+            self.ui.focus = None;
+        }
+
+        *selected != prev_selected
+    }
+
+    pub fn tabs<K, V, I>(&mut self, key: K, value: &mut V, tabs: I) -> bool
+    where
+        K: UniqueKey,
+        V: UniqueKey + PartialEq,
+        I: Iterator<Item = (V, String)>,
+    {
+        let key = self.ui.key(key.key());
+
+        self.ui.begin_control(key, Widget::Group);
+
+        let ctrl = self.current_mut();
+        ctrl.layout.direction = layout::Dir::LeftRight { wrap: false };
+
+        let mut changed = false;
+
+        for (i, (v, label)) in tabs.enumerate() {
+            let texture;
+            let texture_pressed;
+            if *value == v {
+                texture = "tab_selected";
+                texture_pressed = "tab_selected";
+            } else {
+                texture = "tab";
+                texture_pressed = "tab_pressed";
+            };
+
+            if self.custom_button(i, label, texture, texture_pressed).with_width(200.0).clicked() {
+                *value = v;
+                changed = true;
+            }
+        }
+
+        self.ui.end_control();
+        changed
+    }
+
+    pub fn hdraglist<K, V, F>(&mut self, key: K, vec: &mut Vec<V>, draw: F) -> bool
+    where
+        K: UniqueKey,
+        F: FnMut(&mut Self, &mut V)
+    {
+        self.draglist(layout::Dir::LeftRight { wrap: false }, key, vec, draw)
+    }
+
+    pub fn vdraglist<K, V, F>(&mut self, key: K, vec: &mut Vec<V>, draw: F) -> bool
+    where
+        K: UniqueKey,
+        F: FnMut(&mut Self, &mut V)
+    {
+        self.draglist(layout::Dir::TopBottom { wrap: false }, key, vec, draw)
+    }
+
+    /// A draglist laid out left-to-right, wrapping onto new rows, so elements can be dragged between rows.
+    pub fn grid_draglist<K, V, F>(&mut self, key: K, vec: &mut Vec<V>, draw: F) -> bool
+    where
+        K: UniqueKey,
+        F: FnMut(&mut Self, &mut V)
+    {
+        self.draglist(layout::Dir::LeftRight { wrap: true }, key, vec, draw)
+    }
+
+    fn draglist<K, V, F>(&mut self, dir: layout::Dir, key: K, vec: &mut Vec<V>, mut draw: F) -> bool
+    where
+        K: UniqueKey,
+        F: FnMut(&mut Self, &mut V)
+    {
+        /// The distance a dropped control must be from a drag target (see drag_targets below) to actually get
+        /// inserted there.
+        const MIN_DISTANCE_FROM_DRAG_TARGET: f32 = 9999.0;
+
+        let key = self.ui.key(key.key());
+        self.ui.begin_control(key.clone(), Widget::Group);
+
+        let ctrl = self.current_mut();
+        ctrl.layout.direction = dir;
+
+        // drag_targets is a list of (position, idx, line) where:
+        //   - position is the point before or after each control, halfway along its row (or column)
+        //   - idx is the index into vec where a dropped element should be inserted
+        //   - line is where to draw the insertion line if the element would be dropped there
+        //
+        // So for something that looks like below, drag_targets would be the points noted by the arrows:
+        //
+        //     [item 0]   [item 1]   [item 2]   [item 3]
+        //    ^        ^ ^        ^ ^        ^ ^        ^
+        //    |        | |        | |        | |        |
+        //    0        1 1        2 2        3 3        4
+        //
+        // These points are the places that will 'accept' the newly-dragged element. Note that the element currently
+        // being dragged will not be included in drag_targets. Because the points are in the middle of each row, the
+        // nearest one is on the nearest row, so this works for wrapped (grid) layouts too.
+        let mut drag_targets = Vec::with_capacity(vec.len() * 2);
+
+        // The index of the element that we are dragging, if any.
+        let mut dragging = None;
+
+        // Whether to actually move `dragging` or not (i.e. has the drag ended).
+        let mut do_move = false;
+
+        for (idx, element) in vec.iter_mut().enumerate() {
+            let key = self.ui.key(idx.key());
+            self.ui.begin_control(key.clone(), Widget::Group);
+
+            let group = self.current_mut();
 
             group.layout.direction = layout::Dir::LeftRight { wrap: false };
 
@@ -794,10 +1908,7 @@ impl UiFrame<'_> {
 
             // If the element is not currently being dragged, add its bounds to drag_targets.
             if group.drag.is_none() {
-                drag_targets.extend_from_slice(&[
-                    (group.region.rect.min(), idx),
-                    (group.region.rect.max(), idx + 1),
-                ]);
+                drag_targets.extend_from_slice(&drag_targets_around(dir, &group.region.rect, idx));
                 group.layout.new_layer = false;
             } else {
                 dragging = Some(idx);
@@ -831,15 +1942,15 @@ impl UiFrame<'_> {
 
             let mut target_idx = dragging_idx;
             let mut closest_distance = MIN_DISTANCE_FROM_DRAG_TARGET;
-            let mut closest_pos = dragging_new_pos;
+            let mut closest_line = Rect::zero();
 
-            for (pos, idx) in drag_targets {
+            for (pos, idx, line) in drag_targets {
                 let distance = pos.distance_to(dragging_new_pos);
 
                 if distance < closest_distance {
                     target_idx = idx;
                     closest_distance = distance;
-                    closest_pos = pos;
+                    closest_line = line;
                 }
             }
 
@@ -849,11 +1960,7 @@ impl UiFrame<'_> {
                     move_vec_idx(vec, dragging_idx, target_idx);
                     move_vec_idx(&mut self.current_mut().children, dragging_idx, target_idx);
                 } else {
-                    self.ui.input_highlight = Some(Rect {
-                        // TODO: line depending on dir
-                        origin: closest_pos,
-                        size: Size::new(10.0, 10.0),
-                    });
+                    self.ui.input_highlight = Some(closest_line);
                 }
             }
         }
@@ -863,6 +1970,11 @@ impl UiFrame<'_> {
 }
 
 impl Key {
+    /// Whether this is `ancestor`, or one of its children (or their children, etc).
+    pub fn is_within(&self, ancestor: &Key) -> bool {
+        self == ancestor || self.parent.as_ref().is_some_and(|parent| parent.is_within(ancestor))
+    }
+
     /// Returns the key of the root control. The root is guaranteed to always exist in `Ui::pool`.
     pub const fn root() -> Self {
         Self {
@@ -883,6 +1995,7 @@ impl Control {
             region: Region {
                 rect: Rect::zero(),
                 layer: LAYER_DEFAULT,
+                clip: None,
             },
 
             inputs_active: InputFlags::empty(),
@@ -895,6 +2008,14 @@ impl Control {
             drag: None,
             unhandled_drag_end: false,
             drag_trigger_update: false,
+            ended_drag: None,
+
+            focusable: false,
+            tooltip: None,
+            hovered_since: None,
+
+            clicked_at: None,
+            is_double_click: false,
         }
     }
 
@@ -910,9 +2031,24 @@ impl Control {
     fn advance_left_click(&mut self) -> ClickFSM {
         self.inputs_trigger_update |= Input::LeftMouseDown | Input::MouseOver;
         self.left_click = self.left_click.advance(Input::LeftMouseDown, self.inputs_active);
+
+        if self.left_click.is_click() {
+            let now = Instant::now();
+            self.is_double_click = self.clicked_at.is_some_and(|at| now - at <= DOUBLE_CLICK_TIME);
+            // A third click starts a new double-click rather than finishing another.
+            self.clicked_at = if self.is_double_click { None } else { Some(now) };
+        }
+
         self.left_click
     }
 
+    /// Advances the right_click FSM and sets the relevant inputs_trigger_update flags.
+    fn advance_right_click(&mut self) -> ClickFSM {
+        self.inputs_trigger_update |= Input::RightMouseDown | Input::MouseOver;
+        self.right_click = self.right_click.advance(Input::RightMouseDown, self.inputs_active);
+        self.right_click
+    }
+
     fn apply_drag(&mut self) {
         self.drag_trigger_update = true;
         self.inputs_trigger_update |= Input::MouseOver | Input::LeftMouseDown;
@@ -937,11 +2073,30 @@ impl Control {
     fn accept_widget(&mut self, new: Widget) {
         match (&mut self.widget, new) {
             (Widget::Modal { .. }, Widget::Modal { .. }) => (),
+            (Widget::ScrollArea { .. }, Widget::ScrollArea { .. }) => (),
             (_, new) => self.widget = new,
         }
     }
 }
 
+impl Region {
+    /// Whether `point` is inside this region and not clipped away.
+    pub fn contains(&self, point: Point) -> bool {
+        self.rect.contains(point) && self.clip.is_none_or(|clip| clip.contains(point))
+    }
+}
+
+/// What a text control does with text that is wider than it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOverflow {
+    /// Lay the text out on one line, cutting off whatever doesn't fit.
+    Clip,
+    /// Break the text into lines between words, making the control taller.
+    Wrap,
+    /// Cut the text short and end it with "..." to show that some is missing.
+    Ellipsis,
+}
+
 impl Default for Widget {
     fn default() -> Self {
         Widget::Group
@@ -958,6 +2113,12 @@ impl Button<'_> {
         self.is_click
     }
 
+    /// Whether this update's click followed another quickly enough to make a double-click. The first click of the
+    /// two is reported by [Button::clicked] as usual.
+    pub fn double_clicked(&self) -> bool {
+        self.ctrl.left_click.is_click() && self.ctrl.is_double_click
+    }
+
     pub fn with_width(&mut self, width: f32) -> &mut Self {
         self.ctrl.layout.width = Dimension::Range(width..=width);
         self
@@ -972,6 +2133,52 @@ impl Button<'_> {
         self.ctrl.layout.width = Dimension::Fill;
         self
     }
+
+    /// Shown near the mouse after it rests on the button for a moment.
+    pub fn tooltip(&mut self, text: &str) -> &mut Self {
+        self.ctrl.tooltip = Some(text.to_owned());
+        self
+    }
+
+    /// Puts the button on `layer` rather than its parent's. See [UiFrame::layer].
+    pub fn on_layer(&mut self, layer: Layer) -> &mut Self {
+        self.ctrl.layout.layer = Some(layer);
+        self
+    }
+}
+
+pub struct TextInputResponse<'a> {
+    ctrl: &'a mut Control,
+    changed: bool,
+}
+
+impl TextInputResponse<'_> {
+    /// Whether the text was edited during this update.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Whether the text input is being typed into. It stops when Enter or Escape is pressed, or something else is
+    /// clicked.
+    pub fn is_focused(&self) -> bool {
+        matches!(self.ctrl.widget, Widget::TextInput { is_focused: true, .. })
+    }
+
+    pub fn with_width(&mut self, width: f32) -> &mut Self {
+        self.ctrl.layout.width = Dimension::Range(width..=width);
+        self
+    }
+
+    pub fn fill_width(&mut self) -> &mut Self {
+        self.ctrl.layout.width = Dimension::Fill;
+        self
+    }
+
+    /// Puts the text input on `layer` rather than its parent's. See [UiFrame::layer].
+    pub fn on_layer(&mut self, layer: Layer) -> &mut Self {
+        self.ctrl.layout.layer = Some(layer);
+        self
+    }
 }
 
 pub struct Text<'a> {
@@ -998,26 +2205,249 @@ impl Text<'_> {
         self.ctrl.layout.height = Dimension::Range(height..=height);
         self
     }
+
+    /// Break text that is too wide onto more lines. Give the text a width, or it will be as wide as its parent.
+    pub fn wrap(&mut self) -> &mut Self {
+        self.set_overflow(TextOverflow::Wrap)
+    }
+
+    /// Shorten text that is too wide to fit, ending it with "...".
+    pub fn ellipsize(&mut self) -> &mut Self {
+        self.set_overflow(TextOverflow::Ellipsis)
+    }
+
+    /// Puts the text on `layer` rather than its parent's. See [UiFrame::layer].
+    pub fn on_layer(&mut self, layer: Layer) -> &mut Self {
+        self.ctrl.layout.layer = Some(layer);
+        self
+    }
+
+    /// Let the user select part of the text by dragging across it, e.g. to copy it. See [Ui::selected_text].
+    pub fn selectable(&mut self) -> &mut Self {
+        if let Widget::Text { is_selectable, .. } = &mut self.ctrl.widget {
+            *is_selectable = true;
+        }
+
+        self.ctrl.drag_trigger_update = true;
+        self.ctrl.inputs_trigger_update |= Input::MouseOver | Input::LeftMouseDown;
+        self.ctrl.unhandled_drag_end = false;
+        self
+    }
+
+    fn set_overflow(&mut self, new: TextOverflow) -> &mut Self {
+        if let Widget::Text { overflow, .. } = &mut self.ctrl.widget {
+            *overflow = new;
+        }
+        self
+    }
+}
+
+/// Shortens `text` to as many characters as fit in `max_width` followed by "...", as measured by `measure`. Returns
+/// `None` if `text` fits already.
+fn ellipsize<M: FnMut(&str) -> f32>(text: &str, max_width: f32, mut measure: M) -> Option<String> {
+    const ELLIPSIS: &str = "...";
+
+    if measure(text) <= max_width {
+        return None;
+    }
+
+    // Binary search for the longest prefix, in characters, that fits.
+    let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let (mut fits, mut too_long) = (0, boundaries.len());
+    while too_long - fits > 1 {
+        let mid = (fits + too_long) / 2;
+        let candidate = format!("{}{}", text[..boundaries[mid]].trim_end(), ELLIPSIS);
+
+        if measure(&candidate) <= max_width {
+            fits = mid;
+        } else {
+            too_long = mid;
+        }
+    }
+
+    let end = boundaries.get(fits).copied().unwrap_or(0);
+    Some(format!("{}{}", text[..end].trim_end(), ELLIPSIS))
+}
+
+/// The byte index in `text` of the gap between characters closest to `x`, measured from the left of the text.
+fn text_index_at<R: Render>(renderer: &mut R, text: &str, x: f32) -> usize {
+    let mut closest = (0, f32::INFINITY);
+
+    for index in text.char_indices().map(|(i, _)| i).chain(std::iter::once(text.len())) {
+        let distance = (renderer.caret_x(text, index) - x).abs();
+        if distance < closest.1 {
+            closest = (index, distance);
+        }
+    }
+
+    closest.0
+}
+
+/// The drag targets (see `UiFrame::draglist`) before and after the draglist element at `idx`, which occupies `rect`.
+fn drag_targets_around(dir: layout::Dir, rect: &Rect, idx: usize) -> [(Point, usize, Rect); 2] {
+    const LINE_WIDTH: f32 = 2.0;
+
+    match dir {
+        layout::Dir::TopBottom { .. } => {
+            let line = |y: f32| {
+                Rect::new(Point::new(rect.min_x(), y - LINE_WIDTH / 2.0), Size::new(rect.width(), LINE_WIDTH))
+            };
+
+            [
+                (Point::new(rect.center().x, rect.min_y()), idx, line(rect.min_y())),
+                (Point::new(rect.center().x, rect.max_y()), idx + 1, line(rect.max_y())),
+            ]
+        }
+        layout::Dir::LeftRight { .. } | layout::Dir::BackFront => {
+            let line = |x: f32| {
+                Rect::new(Point::new(x - LINE_WIDTH / 2.0, rect.min_y()), Size::new(LINE_WIDTH, rect.height()))
+            };
+
+            [
+                (Point::new(rect.min_x(), rect.center().y), idx, line(rect.min_x())),
+                (Point::new(rect.max_x(), rect.center().y), idx + 1, line(rect.max_x())),
+            ]
+        }
+    }
 }
 
-/// Move the element at `from_idx` to `to_idx` without cloning.
+/// Move the element at `src_idx` so it is inserted before the element that was at `dest_idx`, without cloning.
 fn move_vec_idx<T>(vec: &mut Vec<T>, src_idx: usize, dest_idx: usize) {
-    use std::mem::{replace, zeroed};
+    if src_idx < dest_idx {
+        // Everything between the two shifts left by 1 to fill the gap.
+        vec[src_idx..dest_idx].rotate_left(1);
+    } else if src_idx > dest_idx {
+        vec[dest_idx..=src_idx].rotate_right(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_move_vec_idx() {
+        // Strings own heap memory, so any double-free or use of uninitialised memory shows up under Miri.
+        let mut vec: Vec<String> = ["a", "b", "c", "d"].iter().map(|s| s.to_string()).collect();
 
-    if src_idx == dest_idx {
-        return;
+        move_vec_idx(&mut vec, 0, 3);
+        assert_eq!(vec, ["b", "c", "a", "d"]);
+
+        move_vec_idx(&mut vec, 3, 0);
+        assert_eq!(vec, ["d", "b", "c", "a"]);
+
+        move_vec_idx(&mut vec, 1, 4);
+        assert_eq!(vec, ["d", "c", "a", "b"]);
+
+        // Dropping before or after itself leaves the element where it is.
+        move_vec_idx(&mut vec, 2, 2);
+        move_vec_idx(&mut vec, 2, 3);
+        assert_eq!(vec, ["d", "c", "a", "b"]);
     }
 
-    // Take the src element out of the vec, replacing it with uninitialised memory (!!!).
-    let src = replace(&mut vec[src_idx], unsafe { zeroed() });
+    /// Lays out without drawing anything. Every character is 10 wide and 10 high.
+    struct Measure;
+
+    impl Render for Measure {
+        fn measure_text(&mut self, text: &str, _max_width: Option<f32>) -> Size {
+            Size::new(text.chars().count() as f32 * 10.0, 10.0)
+        }
+
+        fn render_text(&mut self, _: &Region, _: &str) {}
+        fn render_text_selection(&mut self, _: &Region, _: &str, _: Range<usize>) {}
+        fn render_button(&mut self, _: &Region, _: &'static str) {}
+        fn render_toggle_button(&mut self, _: &Region, _: bool, _: bool) {}
+        fn render_window(&mut self, _: &Region) {}
+        fn render_text_input(&mut self, _: &Region, _: &str, _: Option<usize>) {}
+        fn render_slider(&mut self, _: &Region, _: f32, _: bool) {}
+        fn render_scrollbar(&mut self, _: &Region, _: &Rect, _: bool) {}
+        fn render_progress_bar(&mut self, _: &Region, _: Option<f32>, _: f32) {}
+        fn render_block(&mut self, _: &Region, _: [f32; 4]) {}
+        fn render_input_highlight(&mut self, _: &Rect) {}
+        fn render_focus_ring(&mut self, _: &Region) {}
+        fn render_tooltip(&mut self, _: &Region, _: &str) {}
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut ui = Ui::new();
+        ui.resize(Rect::new(Point::zero(), Size::new(400.0, 400.0)), &mut Measure);
+
+        // Two buttons in the same place, one put above the other.
+        let frame = |over: Option<Layer>| {
+            move |ui: &mut UiFrame| {
+                ui.button(0, "Under");
+                let mut button = ui.button(1, "Over");
+                if let Some(layer) = over {
+                    button.on_layer(layer);
+                }
+            }
+        };
+
+        // (layer, whether the mouse is over it) of each button
+        let buttons = |ui: &Ui| {
+            let mut buttons: Vec<(Layer, bool)> = ui.pool
+                .values()
+                .filter(|ctrl| matches!(ctrl.widget, Widget::Button { .. }))
+                .map(|ctrl| (ctrl.region.layer, ctrl.inputs_active.contains(Input::MouseOver)))
+                .collect();
+            buttons.sort_unstable();
+            buttons
+        };
+
+        ui.update(frame(Some(LAYER_DEFAULT + 2)), &mut Measure);
+        let _ = ui.set_mouse_pos(Point::new(10.0, 10.0));
+        assert_eq!(buttons(&ui), vec![(LAYER_DEFAULT, false), (LAYER_DEFAULT + 2, true)]);
+
+        // The layer is only kept for as long as it is asked for.
+        ui.update(frame(None), &mut Measure);
+        let _ = ui.set_mouse_pos(Point::new(11.0, 10.0));
+        assert_eq!(buttons(&ui), vec![(LAYER_DEFAULT, false), (LAYER_DEFAULT, true)]);
+
+        // A group on a layer takes its children with it, and a lower layer than the rest gets no input.
+        ui.update(|ui| {
+            ui.layer(0, LAYER_DEFAULT - 1, |ui| {
+                ui.button(0, "Under");
+            });
+            ui.button(1, "Over");
+        }, &mut Measure);
+        let _ = ui.set_mouse_pos(Point::new(10.0, 10.0));
+        assert_eq!(buttons(&ui), vec![(LAYER_DEFAULT - 1, false), (LAYER_DEFAULT, true)]);
+    }
+
+    #[test]
+    fn test_slider_out_of_range() {
+        let mut ui = Ui::new();
+        ui.resize(Rect::new(Point::zero(), Size::new(400.0, 400.0)), &mut Measure);
+
+        // Without any input, a value outside the range is neither clamped nor reported as changed.
+        for &initial in &[-10.0, 500.0] {
+            let mut value = initial;
+            let mut changed = true;
+            ui.update(|ui| changed = ui.slider(0, &mut value, 20.0..=400.0), &mut Measure);
+            assert!(!changed);
+            assert_eq!(value, initial);
+
+            let fraction = ui.pool
+                .values()
+                .find_map(|ctrl| match ctrl.widget {
+                    Widget::Slider { fraction, .. } => Some(fraction),
+                    _ => None,
+                })
+                .unwrap();
+            assert!((0.0..=1.0).contains(&fraction));
+        }
+    }
 
-    vec.insert(dest_idx, src);
+    #[test]
+    fn test_ellipsize() {
+        // Every character is 10 wide.
+        let measure = |text: &str| text.chars().count() as f32 * 10.0;
 
-    // Remove the zero memory that we left at `src_idx` previously.
-    if src_idx > dest_idx {
-        // `insert` shifts all elements after `dest_idx` to the right by 1.
-        vec.remove(src_idx + 1);
-    } else {
-        vec.remove(src_idx);
+        assert_eq!(ellipsize("Short", 50.0, measure), None);
+        assert_eq!(ellipsize("Overworld theme", 100.0, measure).as_deref(), Some("Overwor..."));
+        assert_eq!(ellipsize("Big band", 70.0, measure).as_deref(), Some("Big..."));
+        assert_eq!(ellipsize("Ünïcödé", 50.0, measure).as_deref(), Some("Ün..."));
+        assert_eq!(ellipsize("Anything", 10.0, measure).as_deref(), Some("..."));
     }
 }
@@ -1,13 +1,40 @@
+use std::ops::Range;
+
 use super::{Region, Size, Rect};
 
 pub trait Render {
     // Layout utilities.
-    fn measure_text(&mut self, text: &str) -> Size;
+    /// The size of `text` when drawn. If `max_width` is given, lines are broken between words to fit within it.
+    fn measure_text(&mut self, text: &str, max_width: Option<f32>) -> Size;
+
+    /// How far from the left of `text` a caret before the byte at `index` would be drawn.
+    fn caret_x(&mut self, text: &str, index: usize) -> f32 {
+        // Measuring the text with a character after it includes the advance of any trailing spaces.
+        let bar = self.measure_text("|", None).width;
+        self.measure_text(&format!("{}|", &text[..index]), None).width - bar
+    }
 
     // Visitor pattern for rendering.
     fn render_text(&mut self, region: &Region, text: &str);
+    /// Drawn behind `text`, which is about to be drawn in `region`, to show that the bytes in `selection` are selected.
+    fn render_text_selection(&mut self, region: &Region, text: &str, selection: Range<usize>);
     fn render_button(&mut self, region: &Region, texture: &'static str);
     fn render_toggle_button(&mut self, region: &Region, is_pressed: bool, is_enabled: bool);
     fn render_window(&mut self, region: &Region);
+    /// `cursor` is a byte index into `text`, given only if the text input has keyboard focus.
+    fn render_text_input(&mut self, region: &Region, text: &str, cursor: Option<usize>);
+    /// `fraction` is the position of the handle along the track, from 0.0 (left) to 1.0 (right).
+    fn render_slider(&mut self, region: &Region, fraction: f32, is_focused: bool);
+    /// `thumb` is in screen space, within `region`.
+    fn render_scrollbar(&mut self, region: &Region, thumb: &Rect, is_dragging: bool);
+    /// `fraction` is from 0.0 (empty) to 1.0 (full). If it is None, the progress is unknown, and the bar should animate
+    /// using `phase`, which repeatedly increases from 0.0 to 1.0 over time.
+    fn render_progress_bar(&mut self, region: &Region, fraction: Option<f32>, phase: f32);
+    /// A plain rectangle in a canvas. `color` is RGBA, from 0.0 to 1.0.
+    fn render_block(&mut self, region: &Region, color: [f32; 4]);
     fn render_input_highlight(&mut self, rect: &Rect);
+    /// Drawn over the control with keyboard focus.
+    fn render_focus_ring(&mut self, region: &Region);
+    /// `region` is sized to fit `text` with some padding, and is above everything else.
+    fn render_tooltip(&mut self, region: &Region, text: &str);
 }
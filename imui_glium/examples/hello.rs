@@ -4,6 +4,7 @@ use imui_glium::*;
 struct Interface {
     num_buttons: u32,
     draggables: Vec<u32>,
+    tiles: Vec<u32>,
 }
 
 impl Interface {
@@ -17,7 +18,7 @@ impl Interface {
 
                     ui.hbox("buttons", |ui| {
                         for i in 0..self.num_buttons {
-                            if ui.button(i, format!("Button {}", i)).clicked() {
+                            if ui.button(i, format!("Button {}", i)).tooltip("Adds another button").clicked() {
                                 println!("button {} clicked", i);
 
                                 self.num_buttons += 1;
@@ -31,6 +32,10 @@ impl Interface {
 
                     ui.text("num buttons", format!("Above are {} buttons", self.num_buttons));
 
+                    // Fills up as buttons are added, and one that's always busy.
+                    ui.progress_bar("progress", self.num_buttons as f32 / 10.0);
+                    ui.progress_bar("busy", None);
+
                     if ui.vdraglist("draglist", &mut self.draggables, |ui, item| {
                         ui.pad(-1, 10.0);
                         ui.text(0, "Drag me!").center_y();
@@ -39,6 +44,15 @@ impl Interface {
                     }) {
                         updated = true;
                     }
+
+                    // Wider than the box holds, so the tiles wrap into a grid that can be reordered across rows.
+                    ui.known_size("grid", 400.0, 200.0, |ui| {
+                        if ui.grid_draglist("grid draglist", &mut self.tiles, |ui, tile| {
+                            ui.button(0, format!("Tile {}", tile)).with_width(90.0);
+                        }) {
+                            updated = true;
+                        }
+                    });
                 });
             });
 
@@ -72,6 +86,7 @@ fn main() {
     let mut interface = Interface {
         num_buttons: 1,
         draggables: vec![1, 2, 3, 4, 5],
+        tiles: (1..=10).collect(),
     };
     interface.update(&mut glue);
 
@@ -113,5 +128,12 @@ fn main() {
             glue.draw(&mut surface, &display).unwrap();
             surface.finish().unwrap();
         }
+
+        // Tooltips appear after a delay, so wake up to draw them.
+        if *control_flow == ControlFlow::Wait {
+            if let Some(deadline) = glue.redraw_deadline() {
+                *control_flow = ControlFlow::WaitUntil(deadline);
+            }
+        }
     })
 }
@@ -1,20 +1,48 @@
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+use std::time::SystemTime;
 
+use fontdue::layout::GlyphRasterConfig;
 use image::GenericImageView;
 use imui::{Rect, Point, Size};
 use rect_packer::Packer;
-use glium::backend::Facade;
+use glium::{CapabilitiesSource, Surface};
+use glium::backend::{Context, Facade};
 use glium::texture::{Texture2d, RawImage2d, TextureCreationError};
 
 const INITIAL_HEIGHT: u32 = 1024;
 const INITIAL_WIDTH: u32 = 1024;
 
+/// How big the atlas can grow along each side when everything in it is in use, unless the graphics driver allows less.
+/// The atlas is drawn from as one texture, so it can't be split up instead.
+const MAX_SIZE: u32 = 4096;
+
 pub struct TextureAtlas {
     texture: Texture2d,
+    /// For making a bigger texture when this one fills up.
+    context: Rc<Context>,
     packer: Packer,
     map: HashMap<SpriteId, Sprite>,
+
+    /// A copy of every sprite's pixels, so that the atlas can be packed again without some of them.
+    images: HashMap<SpriteId, RawImage2d<'static, u8>>,
+
+    /// Images that were inserted from files, so that they can be [reloaded](TextureAtlas::reload).
+    sources: HashMap<SpriteId, Source>,
+
+    /// Counts [frames](TextureAtlas::next_frame), so that glyphs that haven't been drawn for a while can make room for
+    /// new ones when the atlas fills up.
+    frame: u64,
+
+    /// The frame each glyph was last [used](TextureAtlas::use_glyph) in.
+    glyph_frames: HashMap<GlyphRasterConfig, u64>,
+
+    /// Whether sprites have moved since the start of the frame.
+    repacked: bool,
 }
 
 pub struct Sprite {
@@ -23,25 +51,36 @@ pub struct Sprite {
 
     /// Dimensions of the source image.
     pub src_dimensions: Size,
+
+    /// Where this texture is found on the atlas, in pixels.
+    texture_rect: glium::Rect,
+}
+
+struct Source {
+    path: PathBuf,
+
+    /// When the file was last modified as of loading it, if the platform says.
+    modified: Option<SystemTime>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum SpriteId {
     StaticStr(&'static str),
-    Glyph(fontdue::layout::GlyphRasterConfig),
+    Glyph(GlyphRasterConfig),
 }
 
 impl TextureAtlas {
     pub fn new<F: Facade>(facade: &F) -> Result<Self, TextureCreationError> {
         Ok(TextureAtlas {
             texture: Texture2d::empty(facade, INITIAL_WIDTH, INITIAL_HEIGHT)?,
-            packer: Packer::new(rect_packer::Config {
-                width: INITIAL_WIDTH as i32,
-                height: INITIAL_HEIGHT as i32,
-                border_padding: 4,
-                rectangle_padding: 4,
-            }),
+            context: facade.get_context().clone(),
+            packer: new_packer(INITIAL_WIDTH, INITIAL_HEIGHT),
             map: HashMap::new(),
+            images: HashMap::new(),
+            sources: HashMap::new(),
+            frame: 0,
+            glyph_frames: HashMap::new(),
+            repacked: false,
         })
     }
 
@@ -49,65 +88,195 @@ impl TextureAtlas {
         &self.texture
     }
 
-    /// Inserts an image into the atlas.
+    /// Inserts an image into the atlas. The file is remembered, so that [reload](TextureAtlas::reload) can read it
+    /// again if it changes.
     pub fn insert<I, P>(&mut self, id: I, path: P) -> Result<Option<Sprite>, Box<dyn Error>>
     where
         I: Into<SpriteId>,
         P: AsRef<Path>,
     {
-        let image = image::io::Reader::open(path)?.decode()?;
-        let data = image.as_rgba8().unwrap().to_vec();
+        let id = id.into();
+        let path = path.as_ref().to_owned();
+        let image = load_image(&path)?;
+
+        self.sources.insert(id.clone(), Source {
+            modified: modified_time(&path),
+            path,
+        });
+        Ok(self.insert_raw(id, image))
+    }
+
+    /// Reads again every image [inserted](TextureAtlas::insert) from a file that has changed since, returning whether
+    /// any had. Sprites keep their ids, and keep their place on the atlas too unless an image changed size, in which
+    /// case everything is packed again. Either way, draw the UI again afterwards.
+    ///
+    /// If an image can't be read, e.g. because it is still being written, nothing changes.
+    pub fn reload(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut changed = Vec::new();
+
+        for (id, source) in &self.sources {
+            let modified = modified_time(&source.path);
+
+            if modified.is_none() || modified != source.modified {
+                changed.push((id.clone(), load_image(&source.path)?, modified));
+            }
+        }
+
+        if changed.is_empty() {
+            return Ok(false);
+        }
+
+        let same_size = changed.iter().all(|(id, image, _)| match self.map.get(id) {
+            Some(sprite) => sprite.texture_rect.width == image.width && sprite.texture_rect.height == image.height,
+            None => false,
+        });
+
+        for (id, image, modified) in changed {
+            if same_size {
+                self.texture.write(self.map[&id].texture_rect, borrow_image(&image));
+            }
+
+            self.images.insert(id.clone(), image);
+            self.sources.get_mut(&id).unwrap().modified = modified;
+        }
+
+        if !same_size {
+            self.repack();
+        }
 
-        Ok(self.insert_raw(id, RawImage2d::from_raw_rgba(data, image.dimensions())))
+        Ok(true)
     }
 
-    pub fn insert_raw<I, R>(&mut self, id: I, image: RawImage2d<'_, R>) -> Option<Sprite>
+    /// Inserts an image into the atlas. If there is no room for it, glyphs that haven't been
+    /// [used](TextureAtlas::use_glyph) this frame are evicted to make some; they are rasterized again when next needed.
+    /// If that isn't enough, the atlas grows. An image that doesn't fit even in the biggest atlas is left out, so
+    /// [get](TextureAtlas::get) gives `None` for it.
+    pub fn insert_raw<I>(&mut self, id: I, image: RawImage2d<'static, u8>) -> Option<Sprite>
     where
         I: Into<SpriteId>,
-        R: glium::texture::PixelValue + Clone,
     {
         let id = id.into();
 
-        if let Some(rect) = self.packer.pack(image.width as i32, image.height as i32, false) {
-            assert!(rect.width == image.width as i32);
-            assert!(rect.height == image.height as i32);
-
-            // Convert rect to UV coordinates.
-            let uv_rect = Rect {
-                origin: Point::new(
-                    rect.x as f32 / self.texture.width() as f32,
-                    rect.y as f32 / self.texture.height() as f32,
-                ),
-                size: Size::new(
-                    rect.width as f32 / self.texture.width() as f32,
-                    rect.height as f32 / self.texture.height() as f32,
-                ),
-            };
-
-            // Check uv_rect is actually in UV coordinates.
-            assert!(uv_rect.min_x() >= 0.0);
-            assert!(uv_rect.min_y() >= 0.0);
-            assert!(uv_rect.max_x() <= 1.0);
-            assert!(uv_rect.max_y() <= 1.0);
-
-            // Add the sprite to the map.
-            let old = self.map.insert(id, Sprite {
-                uv_rect,
-                src_dimensions: Size::new(rect.width as f32, rect.height as f32),
-            });
-
-            // Actually write the sprite image to our texture.
-            self.texture.write(glium::Rect {
-                left: rect.x as u32,
-                bottom: rect.y as u32,
-                width: rect.width as u32,
-                height: rect.height as u32,
-            }, image);
-
-            old
-        } else {
-            todo!("resize TextureAtlas when out of space")
+        let image = match self.try_insert(id.clone(), image) {
+            Ok(old) => return old,
+            Err(image) => image,
+        };
+
+        // Make room by forgetting the glyphs that aren't on screen.
+        let frame = self.frame;
+        let glyph_frames = &self.glyph_frames;
+        self.images.retain(|id, _| match id {
+            SpriteId::Glyph(key) => glyph_frames.get(key) == Some(&frame),
+            SpriteId::StaticStr(_) => true,
+        });
+        self.glyph_frames.retain(|_, used| *used == frame);
+        self.repack();
+
+        let image = match self.try_insert(id.clone(), image) {
+            Ok(old) => return old,
+            Err(image) => image,
+        };
+
+        // Everything left is in use, so the atlas has to grow.
+        let old = self.map.remove(&id);
+        self.images.insert(id, image);
+        self.repack();
+        old
+    }
+
+    /// Packs and writes a sprite, giving the image back if there is no room for it.
+    fn try_insert(
+        &mut self,
+        id: SpriteId,
+        image: RawImage2d<'static, u8>,
+    ) -> Result<Option<Sprite>, RawImage2d<'static, u8>> {
+        let rect = match self.packer.pack(image.width as i32, image.height as i32, false) {
+            Some(rect) => rect,
+            None => return Err(image),
+        };
+
+        assert!(rect.width == image.width as i32);
+        assert!(rect.height == image.height as i32);
+
+        // Convert rect to UV coordinates.
+        let uv_rect = Rect {
+            origin: Point::new(
+                rect.x as f32 / self.texture.width() as f32,
+                rect.y as f32 / self.texture.height() as f32,
+            ),
+            size: Size::new(
+                rect.width as f32 / self.texture.width() as f32,
+                rect.height as f32 / self.texture.height() as f32,
+            ),
+        };
+
+        // Check uv_rect is actually in UV coordinates.
+        assert!(uv_rect.min_x() >= 0.0);
+        assert!(uv_rect.min_y() >= 0.0);
+        assert!(uv_rect.max_x() <= 1.0);
+        assert!(uv_rect.max_y() <= 1.0);
+
+        // Add the sprite to the map.
+        let texture_rect = glium::Rect {
+            left: rect.x as u32,
+            bottom: rect.y as u32,
+            width: rect.width as u32,
+            height: rect.height as u32,
+        };
+
+        let old = self.map.insert(id.clone(), Sprite {
+            uv_rect,
+            src_dimensions: Size::new(rect.width as f32, rect.height as f32),
+            texture_rect,
+        });
+
+        // Actually write the sprite image to our texture.
+        self.texture.write(texture_rect, borrow_image(&image));
+        self.images.insert(id, image);
+
+        Ok(old)
+    }
+
+    /// Clears the atlas and inserts every image in `self.images` again, moving sprites around. If they don't all fit,
+    /// the atlas doubles in size until they do, up to [MAX_SIZE]. Images that don't fit even then are left out.
+    fn repack(&mut self) {
+        self.repacked = true;
+
+        loop {
+            let left_out = self.pack_all();
+            if left_out.is_empty() {
+                return;
+            }
+
+            let max_size = MAX_SIZE.min(self.context.get_capabilities().max_texture_size as u32);
+            let width = (self.texture.width() * 2).min(max_size);
+            let height = (self.texture.height() * 2).min(max_size);
+            if width == self.texture.width() && height == self.texture.height() {
+                return;
+            }
+
+            match Texture2d::empty(&self.context, width, height) {
+                Ok(texture) => self.texture = texture,
+                Err(_) => return,
+            }
+
+            self.images.extend(left_out);
+        }
+    }
+
+    /// Clears the atlas and inserts every image in `self.images` again, returning those there was no room for.
+    fn pack_all(&mut self) -> Vec<(SpriteId, RawImage2d<'static, u8>)> {
+        self.packer = new_packer(self.texture.width(), self.texture.height());
+        self.map.clear();
+        self.texture.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+
+        let mut left_out = Vec::new();
+        for (id, image) in std::mem::take(&mut self.images) {
+            if let Err(image) = self.try_insert(id.clone(), image) {
+                left_out.push((id, image));
+            }
         }
+        left_out
     }
 
     pub fn get<I>(&self, id: I) -> Option<&Sprite>
@@ -116,6 +285,54 @@ impl TextureAtlas {
     {
         self.map.get(&id.into())
     }
+
+    /// Records that a glyph is being drawn this frame, so that it won't be evicted to make room for others until the
+    /// next.
+    pub fn use_glyph(&mut self, key: GlyphRasterConfig) {
+        self.glyph_frames.insert(key, self.frame);
+    }
+
+    /// Starts a new frame. Call this before drawing anything that [uses](TextureAtlas::use_glyph) glyphs.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        self.repacked = false;
+    }
+
+    /// Whether sprites have moved since the [frame](TextureAtlas::next_frame) started, in which case any vertices
+    /// made since hold stale UV coordinates and should be made again.
+    pub fn was_repacked(&self) -> bool {
+        self.repacked
+    }
+}
+
+fn new_packer(width: u32, height: u32) -> Packer {
+    Packer::new(rect_packer::Config {
+        width: width as i32,
+        height: height as i32,
+        border_padding: 4,
+        rectangle_padding: 4,
+    })
+}
+
+fn load_image(path: &Path) -> Result<RawImage2d<'static, u8>, Box<dyn Error>> {
+    let image = image::io::Reader::open(path)?.decode()?;
+    let data = image.as_rgba8().ok_or("image is not RGBA")?.to_vec();
+
+    Ok(RawImage2d::from_raw_rgba(data, image.dimensions()))
+}
+
+/// A view of an image that can be written to a texture while keeping the image.
+fn borrow_image<'a>(image: &'a RawImage2d<'_, u8>) -> RawImage2d<'a, u8> {
+    RawImage2d {
+        data: Cow::Borrowed(&image.data),
+        width: image.width,
+        height: image.height,
+        format: image.format,
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
 }
 
 impl From<&'static str> for SpriteId {
@@ -124,8 +341,8 @@ impl From<&'static str> for SpriteId {
     }
 }
 
-impl From<fontdue::layout::GlyphRasterConfig> for SpriteId {
-    fn from(g: fontdue::layout::GlyphRasterConfig) -> Self {
+impl From<GlyphRasterConfig> for SpriteId {
+    fn from(g: GlyphRasterConfig) -> Self {
         SpriteId::Glyph(g)
     }
 }
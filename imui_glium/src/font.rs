@@ -37,6 +37,8 @@ impl Face {
         self.layout.append(&[&self.font], style);
 
         for glyph in self.layout.glyphs() {
+            atlas.use_glyph(glyph.key);
+
             let sprite = if let Some(sprite) = atlas.get(glyph.key) {
                 // Glyph already loaded, use that.
                 sprite
@@ -55,7 +57,10 @@ impl Face {
                 }
 
                 atlas.insert_raw(glyph.key, RawImage2d::from_raw_rgba(rgba, dimensions));
-                atlas.get(glyph.key).unwrap()
+                match atlas.get(glyph.key) {
+                    Some(sprite) => sprite,
+                    None => continue, // No room for it even in the biggest atlas
+                }
             };
 
             render_quad(sprite, Rect {
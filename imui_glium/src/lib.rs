@@ -1,7 +1,10 @@
 pub mod atlas;
 pub mod font;
+mod theme;
 
 use std::error::Error;
+use std::ops::Range;
+use std::time::Instant;
 
 pub use imui::*;
 pub use glium;
@@ -11,12 +14,18 @@ pub use glium::glutin::event_loop::{EventLoop, ControlFlow};
 use glium::{Display, IndexBuffer, VertexBuffer, implement_vertex, uniform};
 use glium::program::{Program, ProgramCreationInput};
 use glium::backend::Facade;
+use glium::glutin::dpi::{LogicalSize, PhysicalSize};
+use imui::input::EditKey;
 
 use atlas::{TextureAtlas, SpriteId};
+pub use theme::Theme;
 
 const INITIAL_VERTEX_BUF_CAPACITY: usize = 512;
 const INITIAL_INDEX_BUF_CAPACITY: usize = INITIAL_VERTEX_BUF_CAPACITY * 3 / 4;
 
+/// How far one notch of a mouse wheel scrolls, in logical pixels.
+const SCROLL_LINE_HEIGHT: f32 = 36.0;
+
 type Transform3D = euclid::default::Transform3D<f32>;
 
 /// RGBA colour.
@@ -24,40 +33,68 @@ type Color = [f32; 4];
 
 pub struct Glue {
     ui: Ui,
+
+    /// Something the vertices depend on besides the UI tree changed, e.g. the window size or the theme.
     need_render: bool,
 
+    /// The vertices differ from what was last uploaded to the GPU.
+    need_upload: bool,
+    modifiers: glium::glutin::event::ModifiersState,
+
     program: Program,
     vertex_buf: VertexBuffer<Vertex>,
-    index_buf: IndexBuffer<u16>,
+    index_buf: IndexBuffer<u32>,
     projection: Transform3D,
 
     renderer: Renderer,
+    stats: DrawStats,
+
+    /// Copies of what is in `vertex_buf` and `index_buf`, to tell whether rendering again changed anything.
+    uploaded_vertices: Vec<Vertex>,
+    uploaded_indices: Vec<u32>,
 }
 
-#[derive(Clone, Copy)]
+/// What the last [draw](Glue::draw) sent to the GPU, to see how expensive the UI is to draw. Everything is drawn from
+/// one texture atlas, with layers ordered by depth, so there is one draw call however many controls there are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: usize,
+    pub vertices: usize,
+    pub triangles: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
 struct Vertex {
     position: [f32; 2],
     uv: [f32; 2],
     color: [f32; 4],
     z: f32,
+
+    /// Fragments outside of this rectangle (min x, min y, max x, max y) are discarded.
+    clip: [f32; 4],
 }
 
-implement_vertex!(Vertex, position, uv, color, z);
+implement_vertex!(Vertex, position, uv, color, z, clip);
+
+/// The `Vertex::clip` value for a region.
+fn clip_rect(region: &Region) -> [f32; 4] {
+    match &region.clip {
+        Some(clip) => [clip.min_x(), clip.min_y(), clip.max_x(), clip.max_y()],
+        None => [f32::MIN, f32::MIN, f32::MAX, f32::MAX],
+    }
+}
 
 struct Renderer {
     pub vertex_vec: Vec<Vertex>,
-    pub index_vec: Vec<u16>,
+    pub index_vec: Vec<u32>,
     pub atlas: TextureAtlas,
     pub face: Option<font::Face>,
     pub dpi: f32,
+    pub theme: Theme,
 }
 
-/// Calculates a screen-space projection matrix for the given display.
-fn screen_to_clip(display: &Display) -> Transform3D {
-    let gl_window = display.gl_window();
-    let window = gl_window.window();
-    let size = window.inner_size().to_logical(window.scale_factor());
-
+/// Calculates a screen-space projection matrix for a window of the given logical size.
+fn screen_to_clip(size: LogicalSize<f32>) -> Transform3D {
     // This orthographic projection converts logical screen-space coords to normalized (-1.0..1.0) coords for GL.
     Transform3D::ortho(0.0, size.width, size.height, 0.0, 1000.0, -1000.0)
 }
@@ -67,6 +104,8 @@ impl Glue {
         Ok(Self {
             ui: Ui::new(),
             need_render: false, // Nothing in UI yet to be drawn.
+            need_upload: false,
+            modifiers: Default::default(),
 
             program: Program::new(facade, ProgramCreationInput::SourceCode {
                 vertex_shader: include_str!("shader.vert"),
@@ -81,7 +120,11 @@ impl Glue {
 
             vertex_buf: VertexBuffer::empty_dynamic(facade, INITIAL_VERTEX_BUF_CAPACITY)?,
             index_buf: IndexBuffer::empty_dynamic(facade, glium::index::PrimitiveType::TrianglesList, INITIAL_INDEX_BUF_CAPACITY)?,
-            projection: screen_to_clip(facade),
+            projection: {
+                let gl_window = facade.gl_window();
+                let window = gl_window.window();
+                screen_to_clip(window.inner_size().to_logical(window.scale_factor()))
+            },
 
             renderer: Renderer {
                 vertex_vec: Vec::with_capacity(INITIAL_VERTEX_BUF_CAPACITY),
@@ -92,10 +135,29 @@ impl Glue {
                     let gl_window = facade.gl_window();
                     gl_window.window().scale_factor() as f32
                 },
-            }
+                theme: Theme::default(),
+            },
+            stats: DrawStats::default(),
+            uploaded_vertices: Vec::new(),
+            uploaded_indices: Vec::new(),
         })
     }
 
+    /// Lays the UI out again for a window of the given physical size and DPI scale. Everything is laid out in logical
+    /// pixels, which the projection scales up, so controls are the same physical size whatever the scale.
+    fn resize(&mut self, size: PhysicalSize<u32>, dpi: f64) {
+        let size: LogicalSize<f32> = size.to_logical(dpi);
+
+        self.projection = screen_to_clip(size);
+        self.renderer.dpi = dpi as f32;
+        self.ui.resize(Rect {
+            origin: Point::zero(),
+            size: Size::new(size.width, size.height),
+        }, &mut self.renderer);
+
+        self.need_render = true;
+    }
+
     /// Handle glutin input and window resize events. Returns `true` if an `update()` call is recommended.
     #[must_use = "if true is returned, call update"]
     pub fn handle_window_event(&mut self, event: &WindowEvent, display: &Display) -> bool {
@@ -108,20 +170,17 @@ impl Glue {
 
         match event {
             WindowEvent::Resized(size) => {
-                let dpi = dpi_scale();
-                let size = size.to_logical(dpi);
-
-                self.projection = screen_to_clip(display);
-                self.ui.resize(Rect {
-                    origin: Point::zero(),
-                    size: Size::new(size.width, size.height),
-                }, &mut self.renderer);
-                self.renderer.dpi = dpi as f32;
-
-                self.need_render = true;
+                self.resize(*size, dpi_scale());
                 false // Only the layout changed, which imui handles internally.
             }
 
+            // E.g. the window was dragged to a monitor with a different scale. The window doesn't necessarily say it
+            // was resized too, so use the size it will be.
+            WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                self.resize(**new_inner_size, *scale_factor);
+                true // Text is rasterized at a new size, so must be measured again.
+            }
+
             WindowEvent::CursorMoved { position, .. } => {
                 let position = position.to_logical(dpi_scale());
                 self.ui.set_mouse_pos(Point::new(position.x, position.y))
@@ -131,35 +190,166 @@ impl Glue {
 
             WindowEvent::MouseInput { state, button, .. } => {
                 match (state, button) {
-                    // TODO right, middle
+                    // TODO middle
                     (ElementState::Pressed, MouseButton::Left) => self.ui.set_left_mouse(true),
-                    (ElementState::Pressed, MouseButton::Right) => false,
+                    (ElementState::Pressed, MouseButton::Right) => self.ui.set_right_mouse(true),
                     (ElementState::Pressed, MouseButton::Middle) => false,
                     (ElementState::Released, MouseButton::Left) => self.ui.set_left_mouse(false),
-                    (ElementState::Released, MouseButton::Right) => false,
+                    (ElementState::Released, MouseButton::Right) => self.ui.set_right_mouse(false),
                     (ElementState::Released, MouseButton::Middle) => false,
                     (_, MouseButton::Other(_)) => false
                 }
             }
 
+            WindowEvent::MouseWheel { delta, .. } => {
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => Vector::new(*x, *y) * SCROLL_LINE_HEIGHT,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        let position = position.to_logical(dpi_scale());
+                        Vector::new(position.x, position.y)
+                    }
+                };
+
+                self.ui.scroll(delta)
+            }
+
+            WindowEvent::ReceivedCharacter(ch) => self.ui.type_char(*ch),
+
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                false
+            }
+
+            WindowEvent::KeyboardInput { input, .. } => {
+                if input.virtual_keycode == Some(VirtualKeyCode::Tab) {
+                    return input.state == ElementState::Pressed && self.ui.focus_next(self.modifiers.shift());
+                }
+
+                let key = match input.virtual_keycode {
+                    Some(VirtualKeyCode::Back) => EditKey::Backspace,
+                    Some(VirtualKeyCode::Delete) => EditKey::Delete,
+                    Some(VirtualKeyCode::Left) => EditKey::Left,
+                    Some(VirtualKeyCode::Right) => EditKey::Right,
+                    Some(VirtualKeyCode::Up) => EditKey::Up,
+                    Some(VirtualKeyCode::Down) => EditKey::Down,
+                    Some(VirtualKeyCode::Home) => EditKey::Home,
+                    Some(VirtualKeyCode::End) => EditKey::End,
+                    Some(VirtualKeyCode::Return) => EditKey::Enter,
+                    Some(VirtualKeyCode::Escape) => EditKey::Escape,
+                    _ => return false,
+                };
+
+                input.state == ElementState::Pressed && self.ui.press_key(key)
+            }
+
             _ => false
         }
     }
 
-    /// Update the UI tree.
+    /// Whether a control, such as a text input, is taking keyboard input. If so, don't treat key presses as shortcuts.
+    pub fn has_focus(&self) -> bool {
+        self.ui.has_focus()
+    }
+
+    /// The text input being typed into, if any.
+    pub fn editing_text(&self) -> Option<&Key> {
+        self.ui.editing_text()
+    }
+
+    /// Types `text` into the focused text input, e.g. when pasting. Returns `true` if an `update()` call is
+    /// recommended.
+    #[must_use = "if true is returned, call update"]
+    pub fn type_text(&mut self, text: &str) -> bool {
+        let mut needs_update = false;
+        for ch in text.chars() {
+            needs_update |= self.ui.type_char(ch);
+        }
+        needs_update
+    }
+
+    /// The part of a selectable text that the user has selected, if any.
+    pub fn selected_text(&self) -> Option<&str> {
+        self.ui.selected_text()
+    }
+
+    /// The control being dragged with the mouse, if any.
+    pub fn dragging(&self) -> Option<&Key> {
+        self.ui.dragging()
+    }
+
+    /// Update the UI tree. If it looks the same as before, e.g. because the mouse moved without hovering over anything
+    /// new, there is no need to draw it again.
     pub fn update<F: FnOnce(&mut UiFrame<'_>)>(&mut self, f: F) {
         self.ui.update(f, &mut self.renderer);
-        self.need_render = true;
+        self.render();
     }
 
+    /// Whether the UI looks different to when it was last [drawn](Glue::draw), including because an animation or
+    /// tooltip is due. If not, there is no need to draw it, so the app can sleep until the next event.
     pub fn needs_redraw(&self) -> bool {
-        self.need_render
+        self.need_render || self.need_upload || self.is_redraw_due()
+    }
+
+    fn is_redraw_due(&self) -> bool {
+        matches!(self.redraw_deadline(), Some(deadline) if deadline <= Instant::now())
+    }
+
+    /// Renders the UI to vertices, noting whether they changed.
+    fn render(&mut self) {
+        self.renderer.clear();
+        self.renderer.atlas.next_frame();
+        self.ui.render(&mut self.renderer);
+
+        // Making room for new glyphs moves the others, so the vertices made before then point at the wrong place.
+        // Everything on screen fits now, so the second attempt won't move anything.
+        if self.renderer.atlas.was_repacked() {
+            self.renderer.clear();
+            self.ui.render(&mut self.renderer);
+        }
+
+        if self.renderer.vertex_vec != self.uploaded_vertices || self.renderer.index_vec != self.uploaded_indices {
+            self.need_upload = true;
+        }
+        self.need_render = false;
+    }
+
+    /// When the UI needs drawing again even if no input arrives, e.g. to animate or show a tooltip. Pass this to
+    /// `ControlFlow::WaitUntil` so the event loop wakes up in time.
+    pub fn redraw_deadline(&self) -> Option<Instant> {
+        self.ui.redraw_deadline()
     }
 
     pub fn atlas(&mut self) -> &mut TextureAtlas {
         &mut self.renderer.atlas
     }
 
+    /// Reads again any images in the atlas whose files have changed, e.g. while working on the look of an app without
+    /// restarting it. Returns whether any had, in which case the UI is drawn again on the next [draw](Glue::draw).
+    pub fn reload_atlas(&mut self) -> Result<bool, Box<dyn Error>> {
+        let changed = self.renderer.atlas.reload()?;
+        self.need_render |= changed;
+        Ok(changed)
+    }
+
+    /// How many controls are in the UI tree.
+    pub fn control_count(&self) -> usize {
+        self.ui.len()
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        self.stats
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.renderer.theme
+    }
+
+    /// Changes the colours widgets are drawn with, from the next [draw](Glue::draw).
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.renderer.theme = theme;
+        self.need_render = true;
+    }
+
     pub fn load_font(&mut self, font_bytes: &[u8]) -> Result<(), &'static str> {
         self.renderer.face = Some(font::Face::load(font_bytes)?);
         Ok(())
@@ -168,10 +358,11 @@ impl Glue {
     pub fn draw<S: Surface, F: Facade>(&mut self, surface: &mut S, facade: &F)  -> Result<(), Box<dyn Error>> {
         let projection: [[f32; 4]; 4] = self.projection.to_arrays();
 
-        if self.need_render {
-            self.renderer.clear();
-            self.ui.render(&mut self.renderer);
+        if self.need_render || self.is_redraw_due() {
+            self.render();
+        }
 
+        if self.need_upload {
             if !self.renderer.index_vec.is_empty() {
                 // Increase buffer sizes to fit the vectors if required.
                 if self.renderer.vertex_vec.capacity() > (self.vertex_buf.get_size() / std::mem::size_of::<Vertex>()) {
@@ -180,7 +371,7 @@ impl Glue {
                         self.renderer.vertex_vec.capacity(),
                     )?;
                 }
-                if self.renderer.index_vec.capacity() > (self.index_buf.get_size() / std::mem::size_of::<u16>()) {
+                if self.renderer.index_vec.capacity() > (self.index_buf.get_size() / std::mem::size_of::<u32>()) {
                     self.index_buf = IndexBuffer::empty_dynamic(
                         facade,
                         glium::index::PrimitiveType::TrianglesList,
@@ -193,7 +384,19 @@ impl Glue {
                 self.index_buf.as_mut_slice().write(&self.renderer.index_vec);
             }
 
-            self.need_render = false;
+            self.uploaded_vertices.clone_from(&self.renderer.vertex_vec);
+            self.uploaded_indices.clone_from(&self.renderer.index_vec);
+            self.need_upload = false;
+        }
+
+        self.stats = DrawStats {
+            draw_calls: 0,
+            vertices: self.renderer.vertex_vec.len(),
+            triangles: self.renderer.index_vec.len() / 3,
+        };
+
+        if self.renderer.index_vec.is_empty() {
+            return Ok(());
         }
 
         surface.draw(
@@ -214,6 +417,7 @@ impl Glue {
                 ..Default::default()
             },
         )?;
+        self.stats.draw_calls += 1;
 
         Ok(())
     }
@@ -225,10 +429,14 @@ impl Renderer {
         self.vertex_vec.clear();
     }
 
-    #[allow(dead_code)]
     fn render_sprite_scaled<I: Into<SpriteId>>(&mut self, region: &Region, sprite_id: I, color: Color) {
         let rect = &region.rect;
-        let uv = &self.atlas.get(sprite_id).expect("tried to render unknown sprite").uv_rect;
+        // Sprites can be left out of a full atlas, so skip them rather than crash.
+        let uv = match self.atlas.get(sprite_id) {
+            Some(sprite) => &sprite.uv_rect,
+            None => return,
+        };
+        let clip = clip_rect(region);
 
         let top_left_color = color.clone();
         let top_right_color = color.clone();
@@ -241,7 +449,7 @@ impl Renderer {
         //    | /  |
         //    2 -- 3
         //
-        let vtx_number = self.vertex_vec.len() as u16;
+        let vtx_number = self.vertex_vec.len() as u32;
         self.index_vec.extend_from_slice(&[
             vtx_number + 0, vtx_number + 1, vtx_number + 2,
             vtx_number + 1, vtx_number + 3, vtx_number + 2,
@@ -252,24 +460,28 @@ impl Renderer {
                 uv: [uv.min_x(), uv.min_y()],
                 color: top_left_color,
                 z: region.layer as f32,
+                clip,
             },
             Vertex {
                 position: [rect.max_x(), rect.min_y()],
                 uv: [uv.max_x(), uv.min_y()],
                 color: top_right_color,
                 z: region.layer as f32,
+                clip,
             },
             Vertex {
                 position: [rect.min_x(), rect.max_y()],
                 uv: [uv.min_x(), uv.max_y()],
                 color: bottom_left_color,
                 z: region.layer as f32,
+                clip,
             },
             Vertex {
                 position: [rect.max_x(), rect.max_y()],
                 uv: [uv.max_x(), uv.max_y()],
                 color: bottom_right_color,
                 z: region.layer as f32,
+                clip,
             },
         ]);
     }
@@ -278,7 +490,11 @@ impl Renderer {
     /// This means that the corners of the sprite will stay their source size, with the other segments scaled.
     fn render_sprite_9slice<I: Into<SpriteId>>(&mut self, region: &Region, sprite_id: I, color: Color) {
         let rect = &region.rect;
-        let sprite = &self.atlas.get(sprite_id).expect("tried to render unknown sprite");
+        let sprite = match self.atlas.get(sprite_id) {
+            Some(sprite) => sprite,
+            None => return, // Left out of a full atlas
+        };
+        let clip = clip_rect(region);
 
         let corner_pos = Size::new(
             sprite.src_dimensions.width / 3.0,
@@ -300,7 +516,7 @@ impl Renderer {
         //    | /  | /  | /  |
         //    12-- 13-- 14--15
 
-        let vtx_number = self.vertex_vec.len() as u16;
+        let vtx_number = self.vertex_vec.len() as u32;
         self.index_vec.extend_from_slice(&[
             // Top-left corner.
             vtx_number + 0, vtx_number + 1, vtx_number + 4,
@@ -345,6 +561,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x(), sprite.uv_rect.min_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 1
             Vertex {
@@ -352,6 +569,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x() + corner_uv.width, sprite.uv_rect.min_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 2
             Vertex {
@@ -359,6 +577,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x() - corner_uv.width, sprite.uv_rect.min_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 3
             Vertex {
@@ -366,6 +585,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x(), sprite.uv_rect.min_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
 
             // 4
@@ -374,6 +594,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x(), sprite.uv_rect.min_y() + corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 5
             Vertex {
@@ -381,6 +602,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x() + corner_uv.width, sprite.uv_rect.min_y() + corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 6
             Vertex {
@@ -388,6 +610,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x() - corner_uv.width, sprite.uv_rect.min_y() + corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 7
             Vertex {
@@ -395,6 +618,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x(), sprite.uv_rect.min_y() + corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
 
             // 8
@@ -403,6 +627,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x(), sprite.uv_rect.max_y() - corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 9
             Vertex {
@@ -410,6 +635,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x() + corner_uv.width, sprite.uv_rect.max_y() - corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 10
             Vertex {
@@ -417,6 +643,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x() - corner_uv.width, sprite.uv_rect.max_y() - corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 11
             Vertex {
@@ -424,6 +651,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x(), sprite.uv_rect.max_y() - corner_uv.height],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
 
             // 12
@@ -432,6 +660,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x(), sprite.uv_rect.max_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 13
             Vertex {
@@ -439,6 +668,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.min_x() + corner_uv.width, sprite.uv_rect.max_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 14
             Vertex {
@@ -446,6 +676,7 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x() - corner_uv.width, sprite.uv_rect.max_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
             // 15
             Vertex {
@@ -453,19 +684,23 @@ impl Renderer {
                 uv: [sprite.uv_rect.max_x(), sprite.uv_rect.max_y()],
                 color: color.clone(),
                 z: region.layer as f32,
+                clip,
             },
         ]);
     }
 }
 
 impl Render for Renderer {
-    fn measure_text(&mut self, text: &str) -> Size {
+    fn measure_text(&mut self, text: &str, max_width: Option<f32>) -> Size {
         let size = 14.0 * self.dpi;
 
+        // Layout happens in physical coordinates, like in render_text. The height is only there to go with the width.
+        let layout_rect = max_width.map(|width| Rect::new(Point::zero(), Size::new(width * self.dpi, f32::MAX)));
+
         if let Some(face) = &mut self.face {
             let mut dimensions = Size::zero();
 
-            face.layout(&mut self.atlas, None, &font::TextStyle::new(text, size, 0), |_, rect| {
+            face.layout(&mut self.atlas, layout_rect.as_ref(), &font::TextStyle::new(text, size, 0), |_, rect| {
                 if rect.max_x() > dimensions.width {
                     dimensions.width = rect.max_x();
                 }
@@ -482,19 +717,21 @@ impl Render for Renderer {
     }
 
     fn render_text(&mut self, region: &Region, text: &str) {
-        let color = [1.0, 1.0, 1.0, 1.0];
+        let color = self.theme.text;
 
-        // Text layouting and rendering happens in physical coordinates (DPI-unaware), so account for that.
-        let size = 14.0 * self.dpi;
-        let offset = Point::new(region.rect.origin.x, region.rect.origin.y);
+        // Text layouting and rendering happens in physical coordinates (DPI-unaware), so account for that. Glyphs are
+        // rasterized at their physical size, and placed on whole physical pixels so they stay crisp.
+        let dpi = self.dpi;
+        let size = 14.0 * dpi;
+        let offset = Point::new((region.rect.origin.x * dpi).round() / dpi, (region.rect.origin.y * dpi).round() / dpi);
         let layout_rect = Rect {
             origin: Point::zero(), // For some reason we have to apply `offset` later or layouting goes haywire...
             size: Size::new(
-                region.rect.size.width * self.dpi + 16.0, // FIXME remove this +16
-                region.rect.size.height * self.dpi,
+                (region.rect.size.width + 16.0) * dpi, // FIXME remove this +16
+                region.rect.size.height * dpi,
             ),
         };
-        let dpi = self.dpi;
+        let clip = clip_rect(region);
 
         if let Some(face) = &mut self.face {
             let vtx = &mut self.vertex_vec;
@@ -505,7 +742,7 @@ impl Render for Renderer {
 
                 // TODO: region.layer
 
-                let vtx_number = vtx.len() as u16;
+                let vtx_number = vtx.len() as u32;
                 idx.extend_from_slice(&[
                     vtx_number + 0, vtx_number + 1, vtx_number + 2,
                     vtx_number + 1, vtx_number + 3, vtx_number + 2,
@@ -516,32 +753,55 @@ impl Render for Renderer {
                         uv: [uv.min_x(), uv.min_y()],
                         color: color.clone(),
                         z: region.layer as f32,
+                        clip,
                     },
                     Vertex {
                         position: [offset.x + rect.max_x() / dpi, offset.y + rect.min_y() / dpi],
                         uv: [uv.max_x(), uv.min_y()],
                         color: color.clone(),
                         z: region.layer as f32,
+                        clip,
                     },
                     Vertex {
                         position: [offset.x + rect.min_x() / dpi, offset.y + rect.max_y() / dpi],
                         uv: [uv.min_x(), uv.max_y()],
                         color: color.clone(),
                         z: region.layer as f32,
+                        clip,
                     },
                     Vertex {
                         position: [offset.x + rect.max_x() / dpi, offset.y + rect.max_y() / dpi],
                         uv: [uv.max_x(), uv.max_y()],
                         color: color.clone(),
                         z: region.layer as f32,
+                        clip,
                     },
                 ]);
             });
         }
     }
 
+    fn render_text_selection(&mut self, region: &Region, text: &str, selection: Range<usize>) {
+        let start = self.caret_x(text, selection.start);
+        let end = self.caret_x(text, selection.end);
+        let line_height = self.measure_text("|", None).height;
+
+        // Faint enough that the text can still be read on top of it.
+        let mut color = self.theme.highlight;
+        color[3] *= 0.4;
+
+        self.render_sprite_scaled(&Region {
+            rect: Rect::new(
+                Point::new(region.rect.min_x() + start, region.rect.min_y()),
+                Size::new(end - start, line_height),
+            ),
+            layer: region.layer,
+            clip: region.clip,
+        }, "white", color);
+    }
+
     fn render_button(&mut self, region: &Region, texture: &'static str) {
-        self.render_sprite_9slice(region, texture, [1.0, 1.0, 1.0, 1.0]);
+        self.render_sprite_9slice(region, texture, self.theme.button_tint);
     }
 
     fn render_toggle_button(&mut self, region: &Region, is_pressed: bool, is_enabled: bool) {
@@ -552,18 +812,152 @@ impl Render for Renderer {
             (false, false) => "toggle_button_off",
         };
 
-        self.render_sprite_9slice(region, sprite, [1.0, 1.0, 1.0, 1.0]);
+        self.render_sprite_9slice(region, sprite, self.theme.button_tint);
     }
 
     fn render_window(&mut self, region: &Region) {
-        self.render_sprite_9slice(region, "window", [1.0, 1.0, 1.0, 1.0]);
+        self.render_sprite_9slice(region, "window", self.theme.button_tint);
+    }
+
+    fn render_text_input(&mut self, region: &Region, text: &str, cursor: Option<usize>) {
+        let padding = 8.0;
+        let sprite = if cursor.is_some() { "button_pressed" } else { "button" };
+
+        self.render_sprite_9slice(region, sprite, self.theme.button_tint);
+
+        // There is no sprite for the caret, so draw it as a '|' glyph.
+        let caret_size = self.measure_text("|", None);
+        let text_region = Region {
+            rect: Rect {
+                origin: Point::new(
+                    region.rect.min_x() + padding,
+                    region.rect.center().y - caret_size.height / 2.0,
+                ),
+                size: Size::new(region.rect.width() - padding * 2.0, caret_size.height),
+            },
+            layer: region.layer,
+            clip: region.clip,
+        };
+
+        self.render_text(&text_region, text);
+
+        if let Some(cursor) = cursor {
+            let x = self.caret_x(text, cursor);
+
+            let mut caret_region = text_region.clone();
+            caret_region.rect.origin.x += x - caret_size.width / 2.0;
+            self.render_text(&caret_region, "|");
+        }
+    }
+
+    fn render_slider(&mut self, region: &Region, fraction: f32, is_focused: bool) {
+        let track_height = 4.0;
+        let handle_width = 16.0;
+        let rect = &region.rect;
+
+        self.render_sprite_scaled(&Region {
+            rect: Rect {
+                origin: Point::new(rect.min_x(), rect.center().y - track_height / 2.0),
+                size: Size::new(rect.width(), track_height),
+            },
+            layer: region.layer,
+            clip: region.clip,
+        }, "white", self.theme.handle);
+
+        // The handle is centred on the value, but kept within the track.
+        let x = rect.min_x() + fraction * rect.width() - handle_width / 2.0;
+        let x = x.clamp(rect.min_x(), rect.max_x() - handle_width);
+        let sprite = if is_focused { "toggle_button_on" } else { "button" };
+
+        self.render_sprite_scaled(&Region {
+            rect: Rect {
+                origin: Point::new(x, rect.min_y()),
+                size: Size::new(handle_width, rect.height()),
+            },
+            layer: region.layer,
+            clip: region.clip,
+        }, sprite, self.theme.button_tint);
+    }
+
+    fn render_scrollbar(&mut self, region: &Region, thumb: &Rect, is_dragging: bool) {
+        self.render_sprite_scaled(region, "white", self.theme.trough);
+
+        let color = if is_dragging { self.theme.handle_active } else { self.theme.handle };
+        self.render_sprite_scaled(&Region {
+            rect: *thumb,
+            layer: region.layer,
+            clip: region.clip,
+        }, "white", color);
+    }
+
+    fn render_progress_bar(&mut self, region: &Region, fraction: Option<f32>, phase: f32) {
+        let rect = &region.rect;
+
+        self.render_sprite_scaled(region, "white", self.theme.trough);
+
+        let filled = match fraction {
+            Some(fraction) => Rect::new(rect.origin, Size::new(rect.width() * fraction, rect.height())),
+            None => {
+                // A block a quarter of the width sweeps from just off the left edge to just off the right.
+                let width = rect.width() / 4.0;
+                let min_x = rect.min_x() - width + phase * (rect.width() + width);
+                let max_x = min_x + width;
+
+                Rect::new(
+                    Point::new(min_x.max(rect.min_x()), rect.min_y()),
+                    Size::new(max_x.min(rect.max_x()) - min_x.max(rect.min_x()), rect.height()),
+                )
+            }
+        };
+
+        if filled.width() > 0.0 {
+            self.render_sprite_scaled(&Region {
+                rect: filled,
+                layer: region.layer,
+                clip: region.clip,
+            }, "white", self.theme.highlight);
+        }
+    }
+
+    fn render_block(&mut self, region: &Region, color: [f32; 4]) {
+        self.render_sprite_scaled(region, "white", color);
+    }
+
+    fn render_focus_ring(&mut self, region: &Region) {
+        let width = 2.0;
+        let color = self.theme.highlight;
+        let rect = region.rect.inflate(width, width);
+
+        let edges = [
+            Rect::new(rect.origin, Size::new(rect.width(), width)),
+            Rect::new(Point::new(rect.min_x(), rect.max_y() - width), Size::new(rect.width(), width)),
+            Rect::new(rect.origin, Size::new(width, rect.height())),
+            Rect::new(Point::new(rect.max_x() - width, rect.min_y()), Size::new(width, rect.height())),
+        ];
+
+        for edge in edges.iter() {
+            self.render_sprite_scaled(&Region {
+                rect: *edge,
+                layer: region.layer,
+                clip: region.clip,
+            }, "white", color);
+        }
+    }
+
+    fn render_tooltip(&mut self, region: &Region, text: &str) {
+        self.render_sprite_scaled(region, "white", self.theme.tooltip);
+        self.render_text(&Region {
+            rect: region.rect.inflate(-6.0, -6.0),
+            layer: region.layer,
+            clip: None,
+        }, text);
     }
 
-    fn render_input_highlight(&mut self, _rect: &Rect) {
-        // TODO
-        /*self.render_sprite_scaled(&Region {
-            rect: rect.clone(),
-            layer: u8::max_value(),
-        }, "white", [1.0, 1.0, 1.0, 1.0])*/
+    fn render_input_highlight(&mut self, rect: &Rect) {
+        self.render_sprite_scaled(&Region {
+            rect: *rect,
+            layer: u8::MAX,
+            clip: None,
+        }, "white", self.theme.highlight);
     }
 }
@@ -5,9 +5,15 @@ uniform mat4 projection;
 
 in vec2 v_uv;
 in vec4 v_color;
+in vec2 v_position;
+flat in vec4 v_clip;
 
 out vec4 color;
 
 void main() {
+    if (v_position.x < v_clip.x || v_position.y < v_clip.y || v_position.x > v_clip.z || v_position.y > v_clip.w) {
+        discard;
+    }
+
     color = v_color * texture(tex, v_uv);
 }
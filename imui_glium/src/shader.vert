@@ -7,12 +7,17 @@ in vec2 position;
 in vec2 uv;
 in vec4 color;
 in float z;
+in vec4 clip;
 
 out vec2 v_uv;
 out vec4 v_color;
+out vec2 v_position;
+flat out vec4 v_clip;
 
 void main() {
     gl_Position = projection * vec4(position, z + 1.0, 1.0);
     v_uv = uv;
     v_color = color;
+    v_position = position;
+    v_clip = clip;
 }
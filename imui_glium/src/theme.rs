@@ -0,0 +1,144 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The colours that widgets are drawn with. Textured widgets (buttons, windows, tabs) are multiplied by
+/// [button_tint](Theme::button_tint), so a tint above 1.0 lightens them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    /// Behind everything. Clear the surface with it before [drawing](crate::Glue::draw).
+    pub background: [f32; 4],
+    pub text: [f32; 4],
+    pub button_tint: [f32; 4],
+
+    /// Focus rings, progress bars and the input highlight.
+    pub highlight: [f32; 4],
+
+    /// Behind scrollbars and progress bars.
+    pub trough: [f32; 4],
+
+    /// Slider tracks and scrollbar thumbs.
+    pub handle: [f32; 4],
+
+    /// A scrollbar thumb being dragged.
+    pub handle_active: [f32; 4],
+
+    pub tooltip: [f32; 4],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme {
+            background: [21.0 / 255.0, 26.0 / 255.0, 30.0 / 255.0, 1.0],
+            text: [1.0, 1.0, 1.0, 1.0],
+            button_tint: [1.0, 1.0, 1.0, 1.0],
+            highlight: [0.4, 0.6, 1.0, 1.0],
+            trough: [0.2, 0.2, 0.2, 1.0],
+            handle: [0.5, 0.5, 0.5, 1.0],
+            handle_active: [0.8, 0.8, 0.8, 1.0],
+            tooltip: [0.05, 0.06, 0.07, 0.95],
+        }
+    }
+
+    pub fn light() -> Self {
+        Theme {
+            background: [0.92, 0.92, 0.94, 1.0],
+            text: [0.08, 0.09, 0.1, 1.0],
+            button_tint: [2.0, 2.0, 2.0, 1.0],
+            highlight: [0.15, 0.4, 0.9, 1.0],
+            trough: [0.8, 0.8, 0.82, 1.0],
+            handle: [0.55, 0.55, 0.57, 1.0],
+            handle_active: [0.35, 0.35, 0.37, 1.0],
+            tooltip: [1.0, 1.0, 1.0, 0.97],
+        }
+    }
+
+    /// Reads a theme from a file; see [Theme::parse].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Theme::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reads a theme from text with one `name = value` line per colour, where the name is a field of [Theme] and the
+    /// value is either `#RRGGBB`, `#RRGGBBAA` or three or four numbers. Colours that aren't given come from the `base`
+    /// theme, `dark` unless the text says otherwise. Lines starting with `#` are comments.
+    ///
+    /// ```
+    /// # use imui_glium::Theme;
+    /// let theme = Theme::parse("
+    ///     ## Light, but with red text
+    ///     base = light
+    ///     text = #FF0000
+    ///     button_tint = 1.5 1.5 1.5
+    /// ").unwrap();
+    ///
+    /// assert_eq!(theme.text, [1.0, 0.0, 0.0, 1.0]);
+    /// assert_eq!(theme.button_tint, [1.5, 1.5, 1.5, 1.0]);
+    /// assert_eq!(theme.background, Theme::light().background);
+    ///
+    /// assert!(Theme::parse("text = red").is_err());
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let lines: Vec<(&str, &str)> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.splitn(2, '=').map(str::trim);
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) => Ok((name, value)),
+                    _ => Err(format!("expected `name = value`, found `{}`", line)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut theme = match lines.iter().find(|(name, _)| *name == "base") {
+            None | Some((_, "dark")) => Theme::dark(),
+            Some((_, "light")) => Theme::light(),
+            Some((_, base)) => return Err(format!("unknown base theme `{}`; expected light or dark", base).into()),
+        };
+
+        for (name, value) in lines {
+            let field = match name {
+                "base" => continue,
+                "background" => &mut theme.background,
+                "text" => &mut theme.text,
+                "button_tint" => &mut theme.button_tint,
+                "highlight" => &mut theme.highlight,
+                "trough" => &mut theme.trough,
+                "handle" => &mut theme.handle,
+                "handle_active" => &mut theme.handle_active,
+                "tooltip" => &mut theme.tooltip,
+                _ => return Err(format!("unknown theme colour `{}`", name).into()),
+            };
+
+            *field = parse_color(value).ok_or_else(|| format!("invalid colour for {}: `{}`", name, value))?;
+        }
+
+        Ok(theme)
+    }
+}
+
+fn parse_color(value: &str) -> Option<[f32; 4]> {
+    if let Some(hex) = value.strip_prefix('#') {
+        let channel = |i: usize| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok().map(|c| c as f32 / 255.0);
+
+        return match hex.len() {
+            6 => Some([channel(0)?, channel(1)?, channel(2)?, 1.0]),
+            8 => Some([channel(0)?, channel(1)?, channel(2)?, channel(3)?]),
+            _ => None,
+        };
+    }
+
+    let numbers = value.split_whitespace().map(|n| n.parse().ok()).collect::<Option<Vec<f32>>>()?;
+    match numbers[..] {
+        [r, g, b] => Some([r, g, b, 1.0]),
+        [r, g, b, a] => Some([r, g, b, a]),
+        _ => None,
+    }
+}
@@ -0,0 +1,193 @@
+//! Command-line mode, for converting songs without opening the editor, e.g. from a script or CI.
+//!
+//! ```text
+//! mamar convert <input> [<output>] [--validate] [--variation <n>]
+//! ```
+//!
+//! Input can be a `.bgm`, MIDI, `.ron`, `.json` or gzip-compressed file, whatever its extension; the output format is
+//! picked by extension, and can be `.bgm`, `.mid`/`.midi`, `.ron` or `.json`.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use pm64::bgm::*;
+
+use crate::formats;
+
+const USAGE: &str = "\
+Usage: mamar convert <input> [<output>] [--validate] [--variation <n>]
+
+Converts between .bgm, MIDI (.mid, .midi), .ron and .json files, picking the output format by file extension.
+
+Options:
+    --validate         Check that the game could play the song, and exit with an error if not
+    --variation <n>    Variation to export to MIDI, starting from 1 (default 1)";
+
+/// Exit code for a song that fails validation or can't be converted.
+const EXIT_FAILURE: i32 = 1;
+
+/// Exit code for arguments that don't make sense.
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    validate: bool,
+    /// Index of the variation exported to MIDI.
+    segment_idx: usize,
+}
+
+/// Whether `args` (without the program name) ask for command-line mode rather than the editor.
+pub fn is_cli(args: &[String]) -> bool {
+    matches!(args.first().map(String::as_str), Some("convert") | Some("help") | Some("--help"))
+}
+
+/// Runs the command given by `args` (without the program name), returning the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let options = match args.split_first() {
+        Some((command, rest)) if command == "convert" => parse_options(rest),
+        _ => {
+            println!("{}", USAGE);
+            return 0;
+        }
+    };
+
+    let options = match options {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    match convert(&options) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut paths = Vec::new();
+    let mut validate = false;
+    let mut segment_idx = 0;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--validate" => validate = true,
+            "--variation" => {
+                let n: usize = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or("--variation needs a number from 1 to 4")?;
+
+                if !(1..=4).contains(&n) {
+                    return Err("--variation needs a number from 1 to 4".to_owned());
+                }
+                segment_idx = n - 1;
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            path => paths.push(PathBuf::from(path)),
+        }
+    }
+
+    let mut paths = paths.into_iter();
+    let input = paths.next().ok_or("no input file given")?;
+    let output = paths.next();
+
+    if paths.next().is_some() {
+        return Err("too many files given; convert one file at a time".to_owned());
+    }
+    if output.is_none() && !validate {
+        return Err("no output file given".to_owned());
+    }
+
+    Ok(Options {
+        input,
+        output,
+        validate,
+        segment_idx,
+    })
+}
+
+fn convert(options: &Options) -> Result<(), Box<dyn Error>> {
+    let (bgm, _, warnings) = formats::read(&options.input)?;
+
+    for warning in &warnings {
+        eprintln!("{}: warning: {}", options.input.display(), warning);
+    }
+
+    if options.validate {
+        let problems = bgm.validate();
+
+        if !problems.is_empty() {
+            for problem in &problems {
+                eprintln!("{}: {}", options.input.display(), problem);
+            }
+            return Err(format!("{} problem(s) found", problems.len()).into());
+        }
+    }
+
+    if let Some(output) = &options.output {
+        write(&bgm, output, options.segment_idx)?;
+    }
+
+    Ok(())
+}
+
+fn write(bgm: &Bgm, path: &Path, segment_idx: usize) -> Result<(), Box<dyn Error>> {
+    match extension(path).as_str() {
+        "bgm" | "bin" => fs::write(path, bgm.as_bytes()?)?,
+        "mid" | "midi" => {
+            let midi = bgm
+                .to_midi(segment_idx)
+                .ok_or_else(|| format!("there is no variation {} to export", segment_idx + 1))?;
+            fs::write(path, midi)?;
+        }
+        "ron" => {
+            let config = ron::ser::PrettyConfig::new()
+                .with_indentor("  ".to_string())
+                .with_depth_limit(5);
+            ron::ser::to_writer_pretty(File::create(path)?, bgm, config)?;
+        }
+        "json" => serde_json::to_writer_pretty(File::create(path)?, bgm)?,
+        _ => return Err(format!("can't tell what format to write {} in", path.display()).into()),
+    }
+
+    Ok(())
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().unwrap_or_default().to_string_lossy().to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_options(&args(&["in.mid", "out.bgm", "--variation", "2"])), Ok(Options {
+            input: PathBuf::from("in.mid"),
+            output: Some(PathBuf::from("out.bgm")),
+            validate: false,
+            segment_idx: 1,
+        }));
+
+        // Validating alone needs no output
+        assert!(parse_options(&args(&["in.bgm", "--validate"])).is_ok());
+
+        assert!(parse_options(&args(&["in.bgm"])).is_err());
+        assert!(parse_options(&args(&["in.bgm", "out.mid", "--variation", "5"])).is_err());
+        assert!(parse_options(&args(&["a.bgm", "b.bgm", "c.bgm"])).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! Reporting crashes, so that a panic leaves something behind to attach to a bug report rather than the window just
+//! vanishing.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::panic;
+use std::process::Command;
+use std::thread;
+
+use tinyfiledialogs::{MessageBoxIcon, YesNo};
+
+use crate::logger;
+
+/// Logs panics with a backtrace. If the main thread panics, which takes the whole app down, the user is told where
+/// the log is and offered to reopen Mamar.
+pub fn install_handler() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        log::error!("{}\n{}", info, Backtrace::force_capture());
+        default_hook(info);
+
+        if thread::current().name() == Some("main") {
+            report(&message(info.payload()));
+        }
+    }));
+}
+
+fn report(message: &str) {
+    let saved_to = match logger::file_path() {
+        Some(path) => format!("A report was saved to {}.", path.display()),
+        None => "No report could be saved.".to_owned(),
+    };
+
+    let reopen = tinyfiledialogs::message_box_yes_no(
+        "Mamar crashed",
+        &format!("Mamar crashed: {}\n\n{}\n\nReopen Mamar?", message, saved_to),
+        MessageBoxIcon::Error,
+        YesNo::Yes,
+    );
+
+    if let YesNo::Yes = reopen {
+        let reopened = std::env::current_exe().and_then(|exe| Command::new(exe).spawn());
+
+        if let Err(error) = reopened {
+            log::error!("unable to reopen: {}", error);
+        }
+    }
+}
+
+/// What was passed to `panic!`, if it was a message.
+fn message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_owned())
+}
@@ -0,0 +1,285 @@
+//! Reading songs from any file Mamar can open, whatever its extension says, and saving them again.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::Path;
+
+use pm64::bgm::*;
+use pm64::gzip;
+
+/// A kind of file Mamar can open, and whether it can save to it too.
+pub struct Format {
+    pub source: SourceFormat,
+    /// Without the dot. The first is the one new files are given.
+    pub extensions: &'static [&'static str],
+    pub can_save: bool,
+}
+
+/// Every kind of file Mamar can open. The file dialogs, and which format a file is saved in, all go by this list so
+/// that they agree. Any of them can be opened gzip-compressed too.
+pub static FORMATS: [Format; 4] = [
+    Format {
+        source: SourceFormat::Bgm,
+        extensions: &["bgm", "bin"],
+        can_save: true,
+    },
+    Format {
+        source: SourceFormat::Ron,
+        extensions: &["ron"],
+        can_save: true,
+    },
+    Format {
+        source: SourceFormat::Json,
+        extensions: &["json"],
+        can_save: true,
+    },
+    // Songs are exported as MIDI instead, which leaves out anything MIDI can't hold.
+    Format {
+        source: SourceFormat::Midi,
+        extensions: &["mid", "midi"],
+        can_save: false,
+    },
+];
+
+/// Extension of gzip-compressed files, which could hold any of [FORMATS].
+const GZIP_EXTENSION: &str = "gz";
+
+/// Patterns for an 'Open File' dialog, e.g. `*.bgm`.
+pub fn open_filter() -> Vec<String> {
+    FORMATS
+        .iter()
+        .flat_map(|format| format.extensions)
+        .chain(std::iter::once(&GZIP_EXTENSION))
+        .map(|extension| format!("*.{}", extension))
+        .collect()
+}
+
+/// Patterns for a 'Save As' dialog, e.g. `*.bgm`.
+pub fn save_filter() -> Vec<String> {
+    FORMATS
+        .iter()
+        .filter(|format| format.can_save)
+        .flat_map(|format| format.extensions)
+        .map(|extension| format!("*.{}", extension))
+        .collect()
+}
+
+/// The format saving to `path` writes, going by its extension. Extensions that aren't in [FORMATS] get a BGM, which
+/// the game reads whatever it's called. Returns `None` if `path` is for a kind of file Mamar can't save, e.g. MIDI.
+pub fn save_format(path: &Path) -> Option<SourceFormat> {
+    match path.extension().and_then(OsStr::to_str).and_then(from_extension) {
+        Some(format) if format.can_save => Some(format.source.clone()),
+        Some(_) => None,
+        None => Some(SourceFormat::Bgm),
+    }
+}
+
+/// The format that files with `extension` (without the dot) hold, if it's one in [FORMATS].
+fn from_extension(extension: &str) -> Option<&'static Format> {
+    let extension = extension.to_ascii_lowercase();
+    FORMATS.iter().find(|format| format.extensions.contains(&extension.as_str()))
+}
+
+/// Writes `bgm` to `path` in `format`, which should be one that [save_format] can return.
+pub fn write(path: &Path, bgm: &Bgm, format: &SourceFormat) -> Result<(), Box<dyn Error>> {
+    let mut file = File::create(path)?;
+
+    match format {
+        SourceFormat::Ron => ron::ser::to_writer_pretty(
+            &mut file,
+            bgm,
+            ron::ser::PrettyConfig::new()
+                .with_indentor("  ".to_string())
+                .with_depth_limit(5),
+        )?,
+        SourceFormat::Json => serde_json::to_writer_pretty(&mut file, bgm)?,
+        SourceFormat::Bgm => bgm.encode(&mut file)?,
+        _ => return Err(format!("unable to save as {}", format).into()),
+    }
+
+    Ok(())
+}
+
+/// Reads a song from `path`, working out its format from its contents. The extension only breaks ties between formats
+/// that start the same way, so e.g. a `.bin` file holding RON still opens. Anything unexpected in a BGM file that
+/// didn't stop it being read is returned too.
+pub fn read(path: &Path) -> Result<(Bgm, SourceFormat, Vec<de::Warning>), Box<dyn Error>> {
+    let data = fs::read(path)?;
+    let extension = path.extension().and_then(OsStr::to_str);
+    let expected = extension.and_then(from_extension).map(|format| format.source.clone());
+
+    let format = match detect_format(&data, extension) {
+        Some(format) => format,
+        None => {
+            return Err(match expected {
+                Some(expected) => format!(
+                    "{} doesn't look like a {} file, or any other kind of file Mamar can open",
+                    file_name(path),
+                    expected,
+                ),
+                None => format!("{} isn't a kind of file Mamar can open", file_name(path)),
+            }
+            .into())
+        }
+    };
+
+    match read_as(&data, &format) {
+        Ok((bgm, warnings)) => Ok((bgm, format, warnings)),
+        Err(error) => match expected {
+            // Say what the file was read as, in case the extension is right and the contents are broken
+            Some(expected) if expected != *format.inner() => Err(format!(
+                "{} looks like a {} file rather than {} like its extension says, but it couldn't be read: {}",
+                file_name(path),
+                format,
+                expected,
+                error,
+            )
+            .into()),
+            _ => Err(error),
+        },
+    }
+}
+
+/// What importing the file at `path` would do, if it's a MIDI file, so the import can be checked before going ahead.
+/// `None` for other kinds of file, and for files that can't be read, which [read] gives the reason for.
+pub fn midi_import_report(path: &Path) -> Option<midi::ImportReport> {
+    let data = fs::read(path).ok()?;
+    let format = detect_format(&data, None)?;
+
+    match format.inner() {
+        SourceFormat::Midi => {
+            let data = decompress_layers(&data, &format).ok()?;
+            midi::import_report(&data, &midi::ImportOptions::default()).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Removes as many layers of gzip compression from `data` as `format`, from [detect_format], says it has.
+fn decompress_layers<'a>(data: &'a [u8], format: &SourceFormat) -> Result<Cow<'a, [u8]>, gzip::Error> {
+    let mut data = Cow::Borrowed(data);
+    let mut format = format;
+
+    while let SourceFormat::Gzip(inner) = format {
+        data = Cow::Owned(gzip::decompress(&data)?);
+        format = inner;
+    }
+
+    Ok(data)
+}
+
+fn read_as(data: &[u8], format: &SourceFormat) -> Result<(Bgm, Vec<de::Warning>), Box<dyn Error>> {
+    match format.inner() {
+        SourceFormat::Ron | SourceFormat::Json => {
+            let text = decompress_layers(data, format)?;
+
+            if *format.inner() == SourceFormat::Ron {
+                Ok((ron::de::from_bytes(&text)?, Vec::new()))
+            } else {
+                Ok((serde_json::from_slice(&text)?, Vec::new()))
+            }
+        }
+        _ => {
+            let (bgm, _, warnings) = read_agnostic(data)?;
+            Ok((bgm, warnings))
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn read_misnamed() {
+        let dir = TempDir::new("read_misnamed");
+
+        let ron = dir.join("ron.bin");
+        fs::write(&ron, ron::ser::to_string(&Bgm::new()).unwrap()).unwrap();
+        assert_eq!(read(&ron).unwrap().1, SourceFormat::Ron);
+
+        let bgm = dir.join("bgm.json");
+        fs::write(&bgm, Bgm::new().as_bytes().unwrap()).unwrap();
+        assert_eq!(read(&bgm).unwrap().1, SourceFormat::Bgm);
+
+        // Both formats are named when the file can't be read
+        let broken = dir.join("broken.bgm");
+        fs::write(&broken, "{\"name\": 5}").unwrap();
+        let error = read(&broken).unwrap_err().to_string();
+        assert!(error.contains("JSON") && error.contains("BGM"), "{}", error);
+
+        let unknown = dir.join("unknown.bgm");
+        fs::write(&unknown, "RIFF").unwrap();
+        assert!(read(&unknown).unwrap_err().to_string().contains("BGM"));
+    }
+
+    #[test]
+    fn read_nested_gzip() {
+        let dir = TempDir::new("read_nested_gzip");
+
+        // gzip.compress(gzip.compress(b'(name: "Twice")', mtime=0), mtime=0)
+        let compressed = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x93, 0xEF, 0xE6, 0x60, 0x00, 0x01, 0x26, 0xE6,
+            0xCB, 0x27, 0xBC, 0xCF, 0xF8, 0x6E, 0x0D, 0x0A, 0xE0, 0xD2, 0x3C, 0xEF, 0xE3, 0x17, 0x7A, 0x89, 0x85, 0xE1,
+            0xD1, 0x83, 0x85, 0x4C, 0xFC, 0x40, 0x29, 0x00, 0xBE, 0x1B, 0x6E, 0xED, 0x23, 0x00, 0x00, 0x00,
+        ];
+
+        let song = dir.join("twice.ron.gz");
+        fs::write(&song, compressed).unwrap();
+        let (bgm, format, _) = read(&song).unwrap();
+        assert_eq!(bgm.name, "Twice");
+        assert_eq!(format, SourceFormat::Gzip(Box::new(SourceFormat::Gzip(Box::new(SourceFormat::Ron)))));
+    }
+
+    #[test]
+    fn read_warnings() {
+        let dir = TempDir::new("read_warnings");
+
+        let mut bytes = Bgm::new().as_bytes().unwrap();
+        bytes.extend_from_slice(&[0xFF; 0x30]);
+
+        let song = dir.join("trailing.bgm");
+        fs::write(&song, bytes).unwrap();
+        let (_, _, warnings) = read(&song).unwrap();
+        assert!(matches!(warnings.first(), Some(de::Warning::SizeMismatch { .. })), "{:?}", warnings);
+    }
+
+    #[test]
+    fn midi_report() {
+        let dir = TempDir::new("midi_report");
+
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let midi = dir.join("song.mid");
+        fs::write(&midi, bgm.to_midi(0).unwrap()).unwrap();
+        assert_eq!(midi_import_report(&midi).unwrap().tempo_changes, 1);
+
+        let song = dir.join("song.bgm");
+        fs::write(&song, bgm.as_bytes().unwrap()).unwrap();
+        assert_eq!(midi_import_report(&song), None);
+    }
+
+    #[test]
+    fn filters_match_formats() {
+        // Whatever can be picked in the save dialog is saved in the format its extension says.
+        for pattern in save_filter() {
+            let path = Path::new("song").with_extension(pattern.trim_start_matches("*."));
+            let extension = path.extension().and_then(OsStr::to_str).unwrap();
+            assert_eq!(save_format(&path), SourceFormat::from_extension(extension), "{}", pattern);
+            assert!(open_filter().contains(&pattern));
+        }
+
+        assert!(save_filter().contains(&"*.bin".to_owned()));
+        assert!(open_filter().contains(&"*.gz".to_owned()));
+        assert_eq!(save_format(Path::new("song.MID")), None);
+        assert_eq!(save_format(Path::new("song")), Some(SourceFormat::Bgm));
+    }
+}
@@ -1,50 +1,110 @@
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
 
-const NUM_STATES: usize = 32;
+/// Number of undo steps kept by [History::new].
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// How soon after coalescing ends it can resume with the same tag and carry on the same undo step, e.g. when nudging a
+/// slider several times in quick succession.
+const COALESCE_WINDOW: Duration = Duration::from_millis(500);
 
 // TODO: use https://lib.rs/crates/diff-struct instead of cloning entire state
 #[derive(Clone, PartialEq, Debug)]
 pub struct History<State: Clone + PartialEq> {
-    mut_current_state: State,    // Copy of current state
-    states: [State; NUM_STATES], // Ring buffer
-    newest_state_index: usize,   // Future (for redo)
-    current_state_index: usize,  // Immutable current state
-    oldest_state_index: usize,   // Past (for undo)
+    mut_current_state: State,          // Copy of current state
+    states: VecDeque<Snapshot<State>>, // Oldest first; those after the current state are the future (for redo)
+    current_state_index: usize,        // Immutable current state
+    max_depth: usize,                  // Number of states kept before the current one (for undo)
+
+    next_state_id: u64,
+    saved_state_id: Option<u64>, // ID of the state that was last saved, if it's still around
+
+    coalescing: Option<Coalescing>,
+}
+
+/// A run of commits that are being merged into a single undo step.
+#[derive(Clone, PartialEq, Debug)]
+struct Coalescing {
+    tag: u64,
+    state_id: Option<u64>, // ID of the state that further commits will replace, once there is one
+    ended_at: Option<Instant>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Snapshot<State> {
+    id: u64,
+    state: State,
+}
+
+/// Estimates the memory used by a state, for [History::memory_estimate].
+pub trait MemoryUsage {
+    /// Approximate size in bytes, including heap allocations.
+    fn memory_usage(&self) -> usize;
 }
 
 impl<State: Clone + PartialEq> History<State> {
     pub fn new(initial_state: State) -> Self {
-        Self {
-            mut_current_state: initial_state.clone(),
-            states: {
-                // Safety: we (should) never access these zeroed elements
-                let mut states: [State; NUM_STATES] = unsafe { mem::zeroed() };
+        Self::with_max_depth(initial_state, DEFAULT_MAX_DEPTH)
+    }
 
-                states[0] = initial_state;
+    /// Creates a history that can undo at most `max_depth` times in a row. Older states are forgotten.
+    pub fn with_max_depth(initial_state: State, max_depth: usize) -> Self {
+        let mut states = VecDeque::new();
+        states.push_back(Snapshot {
+            id: 0,
+            state: initial_state.clone(),
+        });
 
-                states
-            },
-            newest_state_index: 0,
+        Self {
+            mut_current_state: initial_state,
+            states,
             current_state_index: 0,
-            oldest_state_index: 0,
+            max_depth,
+            next_state_id: 1,
+            saved_state_id: Some(0),
+            coalescing: None,
         }
     }
 
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Changes how many undo steps are kept, forgetting the oldest states if there are now too many.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+        self.evict_oldest();
+    }
+
     /// Returns true if state changed and a commit actually occurred.
     pub fn commit(&mut self) -> bool {
         // was the current state changed?
         if &self.mut_current_state != self.true_current_state() {
-            self.current_state_index = Self::next(self.current_state_index);
-            self.newest_state_index = self.current_state_index; // Discard future states
-
-            if self.current_state_index == self.oldest_state_index {
-                // Move the oldest state index into its future, since we're about to replace it
-                self.oldest_state_index = Self::next(self.oldest_state_index);
+            if self.is_coalescing_current_state() {
+                return self.replace_current_state();
             }
 
+            self.states.truncate(self.current_state_index + 1); // Discard future states
+
             // Actually commit the change
-            self.states[self.current_state_index] = self.mut_current_state.clone();
+            self.states.push_back(Snapshot {
+                id: self.next_state_id,
+                state: self.mut_current_state.clone(),
+            });
+            self.next_state_id += 1;
+            self.current_state_index = self.states.len() - 1;
+
+            self.evict_oldest();
+
+            if let Some(coalescing) = &mut self.coalescing {
+                if coalescing.ended_at.is_none() {
+                    coalescing.state_id = Some(self.states[self.current_state_index].id);
+                }
+            }
 
             true // Did commit
         } else {
@@ -53,9 +113,9 @@ impl<State: Clone + PartialEq> History<State> {
     }
 
     pub fn undo(&mut self) -> bool {
-        if self.current_state_index != self.oldest_state_index {
+        if self.current_state_index > 0 {
             // Back to the past
-            self.current_state_index = Self::prev(self.current_state_index);
+            self.current_state_index -= 1;
             self.mut_current_state = self.true_current_state().clone();
 
             true // Did undo
@@ -65,9 +125,9 @@ impl<State: Clone + PartialEq> History<State> {
     }
 
     pub fn redo(&mut self) -> bool {
-        if self.current_state_index != self.newest_state_index {
+        if self.current_state_index + 1 < self.states.len() {
             // Back to the future
-            self.current_state_index = Self::next(self.current_state_index);
+            self.current_state_index += 1;
             self.mut_current_state = self.true_current_state().clone();
 
             true // Did redo
@@ -76,20 +136,114 @@ impl<State: Clone + PartialEq> History<State> {
         }
     }
 
-    fn next(index: usize) -> usize {
-        (index + 1) % NUM_STATES
+    /// Merges commits from now until [History::end_coalescing] into a single undo step, e.g. for the duration of a
+    /// slider drag. If coalescing with the same `tag` ended only a moment ago, and nothing else was committed since,
+    /// the same step carries on. Calling this again with the same tag while already coalescing does nothing.
+    pub fn begin_coalescing<T: Hash>(&mut self, tag: T) {
+        let mut hasher = DefaultHasher::new();
+        tag.hash(&mut hasher);
+        let tag = hasher.finish();
+
+        if let Some(coalescing) = &mut self.coalescing {
+            let is_recent = match coalescing.ended_at {
+                Some(ended_at) => ended_at.elapsed() < COALESCE_WINDOW,
+                None => true, // Still coalescing
+            };
+
+            if coalescing.tag == tag && is_recent {
+                coalescing.ended_at = None;
+                return;
+            }
+        }
+
+        self.coalescing = Some(Coalescing {
+            tag,
+            state_id: None,
+            ended_at: None,
+        });
+    }
+
+    /// Stops merging commits. The next commit will be a new undo step.
+    pub fn end_coalescing(&mut self) {
+        if let Some(coalescing) = &mut self.coalescing {
+            coalescing.ended_at.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// Whether the next commit should replace the current state rather than add a new one.
+    fn is_coalescing_current_state(&self) -> bool {
+        match &self.coalescing {
+            Some(Coalescing { state_id: Some(id), ended_at: None, .. }) => {
+                *id == self.states[self.current_state_index].id && self.current_state_index + 1 == self.states.len()
+            }
+            _ => false,
+        }
     }
 
-    fn prev(index: usize) -> usize {
-        if index == 0 {
-            NUM_STATES - 1
+    /// Commits by overwriting the current state, which was itself committed while coalescing.
+    fn replace_current_state(&mut self) -> bool {
+        let before_coalescing = self.current_state_index.checked_sub(1).map(|i| &self.states[i].state);
+
+        if before_coalescing == Some(&self.mut_current_state) {
+            // Back where we started, so the step has no effect and can be dropped.
+            self.states.pop_back();
+            self.current_state_index -= 1;
+
+            if let Some(coalescing) = &mut self.coalescing {
+                coalescing.state_id = None;
+            }
         } else {
-            index - 1
+            // A new ID, as the state may differ from one that was saved during the step.
+            let id = self.next_state_id;
+            self.next_state_id += 1;
+
+            let snapshot = &mut self.states[self.current_state_index];
+            snapshot.id = id;
+            snapshot.state = self.mut_current_state.clone();
+
+            if let Some(coalescing) = &mut self.coalescing {
+                coalescing.state_id = Some(id);
+            }
+        }
+
+        true
+    }
+
+    /// Records the current state as the one that was saved, so [History::is_dirty] is false until it changes again.
+    /// Uncommitted changes are not included, so commit first.
+    pub fn mark_saved(&mut self) {
+        self.saved_state_id = Some(self.states[self.current_state_index].id);
+    }
+
+    /// Whether the current state differs from the one that was last saved. Undoing or redoing back to the saved state
+    /// makes this false again.
+    pub fn is_dirty(&self) -> bool {
+        self.saved_state_id != Some(self.states[self.current_state_index].id)
+    }
+
+    /// Forgets the oldest states until there are no more than `max_depth` before the current one. Redo states are
+    /// kept, as they don't count towards the undo depth.
+    fn evict_oldest(&mut self) {
+        while self.current_state_index > self.max_depth {
+            self.states.pop_front();
+            self.current_state_index -= 1;
         }
     }
 
     fn true_current_state(&self) -> &State {
-        &self.states[self.current_state_index]
+        &self.states[self.current_state_index].state
+    }
+}
+
+impl<State: Clone + PartialEq + MemoryUsage> History<State> {
+    /// Approximate number of bytes used by every state kept, for debugging.
+    pub fn memory_estimate(&self) -> usize {
+        let snapshots: usize = self.states
+            .iter()
+            .map(|snapshot| mem::size_of_val(&snapshot.id) + snapshot.state.memory_usage())
+            .sum();
+
+        snapshots + self.mut_current_state.memory_usage()
     }
 }
 
@@ -139,3 +293,131 @@ fn history() {
     assert!(!h.redo()); // Redo should not be possible, the present has diverged
     assert_eq!(*h, 2);
 }
+
+#[test]
+fn saved_state() {
+    let mut h = History::new(0);
+    assert!(!h.is_dirty()); // The initial state counts as saved
+
+    *h = 1;
+    assert!(h.commit());
+    assert!(h.is_dirty());
+
+    h.mark_saved();
+    assert!(!h.is_dirty());
+
+    // Moving away from the saved state and back again
+    *h = 2;
+    assert!(h.commit());
+    assert!(h.is_dirty());
+    assert!(h.undo());
+    assert!(!h.is_dirty());
+    assert!(h.undo());
+    assert!(h.is_dirty());
+    assert!(h.redo());
+    assert!(!h.is_dirty());
+
+    // Editing after an undo discards the saved state from history, even if the edit recreates it
+    assert!(h.undo());
+    *h = 1;
+    assert!(h.commit());
+    assert!(h.is_dirty());
+    assert!(!h.redo());
+}
+
+#[cfg(test)]
+impl MemoryUsage for i32 {
+    fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+}
+
+#[test]
+fn max_depth() {
+    let mut h = History::with_max_depth(0, 3);
+
+    for i in 1..=5 {
+        *h = i;
+        assert!(h.commit());
+    }
+
+    // Only the 3 most recent states before the current one are kept; 0 and 1 have been evicted
+    assert!(h.undo());
+    assert!(h.undo());
+    assert!(h.undo());
+    assert_eq!(*h, 2);
+    assert!(!h.undo());
+    assert_eq!(h.memory_estimate(), mem::size_of::<u64>() * 4 + mem::size_of::<i32>() * 5);
+
+    // Redo still works within the window
+    assert!(h.redo());
+    assert!(h.redo());
+    assert!(h.redo());
+    assert_eq!(*h, 5);
+    assert!(!h.redo());
+
+    // Reducing the depth drops the oldest remaining states
+    h.set_max_depth(1);
+    assert!(h.undo());
+    assert_eq!(*h, 4);
+    assert!(!h.undo());
+}
+
+#[test]
+fn coalescing() {
+    let mut h = History::new(0);
+
+    // A drag from 0 to 3 is a single step
+    h.begin_coalescing("slider");
+    for i in 1..=3 {
+        *h = i;
+        assert!(h.commit());
+        h.begin_coalescing("slider"); // Called every update while dragging
+    }
+    h.end_coalescing();
+
+    *h = 10;
+    assert!(h.commit()); // Not coalesced: coalescing ended
+
+    assert!(h.undo());
+    assert_eq!(*h, 3);
+    assert!(h.undo());
+    assert_eq!(*h, 0);
+    assert!(!h.undo());
+    assert!(h.redo());
+    assert_eq!(*h, 3);
+
+    // Resuming straight away with the same tag continues the same step, as long as nothing else was committed
+    h.begin_coalescing("slider");
+    *h = 4;
+    assert!(h.commit());
+    h.end_coalescing();
+    h.begin_coalescing("slider");
+    *h = 5;
+    assert!(h.commit());
+    h.end_coalescing();
+    assert!(h.undo());
+    assert_eq!(*h, 3);
+
+    // A different tag starts a new step
+    h.begin_coalescing("a");
+    *h = 6;
+    assert!(h.commit());
+    h.begin_coalescing("b");
+    *h = 7;
+    assert!(h.commit());
+    h.end_coalescing();
+    assert!(h.undo());
+    assert_eq!(*h, 6);
+
+    // Dragging back to the starting value leaves no step behind
+    h.begin_coalescing("c");
+    *h = 8;
+    assert!(h.commit());
+    *h = 6;
+    assert!(h.commit());
+    h.end_coalescing();
+    assert!(!h.redo());
+    assert!(h.undo());
+    assert_eq!(*h, 3);
+}
@@ -0,0 +1,45 @@
+//! Copying and pasting, both within Mamar and to and from other programs.
+
+use std::error::Error;
+
+use ::clipboard::{ClipboardContext, ClipboardProvider};
+use pm64::bgm::Command;
+
+#[derive(Default)]
+pub struct Clipboard {
+    /// Commands copied from a track, ready to be pasted into another. Other programs have no use for them, so they
+    /// are kept here rather than on the system clipboard. Not part of the undo history.
+    commands: Vec<Command>,
+
+    /// The clipboard shared with other programs. Made when first needed, and kept because on some platforms what was
+    /// copied is lost when it is dropped.
+    system: Option<ClipboardContext>,
+}
+
+impl Clipboard {
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn set_commands(&mut self, commands: Vec<Command>) {
+        self.commands = commands;
+    }
+
+    /// The text on the system clipboard, e.g. copied from another program.
+    pub fn get_text(&mut self) -> Result<String, Box<dyn Error>> {
+        self.system()?.get_contents()
+    }
+
+    /// Puts `text` on the system clipboard, so it can be pasted into other programs.
+    pub fn set_text(&mut self, text: String) -> Result<(), Box<dyn Error>> {
+        self.system()?.set_contents(text)
+    }
+
+    fn system(&mut self) -> Result<&mut ClipboardContext, Box<dyn Error>> {
+        if self.system.is_none() {
+            self.system = Some(ClipboardProvider::new()?);
+        }
+
+        Ok(self.system.as_mut().expect("system clipboard was just made"))
+    }
+}
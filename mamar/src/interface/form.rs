@@ -1,7 +1,7 @@
-use std::{convert::TryInto, ops::RangeBounds};
+use std::{convert::TryInto, ops::{RangeBounds, RangeInclusive}};
 use std::convert::TryFrom;
 
-use imui_glium::UiFrame;
+use imui_glium::{UiFrame, UniqueKey};
 
 pub fn range_select<N, R, L>(ui: &mut UiFrame<'_>, key: u8, range: R, step: isize, value: &mut N, label: L) -> bool
 where
@@ -40,3 +40,22 @@ where
 
     changed
 }
+
+pub fn labelled_slider<K: UniqueKey>(
+    ui: &mut UiFrame<'_>,
+    key: K,
+    label: String,
+    value: &mut f32,
+    range: RangeInclusive<f32>,
+) -> bool {
+    let mut changed = false;
+
+    ui.hbox(key, |ui| {
+        ui.known_size(0, 150.0, 36.0, |ui| {
+            ui.text(0, label).center_y();
+        });
+        changed = ui.slider(1, value, range);
+    });
+
+    changed
+}
@@ -1,50 +1,283 @@
+use std::error::Error;
 use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::{Duration, Instant};
 
-/// An interface for talking to an emulator ('hot-reloading').
+use pm64::bgm::{Bgm, Segment};
+use pm64::hot::{ConnectionInfo, LiveChange, Message, Status};
+
+use super::synth::Synth;
+
+/// Live changes are sent at most this often, e.g. while a slider is dragged.
+const LIVE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// If the emulator can't take live changes, the song is only reloaded once changes have stopped for this long, rather
+/// than restarting on every step of a slider drag.
+const RELOAD_DELAY: Duration = Duration::from_millis(400);
+
+/// How often the playhead moves while a song plays.
+const PLAYHEAD_INTERVAL: Duration = Duration::from_millis(33);
+
+/// An interface for talking to an emulator ('hot-reloading'). Songs are played with the built-in [Synth] instead when
+/// no emulator is connected.
 pub struct Hot {
-    bgm_tx: Sender<Vec<u8>>,
-    conn_state_rx: Receiver<bool>,
+    message_tx: Sender<Message>,
+    conn_state_rx: Receiver<Status>,
 
-    is_client_connected: bool,
+    status: Status,
+    /// Whether we have asked the emulator to play something, and not yet to stop. Songs loop, so we can't tell when
+    /// they finish.
+    is_playing: bool,
+
+    /// Live changes waiting for [Hot::send_at], latest first.
+    pending_changes: Vec<LiveChange>,
+    /// The song to reload at [Hot::send_at], for emulators that can't take live changes.
+    pending_reload: Option<Vec<u8>>,
+    send_at: Option<Instant>,
+
+    synth: Synth,
+    /// What the synth was last asked to play, to tell where it is in the song being edited.
+    playing: Option<Playing>,
+    /// The synth's position as of the last [Hot::update].
+    last_position: Option<usize>,
+}
+
+struct Playing {
+    /// The variation being played, as it is in the song being edited.
+    segment: Segment,
 }
 
 impl Hot {
     pub fn new() -> Self {
-        let (bgm_tx, bgm_rx) = channel();
+        let (message_tx, message_rx) = channel();
         let (conn_state_tx, conn_state_rx) = channel();
 
-        // This thread runs in the background, but it will kill itself once `bgm_rx` is dropped (i.e. Hot is dropped).
+        // This thread runs in the background, but it will kill itself once `message_rx` is dropped (i.e. Hot is
+        // dropped).
         std::thread::spawn(move || {
-            pm64::hot::run(conn_state_tx, bgm_rx)
-                .expect("hot thread died unexpectedly");
+            if let Err(error) = pm64::hot::run(conn_state_tx, message_rx) {
+                log::error!("hot-reload server stopped: {}", error);
+            }
         });
 
         Hot {
-            bgm_tx,
+            message_tx,
             conn_state_rx,
-            is_client_connected: false,
+            status: Status::Listening { reason: None },
+            is_playing: false,
+            pending_changes: Vec::new(),
+            pending_reload: None,
+            send_at: None,
+            synth: Synth::new(),
+            playing: None,
+            last_position: None,
         }
     }
 
-    /// Check for events. Returns `true` if state changed.
+    /// Check for events, and send any changes that are due. Returns `true` if state changed, including the playhead
+    /// moving.
     pub fn update(&mut self) -> bool {
-        if let Some(new_state) = self.conn_state_rx.try_iter().last() {
-            if new_state != self.is_client_connected {
-                self.is_client_connected = new_state;
+        if matches!(self.send_at, Some(send_at) if send_at <= Instant::now()) {
+            self.send_pending();
+        }
+
+        let position = self.synth.position();
+        let has_moved = position != self.last_position;
+        self.last_position = position;
+
+        if let Some(new_status) = self.conn_state_rx.try_iter().last() {
+            if new_status != self.status {
+                self.status = new_status;
+
+                // Whatever was playing was lost with the connection.
+                if !self.has_connections() {
+                    self.is_playing = false;
+                    self.clear_pending();
+                }
+
                 return true;
             }
         }
 
-        false
+        has_moved
     }
 
     pub fn has_connections(&self) -> bool {
-        self.is_client_connected
+        !self.connections().is_empty()
+    }
+
+    /// The emulators that are connected. Only one can connect at a time, for now.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        match &self.status {
+            Status::Connected(info) => vec![info.clone()],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Why no emulator is connected, if there is a reason to give.
+    pub fn disconnected_reason(&self) -> Option<&str> {
+        match &self.status {
+            Status::Listening { reason } => reason.as_deref(),
+            Status::Unavailable(reason) => Some(reason),
+            Status::Connected(_) => None,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.is_playing || self.synth.is_playing()
+    }
+
+    /// Whether what's playing can be paused. Only the built-in synth can pause; emulators can only stop.
+    pub fn can_pause(&self) -> bool {
+        self.synth.is_playing()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.synth.is_paused()
+    }
+
+    pub fn pause(&mut self) {
+        self.synth.pause();
     }
 
-    /// Queues playback of the given BGM. If no client is currently connected, this will play when one does.
-    pub fn play_bgm(&mut self, bgm: &pm64::bgm::Bgm) -> Result<(), pm64::bgm::en::Error> {
-        let _ = self.bgm_tx.send(bgm.as_bytes()?);
+    pub fn resume(&mut self) {
+        self.synth.resume();
+    }
+
+    /// Whether the built-in synth clicks on every beat. Emulators don't.
+    pub fn metronome(&self) -> bool {
+        self.synth.metronome()
+    }
+
+    pub fn set_metronome(&mut self, enabled: bool) {
+        self.synth.set_metronome(enabled);
+    }
+
+    /// When [Hot::update] next needs calling to send changes that are waiting or to move the playhead, if either
+    /// needs doing.
+    pub fn deadline(&self) -> Option<Instant> {
+        let playhead = if self.synth.is_playing() && !self.synth.is_paused() {
+            Some(Instant::now() + PLAYHEAD_INTERVAL)
+        } else {
+            None
+        };
+
+        match (self.send_at, playhead) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Where the built-in synth has got to in `segment`, in ticks from its start, if it is playing that variation of
+    /// the song. This jumps back when the variation loops. Emulators don't say where they are, so this is `None` while
+    /// one is connected.
+    pub fn playback_position(&self, segment: &Segment) -> Option<usize> {
+        self.playing
+            .as_ref()
+            .filter(|playing| playing.segment == *segment)
+            .and_then(|_| self.synth.position())
+    }
+
+    /// Plays the given BGM in the connected emulator, or with the built-in synth if no emulator is connected.
+    pub fn play_bgm(&mut self, bgm: &Bgm) -> Result<(), Box<dyn Error>> {
+        // The new song already has any changes that were waiting.
+        self.clear_pending();
+
+        if !self.has_connections() {
+            self.playing = bgm.segments[0].clone().map(|segment| Playing { segment });
+            return self.synth.play(&bgm.with_scales_applied());
+        }
+
+        let bytes = bgm.as_bytes()?;
+        self.synth.stop();
+        self.playing = None;
+        let _ = self.message_tx.send(Message::Bgm(bytes));
+        self.is_playing = true;
+        Ok(())
+    }
+
+    /// Applies `change` to the song that is playing without restarting it, if the emulator supports that. Otherwise,
+    /// `bgm`, which should already have the change made to it, is played from the start once changes stop coming.
+    /// See [LiveChange] for what can be changed live. Does nothing if nothing is playing in an emulator; the built-in
+    /// synth only hears changes when the song is played again.
+    ///
+    /// Changes are held back for a moment, so that dragging a slider doesn't flood the connection. Call
+    /// [Hot::update] by [Hot::deadline] to send them.
+    pub fn send_live(&mut self, change: LiveChange, bgm: &Bgm) -> Result<(), Box<dyn Error>> {
+        if !self.is_playing {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+
+        if self.connections().iter().all(ConnectionInfo::supports_live) {
+            self.pending_changes.retain(|pending| !pending.replaces(&change));
+            self.pending_changes.insert(0, change);
+
+            // Throttled rather than debounced, so the change is heard while the slider is still moving.
+            if self.send_at.is_none() {
+                self.send_at = Some(now + LIVE_INTERVAL);
+            }
+        } else {
+            self.pending_reload = Some(bgm.as_bytes()?);
+            self.send_at = Some(now + RELOAD_DELAY);
+        }
+
+        Ok(())
+    }
+
+    fn send_pending(&mut self) {
+        // Oldest first, though there is at most one change per parameter.
+        for change in self.pending_changes.drain(..).rev() {
+            let _ = self.message_tx.send(Message::Live(change));
+        }
+
+        if let Some(bytes) = self.pending_reload.take() {
+            let _ = self.message_tx.send(Message::Bgm(bytes));
+        }
+
+        self.send_at = None;
+    }
+
+    fn clear_pending(&mut self) {
+        self.pending_changes.clear();
+        self.pending_reload = None;
+        self.send_at = None;
+    }
+
+    /// Like [Hot::play_bgm], but starts `time` ticks into the first variation. Everything before `time` is skipped,
+    /// except for commands that set things up, like the tempo. The built-in synth [seeks](Synth::seek) there within
+    /// the song itself, so [Hot::playback_position] stays in its ticks; emulators are sent a copy of the song starting
+    /// there (see [Bgm::starting_at]).
+    pub fn play_bgm_from(&mut self, bgm: &Bgm, time: usize) -> Result<(), Box<dyn Error>> {
+        if !self.has_connections() {
+            self.play_bgm(bgm)?;
+            self.synth.seek(time);
+            return Ok(());
+        }
+
+        match bgm.starting_at(0, time) {
+            Some(preview) => self.play_bgm(&preview),
+            None => self.play_bgm(bgm),
+        }
+    }
+
+    /// Stops playback. Emulators are stopped by playing a song with no notes, which every hot-reload script supports.
+    pub fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.synth.stop();
+
+        if !self.has_connections() {
+            self.is_playing = false;
+            return Ok(());
+        }
+
+        let mut silence = Bgm::new();
+        let _ = silence.add_segment();
+        if let Some(segment) = silence.segments[0].as_mut() {
+            segment.add_tracks_subsegment(&mut silence.track_lists, 0x10);
+        }
+
+        self.play_bgm(&silence)?;
+        self.is_playing = false;
         Ok(())
     }
 }
@@ -0,0 +1,146 @@
+//! Showing the raw bytes of the BGM file a song was read from, split up by what each part decodes to, for
+//! reverse-engineering the format.
+
+use std::fmt::Write as _;
+
+use pm64::bgm::FilePos;
+
+use super::state::Document;
+
+/// How many bytes are shown on each line.
+const BYTES_PER_ROW: FilePos = 16;
+
+#[derive(Default)]
+pub struct Inspector {
+    /// Index into the file's layout of the part whose bytes are shown.
+    selected: usize,
+}
+
+impl Inspector {
+    /// Returns whether the inspector should stay open.
+    pub fn update(&mut self, ui: &mut imui_glium::UiFrame<'_>, doc: &mut Document) -> bool {
+        let mut is_open = true;
+        let mut to_show = None;
+
+        ui.modal("inspector", true, (900.0, 650.0), |ui| {
+            let original = match doc.original() {
+                Some(original) => original,
+                None => {
+                    ui.text(0, "Only songs read from BGM files can be inspected.");
+                    ui.pad(1, 20.0);
+                    if ui.button(2, "Close").clicked() {
+                        is_open = false;
+                    }
+                    return;
+                }
+            };
+
+            ui.text(0, format!("{} bytes, in {} parts", original.bytes.len(), original.layout.len()));
+            ui.pad(1, 10.0);
+
+            ui.hbox(2, |ui| {
+                ui.known_size(0, 350.0, 500.0, |ui| {
+                    ui.scroll_area_with_scrollbar(0, |ui| {
+                        for (i, (location, range)) in original.layout.iter().enumerate() {
+                            let label = format!("{:#06X} {}", range.start, location);
+                            let (texture, texture_pressed) = if i == self.selected {
+                                ("tab_selected", "tab_selected")
+                            } else {
+                                ("button", "button_pressed")
+                            };
+
+                            if ui.custom_button(i, label, texture, texture_pressed).with_width(330.0).clicked() {
+                                self.selected = i;
+                            }
+                        }
+                    });
+                });
+
+                ui.pad(1, 10.0);
+
+                ui.known_size(2, 500.0, 500.0, |ui| {
+                    ui.scroll_area_with_scrollbar(0, |ui| {
+                        if let Some((_, range)) = original.layout.get(self.selected) {
+                            for (i, row) in hex_rows(&original.bytes, range.start, range.end).into_iter().enumerate() {
+                                ui.text(i, row).selectable();
+                            }
+                        }
+                    });
+                });
+            });
+
+            ui.pad(3, 10.0);
+
+            ui.hbox(4, |ui| {
+                if let Some((location, range)) = original.layout.get(self.selected) {
+                    if ui.button(0, "Open in editor")
+                        .with_width(200.0)
+                        .tooltip("Show this part of the song in the editor, if it has a view of its own")
+                        .clicked()
+                    {
+                        to_show = Some(*location);
+                    }
+
+                    ui.pad(1, 10.0);
+                    ui.text(2, format!("{} bytes", range.end - range.start)).center_y();
+                    ui.pad(3, 10.0);
+                }
+
+                if ui.button(99, "Close").clicked() {
+                    is_open = false;
+                }
+            });
+        });
+
+        if let Some(location) = to_show {
+            if doc.show(location) {
+                is_open = false;
+            } else {
+                log::warn!("no view to show {} in", location);
+            }
+        }
+
+        is_open
+    }
+}
+
+/// Formats the bytes from `start` up to `end` as lines of hex, e.g. `0x0020  00 00 12 34`. Lines start at multiples
+/// of [BYTES_PER_ROW], with blanks before `start`, so that columns line up with offsets.
+fn hex_rows(bytes: &[u8], start: FilePos, end: FilePos) -> Vec<String> {
+    let end = end.min(bytes.len() as FilePos);
+    let mut rows = Vec::new();
+    let mut row_start = start - start % BYTES_PER_ROW;
+
+    while row_start < end {
+        let mut row = format!("{:#06X} ", row_start);
+        for offset in row_start..row_start + BYTES_PER_ROW {
+            if offset >= start && offset < end {
+                let _ = write!(row, " {:02X}", bytes[offset as usize]);
+            } else {
+                row.push_str("   ");
+            }
+        }
+
+        rows.push(row.trim_end().to_owned());
+        row_start += BYTES_PER_ROW;
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rows_line_up_with_offsets() {
+        let bytes: Vec<u8> = (0..40).collect();
+
+        assert_eq!(hex_rows(&bytes, 0x0E, 0x12), vec![
+            "0x0000                                            0E 0F",
+            "0x0010  10 11",
+        ]);
+        assert_eq!(hex_rows(&bytes, 0x20, 0x30), vec!["0x0020  20 21 22 23 24 25 26 27"]);
+        assert!(hex_rows(&bytes, 0x30, 0x30).is_empty());
+    }
+}
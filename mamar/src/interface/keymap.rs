@@ -0,0 +1,265 @@
+//! Keyboard shortcuts, which can be rebound and are saved between sessions.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+
+use imui_glium::glium::glutin::event::{VirtualKeyCode, ModifiersState};
+
+use super::Action;
+use super::settings;
+
+const KEYMAP_FILE: &str = "keymap.txt";
+
+/// The actions that can be given a shortcut, in the order they are listed in the shortcut editor.
+pub const REBINDABLE: [Action; 23] = [
+    Action::NewDocument,
+    Action::OpenDocument,
+    Action::ReloadDocument,
+    Action::Revert,
+    Action::CloseTab,
+    Action::SaveDocument,
+    Action::SaveDocumentAs,
+    Action::ExportMidi,
+    Action::ExportRom,
+    Action::ImportVariation,
+    Action::Optimize,
+    Action::Undo,
+    Action::Redo,
+    Action::Copy,
+    Action::Cut,
+    Action::Paste,
+    Action::Play,
+    Action::PlayFromPlayhead,
+    Action::Pause,
+    Action::ToggleMetronome,
+    Action::ToggleTheme,
+    Action::PreviousSegment,
+    Action::NextSegment,
+];
+
+/// A key pressed while holding some modifiers, e.g. Ctrl+S.
+pub type Binding = (VirtualKeyCode, ModifiersState);
+
+pub struct Keymap {
+    bindings: HashMap<Binding, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let ctrl = ModifiersState::CTRL;
+        let ctrl_shift = ModifiersState::CTRL | ModifiersState::SHIFT;
+
+        Self {
+            bindings: vec![
+                ((VirtualKeyCode::O, ctrl), Action::OpenDocument),
+                ((VirtualKeyCode::W, ctrl), Action::CloseTab),
+                ((VirtualKeyCode::S, ctrl), Action::SaveDocument),
+                ((VirtualKeyCode::S, ctrl_shift), Action::SaveDocumentAs),
+                ((VirtualKeyCode::Z, ctrl), Action::Undo),
+                ((VirtualKeyCode::Z, ctrl_shift), Action::Redo),
+                ((VirtualKeyCode::C, ctrl), Action::Copy),
+                ((VirtualKeyCode::X, ctrl), Action::Cut),
+                ((VirtualKeyCode::V, ctrl), Action::Paste),
+                ((VirtualKeyCode::Space, ModifiersState::empty()), Action::Play),
+                ((VirtualKeyCode::Space, ModifiersState::SHIFT), Action::PlayFromPlayhead),
+                ((VirtualKeyCode::PageUp, ctrl), Action::PreviousSegment),
+                ((VirtualKeyCode::PageDown, ctrl), Action::NextSegment),
+            ].into_iter().collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Loads the saved keymap, falling back to the default shortcuts for anything it doesn't mention.
+    pub fn load() -> Self {
+        let mut keymap = Self::default();
+
+        let text = match settings::path(KEYMAP_FILE).map(fs::read_to_string) {
+            Some(Ok(text)) => text,
+            _ => return keymap, // Nothing saved yet
+        };
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            let action = parts.next().and_then(action_from_name);
+
+            match (action, parts.next()) {
+                (Some(action), Some("none")) => keymap.unbind(action),
+                (Some(action), Some(binding)) => match parse_binding(binding) {
+                    Some(binding) => keymap.bind(action, binding),
+                    None => log::warn!("unknown key binding in keymap: {}", line),
+                },
+                _ => log::warn!("ignoring invalid line in keymap: {}", line),
+            }
+        }
+
+        keymap
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let mut text = String::from("# Mamar keyboard shortcuts. Each line is `action = keys`, e.g. `save = Ctrl+S`.\n");
+        for action in REBINDABLE.iter() {
+            let binding = self.binding_name(*action).unwrap_or_else(|| "none".to_owned());
+            writeln!(text, "{} = {}", action_name(*action), binding)?;
+        }
+
+        settings::write(KEYMAP_FILE, &text)
+    }
+
+    pub fn get(&self, key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Action> {
+        self.bindings.get(&(key, modifiers)).copied()
+    }
+
+    /// Makes `binding` the only shortcut for `action`, taking it away from any other action that used it.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        self.unbind(action);
+        self.bindings.insert(binding, action);
+    }
+
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.retain(|_, a| *a != action);
+    }
+
+    /// The shortcut for `action` as it would be written, e.g. "Ctrl+Shift+S".
+    pub fn binding_name(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, a)| **a == action)
+            .map(|((key, modifiers), _)| format_binding(*key, *modifiers))
+    }
+}
+
+/// Whether `key` can be part of a shortcut. Modifier keys can't be bound on their own, for example.
+pub fn is_bindable(key: VirtualKeyCode) -> bool {
+    KEY_NAMES.iter().any(|(k, _)| *k == key)
+}
+
+pub fn action_label(action: Action) -> &'static str {
+    match action {
+        Action::None => "Nothing",
+        Action::NewDocument => "New file",
+        Action::OpenDocument => "Open file",
+        Action::OpenRecent(_) => "Open recent file",
+        Action::OpenExample => "Open example song",
+        Action::OpenDropped => "Open dropped files",
+        Action::ReloadDocument => "Reload file",
+        Action::Revert => "Revert to saved",
+        Action::SaveDocument => "Save",
+        Action::SaveDocumentAs => "Save as",
+        Action::ExportMidi => "Export MIDI",
+        Action::ExportRom => "Export to ROM",
+        Action::ImportVariation => "Import variation from file",
+        Action::Optimize => "Optimize song",
+        Action::Undo => "Undo",
+        Action::Redo => "Redo",
+        Action::Copy => "Copy selection",
+        Action::Cut => "Cut selection",
+        Action::Paste => "Paste at playhead",
+        Action::Play => "Play from beginning or stop",
+        Action::PlayFromPlayhead => "Play from playhead",
+        Action::Pause => "Pause or resume",
+        Action::ToggleMetronome => "Metronome on/off",
+        Action::ToggleTheme => "Switch light/dark theme",
+        Action::PreviousSegment => "Previous variation",
+        Action::NextSegment => "Next variation",
+        Action::SwitchTab(_) => "Switch tab",
+        Action::CloseTab => "Close file",
+    }
+}
+
+/// Name of `action` in the keymap file.
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::None => "none",
+        Action::NewDocument => "new",
+        Action::OpenDocument => "open",
+        Action::OpenRecent(_) => "open_recent",
+        Action::OpenExample => "open_example",
+        Action::OpenDropped => "open_dropped",
+        Action::ReloadDocument => "reload",
+        Action::Revert => "revert",
+        Action::SaveDocument => "save",
+        Action::SaveDocumentAs => "save_as",
+        Action::ExportMidi => "export_midi",
+        Action::ExportRom => "export_rom",
+        Action::ImportVariation => "import_variation",
+        Action::Optimize => "optimize",
+        Action::Undo => "undo",
+        Action::Redo => "redo",
+        Action::Copy => "copy",
+        Action::Cut => "cut",
+        Action::Paste => "paste",
+        Action::Play => "play",
+        Action::PlayFromPlayhead => "play_from_playhead",
+        Action::Pause => "pause",
+        Action::ToggleMetronome => "toggle_metronome",
+        Action::ToggleTheme => "toggle_theme",
+        Action::PreviousSegment => "previous_variation",
+        Action::NextSegment => "next_variation",
+        Action::SwitchTab(_) => "switch_tab",
+        Action::CloseTab => "close",
+    }
+}
+
+fn action_from_name(name: &str) -> Option<Action> {
+    REBINDABLE.iter().copied().find(|action| action_name(*action) == name)
+}
+
+const MODIFIER_NAMES: [(ModifiersState, &str); 4] = [
+    (ModifiersState::CTRL, "Ctrl"),
+    (ModifiersState::SHIFT, "Shift"),
+    (ModifiersState::ALT, "Alt"),
+    (ModifiersState::LOGO, "Logo"),
+];
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        &[$((VirtualKeyCode::$key, stringify!($key))),*]
+    };
+}
+
+/// Keys that can be bound, by the name they are written with.
+const KEY_NAMES: &[(VirtualKeyCode, &str)] = key_names![
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Space, Return, Back, Delete, Insert, Home, End, PageUp, PageDown, Left, Right, Up, Down,
+    Minus, Equals, LBracket, RBracket, Semicolon, Apostrophe, Comma, Period, Slash, Backslash, Grave,
+];
+
+fn format_binding(key: VirtualKeyCode, modifiers: ModifiersState) -> String {
+    let mut name = String::new();
+
+    for (modifier, modifier_name) in MODIFIER_NAMES.iter() {
+        if modifiers.contains(*modifier) {
+            name.push_str(modifier_name);
+            name.push('+');
+        }
+    }
+
+    if let Some((_, key_name)) = KEY_NAMES.iter().find(|(k, _)| *k == key) {
+        name.push_str(key_name);
+    }
+
+    name
+}
+
+fn parse_binding(text: &str) -> Option<Binding> {
+    let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+    let key_name = parts.pop()?;
+    let (key, _) = KEY_NAMES.iter().find(|(_, name)| name.eq_ignore_ascii_case(key_name))?;
+
+    let mut modifiers = ModifiersState::empty();
+    for part in parts {
+        let (modifier, _) = MODIFIER_NAMES.iter().find(|(_, name)| name.eq_ignore_ascii_case(part))?;
+        modifiers |= *modifier;
+    }
+
+    Some((*key, modifiers))
+}
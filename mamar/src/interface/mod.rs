@@ -2,36 +2,165 @@ mod icon;
 mod state;
 mod form;
 mod hot;
+mod synth;
+mod keymap;
+mod recovery;
+mod inspector;
+mod clipboard;
+pub(crate) mod settings;
 
 use std::error::Error;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use imui_glium::*;
 use imui_glium::glium::Display;
 use imui_glium::glium::glutin::window::Window;
-use imui_glium::glium::glutin::dpi::LogicalSize;
+use imui_glium::glium::glutin::dpi::{LogicalSize, Size};
 use imui_glium::glium::glutin::event::{ElementState, VirtualKeyCode, ModifiersState};
+use pm64::bgm::de;
 
 use crate::history::History;
+use self::clipboard::Clipboard;
 use hot::Hot;
+use inspector::Inspector;
+use keymap::Keymap;
+use recovery::Recovery;
+use settings::{RecentFiles, WindowGeometry};
 
 pub struct Interface {
     display: Display,
     glue: Glue,
 
+    /// The document being shown, with its undo history.
     state: History<state::State>,
+    /// Documents open in other tabs, each with an undo history of its own, in tab order. The one being shown is
+    /// between `inactive_tabs[..tab]` and `inactive_tabs[tab..]`.
+    inactive_tabs: Vec<History<state::State>>,
+    tab: usize,
     hot: Hot,
     queued_action: Action,
+    clipboard: Clipboard,
+
+    keymap: Keymap,
+    recent_files: RecentFiles,
+    is_keymap_open: bool,
+    /// The action whose shortcut will be replaced by the next key pressed.
+    rebinding: Option<Action>,
+
+    /// Asking whether to save unsaved changes before closing the tab.
+    is_close_prompt_open: bool,
+    /// Close the tab once the document has been saved.
+    close_after_save: bool,
+    close_now: bool,
+    /// Closing the window, one tab at a time, rather than just the tab being shown.
+    is_closing_window: bool,
+
+    recovery: Recovery,
+
+    /// Files dropped onto the window, until [Action::OpenDropped] opens them.
+    dropped_files: Vec<PathBuf>,
+    /// Whether a file is being dragged over the window, so dropping it would open it.
+    is_file_hovered: bool,
+
+    /// Toggled with F3, unless that is bound to something else.
+    is_stats_open: bool,
+    stats: Stats,
+
+    /// Shows the bytes of the file the song was read from. Toggled with F4, unless that is bound to something else.
+    inspector: Option<Inspector>,
+}
+
+/// Measurements shown with [Interface::is_stats_open], for profiling the UI.
+#[derive(Default)]
+struct Stats {
+    /// How long the last frame took to draw.
+    frame_time: Duration,
+    /// How many passes the last update took before the state stopped changing.
+    updates: usize,
+}
+
+/// How a document is labelled on its tab, with a `*` if it has unsaved changes.
+fn tab_label(tab: &History<state::State>) -> String {
+    match &tab.document {
+        Some(doc) if tab.is_dirty() => format!("*{}", doc.bgm.name),
+        Some(doc) => doc.bgm.name.clone(),
+        None => "No song".to_owned(),
+    }
+}
+
+/// How many [de::Warning]s [show_warnings] lists before giving the number of the rest.
+const WARNINGS_SHOWN: usize = 10;
+
+/// Tells the user about anything unexpected in the file at `path` that didn't stop it opening, as it may not play the
+/// way it does in the game. Does nothing if there are no warnings.
+fn show_warnings(path: &Path, warnings: &[de::Warning]) {
+    if warnings.is_empty() {
+        return;
+    }
+
+    for warning in warnings {
+        log::warn!("{}: {}", path.display(), warning);
+    }
+
+    let mut list: Vec<String> = warnings.iter().take(WARNINGS_SHOWN).map(|warning| format!("- {}", warning)).collect();
+    if warnings.len() > WARNINGS_SHOWN {
+        list.push(format!("...and {} more", warnings.len() - WARNINGS_SHOWN));
+    }
+
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+    tinyfiledialogs::message_box_ok(
+        "Opened with warnings",
+        &format!("{} has {} unexpected thing(s) in it:\n\n{}", name, warnings.len(), list.join("\n")),
+        tinyfiledialogs::MessageBoxIcon::Warning,
+    );
 }
 
-/// UI things that can't happen during updates, like opening file dialogs.
-#[derive(Clone)]
+/// UI things that can't happen during updates, like opening file dialogs. These are also what keyboard shortcuts do.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum Action {
     None,
     NewDocument,
     OpenDocument,
+    /// Open the file at this index in [RecentFiles].
+    OpenRecent(usize),
+    /// Open the song that comes with Mamar for trying it out.
+    OpenExample,
+    /// Open the files that were dropped onto the window.
+    OpenDropped,
     SaveDocument,
     SaveDocumentAs,
     ReloadDocument,
+    /// Discard changes to a native file by reading it again, asking first if there are any.
+    Revert,
+    ExportMidi,
+    ExportRom,
+    /// Copy a variation from another song file into this one.
+    ImportVariation,
+    /// Make the song smaller without changing how it sounds, after asking.
+    Optimize,
+    Undo,
+    Redo,
+    Copy,
+    Cut,
+    Paste,
+    /// Play from the beginning, or stop if already playing.
+    Play,
+    PlayFromPlayhead,
+    /// Pause or resume the built-in synth. Emulators can't pause.
+    Pause,
+    /// Switch the built-in synth's click on every beat on or off.
+    ToggleMetronome,
+    ToggleTheme,
+    /// Show the previous variation of the song.
+    PreviousSegment,
+    /// Show the next variation of the song.
+    NextSegment,
+    /// Show the document in the tab at this index.
+    SwitchTab(usize),
+    /// Close the tab being shown, asking to save it first if it has unsaved changes.
+    CloseTab,
 }
 
 impl Interface {
@@ -41,9 +170,14 @@ impl Interface {
 
         let event_loop = EventLoop::new();
 
+        // Open where the window was last time, as long as that's still on screen.
+        let monitors = event_loop.available_monitors().map(|monitor| (monitor.position(), monitor.size()));
+        let geometry = WindowGeometry::load().and_then(|geometry| geometry.fit_to_monitors(monitors));
+        let size: Size = geometry.map_or(LogicalSize::new(800.0, 600.0).into(), |geometry| geometry.size.into());
+
         let wb = imui_glium::glium::glutin::window::WindowBuilder::new()
             .with_title("Mamar")
-            .with_inner_size(LogicalSize::new(800.0, 600.0))
+            .with_inner_size(size)
             .with_min_inner_size(LogicalSize::new(800.0, 600.0))
             .with_window_icon(icon::get_icon())
             .with_visible(false);
@@ -52,6 +186,10 @@ impl Interface {
             .with_srgb(true);
         let display = Display::new(wb, cb, &event_loop)?;
 
+        if let Some(geometry) = geometry {
+            display.gl_window().window().set_outer_position(geometry.position);
+        }
+
         let mut glue = Glue::new(&display)?;
 
         log::info!("loading assets");
@@ -66,6 +204,9 @@ impl Interface {
         glue.atlas().insert("tab", "assets/tex/tab.png")?;
         glue.atlas().insert("tab_selected", "assets/tex/tab_selected.png")?;
         glue.atlas().insert("tab_pressed", "assets/tex/tab_pressed.png")?;
+        glue.atlas().insert("white", "assets/tex/white.png")?;
+
+        glue.set_theme(settings::load_theme());
 
         glue.load_font(&{
             let mut font = File::open("assets/Inter-Medium.otf")?;
@@ -78,8 +219,25 @@ impl Interface {
             display,
             glue,
             state: History::new(Default::default()),
+            inactive_tabs: Vec::new(),
+            tab: 0,
             hot: Hot::new(),
             queued_action: Action::None,
+            clipboard: Clipboard::default(),
+            keymap: Keymap::load(),
+            recent_files: RecentFiles::load(),
+            is_keymap_open: false,
+            rebinding: None,
+            is_close_prompt_open: false,
+            close_after_save: false,
+            close_now: false,
+            is_closing_window: false,
+            recovery: Recovery::default(),
+            dropped_files: Vec::new(),
+            is_file_hovered: false,
+            is_stats_open: false,
+            stats: Stats::default(),
+            inspector: None,
         }, event_loop))
     }
 
@@ -88,9 +246,48 @@ impl Interface {
     }
 
     fn update(&mut self) {
+        // Everything changed during one drag, e.g. of a slider, or while typing into one text input is undone in one
+        // step.
+        let key = self.glue.dragging().or_else(|| self.glue.editing_text()).cloned();
+        match key {
+            Some(key) => self.state.begin_coalescing(key),
+            None => self.state.end_coalescing(),
+        }
+
         let state = &mut self.state;
+        let inactive_tabs = &self.inactive_tabs;
+        let tab = self.tab;
         let hot = &mut self.hot;
         let queued_action = &mut self.queued_action;
+        let keymap = &self.keymap;
+        let recent_files = &self.recent_files;
+        let is_keymap_open = &mut self.is_keymap_open;
+        let rebinding = &mut self.rebinding;
+        let is_close_prompt_open = &mut self.is_close_prompt_open;
+        let close_after_save = &mut self.close_after_save;
+        let close_now = &mut self.close_now;
+        let is_closing_window = &mut self.is_closing_window;
+        let is_file_hovered = self.is_file_hovered;
+        let inspector = &mut self.inspector;
+        let log_path = crate::logger::file_path().map(|path| path.display().to_string());
+
+        // Shown as of the last frame, rather than updating every frame to keep them current and skewing them.
+        let stats = if self.is_stats_open {
+            let draw = self.glue.stats();
+            Some(format!(
+                "{:.1} ms/frame, {} update(s), {} controls, {} draw call(s), {} triangles",
+                self.stats.frame_time.as_secs_f64() * 1000.0,
+                self.stats.updates,
+                self.glue.control_count(),
+                draw.draw_calls,
+                draw.triangles,
+            ))
+        } else {
+            None
+        };
+
+        // Appended to tooltips, e.g. " (Ctrl+S)".
+        let shortcut = |action| keymap.binding_name(action).map_or_else(String::new, |name| format!(" ({})", name));
 
         let mut updates = 0;
         loop {
@@ -102,52 +299,322 @@ impl Interface {
                         // File controls. We have to show file dialogs after rendering is complete (otherwise the window
                         // freezes) so we only set that 'X has been requested' when these buttons are clicked.
 
-                        if ui.button("new", "New File").clicked() {
+                        if ui.button("new", "New File")
+                            .tooltip(&format!("Start a new, empty song{}", shortcut(Action::NewDocument)))
+                            .clicked()
+                        {
                             *queued_action = Action::NewDocument;
                         }
 
-                        if ui.button(1, "Open File...").clicked() {
+                        if ui.button(1, "Open File...")
+                            .tooltip(&format!("Open a song{}", shortcut(Action::OpenDocument)))
+                            .clicked()
+                        {
                             *queued_action = Action::OpenDocument;
                         }
 
+                        let recent: Vec<_> = recent_files.existing().collect();
+                        if !recent.is_empty() {
+                            // The first item stands in for 'nothing chosen' so that any file can be picked.
+                            let names = std::iter::once("Recent Files".to_owned()).chain(recent.iter().map(|(_, path)| {
+                                path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+                            }));
+
+                            let mut selected = 0;
+                            if ui.dropdown("recent", &mut selected, names) && selected > 0 {
+                                *queued_action = Action::OpenRecent(recent[selected - 1].0);
+                            }
+                        }
+
+                        if state.document.is_some() || !inactive_tabs.is_empty() {
+                            if ui.button("close", "Close")
+                                .tooltip(&format!("Close this song{}", shortcut(Action::CloseTab)))
+                                .clicked()
+                            {
+                                *queued_action = Action::CloseTab;
+                            }
+                        }
+
                         if let Some(doc) = state.document.as_mut() {
                             if let state::DocPath::Import(_) = doc.path {
-                                if ui.button(2, "Reload File").clicked() {
+                                if ui.button(2, "Reload File")
+                                    .tooltip(&format!(
+                                        "Import the file again, discarding changes{}",
+                                        shortcut(Action::ReloadDocument),
+                                    ))
+                                    .clicked()
+                                {
                                     *queued_action = Action::ReloadDocument;
                                 }
                             }
 
+                            if let state::DocPath::Native(_) = doc.path {
+                                if ui.button("revert", "Revert")
+                                    .tooltip(&format!(
+                                        "Discard changes since the file was last saved{}",
+                                        shortcut(Action::Revert),
+                                    ))
+                                    .clicked()
+                                {
+                                    *queued_action = Action::Revert;
+                                }
+                            }
+
                             ui.pad("pad", 10.0);
 
-                            if doc.can_save() && ui.button(3, "Save").clicked() {
+                            if doc.can_save() && ui.button(3, "Save")
+                                .tooltip(&format!("Save{}", shortcut(Action::SaveDocument)))
+                                .clicked()
+                            {
                                 *queued_action = Action::SaveDocument;
                             }
 
-                            if ui.button(4, "Save As...").clicked() {
+                            if ui.button(4, "Save As...")
+                                .tooltip(&format!("Save to a new file{}", shortcut(Action::SaveDocumentAs)))
+                                .clicked()
+                            {
                                 *queued_action = Action::SaveDocumentAs;
                             }
 
+                            if ui.button(9, "Export MIDI...")
+                                .tooltip(&format!("Save this variation as a MIDI file{}", shortcut(Action::ExportMidi)))
+                                .clicked()
+                            {
+                                *queued_action = Action::ExportMidi;
+                            }
+
+                            if ui.button(10, "Export to ROM...")
+                                .tooltip(&format!(
+                                    "Save a copy of a Paper Mario ROM with this song in place of another{}",
+                                    shortcut(Action::ExportRom),
+                                ))
+                                .clicked()
+                            {
+                                *queued_action = Action::ExportRom;
+                            }
+
+                            if ui.button(17, "Import Variation...")
+                                .tooltip(&format!(
+                                    "Copy a variation from another song file into this one{}",
+                                    shortcut(Action::ImportVariation),
+                                ))
+                                .clicked()
+                            {
+                                *queued_action = Action::ImportVariation;
+                            }
+
+                            if ui.button(18, "Optimize...")
+                                .tooltip(&format!(
+                                    "Remove commands that make no difference and merge identical track lists{}",
+                                    shortcut(Action::Optimize),
+                                ))
+                                .clicked()
+                            {
+                                *queued_action = Action::Optimize;
+                            }
+
                             ui.pad("pad2", 10.0);
 
-                            if hot.has_connections() {
-                                if ui.button(5, "Play from beginning")
+                            if hot.is_playing() {
+                                if ui.button(13, "Stop")
                                     .with_width(200.0)
+                                    .tooltip(&format!("Stop playback{}", shortcut(Action::Play)))
                                     .clicked()
                                 {
-                                    if let Err(error) = hot.play_bgm(&doc.bgm) {
-                                        todo!("surface error: {}", error);
-                                    }
+                                    *queued_action = Action::Play;
+                                }
+                            } else if ui.button(5, "Play from beginning")
+                                .with_width(200.0)
+                                .tooltip(&format!(
+                                    "{}{}",
+                                    if hot.has_connections() {
+                                        "Play the song in the connected emulator"
+                                    } else {
+                                        "Play the song with stand-in instruments (an emulator plays the real ones)"
+                                    },
+                                    shortcut(Action::Play),
+                                ))
+                                .clicked()
+                            {
+                                *queued_action = Action::Play;
+                            }
+
+                            if hot.can_pause() && ui.button(14, if hot.is_paused() { "Resume" } else { "Pause" })
+                                .with_width(120.0)
+                                .tooltip(&format!("Pause or resume playback{}", shortcut(Action::Pause)))
+                                .clicked()
+                            {
+                                *queued_action = Action::Pause;
+                            }
+
+                            let mut metronome = hot.metronome();
+                            if !hot.has_connections() && ui.toggle_button(16, "Metronome", &mut metronome)
+                                .with_width(150.0)
+                                .tooltip(&format!("Click on every beat{}", shortcut(Action::ToggleMetronome)))
+                                .clicked()
+                            {
+                                *queued_action = Action::ToggleMetronome;
+                            }
+
+                            if hot.has_connections() {
+                                for (i, info) in hot.connections().iter().enumerate() {
+                                    ui.pad((11, i), 10.0);
+                                    ui.text((12, i), format!("Connected to {}", info)).center_y();
                                 }
                             } else {
-                                ui.text(7, "No emulator connected").center_y();
+                                let text = match hot.disconnected_reason() {
+                                    Some(reason) => format!("No emulator connected ({})", reason),
+                                    None => "No emulator connected".to_owned(),
+                                };
+                                ui.pad(15, 10.0);
+                                ui.text(7, text).center_y();
                             }
                         }
+
+                        ui.pad("pad3", 10.0);
+
+                        if ui.button(8, "Shortcuts...").tooltip("Change keyboard shortcuts").clicked() {
+                            *is_keymap_open = true;
+                        }
+
+                        if ui.button("theme", "Theme")
+                            .tooltip(&format!("Switch between light and dark{}", shortcut(Action::ToggleTheme)))
+                            .clicked()
+                        {
+                            *queued_action = Action::ToggleTheme;
+                        }
+
+                        if let Some(stats) = &stats {
+                            ui.pad("pad_stats", 10.0);
+                            ui.text("stats", stats.as_str()).center_y();
+                        }
                     });
 
+                    if *is_keymap_open {
+                        ui.modal("keymap", true, (400.0, 800.0), |ui| {
+                            ui.text(0, "Keyboard Shortcuts").center_x();
+                            ui.pad(1, 20.0);
+
+                            for (i, action) in keymap::REBINDABLE.iter().enumerate() {
+                                ui.hbox((2, i), |ui| {
+                                    ui.known_size(0, 200.0, 36.0, |ui| {
+                                        ui.text(0, keymap::action_label(*action)).center_y();
+                                    });
+
+                                    let label = if *rebinding == Some(*action) {
+                                        "Press keys...".to_owned()
+                                    } else {
+                                        keymap.binding_name(*action).unwrap_or_else(|| "None".to_owned())
+                                    };
+
+                                    if ui.button(1, label)
+                                        .with_width(180.0)
+                                        .tooltip("Click, then press the new shortcut (Esc to cancel)")
+                                        .clicked()
+                                    {
+                                        *rebinding = Some(*action);
+                                    }
+                                });
+                            }
+
+                            ui.pad(3, 20.0);
+
+                            if let Some(path) = &log_path {
+                                ui.text(5, "When reporting a bug, please attach the log file:");
+                                ui.text(6, path.as_str());
+                                ui.pad(7, 20.0);
+                            }
+
+                            if ui.button(4, "Close").clicked() {
+                                *is_keymap_open = false;
+                                *rebinding = None;
+                            }
+                        });
+                    }
+
+                    if *is_close_prompt_open {
+                        ui.modal("close_prompt", true, (400.0, 140.0), |ui| {
+                            let name = state.document.as_ref().map_or("", |doc| doc.bgm.name.as_str());
+                            ui.text(0, format!("Save changes to {} before closing?", name)).center_x();
+                            ui.pad(1, 20.0);
+
+                            ui.hbox(2, |ui| {
+                                if ui.button(0, "Save").clicked() {
+                                    *queued_action = Action::SaveDocument;
+                                    *close_after_save = true;
+                                    *is_close_prompt_open = false;
+                                }
+
+                                if ui.button(1, "Don't Save").clicked() {
+                                    *close_now = true;
+                                }
+
+                                if ui.button(2, "Cancel").clicked() {
+                                    *is_close_prompt_open = false;
+                                    *is_closing_window = false;
+                                }
+                            });
+                        });
+                    }
+
                     ui.pad(1, 10.0);
 
+                    if !inactive_tabs.is_empty() {
+                        let labels: Vec<String> = inactive_tabs[..tab]
+                            .iter()
+                            .chain(std::iter::once(&*state))
+                            .chain(&inactive_tabs[tab..])
+                            .map(tab_label)
+                            .collect();
+
+                        let mut selected = tab;
+                        if ui.tabs("documents", &mut selected, labels.into_iter().enumerate()) {
+                            *queued_action = Action::SwitchTab(selected);
+                        }
+                    }
+
                     if let Some(doc) = state.document.as_mut() {
                         ui.hbox(2, |ui| doc.update(ui, hot));
+
+                        if let Some(is_open) = inspector.as_mut().map(|inspector| inspector.update(ui, doc)) {
+                            if !is_open {
+                                *inspector = None;
+                            }
+                        }
+                    } else {
+                        // Nothing is open, e.g. on first run or after closing every song, so point the way in.
+                        ui.pad(3, 40.0);
+                        ui.vbox(4, |ui| {
+                            ui.text(0, "No song is open.");
+                            ui.pad(1, 20.0);
+
+                            ui.hbox(2, |ui| {
+                                if ui.button(0, "New").clicked() {
+                                    *queued_action = Action::NewDocument;
+                                }
+
+                                if ui.button(1, "Open...").clicked() {
+                                    *queued_action = Action::OpenDocument;
+                                }
+
+                                if ui.button(2, "Open example song")
+                                    .with_width(200.0)
+                                    .tooltip("A short tune to try things out on")
+                                    .clicked()
+                                {
+                                    *queued_action = Action::OpenExample;
+                                }
+                            });
+
+                            ui.pad(3, 20.0);
+                            ui.text(4, "You can also drop a song or MIDI file onto this window to open it.");
+                        });
+                    }
+
+                    if is_file_hovered {
+                        ui.modal("drop_hint", false, (300.0, 60.0), |ui| {
+                            ui.text(0, "Drop to open").center_x().center_y();
+                        });
                     }
                 });
             });
@@ -158,9 +625,15 @@ impl Interface {
             }
         }
 
+        self.stats.updates = updates;
+
+        log::trace!("undo history uses about {} KiB", self.state.memory_estimate() / 1024);
+
         if let Some(doc) = self.state.document.as_ref() {
+            let unsaved = if self.state.is_dirty() { "*" } else { "" };
+
             self.with_window(|w| {
-                w.set_title(&format!("{} - Mamar", doc.bgm.name));
+                w.set_title(&format!("{}{} - Mamar", unsaved, doc.bgm.name));
             });
         } else {
             self.with_window(|w| {
@@ -171,94 +644,419 @@ impl Interface {
 
     /// Keybindings!
     fn handle_key_press(&mut self, key: VirtualKeyCode, modifiers: ModifiersState) {
-        match key {
-            // Redo
-            VirtualKeyCode::Z if modifiers.ctrl() && modifiers.shift() => {
-                if self.state.redo() {
-                    self.update();
-                }
-            }
+        if let Some(action) = self.keymap.get(key, modifiers) {
+            self.queued_action = action;
+        } else if key == VirtualKeyCode::F3 {
+            self.is_stats_open = !self.is_stats_open;
+            self.update();
+        } else if key == VirtualKeyCode::F4 {
+            self.inspector = match self.inspector {
+                Some(_) => None,
+                None => Some(Inspector::default()),
+            };
+            self.update();
+        }
+    }
 
-            // Undo
-            VirtualKeyCode::Z if modifiers.ctrl() => {
-                if self.state.undo() {
+    /// Types the text on the system clipboard into the text input being edited.
+    fn paste_text(&mut self) {
+        match self.clipboard.get_text() {
+            Ok(text) => {
+                if self.glue.type_text(&text) {
                     self.update();
                 }
             }
+            Err(error) => log::error!("unable to paste: {}", error),
+        }
+    }
 
-            // Open
-            VirtualKeyCode::O if modifiers.ctrl() => self.queued_action = Action::OpenDocument,
-
-            // Save As
-            VirtualKeyCode::S if modifiers.ctrl() && modifiers.shift() => self.queued_action = Action::SaveDocumentAs,
-
-            // Save
-            VirtualKeyCode::S if modifiers.ctrl() => self.queued_action = Action::SaveDocument,
+    /// Makes the pressed keys the shortcut for `action`, or cancels rebinding if Escape was pressed.
+    fn rebind(&mut self, action: Action, key: VirtualKeyCode, modifiers: ModifiersState) {
+        if key == VirtualKeyCode::Escape {
+            self.rebinding = None;
+        } else if keymap::is_bindable(key) {
+            self.keymap.bind(action, (key, modifiers));
+            self.rebinding = None;
 
-            // Play
-            VirtualKeyCode::Space => if self.hot.has_connections() {
-                if let Some(doc) = &self.state.document {
-                    if let Err(error) = self.hot.play_bgm(&doc.bgm) {
-                        todo!("surface error: {}", error);
-                    }
-                }
-            },
-
-            _ => {}
+            if let Err(error) = self.keymap.save() {
+                log::error!("unable to save keymap: {}", error);
+            }
+        } else {
+            // Probably a modifier, which is held down before the rest of the shortcut.
+            return;
         }
+
+        self.update();
     }
 
     fn do_queued_action(&mut self) -> Result<bool, Box<dyn Error>> {
-        let action = self.queued_action.clone();
+        let action = self.queued_action;
         self.queued_action = Action::None;
 
         match action {
             Action::None => return Ok(false),
+            Action::Undo => return Ok(self.state.undo()),
+            Action::Redo => return Ok(self.state.redo()),
+            Action::Copy => {
+                // Text selected in a label is copied instead of any notes, since the user selected it more recently.
+                if let Some(text) = self.glue.selected_text() {
+                    let text = text.to_owned();
+                    self.clipboard.set_text(text)?;
+                    return Ok(false);
+                }
+
+                if let Some(clip) = self.state.document.as_mut().and_then(|doc| doc.copy()) {
+                    self.clipboard.set_commands(clip);
+                }
+                return Ok(false);
+            }
+            Action::Cut => {
+                match self.state.document.as_mut().and_then(|doc| doc.cut()) {
+                    Some(clip) => self.clipboard.set_commands(clip),
+                    None => return Ok(false),
+                }
+            }
+            Action::Paste => {
+                let pasted = match &mut self.state.document {
+                    Some(doc) if !self.clipboard.commands().is_empty() => doc.paste(self.clipboard.commands()),
+                    _ => false,
+                };
+
+                if !pasted {
+                    return Ok(false);
+                }
+            }
+            Action::Play => {
+                if self.hot.is_playing() {
+                    self.hot.stop()?;
+                } else if let Some(doc) = &self.state.document {
+                    self.hot.play_bgm(&doc.bgm)?;
+                }
+                return Ok(true); // The play button becomes a stop button, or the other way around
+            }
+            Action::Pause => {
+                if self.hot.is_paused() {
+                    self.hot.resume();
+                } else {
+                    self.hot.pause();
+                }
+                return Ok(true);
+            }
+            Action::ToggleMetronome => {
+                self.hot.set_metronome(!self.hot.metronome());
+                return Ok(true);
+            }
+            Action::PlayFromPlayhead => {
+                if let Some(doc) = &self.state.document {
+                    let (bgm, time) = doc.song_at_playhead();
+                    self.hot.play_bgm_from(&bgm, time)?;
+                }
+                return Ok(true);
+            }
             Action::NewDocument => {
+                self.new_tab();
                 self.state.document = Some(state::Document::new());
+                return Ok(self.commit_saved());
             }
             Action::OpenDocument => {
-                if let Some(doc) = state::Document::open_prompt()? {
-                    self.state.document = Some(doc);
+                if let Some(path) = state::Document::open_prompt() {
+                    return self.open_in_tab(path);
+                }
+            }
+            Action::OpenRecent(index) => {
+                if let Some(path) = self.recent_files.get(index) {
+                    return self.open_in_tab(path.clone());
+                }
+            }
+            Action::OpenExample => {
+                // Read before making the tab, so nothing changes if the example can't be opened.
+                let doc = state::Document::example()?;
+                self.new_tab();
+                self.state.document = Some(doc);
+                return Ok(self.commit_saved());
+            }
+            Action::OpenDropped => {
+                let mut changed = false;
+                for path in mem::take(&mut self.dropped_files) {
+                    changed |= self.open_in_tab(path)?;
+                }
+                return Ok(changed);
+            }
+            Action::PreviousSegment | Action::NextSegment => {
+                let doc = match &mut self.state.document {
+                    Some(doc) => doc,
+                    None => return Ok(false),
+                };
+
+                if !doc.step_segment(action == Action::NextSegment) {
+                    return Ok(false);
+                }
+
+                // Keep listening, now to the variation being shown.
+                if self.hot.is_playing() {
+                    let (bgm, time) = doc.song_at_playhead();
+                    self.hot.play_bgm_from(&bgm, time)?;
+                }
+                return Ok(true);
+            }
+            Action::SwitchTab(index) => {
+                self.switch_tab(index);
+                return Ok(true);
+            }
+            Action::CloseTab => {
+                if self.state.is_dirty() {
+                    self.is_close_prompt_open = true;
+                } else {
+                    self.close_tab();
                 }
+                return Ok(true);
             }
             Action::ReloadDocument => {
-                if let Some(doc) = self.state.document.as_ref() {
-                    match &doc.path {
-                        state::DocPath::Import(path) => {
-                            self.state.document = Some(state::Document::open_from_path(path.clone())?);
-                        }
-                        _ => {}
+                let path = match self.state.document.as_ref().map(|doc| &doc.path) {
+                    Some(state::DocPath::Import(path)) => path.clone(),
+                    _ => return Ok(false),
+                };
+
+                let warnings = self.state.open(path.clone())?;
+                show_warnings(&path, &warnings);
+                return Ok(self.commit_saved());
+            }
+            Action::Revert => {
+                if self.state.is_dirty() {
+                    let revert = tinyfiledialogs::message_box_yes_no(
+                        "Revert to saved?",
+                        "Discard all changes since this file was last saved? Revert can be undone.",
+                        tinyfiledialogs::MessageBoxIcon::Question,
+                        tinyfiledialogs::YesNo::No,
+                    );
+
+                    if let tinyfiledialogs::YesNo::No = revert {
+                        return Ok(false);
                     }
                 }
+
+                let reverted = match &mut self.state.document {
+                    Some(doc) => doc.revert()?,
+                    None => false,
+                };
+
+                // One history entry, so undoing brings the changes back.
+                if reverted {
+                    return Ok(self.commit_saved());
+                }
             }
             Action::SaveDocument => {
                 if let Some(doc) = &mut self.state.document {
-                    if doc.can_save() {
+                    let saved = if doc.can_save() {
                         doc.save()?;
+                        true
                     } else {
-                        doc.save_as()?;
+                        doc.save_as()?
+                    };
+
+                    if saved {
+                        return Ok(self.commit_saved());
                     }
                 }
             }
             Action::SaveDocumentAs => {
                 if let Some(doc) = &mut self.state.document {
-                    doc.save_as()?;
+                    if doc.save_as()? {
+                        return Ok(self.commit_saved());
+                    }
+                }
+            }
+            Action::ExportMidi => {
+                if let Some(doc) = &self.state.document {
+                    doc.export_midi()?;
+                }
+                return Ok(false);
+            }
+            Action::ExportRom => {
+                if let Some(doc) = &self.state.document {
+                    doc.export_rom()?;
+                }
+                return Ok(false);
+            }
+            Action::ImportVariation => {
+                let imported = match &mut self.state.document {
+                    Some(doc) => doc.import_segment_prompt()?,
+                    None => false,
+                };
+
+                if !imported {
+                    return Ok(false);
                 }
             }
+            Action::Optimize => {
+                let optimize = tinyfiledialogs::message_box_yes_no(
+                    "Optimize song?",
+                    "Remove commands that make no difference and merge identical track lists, so the song takes up \
+                     less space? It will sound the same, but won't match the file it was read from anymore.",
+                    tinyfiledialogs::MessageBoxIcon::Question,
+                    tinyfiledialogs::YesNo::No,
+                );
+
+                if let tinyfiledialogs::YesNo::No = optimize {
+                    return Ok(false);
+                }
+
+                let sizes = match &mut self.state.document {
+                    Some(doc) => doc.optimize(),
+                    None => return Ok(false),
+                };
+
+                let message = match sizes {
+                    (Some(before), Some(after)) => format!(
+                        "Saved {} bytes. The song is now {} bytes.",
+                        before.saturating_sub(after),
+                        after
+                    ),
+                    (None, Some(after)) => format!("The song is now {} bytes.", after),
+                    (_, None) => "The song was optimized, but still can't be encoded.".to_owned(),
+                };
+                tinyfiledialogs::message_box_ok("Optimized", &message, tinyfiledialogs::MessageBoxIcon::Info);
+            }
+            Action::ToggleTheme => {
+                self.glue.set_theme(settings::toggle_theme()?);
+                return Ok(true);
+            }
         }
 
         Ok(self.state.commit())
     }
 
+    /// Every tab's document and undo history, in tab order.
+    fn tabs(&self) -> impl Iterator<Item = &History<state::State>> {
+        let (before, after) = self.inactive_tabs.split_at(self.tab);
+        before.iter().chain(std::iter::once(&self.state)).chain(after)
+    }
+
+    /// Shows the document in the tab at `index`.
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.tab || index > self.inactive_tabs.len() {
+            return;
+        }
+
+        let shown = mem::replace(&mut self.state, History::new(Default::default()));
+        self.inactive_tabs.insert(self.tab, shown);
+        self.state = self.inactive_tabs.remove(index);
+        self.tab = index;
+    }
+
+    /// Shows a new, empty tab after the one being shown, unless that one is empty already.
+    fn new_tab(&mut self) {
+        if self.state.document.is_none() {
+            return;
+        }
+
+        let shown = mem::replace(&mut self.state, History::new(Default::default()));
+        self.inactive_tabs.insert(self.tab, shown);
+        self.tab += 1;
+    }
+
+    /// Closes the tab being shown, discarding any unsaved changes, and shows the one after it (or before, if it was
+    /// the last).
+    fn close_tab(&mut self) {
+        if self.inactive_tabs.is_empty() {
+            self.state = History::new(Default::default());
+            return;
+        }
+
+        self.tab = self.tab.min(self.inactive_tabs.len() - 1);
+        self.state = self.inactive_tabs.remove(self.tab);
+    }
+
+    /// Opens the file at `path` in a new tab, or shows it if it's open already.
+    fn open_in_tab(&mut self, path: PathBuf) -> Result<bool, Box<dyn Error>> {
+        let is_open = |tab: &History<state::State>| match tab.document.as_ref().map(|doc| &doc.path) {
+            Some(state::DocPath::Native(open)) | Some(state::DocPath::Import(open)) => *open == path,
+            _ => false,
+        };
+
+        let index = self.tabs().position(is_open);
+        if let Some(index) = index {
+            self.switch_tab(index);
+            return Ok(true);
+        }
+
+        // MIDI files lose whatever the game can't play, so say what will be lost before importing one.
+        if let Some(report) = crate::formats::midi_import_report(&path) {
+            let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+            let import = tinyfiledialogs::message_box_yes_no(
+                "Import MIDI?",
+                &format!("Importing {} will give:\n\n{}\n\nImport it?", name, report),
+                tinyfiledialogs::MessageBoxIcon::Question,
+                tinyfiledialogs::YesNo::Yes,
+            );
+
+            if let tinyfiledialogs::YesNo::No = import {
+                return Ok(false);
+            }
+        }
+
+        // Read before making the tab, so nothing changes if the file can't be opened.
+        let (doc, warnings) = state::Document::open_from_path(path.clone())?;
+        show_warnings(&path, &warnings);
+        self.new_tab();
+        self.state.document = Some(doc);
+        Ok(self.commit_saved())
+    }
+
+    /// Shows the first tab with unsaved changes and asks whether to save them. Returns false if there are none, so the
+    /// window can close.
+    fn prompt_unsaved(&mut self) -> bool {
+        let index = self.tabs().position(|tab| tab.is_dirty());
+        match index {
+            Some(index) => {
+                self.switch_tab(index);
+                self.is_close_prompt_open = true;
+                self.update();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Commits the state and records it as matching what's on disk, which also makes it the most recent file. Always
+    /// returns true, as the window title changes.
+    fn commit_saved(&mut self) -> bool {
+        self.state.commit();
+        self.state.mark_saved();
+
+        if let Some(doc) = &self.state.document {
+            if let state::DocPath::Native(path) | state::DocPath::Import(path) = &doc.path {
+                self.recent_files.add(path.clone());
+
+                if let Err(error) = self.recent_files.save() {
+                    log::error!("unable to save recent files: {}", error);
+                }
+            }
+        }
+
+        true
+    }
+
     fn draw(&mut self) {
+        let start = Instant::now();
+
         let mut surface = self.display.draw();
-        surface.clear_color_srgb_and_depth((21.0 / 255.0, 26.0 / 255.0, 30.0 / 255.0, 1.0), -1000.0);
+        let [r, g, b, a] = self.glue.theme().background;
+        surface.clear_color_srgb_and_depth((r, g, b, a), -1000.0);
         self.glue.draw(&mut surface, &self.display).unwrap();
         surface.finish().unwrap();
+
+        self.stats.frame_time = start.elapsed();
     }
 
     pub fn show(mut self, event_loop: EventLoop<()>) -> ! {
+        for bgm in recovery::prompt_restore() {
+            // Left unsaved, so it's clear the changes still need saving somewhere.
+            self.new_tab();
+            self.state.document = Some(state::Document::recovered(bgm));
+            self.state.commit();
+        }
+
         self.update();
         self.draw();
         self.with_window(|w| w.set_visible(true));
@@ -284,15 +1082,66 @@ impl Interface {
                         WindowEvent::KeyboardInput { input, .. } => {
                             if input.state == ElementState::Pressed {
                                 if let Some(key) = input.virtual_keycode {
-                                    self.handle_key_press(key, kbd_modifiers);
+                                    if let Some(action) = self.rebinding {
+                                        self.rebind(action, key, kbd_modifiers);
+                                    } else if self.glue.editing_text().is_some() {
+                                        // Paste text from other programs into the text input.
+                                        if self.keymap.get(key, kbd_modifiers) == Some(Action::Paste) {
+                                            self.paste_text();
+                                        }
+                                    } else if !self.glue.has_focus() {
+                                        // Keys typed into a text input aren't shortcuts.
+                                        self.handle_key_press(key, kbd_modifiers);
+                                    }
                                 }
                             }
                         },
                         WindowEvent::ModifiersChanged(m) => kbd_modifiers = m,
-                        WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                        WindowEvent::HoveredFile(_) => {
+                            self.is_file_hovered = true;
+                            self.update();
+                        }
+                        WindowEvent::HoveredFileCancelled => {
+                            self.is_file_hovered = false;
+                            self.update();
+                        }
+                        WindowEvent::DroppedFile(path) => {
+                            // Opened like any other file, so what's in it matters more than its extension.
+                            self.dropped_files.push(path);
+                            self.queued_action = Action::OpenDropped;
+                            self.is_file_hovered = false;
+                        }
+                        WindowEvent::Focused(true) if cfg!(debug_assertions) => {
+                            // Pick up changes to the textures made in an image editor, so they can be tried out
+                            // without restarting.
+                            match self.glue.reload_atlas() {
+                                Ok(true) => log::info!("reloaded textures"),
+                                Ok(false) => {}
+                                Err(error) => log::error!("unable to reload textures: {}", error),
+                            }
+                        }
+                        WindowEvent::CloseRequested => {
+                            self.is_closing_window = true;
+
+                            if self.prompt_unsaved() {
+                                redraw = true;
+                            } else {
+                                *control_flow = ControlFlow::Exit;
+                            }
+                        }
                         _ => {}
                     }
                 }
+                Event::LoopDestroyed => {
+                    // Unsaved changes are only left when the user chose not to save them.
+                    self.recovery.discard();
+
+                    if let Some(geometry) = WindowGeometry::of(self.display.gl_window().window()) {
+                        if let Err(error) = geometry.save() {
+                            log::error!("unable to save window position: {}", error);
+                        }
+                    }
+                }
                 Event::RedrawRequested(_window_id) => {
                     redraw = true;
                 }
@@ -303,6 +1152,29 @@ impl Interface {
                 self.draw();
             }
 
+            let (before, after) = self.inactive_tabs.split_at(self.tab);
+            let unsaved = before
+                .iter()
+                .chain(std::iter::once(&self.state))
+                .chain(after)
+                .filter(|tab| tab.is_dirty())
+                .filter_map(|tab| tab.document.as_ref().map(|doc| &doc.bgm));
+            self.recovery.update(unsaved.collect());
+
+            // Wake up when a tooltip is due to appear, held-back changes are due to be sent to the emulator, or unsaved
+            // changes are due to be saved for recovery, even if there's no input before then.
+            if *control_flow == ControlFlow::Wait {
+                let deadline = [self.glue.redraw_deadline(), self.hot.deadline(), self.recovery.deadline()]
+                    .iter()
+                    .flatten()
+                    .min()
+                    .copied();
+
+                if let Some(deadline) = deadline {
+                    *control_flow = ControlFlow::WaitUntil(deadline);
+                }
+            }
+
             match self.do_queued_action() {
                 Ok(true) => {
                     self.update();
@@ -310,10 +1182,34 @@ impl Interface {
                 }
                 Ok(false) => {}
                 Err(error) => {
-                    // TODO: surface error in the UI somepalce
                     log::error!("error: {}", error);
+
+                    tinyfiledialogs::message_box_ok(
+                        "Error",
+                        &format!("{}", error),
+                        tinyfiledialogs::MessageBoxIcon::Error,
+                    );
+
+                    // The document is left as it was, so show it again and let the user carry on.
+                    self.update();
+                    self.draw();
+                }
+            }
+
+            // If saving was cancelled or failed, stay open so the changes aren't lost.
+            if self.close_now || (self.close_after_save && !self.state.is_dirty()) {
+                self.close_now = false;
+                self.is_close_prompt_open = false;
+                self.close_tab();
+
+                if self.is_closing_window && !self.prompt_unsaved() {
+                    *control_flow = ControlFlow::Exit;
+                } else {
+                    self.update();
+                    self.draw();
                 }
             }
+            self.close_after_save = false;
         })
     }
 }
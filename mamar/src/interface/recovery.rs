@@ -0,0 +1,156 @@
+//! Saving a copy of unsaved changes every so often, so that they can be restored if Mamar crashes or is killed.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use pm64::bgm::Bgm;
+
+use super::settings;
+
+const RECOVERY_FILE: &str = "recovery.ron";
+
+/// How long unsaved changes can go without being saved for recovery.
+const INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct Recovery {
+    /// When unsaved changes are next due to be saved for recovery. `None` if there are none.
+    due: Option<Instant>,
+
+    /// Writing the recovery file happens on another thread, so a big song doesn't make the UI stutter.
+    writing: Option<JoinHandle<()>>,
+
+    /// Whether there may be a recovery file that no longer matches the document.
+    has_file: bool,
+}
+
+impl Recovery {
+    /// Call whenever the open documents may have changed, with the songs that have unsaved changes. A copy of them is
+    /// saved every so often; once they are saved properly (or undone), the copy is deleted.
+    pub fn update(&mut self, unsaved: Vec<&Bgm>) {
+        if unsaved.is_empty() {
+            self.due = None;
+            if self.has_file {
+                self.discard();
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        match self.due {
+            None => self.due = Some(now + INTERVAL),
+            Some(due) if due <= now => {
+                self.wait();
+
+                let songs: Vec<Bgm> = unsaved.into_iter().cloned().collect();
+                self.writing = Some(thread::spawn(move || {
+                    let written = match settings::path(RECOVERY_FILE) {
+                        Some(path) => write(&path, &songs),
+                        None => Err("unable to find a directory to save settings in".into()),
+                    };
+
+                    if let Err(error) = written {
+                        log::error!("unable to save for recovery: {}", error);
+                    }
+                }));
+
+                self.has_file = true;
+                self.due = Some(now + INTERVAL);
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// When [update](Recovery::update) should next be called, even if nothing else happens before then.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// Deletes the recovery file, e.g. because the user chose not to save their changes.
+    pub fn discard(&mut self) {
+        self.wait();
+        self.has_file = false;
+
+        if let Some(path) = settings::path(RECOVERY_FILE) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn wait(&mut self) {
+        if let Some(writing) = self.writing.take() {
+            let _ = writing.join();
+        }
+    }
+}
+
+/// If the last session ended with changes that weren't saved, asks whether to restore them, returning the songs if so.
+/// Must be run on the main thread.
+pub fn prompt_restore() -> Vec<Bgm> {
+    let path = match settings::path(RECOVERY_FILE) {
+        Some(path) if path.exists() => path,
+        _ => return Vec::new(),
+    };
+
+    let songs = match read(&path) {
+        Ok(songs) => songs,
+        Err(error) => {
+            log::error!("unable to read recovery file: {}", error);
+            return Vec::new();
+        }
+    };
+
+    let names: Vec<&str> = songs.iter().map(|bgm| bgm.name.as_str()).collect();
+    let restore = tinyfiledialogs::message_box_yes_no(
+        "Restore unsaved changes?",
+        &format!("Mamar closed without saving changes to {}. Would you like to restore them?", names.join(", ")),
+        tinyfiledialogs::MessageBoxIcon::Question,
+        tinyfiledialogs::YesNo::Yes,
+    );
+
+    match restore {
+        tinyfiledialogs::YesNo::Yes => songs,
+        tinyfiledialogs::YesNo::No => {
+            let _ = fs::remove_file(path);
+            Vec::new()
+        }
+    }
+}
+
+/// Writes to a temporary file first, so that a crash part-way through doesn't lose the previous copy.
+fn write(path: &Path, songs: &[Bgm]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let temp = path.with_extension("ron.tmp");
+    ron::ser::to_writer(File::create(&temp)?, &songs)?;
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Vec<Bgm>, Box<dyn Error>> {
+    Ok(ron::de::from_reader(File::open(path)?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn write_and_read() {
+        let dir = TempDir::new("recovery_write_and_read");
+        let path = dir.join(RECOVERY_FILE);
+
+        let mut bgm = Bgm::new();
+        bgm.name = "Unsaved".to_owned();
+
+        let songs = vec![bgm, Bgm::new()];
+        write(&path, &songs).unwrap();
+        assert_eq!(read(&path).unwrap(), songs);
+        assert!(!path.with_extension("ron.tmp").exists());
+    }
+}
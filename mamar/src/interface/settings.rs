@@ -0,0 +1,290 @@
+//! Settings that are remembered between sessions. Each kind is kept in its own text file in the user's config directory.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use imui_glium::glium::glutin::dpi::{PhysicalPosition, PhysicalSize};
+use imui_glium::glium::glutin::window::Window;
+use imui_glium::Theme;
+
+const WINDOW_FILE: &str = "window.txt";
+const RECENT_FILES_FILE: &str = "recent.txt";
+const THEME_FILE: &str = "theme.txt";
+const ROM_FILE: &str = "rom.txt";
+
+/// How many files [RecentFiles] remembers.
+const MAX_RECENT_FILES: usize = 10;
+
+/// Where the settings file called `file_name` is saved, following each platform's convention for settings files.
+pub fn path(file_name: &str) -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    dir.map(|dir| dir.join("mamar").join(file_name))
+}
+
+/// Writes a settings file, creating the config directory if need be.
+pub fn write(file_name: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let path = path(file_name).ok_or("unable to find a directory to save settings in")?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// The colours to draw the UI with, from a file in the format [Theme::parse] reads. The dark theme is used if there is
+/// no such file or it can't be read.
+pub fn load_theme() -> Theme {
+    let path = match path(THEME_FILE) {
+        Some(path) if path.exists() => path,
+        _ => return Theme::default(),
+    };
+
+    Theme::load(&path).unwrap_or_else(|error| {
+        log::error!("unable to load theme from {}: {}", path.display(), error);
+        Theme::default()
+    })
+}
+
+/// Switches the theme file between the light and dark base themes, keeping any colours the user has changed, and
+/// returns the new theme.
+pub fn toggle_theme() -> Result<Theme, Box<dyn Error>> {
+    let text = path(THEME_FILE).and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+    let text = toggle_theme_base(&text);
+    let theme = Theme::parse(&text)?;
+
+    write(THEME_FILE, &text)?;
+    Ok(theme)
+}
+
+/// Replaces the `base` line of a theme file with the other base theme.
+fn toggle_theme_base(text: &str) -> String {
+    let is_base = |line: &&str| line.split('=').next().map(str::trim) == Some("base");
+    let is_light = text.lines().filter(is_base).any(|line| line.split('=').nth(1).map(str::trim) == Some("light"));
+
+    let mut toggled = format!("base = {}\n", if is_light { "dark" } else { "light" });
+    for line in text.lines().filter(|line| !is_base(line)) {
+        toggled.push_str(line);
+        toggled.push('\n');
+    }
+    toggled
+}
+
+/// The ROM that instrument names are read from, if one has been chosen.
+pub fn rom_path() -> Option<PathBuf> {
+    let text = fs::read_to_string(path(ROM_FILE)?).ok()?;
+    let line = text.lines().next()?.trim();
+
+    if line.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(line))
+    }
+}
+
+pub fn set_rom_path(rom: &Path) -> Result<(), Box<dyn Error>> {
+    write(ROM_FILE, &format!("{}\n", rom.to_string_lossy()))
+}
+
+/// Files that were recently opened or saved, most recent first.
+#[derive(Default)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let paths = path(RECENT_FILES_FILE)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default();
+
+        Self { paths }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let mut text = String::new();
+        for path in &self.paths {
+            text.push_str(&path.to_string_lossy());
+            text.push('\n');
+        }
+
+        write(RECENT_FILES_FILE, &text)
+    }
+
+    /// Moves `path` to the top of the list, forgetting the least recent file if the list is full.
+    pub fn add(&mut self, path: PathBuf) {
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&PathBuf> {
+        self.paths.get(index)
+    }
+
+    /// The files that still exist, with their index for [RecentFiles::get].
+    pub fn existing(&self) -> impl Iterator<Item = (usize, &PathBuf)> {
+        self.paths.iter().enumerate().filter(|(_, path)| path.exists())
+    }
+}
+
+/// Where the main window was and how big it was, in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowGeometry {
+    pub position: PhysicalPosition<i32>,
+    pub size: PhysicalSize<u32>,
+}
+
+impl WindowGeometry {
+    pub fn of(window: &Window) -> Option<Self> {
+        let size = window.inner_size();
+
+        // Minimised windows have no size, and restoring that would be useless.
+        if size.width == 0 || size.height == 0 {
+            return None;
+        }
+
+        Some(Self {
+            position: window.outer_position().ok()?,
+            size,
+        })
+    }
+
+    pub fn load() -> Option<Self> {
+        let text = fs::read_to_string(path(WINDOW_FILE)?).ok()?;
+
+        let values: HashMap<&str, i64> = text
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, '=').map(str::trim);
+                Some((parts.next()?, parts.next()?.parse().ok()?))
+            })
+            .collect();
+
+        let width = *values.get("width")?;
+        let height = *values.get("height")?;
+
+        if width <= 0 || height <= 0 {
+            return None;
+        }
+
+        Some(Self {
+            position: PhysicalPosition::new(*values.get("x")? as i32, *values.get("y")? as i32),
+            size: PhysicalSize::new(width as u32, height as u32),
+        })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        write(WINDOW_FILE, &format!(
+            "x = {}\ny = {}\nwidth = {}\nheight = {}\n",
+            self.position.x, self.position.y, self.size.width, self.size.height,
+        ))
+    }
+
+    /// Moves and shrinks the window to fit on the monitor its title bar is on, given the position and size of each
+    /// monitor. Returns `None` if the title bar isn't on any of them, e.g. because that monitor has been unplugged
+    /// since, in which case the window should open wherever it would by default so that it can be reached.
+    pub fn fit_to_monitors<I>(&self, monitors: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = (PhysicalPosition<i32>, PhysicalSize<u32>)>,
+    {
+        let title_bar = (self.position.x + self.size.width as i32 / 2, self.position.y + 16);
+
+        let (monitor_position, monitor_size) = monitors.into_iter().find(|(position, size)| {
+            (position.x..position.x + size.width as i32).contains(&title_bar.0)
+                && (position.y..position.y + size.height as i32).contains(&title_bar.1)
+        })?;
+
+        let size = PhysicalSize::new(
+            self.size.width.min(monitor_size.width),
+            self.size.height.min(monitor_size.height),
+        );
+
+        let max_x = monitor_position.x + (monitor_size.width - size.width) as i32;
+        let max_y = monitor_position.y + (monitor_size.height - size.height) as i32;
+
+        Some(Self {
+            position: PhysicalPosition::new(
+                self.position.x.min(max_x).max(monitor_position.x),
+                self.position.y.min(max_y).max(monitor_position.y),
+            ),
+            size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn geometry(x: i32, y: i32, width: u32, height: u32) -> WindowGeometry {
+        WindowGeometry {
+            position: PhysicalPosition::new(x, y),
+            size: PhysicalSize::new(width, height),
+        }
+    }
+
+    #[test]
+    fn recent_files() {
+        let mut recent = RecentFiles::default();
+
+        for i in 0..MAX_RECENT_FILES + 2 {
+            recent.add(PathBuf::from(format!("{}.bgm", i)));
+        }
+        assert_eq!(recent.paths.len(), MAX_RECENT_FILES);
+        assert_eq!(recent.get(0), Some(&PathBuf::from(format!("{}.bgm", MAX_RECENT_FILES + 1))));
+
+        // Opening a file again moves it to the top rather than listing it twice
+        recent.add(PathBuf::from("5.bgm"));
+        assert_eq!(recent.paths.len(), MAX_RECENT_FILES);
+        assert_eq!(recent.get(0), Some(&PathBuf::from("5.bgm")));
+        assert_eq!(recent.paths.iter().filter(|path| path.as_os_str() == "5.bgm").count(), 1);
+
+        // None of these exist
+        assert_eq!(recent.existing().count(), 0);
+    }
+
+    #[test]
+    fn toggle_theme_keeps_colours() {
+        let light = toggle_theme_base("");
+        assert_eq!(Theme::parse(&light).unwrap(), Theme::light());
+
+        let custom = toggle_theme_base(&format!("{}text = #FF0000\n", light));
+        assert_eq!(custom, "base = dark\ntext = #FF0000\n");
+        assert_eq!(Theme::parse(&custom).unwrap().text, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(Theme::parse(&toggle_theme_base(&custom)).unwrap().background, Theme::light().background);
+    }
+
+    #[test]
+    fn fit_to_monitors() {
+        let monitors = vec![
+            (PhysicalPosition::new(0, 0), PhysicalSize::new(1920, 1080)),
+            (PhysicalPosition::new(1920, 0), PhysicalSize::new(1280, 720)),
+        ];
+
+        // Already fits
+        let on_second = geometry(2000, 100, 800, 600);
+        assert_eq!(on_second.fit_to_monitors(monitors.clone()), Some(on_second));
+
+        // Hanging off the bottom right of the second monitor, and too tall for it
+        assert_eq!(
+            geometry(2700, 300, 800, 900).fit_to_monitors(monitors.clone()),
+            Some(geometry(2400, 0, 800, 720)),
+        );
+
+        // Saved while a third monitor was plugged in
+        assert_eq!(geometry(-1000, 0, 800, 600).fit_to_monitors(monitors), None);
+    }
+}
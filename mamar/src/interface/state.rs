@@ -1,15 +1,31 @@
+mod loop_editor;
+mod piano_roll;
 mod track_list;
+mod voices;
 
-use std::{error::Error, io::Read};
-use std::path::PathBuf;
-use std::fs::File;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::rc::Rc;
 
 use pm64::bgm::*;
+use pm64::bgm::diff::{self, diff_segments, DiffOp, Location};
 
+use loop_editor::LoopEditor;
+use piano_roll::PianoRoll;
 use track_list::TrackListInterface;
-use crate::interface::form::range_select;
 
+use super::form::labelled_slider;
 use super::hot::Hot;
+use crate::formats;
+use crate::history::MemoryUsage;
+
+/// A short tune written for Mamar, rather than taken from the game, relative to the working directory like the other
+/// assets.
+const EXAMPLE_SONG: &str = "assets/example.ron";
 
 #[derive(Default, PartialEq, Clone)]
 pub struct State {
@@ -21,15 +37,26 @@ pub struct Document {
     pub bgm: Bgm,
     pub path: DocPath,
 
+    /// The BGM file the song was read from, if it was read from one. See [Document::original].
+    original: Option<Rc<OriginalFile>>,
+
     ui_state: UiState,
 }
 
+/// A BGM file as it was read, for inspecting. It never changes, so undo states share it.
+pub struct OriginalFile {
+    pub bytes: Vec<u8>,
+
+    /// Each part of the file, with the range of bytes it takes up. See [diff::layout].
+    pub layout: Vec<(Location, Range<FilePos>)>,
+}
+
 #[derive(Clone)]
 pub enum DocPath {
     /// This document has no path associated with it at all, i.e. it was created with 'New File'.
     New,
 
-    /// Natively-supported file types, i.e. bgm and ron.
+    /// Natively-supported file types, i.e. bgm, ron and json.
     Native(PathBuf),
 
     /// Import-only file types, i.e. midi.
@@ -44,6 +71,9 @@ enum UiState {
     /// Top-level view of a whole segment ("variation") and its subsegments ("sections").
     Segment {
         segment_idx: usize,
+        loop_editor: LoopEditor,
+        renaming: Option<Renaming<TrackListId>>,
+        deleting: Option<Deletion>,
     },
 
     Subsegment {
@@ -52,11 +82,28 @@ enum UiState {
         track_list_interface: TrackListInterface,
     },
 
+    /// The notes of one track in a subsegment.
+    PianoRoll {
+        segment_idx: usize,
+        subseg_idx: usize,
+        track_idx: usize,
+        piano_roll: PianoRoll,
+    },
+
+    /// Two segments side by side, showing only the commands that differ.
+    Diff {
+        segment_idx: usize,
+        other_idx: usize,
+        diff: Vec<Vec<DiffOp>>,
+    },
+
     OldOverview {
         selected_segment_idx: u8,
         track_list_interface: TrackListInterface,
         viewing_seg_list: bool,
         selected_track_subseg_idx: u8,
+        renaming: Option<Renaming<usize>>,
+        deleting: Option<Deletion>,
     },
 }
 
@@ -64,6 +111,9 @@ impl Default for UiState {
     fn default() -> Self {
         UiState::Segment {
             segment_idx: 0,
+            loop_editor: LoopEditor::new(),
+            renaming: None,
+            deleting: None,
         }
     }
 }
@@ -75,104 +125,224 @@ impl PartialEq for Document {
     }
 }
 
+impl MemoryUsage for State {
+    fn memory_usage(&self) -> usize {
+        let bgm = match &self.document {
+            Some(doc) => &doc.bgm,
+            None => return mem::size_of::<Self>(),
+        };
+
+        // Only the parts that grow with the song are counted.
+        let tracks: usize = bgm.track_lists
+            .values()
+            .flat_map(|track_list| track_list.tracks.iter())
+            .map(|track| track.name.len() + track.commands.len() * mem::size_of::<Command>())
+            .sum();
+
+        mem::size_of::<Self>()
+            + mem::size_of::<Document>()
+            + bgm.track_lists.len() * mem::size_of::<(TrackListId, TrackList)>()
+            + tracks
+            + bgm.voices.len() * mem::size_of::<Voice>()
+            + bgm.drums.len() * mem::size_of::<Drum>()
+    }
+}
+
+impl State {
+    /// Replaces the open document with the file at `path`. If the file can't be opened, the current document is kept
+    /// as it was, so the user can carry on or try another file. See [Document::open_from_path] for what is returned.
+    pub fn open(&mut self, path: PathBuf) -> Result<Vec<de::Warning>, Box<dyn Error>> {
+        let (doc, warnings) = Document::open_from_path(path)?;
+        self.document = Some(doc);
+        Ok(warnings)
+    }
+}
+
 impl Document {
     pub fn new() -> Self {
         Document {
             bgm: Bgm::new(),
             path: DocPath::New,
+            original: None,
             ui_state: UiState::default(),
         }
     }
 
-    /// Prompt an 'Open File' dialog to open a document. Must be run on the main thread.
-    pub fn open_prompt() -> Result<Option<Self>, Box<dyn Error>> {
-        let path = tinyfiledialogs::open_file_dialog("Open File", "", Some((&[
-            "*.bgm",
-            "*.ron",
-            "*.mid",
-            "*.midi",
-            "*.bin",
-        ], "")));
-
-        if let Some(path) = path {
-            let path = PathBuf::from(path);
-            Ok(Some(Self::open_from_path(path)?))
-        } else {
-            Ok(None)
+    /// A document for a song restored after Mamar closed without saving it. It has no path, like a new one.
+    pub fn recovered(bgm: Bgm) -> Self {
+        Document {
+            bgm,
+            path: DocPath::New,
+            original: None,
+            ui_state: UiState::default(),
         }
     }
 
-    pub fn open_from_path(path: PathBuf) -> Result<Self, Box<dyn Error>> {
-        let mut file = File::open(&path)?;
+    /// The song that comes with Mamar for trying it out. Like a new document it has no path, so saving it asks where
+    /// to, rather than changing the example.
+    pub fn example() -> Result<Self, Box<dyn Error>> {
+        let (bgm, _, _) = formats::read(Path::new(EXAMPLE_SONG))?;
 
-        if path.extension().unwrap_or_default() == "ron" {
-            Ok(Document {
-                bgm: ron::de::from_reader(file)?,
-                path: DocPath::Native(path),
-                ui_state: UiState::default(),
-            })
-        } else {
-            // Import from BGM/MIDI
+        Ok(Document {
+            bgm,
+            path: DocPath::New,
+            original: None,
+            ui_state: UiState::default(),
+        })
+    }
 
-            let mut bgm;
-            let doc_path;
+    /// Prompt an 'Open File' dialog to choose a document to open. Must be run on the main thread.
+    pub fn open_prompt() -> Option<PathBuf> {
+        let filter = formats::open_filter();
+        let patterns: Vec<&str> = filter.iter().map(String::as_str).collect();
 
-            if pm64::bgm::midi::is_midi(&mut file)? {
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf)?;
-                bgm = pm64::bgm::midi::to_bgm(&buf)?;
+        tinyfiledialogs::open_file_dialog("Open File", "", Some((&patterns, ""))).map(PathBuf::from)
+    }
 
-                if let Some(name) = path.file_stem().map(|s| s.to_str()).flatten() {
-                    bgm.name = name.to_owned();
-                }
+    /// Reads the song at `path`. Anything unexpected in the file that didn't stop it being read is returned too, for
+    /// telling the user about.
+    pub fn open_from_path(path: PathBuf) -> Result<(Self, Vec<de::Warning>), Box<dyn Error>> {
+        let (mut bgm, format, warnings) = formats::read(&path)?;
 
-                doc_path = DocPath::Import(path);
-            } else {
-                bgm = Bgm::decode(&mut file)?;
-                doc_path = DocPath::Native(path);
+        if *format.inner() == SourceFormat::Midi {
+            if let Some(name) = path.file_stem().map(|s| s.to_str()).flatten() {
+                bgm.name = name.to_owned();
             }
-
-            Ok(Document {
-                bgm,
-                path: doc_path,
-                ui_state: UiState::default(),
-            })
         }
+
+        let original = match format {
+            SourceFormat::Bgm => {
+                let bytes = fs::read(&path)?;
+                let layout = diff::layout(&bytes);
+                Some(Rc::new(OriginalFile { bytes, layout }))
+            }
+            _ => None,
+        };
+
+        // Saving writes the format the extension asks for, so only overwrite files that are already in it.
+        let doc_path = if Some(&format) == formats::save_format(&path).as_ref() {
+            DocPath::Native(path)
+        } else {
+            DocPath::Import(path)
+        };
+
+        let doc = Document {
+            bgm,
+            path: doc_path,
+            original,
+            ui_state: UiState::default(),
+        };
+        Ok((doc, warnings))
+    }
+
+    /// Reads the song from its file again, discarding any changes since it was last saved. The view is kept where it
+    /// can be. Returns `false` if the document isn't of a file Mamar can save, so has no saved version to go back to.
+    pub fn revert(&mut self) -> Result<bool, Box<dyn Error>> {
+        let path = match &self.path {
+            DocPath::Native(path) => path.clone(),
+            _ => return Ok(false),
+        };
+
+        let (saved, _) = Document::open_from_path(path)?;
+        self.bgm = saved.bgm;
+        self.original = saved.original;
+        self.clamp_ui_state();
+        Ok(true)
+    }
+
+    /// [Optimizes](Bgm::optimize) the song. Returns how many bytes it was encoded as before and after, where it can be
+    /// encoded at all.
+    pub fn optimize(&mut self) -> (Option<usize>, Option<usize>) {
+        let size = |bgm: &Bgm| bgm.as_bytes().ok().map(|bytes| bytes.len());
+
+        let before = size(&self.bgm);
+        self.bgm.optimize();
+        (before, size(&self.bgm))
+    }
+
+    /// The BGM file the song was read from, for matching what's shown up with the game's data. `None` if the song
+    /// wasn't read from a BGM file.
+    pub fn original(&self) -> Option<&OriginalFile> {
+        self.original.as_deref()
+    }
+
+    /// Shows the view for editing a part of the song as it was laid out in [Document::original]. Returns `false` if
+    /// there is no such view, e.g. for the header, or if the part has since been deleted.
+    pub fn show(&mut self, location: Location) -> bool {
+        let has_segment = |segment: usize| matches!(self.bgm.segments.get(segment), Some(Some(_)));
+        let has_track_list = |segment, subsegment| track_list_at(&self.bgm, segment, subsegment).is_some();
+
+        self.ui_state = match location {
+            Location::Segment { segment } if has_segment(segment) => UiState::Segment {
+                segment_idx: segment,
+                loop_editor: LoopEditor::new(),
+                renaming: None,
+                deleting: None,
+            },
+            Location::TrackList { segment, subsegment } if has_track_list(segment, subsegment) => UiState::Subsegment {
+                segment_idx: segment,
+                subseg_idx: subsegment,
+                track_list_interface: TrackListInterface::new(),
+            },
+            Location::Track { segment, subsegment, track } if has_track_list(segment, subsegment) => {
+                UiState::PianoRoll {
+                    segment_idx: segment,
+                    subseg_idx: subsegment,
+                    track_idx: track,
+                    piano_roll: PianoRoll::new(),
+                }
+            }
+            _ => return false,
+        };
+        true
     }
 
     pub fn can_save(&self) -> bool {
-        matches!(self.path, DocPath::Native(_))
+        match &self.path {
+            DocPath::Native(path) => formats::save_format(path).is_some(),
+            _ => false,
+        }
     }
 
     pub fn save(&self) -> Result<(), Box<dyn Error>> {
         if let DocPath::Native(path) = &self.path {
-            let mut file = File::create(&path)?;
-
-            if path.extension().unwrap_or_default() == "ron" {
-                ron::ser::to_writer_pretty(
-                    &mut file,
-                    &self.bgm,
-                    ron::ser::PrettyConfig::new()
-                        .with_indentor("  ".to_string())
-                        .with_depth_limit(5),
-                )?;
-            } else {
-                self.bgm.encode(&mut file)?;
+            let format = formats::save_format(path).ok_or("Mamar can't save this kind of file")?;
+
+            // RON and JSON files may hold a work-in-progress, but the game has to be able to play a BGM file.
+            if format == SourceFormat::Bgm {
+                let problems = self.bgm.validate();
+
+                if !problems.is_empty() {
+                    let list = problems
+                        .iter()
+                        .map(|problem| format!("- {}", problem))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    // Whoever asked to save shows this.
+                    let message = format!("This song has problems that must be fixed before it can be saved:\n\n{}", list);
+                    return Err(message.into());
+                }
             }
 
-            Ok(())
+            formats::write(path, &self.bgm, &format)
         } else {
             // TODO: Err
             Ok(())
         }
     }
 
-    /// Shows as 'Save As' dialog prompt then saves the document to a file. Must be run on the main thread.
-    pub fn save_as(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Shows as 'Save As' dialog prompt then saves the document to a file. Must be run on the main thread. Returns
+    /// false if the user cancelled.
+    pub fn save_as(&mut self) -> Result<bool, Box<dyn Error>> {
+        let default_extension = formats::FORMATS[0].extensions[0];
+        let filter = formats::save_filter();
+        let patterns: Vec<&str> = filter.iter().map(String::as_str).collect();
+
         let path = tinyfiledialogs::save_file_dialog_with_filter(
             "Save As",
-            &format!("{}.bgm", self.bgm.name),
-            &["*.bgm", "*.ron"],
+            &format!("{}.{}", self.bgm.name, default_extension),
+            &patterns,
             "",
         );
 
@@ -180,25 +350,355 @@ impl Document {
             let mut path = PathBuf::from(path);
 
             if path.extension().is_none() {
-                path.set_extension("bgm");
+                path.set_extension(default_extension);
+            }
+
+            if formats::save_format(&path).is_none() {
+                return Err(format!("Mamar can't save {}; try Export MIDI instead", path.display()).into());
             }
 
-            let old_path = self.path.clone();
-            self.path = DocPath::Native(path);
+            let old_path = mem::replace(&mut self.path, DocPath::Native(path));
 
-            if self.can_save() {
-                self.save()
-            } else {
-                // TODO: error
+            if let Err(error) = self.save() {
                 self.path = old_path;
-                Ok(())
+                return Err(error);
             }
+
+            Ok(true)
         } else {
-            Ok(())
+            Ok(false)
         }
     }
 
+    /// The variation being viewed, or the first one if none is.
+    fn current_segment_idx(&self) -> usize {
+        match &self.ui_state {
+            UiState::Segment { segment_idx, .. }
+            | UiState::Subsegment { segment_idx, .. }
+            | UiState::PianoRoll { segment_idx, .. } => *segment_idx,
+            _ => 0,
+        }
+    }
+
+    /// Shows the next variation, or the previous one if `forward` is false, skipping any without data. Stops at the
+    /// first and last variations rather than wrapping around. Returns whether a different variation is now shown.
+    pub fn step_segment(&mut self, forward: bool) -> bool {
+        let current = match &self.ui_state {
+            UiState::OldOverview { selected_segment_idx, .. } => *selected_segment_idx as usize,
+            _ => self.current_segment_idx(),
+        };
+
+        let has_data = |idx: &usize| matches!(self.bgm.segments.get(*idx), Some(Some(_)));
+        let target = if forward {
+            (current + 1..self.bgm.segments.len()).find(has_data)
+        } else {
+            (0..current).rev().find(has_data)
+        };
+
+        let target = match target {
+            Some(target) => target,
+            None => return false,
+        };
+
+        match &mut self.ui_state {
+            UiState::OldOverview { selected_segment_idx, track_list_interface, selected_track_subseg_idx, .. } => {
+                *selected_segment_idx = target as u8;
+                *selected_track_subseg_idx = 0;
+                *track_list_interface = TrackListInterface::new();
+                true
+            }
+            _ => self.show(Location::Segment { segment: target }),
+        }
+    }
+
+    /// Asks for a song file and one of its variations, then copies that variation into this song and shows it. Must
+    /// be run on the main thread. Returns whether a variation was imported.
+    pub fn import_segment_prompt(&mut self) -> Result<bool, Box<dyn Error>> {
+        let path = match Document::open_prompt() {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let (other, _, _) = formats::read(&path)?;
+
+        let names: Vec<String> = other.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, segment)| segment.as_ref().map(|segment| format!("{}: {}", idx + 1, segment.name)))
+            .collect();
+
+        let first = other.segments
+            .iter()
+            .position(Option::is_some)
+            .ok_or_else(|| format!("{} has no variations", other.name))?;
+
+        let idx = match names.len() {
+            // Nothing to choose between
+            1 => first,
+            _ => match tinyfiledialogs::input_box(
+                "Import Variation",
+                &format!("Variation to import ({}):", names.join(", ")),
+                &(first + 1).to_string(),
+            ) {
+                Some(text) => text
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| number.checked_sub(1))
+                    .filter(|idx| matches!(other.segments.get(*idx), Some(Some(_))))
+                    .ok_or_else(|| format!("'{}' is not one of the variations", text.trim()))?,
+                None => return Ok(false),
+            },
+        };
+
+        let imported = self.bgm
+            .import_segment_from(&other, idx)
+            .ok_or("There is no room for another variation; delete one first")?;
+
+        if !imported.unmapped_voices.is_empty() {
+            let voices: Vec<String> = imported.unmapped_voices.iter().map(u8::to_string).collect();
+
+            tinyfiledialogs::message_box_ok(
+                "Import Variation",
+                &format!(
+                    "Some tracks use voices that couldn't be brought over ({}). They will need to be set again.",
+                    voices.join(", "),
+                ),
+                tinyfiledialogs::MessageBoxIcon::Warning,
+            );
+        }
+
+        if !imported.unmapped_drums.is_empty() {
+            let drums: Vec<String> = imported.unmapped_drums.iter().map(u8::to_string).collect();
+
+            tinyfiledialogs::message_box_ok(
+                "Import Variation",
+                &format!(
+                    "Some drum tracks use drums that couldn't be brought over ({}). Those notes will need to be \
+                     set again.",
+                    drums.join(", "),
+                ),
+                tinyfiledialogs::MessageBoxIcon::Warning,
+            );
+        }
+
+        self.ui_state = UiState::Segment {
+            segment_idx: imported.segment_idx,
+            loop_editor: LoopEditor::new(),
+            renaming: None,
+            deleting: None,
+        };
+        Ok(true)
+    }
+
+    /// Shows a 'Save' dialog prompt then exports the current variation as a MIDI file. Must be run on the main thread.
+    pub fn export_midi(&self) -> Result<(), Box<dyn Error>> {
+        let midi = self.bgm
+            .to_midi(self.current_segment_idx())
+            .ok_or("There is no variation to export")?;
+
+        let path = tinyfiledialogs::save_file_dialog_with_filter(
+            "Export MIDI",
+            &format!("{}.mid", self.bgm.name),
+            &["*.mid", "*.midi"],
+            "",
+        );
+
+        if let Some(path) = path {
+            let mut path = PathBuf::from(path);
+
+            if path.extension().is_none() {
+                path.set_extension("mid");
+            }
+
+            std::fs::write(path, midi)?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks for a Paper Mario ROM and which song to replace, then saves a copy of the ROM with this song in its place.
+    /// The original ROM is left untouched. Must be run on the main thread.
+    pub fn export_rom(&self) -> Result<(), Box<dyn Error>> {
+        let rom_path = match tinyfiledialogs::open_file_dialog("Choose Base ROM", "", Some((&["*.z64"], ""))) {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(()),
+        };
+
+        let song_index = match tinyfiledialogs::input_box(
+            "Export to ROM",
+            "Song number to replace (hexadecimal, e.g. 2A):",
+            "0",
+        ) {
+            Some(text) => parse_song_index(&text).ok_or_else(|| format!("'{}' is not a song number", text.trim()))?,
+            None => return Ok(()),
+        };
+
+        // Patch before asking where to save, so a song that won't fit is reported straight away.
+        let mut rom = std::fs::read(&rom_path)?;
+        pm64::rom::patch_bgm(&mut rom, song_index, &self.bgm)?;
+
+        let default_name = rom_path.with_file_name(format!("{}.z64", self.bgm.name));
+        let path = tinyfiledialogs::save_file_dialog_with_filter(
+            "Save Patched ROM",
+            &default_name.to_string_lossy(),
+            &["*.z64"],
+            "",
+        );
+
+        if let Some(path) = path {
+            let mut path = PathBuf::from(path);
+
+            if path.extension().is_none() {
+                path.set_extension("z64");
+            }
+
+            if path == rom_path {
+                return Err("Choose a different file to save to, so the base ROM is kept".into());
+            }
+
+            std::fs::write(path, rom)?;
+        }
+
+        Ok(())
+    }
+
+    /// The piano roll being viewed, if any, and the track it is showing.
+    fn piano_roll(&mut self) -> Option<(&mut PianoRoll, &mut Track)> {
+        if let UiState::PianoRoll {
+            segment_idx,
+            subseg_idx,
+            track_idx,
+            piano_roll,
+        } = &mut self.ui_state
+        {
+            let segment = self.bgm.segments.get(*segment_idx)?.as_ref()?;
+
+            if let Subsegment::Tracks { track_list, .. } = segment.subsegments.get(*subseg_idx)? {
+                let track = self.bgm.track_lists.get_mut(track_list)?.tracks.get_mut(*track_idx)?;
+                return Some((piano_roll, track));
+            }
+        }
+
+        None
+    }
+
+    /// Moves the view somewhere that still exists if what it was showing has gone, e.g. because a variation was
+    /// deleted or an edit was undone, so that nothing is looked up out of bounds.
+    fn clamp_ui_state(&mut self) {
+        let bgm = &self.bgm;
+        let last_segment_idx = bgm.segments.len() - 1;
+        let segment_view = |segment_idx: usize| UiState::Segment {
+            segment_idx: segment_idx.min(last_segment_idx),
+            loop_editor: LoopEditor::new(),
+            renaming: None,
+            deleting: None,
+        };
+
+        let fallback = match &mut self.ui_state {
+            UiState::None => None,
+            UiState::Segment { segment_idx, .. } => {
+                *segment_idx = (*segment_idx).min(last_segment_idx);
+                None
+            }
+            UiState::Subsegment { segment_idx, subseg_idx, .. } => {
+                match track_list_at(bgm, *segment_idx, *subseg_idx) {
+                    Some(_) => None,
+                    None => Some(segment_view(*segment_idx)),
+                }
+            }
+            UiState::PianoRoll { segment_idx, subseg_idx, track_idx, .. } => {
+                match track_list_at(bgm, *segment_idx, *subseg_idx) {
+                    Some(track_list) if *track_idx < track_list.tracks.len() => None,
+                    Some(_) => Some(UiState::Subsegment {
+                        segment_idx: *segment_idx,
+                        subseg_idx: *subseg_idx,
+                        track_list_interface: TrackListInterface::new(),
+                    }),
+                    None => Some(segment_view(*segment_idx)),
+                }
+            }
+            UiState::Diff { segment_idx, other_idx, .. } => {
+                if *segment_idx > last_segment_idx || *other_idx > last_segment_idx {
+                    Some(segment_view(*segment_idx))
+                } else {
+                    None
+                }
+            }
+            UiState::OldOverview { selected_segment_idx, selected_track_subseg_idx, .. } => {
+                *selected_segment_idx = (*selected_segment_idx).min(last_segment_idx as u8);
+
+                let num_track_subsegs = bgm.segments[*selected_segment_idx as usize]
+                    .as_ref()
+                    .map_or(0, |segment| {
+                        segment.subsegments.iter().filter(|s| matches!(s, Subsegment::Tracks { .. })).count()
+                    });
+                if *selected_track_subseg_idx as usize >= num_track_subsegs {
+                    *selected_track_subseg_idx = 0;
+                }
+                None
+            }
+        };
+
+        if let Some(ui_state) = fallback {
+            self.ui_state = ui_state;
+        }
+    }
+
+    /// A copy of the song with the variation being viewed moved to the front, so it is the one that plays, and the
+    /// time within it to play from: the piano roll's playhead if it is open, otherwise the start.
+    pub fn song_at_playhead(&self) -> (Bgm, usize) {
+        let segment_idx = self.current_segment_idx();
+        let mut bgm = self.bgm.clone();
+        bgm.segments = [self.bgm.segments[segment_idx].clone(), None, None, None];
+
+        let time = match (&self.ui_state, &self.bgm.segments[segment_idx]) {
+            (UiState::PianoRoll { subseg_idx, piano_roll, .. }, Some(segment)) => {
+                segment.subsegment_time(&self.bgm.track_lists, *subseg_idx) + piano_roll.playhead()
+            }
+            _ => 0,
+        };
+
+        (bgm, time)
+    }
+
+    /// Copies the commands selected in the piano roll.
+    pub fn copy(&mut self) -> Option<Vec<Command>> {
+        let (piano_roll, track) = self.piano_roll()?;
+        Some(track.commands.copy_range(piano_roll.selection()?))
+    }
+
+    /// Removes the commands selected in the piano roll and returns a copy of them.
+    pub fn cut(&mut self) -> Option<Vec<Command>> {
+        let (piano_roll, track) = self.piano_roll()?;
+        Some(track.commands.cut_range(piano_roll.selection()?))
+    }
+
+    /// Pastes `clip` at the piano roll's playhead, then moves the playhead to the end of it so pasting again follows
+    /// on. Returns false if there is no piano roll to paste into.
+    pub fn paste(&mut self, clip: &[Command]) -> bool {
+        let (piano_roll, track) = match self.piano_roll() {
+            Some(open) => open,
+            None => return false,
+        };
+
+        let time = piano_roll.playhead();
+        let num_left_out = track.commands.paste(time, clip);
+        if num_left_out > 0 {
+            log::warn!("{} pasted commands were past the end of the track, so were left out", num_left_out);
+        }
+
+        let clip_len: usize = clip.iter().map(|command| match command {
+            Command::Delay(delta_time) => *delta_time,
+            _ => 0,
+        }).sum();
+        piano_roll.set_playhead((time + clip_len).min(track.commands.len_time()));
+
+        true
+    }
+
     pub fn update(&mut self, ui: &mut imui_glium::UiFrame<'_>, hot: &mut Hot) {
+        self.clamp_ui_state();
+
         let bgm = &mut self.bgm;
         let mut next_state = None;
 
@@ -207,6 +707,9 @@ impl Document {
 
             UiState::Segment {
                 segment_idx,
+                loop_editor,
+                renaming,
+                deleting,
             } => {
                 ui.vbox("seg", |ui| {
                     ui.hbox("seg selector", |ui| {
@@ -226,17 +729,20 @@ impl Document {
                     });
 
                     // View actual segment.
+                    let uses = track_list_uses(bgm);
                     let opt_segment = &mut bgm.segments[*segment_idx];
-                    let track_lists = &bgm.track_lists;
+                    let track_lists = &mut bgm.track_lists;
 
                     ui.pad("top pad", 16.0);
 
                     if let Some(segment) = opt_segment {
                         let mut to_delete_segment = false;
+                        let mut to_duplicate_segment = false;
                         let mut to_play_segment = false;
+                        let mut to_compare = false;
                         let mut to_add_subseg = false;
-                        let mut to_add_loops = false;
                         let mut to_play_subseg = None;
+                        let mut to_delete_subseg = None;
 
                         ui.vbox(0, |ui| {
                             ui.hbox("toolbar", |ui| {
@@ -244,9 +750,51 @@ impl Document {
                                     to_delete_segment = true;
                                 }
 
+                                if ui.button("dup seg", "Duplicate").with_width(150.0).clicked() {
+                                    to_duplicate_segment = true;
+                                }
+
                                 if ui.button("play", "Play").clicked() {
                                     to_play_segment = true;
                                 }
+
+                                if ui.button("compare", "Compare")
+                                    .tooltip("See how this variation differs from another, command by command")
+                                    .clicked()
+                                {
+                                    to_compare = true;
+                                }
+
+                                ui.pad("name pad", 10.0);
+
+                                ui.text_input("seg name", &mut segment.name).with_width(250.0);
+
+                                ui.pad("length pad", 10.0);
+
+                                // Loops are counted once, so this is how long it takes to first reach the loop end.
+                                let length = format!(
+                                    "{} ({} ticks)",
+                                    format_secs(segment.len_secs(track_lists)),
+                                    segment.len_time(track_lists),
+                                );
+                                ui.text("seg length", length).center_y();
+                            });
+
+                            ui.pad("scales pad", 10.0);
+
+                            // Applied when the song is saved or played, so the commands themselves are untouched.
+                            ui.hbox("scales", |ui| {
+                                let label = format!("Volume {:.0}%", segment.volume_scale * 100.0);
+                                if labelled_slider(ui, "volume", label, &mut segment.volume_scale, 0.0..=2.0) {
+                                    segment.volume_scale = round_scale(segment.volume_scale);
+                                }
+
+                                ui.pad("pad", 20.0);
+
+                                let label = format!("Tempo {:.0}%", segment.tempo_scale * 100.0);
+                                if labelled_slider(ui, "tempo", label, &mut segment.tempo_scale, 0.5..=2.0) {
+                                    segment.tempo_scale = round_scale(segment.tempo_scale);
+                                }
                             });
 
                             ui.pad("top pad", 30.0);
@@ -282,11 +830,26 @@ impl Document {
 
                                     match subseg {
                                         Subsegment::Tracks { track_list, .. } => {
-                                            let name = &track_lists[track_list].name;
-                                            if ui.button("subseg tracks name", name)
-                                                .with_width(400.0)
-                                                .clicked()
-                                            {
+                                            let name = &mut track_lists.get_mut(track_list).unwrap().name;
+
+                                            match renaming {
+                                                Some(rename) if rename.of == *track_list => {
+                                                    if !rename.update(ui, "subseg tracks rename", 400.0, name) {
+                                                        *renaming = None;
+                                                    }
+                                                }
+                                                _ => {
+                                                    if ui.button("subseg tracks name", name.as_str())
+                                                        .with_width(400.0)
+                                                        .tooltip("Double-click to rename")
+                                                        .double_clicked()
+                                                    {
+                                                        *renaming = Some(Renaming::new(*track_list, name));
+                                                    }
+                                                }
+                                            }
+
+                                            if ui.button("subseg open", "Open").clicked() {
                                                 next_state = Some(UiState::Subsegment {
                                                     segment_idx: *segment_idx,
                                                     subseg_idx: i,
@@ -297,14 +860,22 @@ impl Document {
                                             if ui.button("subseg play", "Play").clicked() {
                                                 to_play_subseg = Some(i);
                                             }
+
+                                            // Editing a shared track list changes every section that uses it.
+                                            let uses = uses.get(track_list).copied().unwrap_or_default();
+                                            if uses > 1 {
+                                                ui.pad("subseg shared pad", 10.0);
+                                                ui.text("subseg shared", format!("Shared by {} sections", uses))
+                                                    .center_y();
+                                            }
                                         },
                                         Subsegment::Unknown { flags, .. } => {
                                             let label;
 
                                             // TODO update the enum in pm64::bgm instead
-                                            if *flags == 0x30 {
+                                            if *flags == LOOP_START_FLAGS {
                                                 label = "Loop start";
-                                            } else if *flags == 0x50 {
+                                            } else if *flags == LOOP_END_FLAGS {
                                                 label = "Loop end";
                                             } else {
                                                 label = "Unknown";
@@ -323,48 +894,48 @@ impl Document {
                                 segment.subsegments.swap(a, b);
                             }
 
-                            if let Some(i) = delete {
-                                segment.subsegments.remove(i);
-                            }
+                            to_delete_subseg = delete;
 
                             ui.pad("btm pad", 30.0);
 
                             if ui.button("add subseg", "New section").clicked() {
                                 to_add_subseg = true;
                             }
-
-                            if ui.button("loop subseg", "Add loop").clicked() {
-                                to_add_loops = true;
-                            }
                         });
 
                         if to_add_subseg {
-                            let track_list = bgm.add_track_list(TrackList::default());
-                            bgm.segments[*segment_idx].as_mut().unwrap().subsegments.push(Subsegment::Tracks {
-                                flags: 0x10,
-                                track_list,
-                            });
-                        } else if to_add_loops {
-                            let subsegments = &mut bgm.segments[*segment_idx].as_mut().unwrap().subsegments;
-
-                            subsegments.insert(0, Subsegment::Unknown {
-                                flags: 0x30,
-                                data: [0, 0, 0],
-                            });
-
-                            subsegments.push(Subsegment::Unknown {
-                                flags: 0x30,
-                                data: [0, 0, 0],
-                            });
+                            segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+                        } else if let Some(subseg_idx) = to_delete_subseg {
+                            *deleting = Some(Deletion::Subsegment { segment_idx: *segment_idx, subseg_idx });
                         } else if to_delete_segment {
-                            *opt_segment = None;
+                            *deleting = Some(Deletion::Segment(*segment_idx));
+                        } else if to_duplicate_segment {
+                            if let Some(new_idx) = bgm.duplicate_segment(*segment_idx) {
+                                *segment_idx = new_idx;
+                            } else {
+                                log::warn!("no space to duplicate variation");
+                            }
+                        } else if to_compare {
+                            // Compare with the next variation that has data, if any.
+                            let other_idx = (1..bgm.segments.len())
+                                .map(|n| (*segment_idx + n) % bgm.segments.len())
+                                .find(|idx| bgm.segments[*idx].is_some())
+                                .unwrap_or(*segment_idx);
+
+                            next_state = Some(UiState::Diff {
+                                segment_idx: *segment_idx,
+                                other_idx,
+                                diff: diff_segments(bgm, *segment_idx, bgm, other_idx),
+                            });
                         } else if to_play_segment {
                             let mut bgm = bgm.clone();
 
                             let this_segment = bgm.segments[*segment_idx].clone();
                             bgm.segments = [this_segment, None, None, None];
 
-                            let _ = hot.play_bgm(&bgm);
+                            if let Err(error) = hot.play_bgm(&bgm) {
+                                log::error!("unable to play: {}", error);
+                            }
                         } else if let Some(subseg_idx) = to_play_subseg {
                             let mut bgm = bgm.clone();
                             let segment = bgm.segments[*segment_idx].as_ref().unwrap();
@@ -386,23 +957,28 @@ impl Document {
                                 }
                             }
 
-                            bgm.segments = [Some(Segment {
-                                name: "".to_owned(),
-                                subsegments,
-                            }), None, None, None];
+                            let mut preview = Segment::new(String::new());
+                            preview.subsegments = subsegments;
+                            preview.volume_scale = segment.volume_scale;
+                            preview.tempo_scale = segment.tempo_scale;
+                            bgm.segments = [Some(preview), None, None, None];
 
-                            let _ = hot.play_bgm(&bgm);
+                            if let Err(error) = hot.play_bgm(&bgm) {
+                                log::error!("unable to play: {}", error);
+                            }
                         }
+
+                        ui.pad("loop pad", 30.0);
+                        loop_editor.update(ui, bgm, *segment_idx, hot);
                     } else {
                         // Segment is (no data)
                         if ui.button("new seg", "New variation").with_width(200.0).clicked() {
-                            *opt_segment = Some(Segment {
-                                name: format!("Variation {}", *segment_idx + 1),
-                                subsegments: Default::default(),
-                            });
+                            *opt_segment = Some(Segment::new(format!("Variation {}", *segment_idx + 1)));
                         }
                     }
                 });
+
+                confirm_deletion(ui, bgm, deleting);
             }
 
             UiState::Subsegment {
@@ -410,30 +986,260 @@ impl Document {
                 subseg_idx,
                 track_list_interface,
             } => {
+                let mut live_changes = Vec::new();
+                let mut to_share = None;
+                let mut to_unshare = false;
+
+                let uses = track_list_uses(bgm);
+                let mut choices: Vec<(TrackListId, String)> = bgm.track_lists
+                    .iter()
+                    .map(|(id, track_list)| match track_list.name.as_str() {
+                        "" => (*id, format!("Track list {}", id)),
+                        name => (*id, name.to_owned()),
+                    })
+                    .collect();
+                choices.sort_by_key(|(id, _)| *id);
+
                 ui.vbox(0, |ui| {
                     if let Some(segment) = &bgm.segments[*segment_idx as usize] {
                         let subseg = &segment.subsegments[*subseg_idx];
 
-                        if let Subsegment::Tracks { track_list, .. } = subseg {
-                            let track_list = bgm.track_lists.get_mut(track_list).unwrap();
+                        if let Subsegment::Tracks { track_list: id, .. } = subseg {
+                            let track_list = bgm.track_lists.get_mut(id).unwrap();
 
                             ui.hbox("subseg toolbar", |ui| {
                                 if ui.button("back btn", "Back").clicked() {
-                                    next_state = Some(UiState::Segment { segment_idx: *segment_idx });
+                                    next_state = Some(UiState::Segment {
+                                        segment_idx: *segment_idx,
+                                        loop_editor: LoopEditor::new(),
+                                        renaming: None,
+                                        deleting: None,
+                                    });
                                 }
 
                                 ui.pad("pad", 10.0);
 
-                                ui.text("subseg name", track_list.name.clone())
-                                    .center_y();
+                                ui.text_input("subseg name", &mut track_list.name).with_width(300.0);
+
+                                ui.pad("track list pad", 10.0);
+
+                                let mut selected = choices.iter().position(|(choice, _)| choice == id).unwrap_or(0);
+                                let names = choices.iter().map(|(_, name)| name.clone());
+                                if ui.dropdown("track list", &mut selected, names) {
+                                    to_share = Some(choices[selected].0);
+                                }
+
+                                let uses = uses.get(id).copied().unwrap_or_default();
+                                if uses > 1 {
+                                    ui.pad("unshare pad", 10.0);
+
+                                    let tooltip = format!(
+                                        "Shared by {} sections. Give this one its own copy, so editing it leaves the \
+                                         others alone",
+                                        uses,
+                                    );
+                                    if ui.button("unshare", "Make unique").tooltip(&tooltip).clicked() {
+                                        to_unshare = true;
+                                    }
+                                }
+
+                                ui.pad("voices pad", 10.0);
+
+                                if ui.button("voices btn", "Voices...")
+                                    .tooltip("List every voice in the song, to edit and listen to them")
+                                    .clicked()
+                                {
+                                    track_list_interface.show_voices();
+                                }
                             });
 
                             ui.pad("pad", 10.0);
 
-                            track_list_interface.update(ui, track_list, &mut bgm.voices);
+                            let piano_roll_track = track_list_interface.update(
+                                ui,
+                                track_list,
+                                &mut bgm.voices,
+                                &mut live_changes,
+                            );
+
+                            if let Some(track_idx) = piano_roll_track {
+                                next_state = Some(UiState::PianoRoll {
+                                    segment_idx: *segment_idx,
+                                    subseg_idx: *subseg_idx,
+                                    track_idx,
+                                    piano_roll: PianoRoll::new(),
+                                });
+                            }
                         }
                     }
                 });
+
+                if let Some(id) = to_share {
+                    if bgm.share_track_list(*segment_idx, *subseg_idx, id).is_none() {
+                        log::warn!("unable to use track list {} for section {}", id, subseg_idx);
+                    }
+                } else if to_unshare {
+                    bgm.unshare_track_list(*segment_idx, *subseg_idx);
+                }
+
+                // Let the tweaks be heard in what's already playing.
+                for change in live_changes {
+                    if let Err(error) = hot.send_live(change, bgm) {
+                        log::error!("unable to send change to emulator: {}", error);
+                    }
+                }
+
+                if let Some(voice) = track_list_interface.take_audition() {
+                    if let Err(error) = hot.play_bgm(&voice.audition()) {
+                        log::error!("unable to play: {}", error);
+                    }
+                }
+            }
+
+            UiState::PianoRoll {
+                segment_idx,
+                subseg_idx,
+                track_idx,
+                piano_roll,
+            } => {
+                let mut to_loop = None;
+
+                ui.vbox(0, |ui| {
+                    if let Some(segment) = &bgm.segments[*segment_idx] {
+                        if let Subsegment::Tracks { track_list, .. } = &segment.subsegments[*subseg_idx] {
+                            // The piano roll counts ticks from the start of this section, but loops are set in ticks
+                            // from the start of the variation.
+                            let offset = segment.subsegment_time(&bgm.track_lists, *subseg_idx);
+                            let track = &mut bgm.track_lists.get_mut(track_list).unwrap().tracks[*track_idx];
+
+                            ui.hbox("piano roll toolbar", |ui| {
+                                if ui.button("back btn", "Back").clicked() {
+                                    next_state = Some(UiState::Subsegment {
+                                        segment_idx: *segment_idx,
+                                        subseg_idx: *subseg_idx,
+                                        track_list_interface: TrackListInterface::new(),
+                                    });
+                                }
+
+                                ui.pad("pad", 10.0);
+
+                                ui.text("track name", &track.name).with_width(250.0).ellipsize().center_y();
+
+                                ui.pad("loop pad", 20.0);
+
+                                if ui.button("loop btn", "Loop selection")
+                                    .tooltip("Make the variation loop over the selected part of this section")
+                                    .clicked()
+                                {
+                                    to_loop = piano_roll
+                                        .selection()
+                                        .map(|selection| offset + selection.start..offset + selection.end);
+                                }
+                            });
+
+                            ui.pad("pad", 10.0);
+
+                            let playing_at = hot.playback_position(segment).and_then(|time| time.checked_sub(offset));
+                            piano_roll.set_playing_at(playing_at);
+                            piano_roll.update(ui, track);
+                        }
+                    }
+                });
+
+                if let Some(range) = to_loop {
+                    match bgm.set_loop(*segment_idx, range) {
+                        // Sections may have been split where the loop starts and ends, so show the new sections.
+                        Ok(()) => next_state = Some(UiState::Segment {
+                            segment_idx: *segment_idx,
+                            loop_editor: LoopEditor::new(),
+                            renaming: None,
+                            deleting: None,
+                        }),
+                        Err(error) => log::error!("unable to set loop: {}", error),
+                    }
+                }
+            }
+
+            UiState::Diff {
+                segment_idx,
+                other_idx,
+                diff,
+            } => {
+                ui.vbox(0, |ui| {
+                    let names: Vec<String> = bgm.segments
+                        .iter()
+                        .map(|seg| match seg {
+                            Some(seg) => seg.name.to_owned(),
+                            None => String::from("(no data)"),
+                        })
+                        .collect();
+
+                    ui.hbox("diff toolbar", |ui| {
+                        if ui.button("back btn", "Back").clicked() {
+                            next_state = Some(UiState::Segment {
+                                segment_idx: *segment_idx,
+                                loop_editor: LoopEditor::new(),
+                                renaming: None,
+                                deleting: None,
+                            });
+                        }
+
+                        ui.pad("pad", 10.0);
+
+                        ui.text("name", format!("{} compared with", names[*segment_idx]))
+                            .with_width(250.0)
+                            .ellipsize()
+                            .center_y();
+
+                        ui.pad("other pad", 10.0);
+
+                        if ui.dropdown("other", other_idx, names.iter().cloned()) {
+                            *diff = diff_segments(bgm, *segment_idx, bgm, *other_idx);
+                        }
+                    });
+
+                    ui.pad("pad", 10.0);
+
+                    if diff.iter().flatten().all(DiffOp::is_same) {
+                        ui.text("same", "These variations are identical.");
+                        return;
+                    }
+
+                    ui.scroll_area_with_scrollbar("diff", |ui| {
+                        ui.vbox(0, |ui| {
+                            for (track_idx, ops) in diff.iter().enumerate() {
+                                let differences = ops.iter().filter(|op| !op.is_same()).count();
+                                if differences == 0 {
+                                    continue;
+                                }
+
+                                ui.text(
+                                    (track_idx, "name"),
+                                    format!("Track {} ({} differences)", track_idx + 1, differences),
+                                );
+
+                                for (i, op) in ops.iter().enumerate().filter(|(_, op)| !op.is_same()) {
+                                    let (left, right) = match op {
+                                        DiffOp::Same { .. } => continue,
+                                        DiffOp::Delete { command, .. } => (format!("- {:?}", command), String::new()),
+                                        DiffOp::Insert { command, .. } => (String::new(), format!("+ {:?}", command)),
+                                        DiffOp::Change { from, to, .. } => {
+                                            (format!("~ {:?}", from), format!("~ {:?}", to))
+                                        }
+                                    };
+
+                                    ui.hbox((track_idx, i), |ui| {
+                                        ui.text("time", op.time().to_string()).with_width(100.0);
+                                        ui.text("a", left).with_width(450.0);
+                                        ui.text("b", right).with_width(450.0);
+                                    });
+                                }
+
+                                ui.pad((track_idx, "pad"), 20.0);
+                            }
+                        });
+                    });
+                });
             }
 
             UiState::OldOverview {
@@ -441,23 +1247,18 @@ impl Document {
                 track_list_interface,
                 viewing_seg_list,
                 selected_track_subseg_idx,
+                renaming,
+                deleting,
             } => {
                 ui.vbox(0, |ui| {
                     ui.hbox(0, |ui| {
-                        if range_select(
-                            ui,
-                            0,
-                            0..bgm.segments.len() as isize,
-                            1,
-                            selected_segment_idx,
-                            |i| {
-                                if let Some(seg) = &bgm.segments[*i as usize] {
-                                    seg.name.to_owned()
-                                } else {
-                                    String::from("(no data)")
-                                }
-                            },
-                        ) {
+                        let mut idx = *selected_segment_idx as usize;
+                        let names = bgm.segments.iter().map(|seg| match seg {
+                            Some(seg) => seg.name.to_owned(),
+                            None => String::from("(no data)"),
+                        });
+                        if ui.dropdown(0, &mut idx, names) {
+                            *selected_segment_idx = idx as u8;
                             *track_list_interface = TrackListInterface::new();
                         }
 
@@ -473,14 +1274,36 @@ impl Document {
                                     ui.vbox(0, |ui| {
                                         let mut swap = None;
 
-                                        for (i, seg) in bgm.segments.iter().enumerate() {
+                                        let num_segments = bgm.segments.len();
+
+                                        for (i, seg) in bgm.segments.iter_mut().enumerate() {
                                             ui.hbox(i as u8, |ui| {
-                                                ui.known_size(0, 220.0, 32.0,|ui| {
-                                                    ui.text(0, if let Some(seg) = seg {
-                                                        &seg.name
-                                                    } else {
-                                                        "(no data)"
-                                                    }).center_y();
+                                                ui.known_size(0, 190.0, 32.0,|ui| {
+                                                    let seg = match seg {
+                                                        Some(seg) => seg,
+                                                        None => {
+                                                            ui.text(0, "(no data)").center_y();
+                                                            return;
+                                                        }
+                                                    };
+
+                                                    match renaming {
+                                                        Some(rename) if rename.of == i => {
+                                                            if !rename.update(ui, "rename", 190.0, &mut seg.name) {
+                                                                *renaming = None;
+                                                            }
+                                                        }
+                                                        _ => {
+                                                            if ui.button(0, seg.name.as_str())
+                                                                .with_width(190.0)
+                                                                .with_height(32.0)
+                                                                .tooltip("Double-click to rename")
+                                                                .double_clicked()
+                                                            {
+                                                                *renaming = Some(Renaming::new(i, &seg.name));
+                                                            }
+                                                        }
+                                                    }
                                                 });
 
                                                 if ui.button(1, "^").with_width(32.0).clicked() {
@@ -489,10 +1312,13 @@ impl Document {
                                                     }
                                                 }
                                                 if ui.button(2, "v").with_width(32.0).clicked() {
-                                                    if i < bgm.segments.len() - 1 {
+                                                    if i < num_segments - 1 {
                                                         swap = Some((i, i + 1));
                                                     }
                                                 }
+                                                if ui.button(3, "X").with_width(32.0).clicked() && seg.is_some() {
+                                                    *deleting = Some(Deletion::Segment(i));
+                                                }
                                             });
                                         }
 
@@ -512,49 +1338,65 @@ impl Document {
                     ui.pad(3, 5.0);
 
                     if let Some(segment) = bgm.segments[*selected_segment_idx as usize].as_mut() {
-                        let track_subsegs: Vec<(&u8, &TrackListId)> = segment.subsegments
+                        let track_subsegs: Vec<(usize, &u8, &TrackListId)> = segment.subsegments
                             .iter()
-                            .filter_map(|s| match s {
-                                Subsegment::Tracks { flags, track_list } => Some((flags, track_list)),
+                            .enumerate()
+                            .filter_map(|(i, s)| match s {
+                                Subsegment::Tracks { flags, track_list } => Some((i, flags, track_list)),
                                 Subsegment::Unknown { .. } => None,
                             })
                             .collect();
 
-                        let range = 0..track_subsegs.len() as isize;
+                        if track_subsegs.is_empty() {
+                            ui.text(11, "This variation has no sections.");
+                            return;
+                        }
 
-                        if !range.contains(&(*selected_track_subseg_idx as isize)) {
+                        if *selected_track_subseg_idx as usize >= track_subsegs.len() {
                             *selected_track_subseg_idx = 0;
                         }
 
+                        let (subseg_idx, flags, track_list_id) = track_subsegs[*selected_track_subseg_idx as usize];
+
                         let track_lists = &bgm.track_lists;
-                        if range_select(
-                            ui,
-                            4,
-                            range,
-                            1,
-                            &mut *selected_track_subseg_idx,
-                            |v| {
-                                let (_, track_list_id) = track_subsegs[*v as usize];
-                                let track_list = &track_lists[track_list_id];
-                                track_list.name.to_owned()
-                            },
-                        ) {
-                            *track_list_interface = TrackListInterface::new();
-                        }
+                        ui.hbox(4, |ui| {
+                            let mut idx = *selected_track_subseg_idx as usize;
+                            let names = track_subsegs
+                                .iter()
+                                .map(|(_, _, track_list_id)| track_lists[*track_list_id].name.to_owned());
+                            if ui.dropdown(0, &mut idx, names) {
+                                *selected_track_subseg_idx = idx as u8;
+                                *track_list_interface = TrackListInterface::new();
+                            }
+
+                            if ui.button(1, "Delete section").with_width(200.0).clicked() {
+                                *deleting = Some(Deletion::Subsegment {
+                                    segment_idx: *selected_segment_idx as usize,
+                                    subseg_idx,
+                                });
+                            }
+                        });
 
                         ui.pad(7, 10.0);
 
-                        let (flags, track_list_id) = track_subsegs[*selected_track_subseg_idx as usize];
                         let track_list = bgm.track_lists.get_mut(track_list_id).unwrap();
                         let voices = &mut bgm.voices;
 
                         ui.text(8, format!("Flags: {:08X}", flags));
                         ui.pad(9, 10.0);
                         ui.vbox(10, |ui| {
-                            track_list_interface.update(ui, track_list, voices);
+                            // The old overview has no piano roll to open, and doesn't change the song live.
+                            track_list_interface.update(ui, track_list, voices, &mut Vec::new());
                         });
                     }
                 });
+
+                if confirm_deletion(ui, bgm, deleting) {
+                    // The selected section may have gone, or be another one now.
+                    *selected_track_subseg_idx = 0;
+                    *track_list_interface = TrackListInterface::new();
+                    *renaming = None;
+                }
             }
         }
 
@@ -564,3 +1406,299 @@ impl Document {
         }
     }
 }
+
+/// The track list that a subsegment plays, if both it and the segment it is in exist.
+fn track_list_at(bgm: &Bgm, segment_idx: usize, subseg_idx: usize) -> Option<&TrackList> {
+    let segment = bgm.segments.get(segment_idx)?.as_ref()?;
+    match segment.subsegments.get(subseg_idx)? {
+        Subsegment::Tracks { track_list, .. } => bgm.track_lists.get(track_list),
+        Subsegment::Unknown { .. } => None,
+    }
+}
+
+/// How many sections use each track list. See [Bgm::track_list_uses].
+fn track_list_uses(bgm: &Bgm) -> HashMap<TrackListId, usize> {
+    bgm.track_lists.keys().map(|id| (*id, bgm.track_list_uses(*id))).collect()
+}
+
+/// Something the user has asked to delete, waiting for them to confirm it.
+#[derive(Clone, Copy, PartialEq)]
+enum Deletion {
+    Segment(usize),
+    Subsegment { segment_idx: usize, subseg_idx: usize },
+}
+
+/// Asks whether to go ahead with `deleting`, if it is set, and does so if the user agrees. Track lists that are no
+/// longer used are deleted along with it. Returns whether anything was deleted.
+fn confirm_deletion(ui: &mut imui_glium::UiFrame<'_>, bgm: &mut Bgm, deleting: &mut Option<Deletion>) -> bool {
+    let deletion = match *deleting {
+        Some(deletion) => deletion,
+        None => return false,
+    };
+
+    let what = match deletion {
+        Deletion::Segment(idx) => match &bgm.segments[idx] {
+            Some(segment) => format!("variation \"{}\"", segment.name),
+            None => "this variation".to_owned(),
+        },
+        Deletion::Subsegment { segment_idx, subseg_idx } => {
+            let subsegment = bgm.segments[segment_idx].as_ref().and_then(|seg| seg.subsegments.get(subseg_idx));
+            match subsegment {
+                Some(Subsegment::Tracks { track_list, .. }) => {
+                    format!("section \"{}\"", bgm.track_lists[track_list].name)
+                }
+                _ => "this section".to_owned(),
+            }
+        }
+    };
+
+    let mut is_confirmed = false;
+
+    ui.modal("delete_prompt", true, (400.0, 140.0), |ui| {
+        ui.text(0, format!("Delete {}?", what)).center_x();
+        ui.pad(1, 20.0);
+
+        ui.hbox(2, |ui| {
+            if ui.button(0, "Delete").clicked() {
+                is_confirmed = true;
+                *deleting = None;
+            }
+
+            if ui.button(1, "Cancel").clicked() {
+                *deleting = None;
+            }
+        });
+    });
+
+    if !is_confirmed {
+        return false;
+    }
+
+    match deletion {
+        Deletion::Segment(idx) => bgm.remove_segment(idx).is_some(),
+        Deletion::Subsegment { segment_idx, subseg_idx } => bgm.remove_subsegment(segment_idx, subseg_idx).is_some(),
+    }
+}
+
+/// Longest name a variation or section can be given. The game doesn't store these names, only Mamar's own formats
+/// do, so this is only to keep them fitting in the buttons and tabs that show them.
+const MAX_NAME_LEN: usize = 32;
+
+/// A name being edited in place, after it was double-clicked.
+#[derive(Clone)]
+struct Renaming<T> {
+    /// Which variation or section is being renamed.
+    of: T,
+    text: String,
+    has_focused: bool,
+}
+
+impl<T> Renaming<T> {
+    fn new(of: T, name: &str) -> Self {
+        Renaming {
+            of,
+            text: name.to_owned(),
+            has_focused: false,
+        }
+    }
+
+    /// Draws a text input in place of the name, writing `name` as it is typed. Returns false once Enter is pressed
+    /// or something else is clicked, when renaming is over.
+    fn update(&mut self, ui: &mut imui_glium::UiFrame<'_>, key: &'static str, width: f32, name: &mut String) -> bool {
+        if !self.has_focused {
+            ui.focus(key);
+            self.has_focused = true;
+        }
+
+        let mut input = ui.text_input(key, &mut self.text);
+        input.with_width(width);
+        let (changed, is_focused) = (input.changed(), input.is_focused());
+
+        if changed {
+            limit_name(&mut self.text);
+
+            // A blank name would leave nothing to double-click, so the old one is kept until there is a new one.
+            let trimmed = self.text.trim();
+            if !trimmed.is_empty() {
+                *name = trimmed.to_owned();
+            }
+        }
+
+        is_focused
+    }
+}
+
+/// Limits a name being typed to [MAX_NAME_LEN] printable characters.
+fn limit_name(text: &mut String) {
+    *text = text.chars().filter(|ch| !ch.is_control()).take(MAX_NAME_LEN).collect();
+}
+
+/// Formats a length of time as minutes and seconds, e.g. `1:05`.
+fn format_secs(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Snaps a volume or tempo scale to whole percentages, so dragging back to 100% leaves the segment unscaled.
+fn round_scale(scale: f32) -> f32 {
+    (scale * 100.0).round() / 100.0
+}
+
+/// Parses a song number as shown in the decomp and in-game debug menus, e.g. `2A` or `0x2A`.
+fn parse_song_index(text: &str) -> Option<usize> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn view_of_deleted_segment() {
+        let mut doc = Document::new();
+        doc.bgm.add_segment().unwrap();
+        let segment = doc.bgm.segments[0].as_mut().unwrap();
+        segment.add_tracks_subsegment(&mut doc.bgm.track_lists, 0x10);
+
+        doc.ui_state = UiState::PianoRoll {
+            segment_idx: 0,
+            subseg_idx: 0,
+            track_idx: 0,
+            piano_roll: PianoRoll::new(),
+        };
+        assert!(doc.piano_roll().is_some());
+
+        doc.bgm.remove_segment(0);
+        assert!(doc.piano_roll().is_none());
+
+        doc.clamp_ui_state();
+        assert!(matches!(doc.ui_state, UiState::Segment { segment_idx: 0, .. }));
+    }
+
+    #[test]
+    fn old_overview_of_deleted_section() {
+        let mut doc = Document::new();
+        doc.bgm.add_segment().unwrap();
+        let segment = doc.bgm.segments[0].as_mut().unwrap();
+        segment.add_tracks_subsegment(&mut doc.bgm.track_lists, 0x10);
+        segment.add_tracks_subsegment(&mut doc.bgm.track_lists, 0x10);
+
+        doc.ui_state = UiState::OldOverview {
+            selected_segment_idx: 0,
+            track_list_interface: TrackListInterface::new(),
+            viewing_seg_list: false,
+            selected_track_subseg_idx: 1,
+            renaming: None,
+            deleting: None,
+        };
+
+        doc.bgm.remove_subsegment(0, 1);
+        doc.clamp_ui_state();
+        assert!(matches!(doc.ui_state, UiState::OldOverview { selected_track_subseg_idx: 0, .. }));
+    }
+
+    #[test]
+    fn example_song() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(EXAMPLE_SONG);
+        let (bgm, format, _) = formats::read(&path).unwrap();
+
+        assert_eq!(format, SourceFormat::Ron);
+        assert!(bgm.validate().is_empty());
+        assert!(bgm.segments[0].is_some());
+    }
+
+    #[test]
+    fn name_limits() {
+        let mut name = "Intro\t(fast)".to_owned();
+        limit_name(&mut name);
+        assert_eq!(name, "Intro(fast)");
+
+        let mut name = "ü".repeat(MAX_NAME_LEN + 1);
+        limit_name(&mut name);
+        assert_eq!(name, "ü".repeat(MAX_NAME_LEN));
+    }
+
+    #[test]
+    fn failed_open_keeps_document() {
+        let dir = TempDir::new("failed_open_keeps_document");
+
+        let invalid = dir.join("invalid.bgm");
+        std::fs::write(&invalid, b"not a song").unwrap();
+
+        let valid = dir.join("valid.bgm");
+        std::fs::write(&valid, Bgm::new().as_bytes().unwrap()).unwrap();
+
+        let mut doc = Document::new();
+        doc.bgm.name = "Unsaved work".to_owned();
+        let mut state = State { document: Some(doc) };
+        let before = state.clone();
+
+        assert!(state.open(invalid).is_err());
+        assert!(state.open(dir.join("missing.bgm")).is_err());
+        assert!(state == before);
+
+        // Opening another file still works afterwards.
+        state.open(valid.clone()).unwrap();
+        assert!(matches!(state.document.map(|doc| doc.path), Some(DocPath::Native(path)) if path == valid));
+    }
+
+    #[test]
+    fn revert_rereads_file() {
+        let dir = TempDir::new("revert_rereads_file");
+
+        let path = dir.join("song.bgm");
+        std::fs::write(&path, Bgm::new().as_bytes().unwrap()).unwrap();
+
+        let (mut doc, _) = Document::open_from_path(path.clone()).unwrap();
+        let saved = doc.bgm.clone();
+        doc.bgm.name = "Unsaved work".to_owned();
+        doc.bgm.add_segment().unwrap();
+
+        assert!(doc.revert().unwrap());
+        assert_eq!(doc.bgm, saved);
+        assert!(matches!(&doc.path, DocPath::Native(p) if *p == path));
+
+        // A new song has no saved version.
+        assert!(!Document::new().revert().unwrap());
+    }
+
+    #[test]
+    fn invalid_bgm_not_saved() {
+        let dir = TempDir::new("invalid_bgm_not_saved");
+        let path = dir.join("song.bgm");
+
+        let mut doc = Document::new();
+        doc.path = DocPath::Native(path.clone());
+
+        let error = doc.save().unwrap_err().to_string();
+        assert!(error.contains("- Song has no variations"), "{}", error);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn text_formats_round_trip() {
+        let dir = TempDir::new("text_formats_round_trip");
+
+        // Two variations sharing a track list
+        let mut doc = Document::new();
+        doc.bgm.add_segment().unwrap();
+        doc.bgm.add_segment().unwrap();
+        let segment = doc.bgm.segments[0].as_mut().unwrap();
+        let track_list = segment.add_tracks_subsegment(&mut doc.bgm.track_lists, 0x10);
+        let subsegment = segment.subsegments[0].clone();
+        doc.bgm.segments[1].as_mut().unwrap().subsegments.push(subsegment);
+
+        for file_name in &["song.ron", "song.json"] {
+            let path = dir.join(file_name);
+            doc.path = DocPath::Native(path.clone());
+            doc.save().unwrap();
+
+            let (opened, _) = Document::open_from_path(path).unwrap();
+            assert!(opened.bgm == doc.bgm, "{} didn't round-trip", file_name);
+            assert_eq!(opened.bgm.track_lists.keys().collect::<Vec<_>>(), vec![&track_list]);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::ops::Range;
+
+use imui_glium::UiFrame;
+use pm64::bgm::*;
+
+use crate::interface::hot::Hot;
+
+/// Controls for where a segment ("variation") loops, given as ticks from its start.
+#[derive(Clone)]
+pub struct LoopEditor {
+    start: String,
+    end: String,
+    /// The segment and loop that `start` and `end` were filled in from. When the loop changes some other way, e.g.
+    /// by undoing, they are filled in again.
+    shown: Option<(usize, Option<Range<usize>>)>,
+    error: Option<String>,
+}
+
+impl LoopEditor {
+    pub fn new() -> Self {
+        LoopEditor {
+            start: String::new(),
+            end: String::new(),
+            shown: None,
+            error: None,
+        }
+    }
+
+    pub fn update(&mut self, ui: &mut UiFrame<'_>, bgm: &mut Bgm, segment_idx: usize, hot: &mut Hot) {
+        let current = match &bgm.segments[segment_idx] {
+            Some(segment) => segment.loop_range(&bgm.track_lists),
+            None => return,
+        };
+
+        if self.shown.as_ref() != Some(&(segment_idx, current.clone())) {
+            match &current {
+                Some(range) => {
+                    self.start = range.start.to_string();
+                    self.end = range.end.to_string();
+                }
+                None => {
+                    self.start.clear();
+                    self.end.clear();
+                }
+            }
+
+            self.shown = Some((segment_idx, current.clone()));
+            self.error = None;
+        }
+
+        let mut to_set = false;
+        let mut to_preview = false;
+
+        ui.hbox("loop", |ui| {
+            ui.text("from", "Loop from tick").center_y();
+            ui.pad("from pad", 10.0);
+            ui.text_input("start", &mut self.start).with_width(100.0);
+            ui.pad("to pad", 10.0);
+            ui.text("to", "to").center_y();
+            ui.pad("end pad", 10.0);
+            ui.text_input("end", &mut self.end).with_width(100.0);
+            ui.pad("btn pad", 10.0);
+
+            if ui.button("set", "Set loop").clicked() {
+                to_set = true;
+            }
+
+            if ui.button("preview", "Preview loop")
+                .tooltip("Play this variation, skipping to the start of the loop")
+                .clicked()
+            {
+                to_preview = true;
+            }
+        });
+
+        if let Some(error) = &self.error {
+            ui.text("loop error", error);
+        }
+
+        if to_set {
+            let range = match (self.start.trim().parse(), self.end.trim().parse()) {
+                (Ok(start), Ok(end)) => start..end,
+                _ => {
+                    self.error = Some("The loop start and end must be whole numbers of ticks".to_owned());
+                    return;
+                }
+            };
+
+            self.error = bgm.set_loop(segment_idx, range).err().map(|error| error.to_string());
+        } else if to_preview {
+            if current.is_none() {
+                self.error = Some("This variation doesn't loop".to_owned());
+            } else if let Err(error) = hot.play_bgm(&loop_preview(bgm, segment_idx)) {
+                self.error = Some(error.to_string());
+            }
+        }
+    }
+}
+
+/// A copy of `bgm` that plays only the segment at `segment_idx`, skipping straight to where it starts looping.
+fn loop_preview(bgm: &Bgm, segment_idx: usize) -> Bgm {
+    let mut preview = bgm.clone();
+    let mut segment = match bgm.segments[segment_idx].clone() {
+        Some(segment) => segment,
+        None => return preview,
+    };
+
+    if let Some(loop_start) = segment.subsegments.iter().position(Subsegment::is_loop_start) {
+        for subsegment in &mut segment.subsegments[..loop_start] {
+            if let Subsegment::Tracks { track_list, .. } = subsegment {
+                // Skipped sections still set things up, like the tempo. The loop might use the same track list, so
+                // skip a copy of it.
+                if let Some(mut skipped) = preview.track_lists.get(&*track_list).cloned() {
+                    skipped.silence_skip();
+                    *track_list = preview.add_track_list(skipped);
+                }
+            }
+        }
+    }
+
+    preview.segments = [Some(segment), None, None, None];
+    preview
+}
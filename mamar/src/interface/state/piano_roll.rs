@@ -0,0 +1,337 @@
+use std::ops::Range;
+
+use imui_glium::{BlockDrag, BlockHandle, Point, Rect, Size, UiFrame};
+use pm64::bgm::*;
+
+const WIDTH: f32 = 740.0;
+const ROW_HEIGHT: f32 = 12.0;
+const RULER_HEIGHT: f32 = 16.0;
+
+/// How far the ruler can be dragged before it counts as selecting rather than clicking, in pixels.
+const RULER_CLICK_SLOP: f32 = 3.0;
+
+/// Ticks per beat. A grid line is drawn on every beat.
+const BEAT: usize = 48;
+
+/// Choices of grid to snap notes to, in ticks.
+const SNAPS: [(usize, &str); 5] = [(48, "1/4"), (24, "1/8"), (12, "1/16"), (6, "1/32"), (1, "Off")];
+
+const MIN_ZOOM: f32 = 0.125;
+const MAX_ZOOM: f32 = 8.0;
+
+const NEW_NOTE_VELOCITY: u8 = 100;
+const MIN_NEW_NOTE_LENGTH: usize = 12;
+
+const BLACK_KEY_COLOR: [f32; 4] = [0.11, 0.13, 0.15, 1.0];
+const BEAT_COLOR: [f32; 4] = [0.25, 0.28, 0.31, 1.0];
+const PAST_END_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+const NOTE_COLOR: [f32; 4] = [0.4, 0.6, 1.0, 1.0];
+const DRAGGED_NOTE_COLOR: [f32; 4] = [0.6, 0.8, 1.0, 1.0];
+const RULER_COLOR: [f32; 4] = [0.18, 0.21, 0.24, 1.0];
+const SELECTION_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.15];
+const PLAYHEAD_COLOR: [f32; 4] = [1.0, 0.8, 0.3, 1.0];
+const PLAYBACK_COLOR: [f32; 4] = [0.4, 1.0, 0.5, 1.0];
+
+/// Editor for the notes of one track, shown as blocks on a grid of pitch (rows) against time (columns).
+#[derive(Clone)]
+pub struct PianoRoll {
+    /// Ticks scrolled past at the left.
+    scroll: f32,
+    /// Pixels per tick.
+    zoom: f32,
+    /// Notes are placed on multiples of this many ticks.
+    snap: usize,
+    /// The note being dragged and how, as of the last update.
+    drag: Option<(usize, BlockDrag)>,
+    /// Where pasted commands go, in ticks.
+    playhead: usize,
+    /// Ticks to copy or cut, chosen by dragging along the ruler.
+    selection: Option<Range<usize>>,
+    /// Where the song playing has got to, in ticks, if it is playing this section.
+    playing_at: Option<usize>,
+}
+
+enum Edit {
+    Change { index: usize, note: NoteRef },
+    Add(NoteRef),
+    Delete(usize),
+}
+
+impl PianoRoll {
+    pub fn new() -> Self {
+        PianoRoll {
+            scroll: 0.0,
+            zoom: 1.0,
+            snap: 12,
+            drag: None,
+            playhead: 0,
+            selection: None,
+            playing_at: None,
+        }
+    }
+
+    pub fn playhead(&self) -> usize {
+        self.playhead
+    }
+
+    pub fn set_playhead(&mut self, time: usize) {
+        self.playhead = time;
+    }
+
+    pub fn selection(&self) -> Option<Range<usize>> {
+        self.selection.clone()
+    }
+
+    /// Shows where the song playing has got to, in ticks from the start of the section. Times past the end of the
+    /// track, e.g. because a later section is playing, aren't shown.
+    pub fn set_playing_at(&mut self, time: Option<usize>) {
+        self.playing_at = time;
+    }
+
+    pub fn update(&mut self, ui: &mut UiFrame<'_>, track: &mut Track) {
+        // Notes have to come before the end of the track, or they would never play.
+        let end_time = track.commands.len_time();
+
+        ui.hbox("toolbar", |ui| {
+            ui.text(0, "Zoom").center_y();
+            ui.pad(1, 10.0);
+            if ui.button(2, "-").with_width(36.0).clicked() {
+                self.zoom = (self.zoom / 2.0).max(MIN_ZOOM);
+            }
+            if ui.button(3, "+").with_width(36.0).clicked() {
+                self.zoom = (self.zoom * 2.0).min(MAX_ZOOM);
+            }
+
+            ui.pad(4, 20.0);
+            ui.text(5, "Snap").center_y();
+            ui.pad(6, 10.0);
+            let mut snap_idx = SNAPS.iter().position(|(ticks, _)| *ticks == self.snap).unwrap_or(0);
+            if ui.dropdown(7, &mut snap_idx, SNAPS.iter().map(|(_, name)| name.to_string())) {
+                self.snap = SNAPS[snap_idx].0;
+            }
+
+            ui.pad(8, 20.0);
+            ui.text(9, "Scroll").center_y();
+            ui.pad(10, 10.0);
+            let max_scroll = (end_time as f32 - WIDTH / self.zoom).max(0.0);
+            self.scroll = self.scroll.min(max_scroll);
+            ui.slider(11, &mut self.scroll, 0.0..=max_scroll);
+        });
+
+        ui.pad("toolbar pad", 10.0);
+
+        if end_time == 0 {
+            ui.text("empty", "This track is empty, so there is nowhere to put notes.");
+            return;
+        }
+
+        let (scroll, zoom) = (self.scroll, self.zoom);
+        let x = move |time: f32| (time - scroll) * zoom;
+        let y = |pitch: u8| (PITCH_MAX - pitch) as f32 * ROW_HEIGHT;
+        let height = y(PITCH_MIN) + ROW_HEIGHT;
+        let playing_at = self.playing_at.filter(|time| *time < end_time);
+
+        ui.text("position", match &self.selection {
+            Some(selection) => format!("Playhead {}, selected {} to {}", self.playhead, selection.start, selection.end),
+            None => format!("Playhead {}, nothing selected", self.playhead),
+        });
+
+        // Click the ruler to move the playhead, or drag along it to select.
+        let mut ruler_drag = None;
+        ui.canvas("ruler", WIDTH, RULER_HEIGHT, |ui| {
+            ruler_drag = ui.block("ruler", Rect::new(Point::zero(), Size::new(WIDTH, RULER_HEIGHT)), RULER_COLOR);
+
+            if let Some(selection) = &self.selection {
+                let rect = Rect::new(
+                    Point::new(x(selection.start as f32), 0.0),
+                    Size::new(selection.len() as f32 * self.zoom, RULER_HEIGHT),
+                );
+                ui.rectangle("selection", rect, SELECTION_COLOR);
+            }
+
+            let rect = Rect::new(Point::new(x(self.playhead as f32), 0.0), Size::new(2.0, RULER_HEIGHT));
+            ui.rectangle("playhead", rect, PLAYHEAD_COLOR);
+
+            if let Some(time) = playing_at {
+                let rect = Rect::new(Point::new(x(time as f32), 0.0), Size::new(2.0, RULER_HEIGHT));
+                ui.rectangle("playing at", rect, PLAYBACK_COLOR);
+            }
+        });
+
+        if let Some(ruler_drag) = ruler_drag {
+            let time = |x: f32| self.snap_nearest((x / self.zoom + self.scroll).max(0.0) as usize).min(end_time);
+            let start = time(ruler_drag.start.x);
+            let end = time(ruler_drag.start.x + ruler_drag.delta.x);
+
+            if ruler_drag.delta.length() <= RULER_CLICK_SLOP {
+                if ruler_drag.is_finished {
+                    self.playhead = start;
+                    self.selection = None;
+                }
+            } else if start != end {
+                self.selection = Some(start.min(end)..start.max(end));
+            } else {
+                self.selection = None;
+            }
+        }
+
+        let mut edit = None;
+        let mut drag = None;
+        let mut clicked = None;
+
+        ui.scroll_area_with_scrollbar("roll", |ui| {
+            clicked = ui.canvas("canvas", WIDTH, height, |ui| {
+                for pitch in PITCH_MIN..=PITCH_MAX {
+                    if is_black_key(pitch) {
+                        let rect = Rect::new(Point::new(0.0, y(pitch)), Size::new(WIDTH, ROW_HEIGHT));
+                        ui.rectangle(("row", pitch), rect, BLACK_KEY_COLOR);
+                    }
+                }
+
+                let visible = self.scroll as usize..(self.scroll + WIDTH / self.zoom) as usize + 1;
+                for time in (0..end_time).step_by(BEAT).filter(|time| visible.contains(time)) {
+                    let rect = Rect::new(Point::new(x(time as f32), 0.0), Size::new(1.0, height));
+                    ui.rectangle(("beat", time), rect, BEAT_COLOR);
+                }
+
+                let end_x = x(end_time as f32).max(0.0);
+                if end_x < WIDTH {
+                    let rect = Rect::new(Point::new(end_x, 0.0), Size::new(WIDTH - end_x, height));
+                    ui.rectangle("past end", rect, PAST_END_COLOR);
+                }
+
+                if let Some(selection) = &self.selection {
+                    let rect = Rect::new(
+                        Point::new(x(selection.start as f32), 0.0),
+                        Size::new(selection.len() as f32 * self.zoom, height),
+                    );
+                    ui.rectangle("selection", rect, SELECTION_COLOR);
+                }
+
+                for (i, note) in track.commands.notes().enumerate() {
+                    // Show where the note will end up while it is being dragged.
+                    let preview = match self.drag {
+                        Some((index, drag)) if index == i => self.drag_note(note, &drag, end_time),
+                        _ => note,
+                    };
+
+                    let rect = Rect::new(
+                        Point::new(x(preview.time as f32), y(preview.pitch)),
+                        Size::new((preview.length as f32 * self.zoom).max(2.0), ROW_HEIGHT),
+                    );
+                    let color = if preview == note { NOTE_COLOR } else { DRAGGED_NOTE_COLOR };
+
+                    if let Some(block_drag) = ui.block(("note", i), rect, color) {
+                        if block_drag.is_finished {
+                            let changed = self.drag_note(note, &block_drag, end_time);
+                            if changed != note {
+                                edit = Some(Edit::Change { index: i, note: changed });
+                            }
+                        } else {
+                            drag = Some((i, block_drag));
+                        }
+                    }
+
+                    if ui.context_menu(("note menu", i), &["Delete"]) == Some(0) {
+                        edit = Some(Edit::Delete(i));
+                    }
+                }
+
+                let rect = Rect::new(Point::new(x(self.playhead as f32), 0.0), Size::new(2.0, height));
+                ui.rectangle("playhead", rect, PLAYHEAD_COLOR);
+
+                if let Some(time) = playing_at {
+                    let rect = Rect::new(Point::new(x(time as f32), 0.0), Size::new(2.0, height));
+                    ui.rectangle("playing at", rect, PLAYBACK_COLOR);
+                }
+            });
+        });
+
+        self.drag = drag;
+
+        if let Some(point) = clicked {
+            let time = self.snap_down((point.x / self.zoom + self.scroll) as usize);
+            let row = (point.y / ROW_HEIGHT) as u8;
+
+            if time < end_time && row <= PITCH_MAX - PITCH_MIN {
+                edit = Some(Edit::Add(NoteRef {
+                    time,
+                    pitch: PITCH_MAX - row,
+                    velocity: NEW_NOTE_VELOCITY,
+                    length: self.snap.max(MIN_NEW_NOTE_LENGTH) as u16,
+                }));
+            }
+        }
+
+        match edit {
+            Some(Edit::Change { index, note }) => {
+                let original = track.commands.notes().nth(index).unwrap();
+
+                if note.time == original.time {
+                    // Times can't be changed in place, but everything else can.
+                    if let Some(note_mut) = track.commands.notes_mut().nth(index) {
+                        *note_mut.pitch = note.pitch;
+                        *note_mut.length = note.length;
+                    }
+                } else {
+                    track.commands.remove_note(index);
+                    insert_note(&mut track.commands, note);
+                }
+            }
+            Some(Edit::Add(note)) => insert_note(&mut track.commands, note),
+            Some(Edit::Delete(index)) => {
+                track.commands.remove_note(index);
+            }
+            None => {}
+        }
+    }
+
+    /// Where `note` would be after being dragged by `drag`, snapped to the grid.
+    fn drag_note(&self, note: NoteRef, drag: &BlockDrag, end_time: usize) -> NoteRef {
+        let ticks = (drag.delta.x / self.zoom).round() as isize;
+
+        match drag.handle {
+            BlockHandle::Body => {
+                let time = (note.time as isize + ticks).max(0) as usize;
+                let rows = (drag.delta.y / ROW_HEIGHT).round() as isize;
+                let pitch = (note.pitch as isize - rows).clamp(PITCH_MIN as isize, PITCH_MAX as isize);
+
+                NoteRef {
+                    time: self.snap_nearest(time).min(end_time - 1),
+                    pitch: pitch as u8,
+                    ..note
+                }
+            }
+            BlockHandle::End => {
+                let end = self.snap_nearest((note.end_time() as isize + ticks).max(0) as usize);
+                let length = end.saturating_sub(note.time).max(self.snap);
+
+                NoteRef {
+                    length: length.min(u16::MAX as usize) as u16,
+                    ..note
+                }
+            }
+        }
+    }
+
+    fn snap_nearest(&self, time: usize) -> usize {
+        (time + self.snap / 2) / self.snap * self.snap
+    }
+
+    fn snap_down(&self, time: usize) -> usize {
+        time / self.snap * self.snap
+    }
+}
+
+fn insert_note(commands: &mut CommandSeq, note: NoteRef) {
+    commands.insert_at_tick(note.time, Command::Note {
+        pitch: note.pitch,
+        velocity: note.velocity,
+        length: note.length,
+    });
+}
+
+/// Whether the row for `pitch` is a black key on a piano keyboard. PITCH_MIN is a C.
+fn is_black_key(pitch: u8) -> bool {
+    matches!((pitch - PITCH_MIN) % 12, 1 | 3 | 6 | 8 | 10)
+}
@@ -1,6 +1,13 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use pm64::bgm::*;
+use pm64::hot::LiveChange;
+
+use super::voices::{self, VoiceList};
+use crate::interface::form::labelled_slider;
 
-use crate::interface::form::range_select;
+/// How far, in ticks, Humanize can move a note.
+const HUMANIZE_TICKS: usize = 2;
 
 #[derive(Clone)]
 pub struct TrackListInterface {
@@ -9,6 +16,12 @@ pub struct TrackListInterface {
 
     is_edit_voice: bool,
     is_set_instrument: bool,
+
+    /// The list of every voice in the song, if it is open.
+    voice_list: Option<VoiceList>,
+
+    /// A voice the user asked to hear, waiting to be played.
+    audition: Option<Voice>,
 }
 
 impl TrackListInterface {
@@ -17,17 +30,57 @@ impl TrackListInterface {
             editing_index: None,
             is_edit_voice: false,
             is_set_instrument: false,
+            voice_list: None,
+            audition: None,
         }
     }
 
-    pub fn update(&mut self, ui: &mut imui_glium::UiFrame<'_>, track_list: &mut TrackList, voices: &mut [Voice]) {
-        ui.vbox(0, |ui| {
+    pub fn show_voices(&mut self) {
+        self.voice_list = Some(VoiceList::default());
+    }
+
+    /// Returns a voice to play a few notes of, if the user asked to hear one since this was last called.
+    pub fn take_audition(&mut self) -> Option<Voice> {
+        self.audition.take()
+    }
+
+    /// Returns the index of a track to open in the piano roll, if one was chosen. Slider changes that can be heard
+    /// without reloading the song are added to `live_changes`.
+    pub fn update(
+        &mut self,
+        ui: &mut imui_glium::UiFrame<'_>,
+        track_list: &mut TrackList,
+        voices: &mut Vec<Voice>,
+        live_changes: &mut Vec<LiveChange>,
+    ) -> Option<usize> {
+        if let Some(voice_list) = &mut self.voice_list {
+            let (to_audition, is_closed) = voice_list.update(ui, voices);
+            self.audition = to_audition.or_else(|| self.audition.take());
+            if is_closed {
+                self.voice_list = None;
+            }
+        }
+
+        let mut to_delete = None;
+        let mut to_duplicate = None;
+
+        ui.scroll_area_with_scrollbar(0, |ui| {
             for (i, track) in track_list.tracks.iter_mut().enumerate() {
                 ui.hbox(i as u8, |ui| {
                     if ui.button(0, &track.name).with_width(300.0).clicked() {
                         self.editing_index = Some(i);
                     }
 
+                    if i != 0 {
+                        match ui.context_menu(7, &["Mute", "Solo", "Delete", "Duplicate"]) {
+                            Some(0) => track.mute = !track.mute,
+                            Some(1) => track.solo = !track.solo,
+                            Some(2) => to_delete = Some(i),
+                            Some(3) => to_duplicate = Some(i),
+                            _ => {}
+                        }
+                    }
+
                     if i == 0 {
                         ui.pad(1, 36.0 * 2.0);
                     } else {
@@ -41,16 +94,50 @@ impl TrackListInterface {
             }
         });
 
+        if let Some(i) = to_delete {
+            track_list.tracks[i] = Track::default();
+            if self.editing_index == Some(i) {
+                self.editing_index = None;
+            }
+        }
+
+        if let Some(i) = to_duplicate {
+            if track_list.duplicate_track(i).is_none() {
+                log::warn!("no empty track to duplicate track {} into", i);
+            }
+        }
+
         if let Some(editing_index) = self.editing_index {
             let track = &mut track_list.tracks[editing_index];
             let is_master = editing_index == 0;
+            let mut to_split = false;
+            let mut open_piano_roll = false;
 
-            ui.modal(1, true, (600.0, 300.0), |ui| {
-                ui.text(0, &track.name);
+            ui.modal(1, true, (600.0, 500.0), |ui| {
+                ui.text(0, &track.name).with_width(560.0).wrap();
                 ui.pad(1, 20.0);
-                ui.text(2, format!("Flags: {:#06X}", track.flags));
+                ui.text(2, format!("Flags: {:#06X}", track.flags)).selectable();
                 ui.pad(3, 10.0);
 
+                let command_count = track.commands.command_count();
+                ui.hbox(21, |ui| {
+                    ui.text(0, format!("Commands: {} / {}", command_count, TRACK_COMMANDS_MAX)).center_y();
+
+                    if command_count > TRACK_COMMANDS_MAX {
+                        ui.pad(1, 10.0);
+
+                        if ui.button(2, "Thin")
+                            .with_width(140.0)
+                            .tooltip("Remove commands that change nothing, such as setting what is already set")
+                            .clicked()
+                        {
+                            let removed = track.commands.remove_redundant();
+                            log::info!("thinned track {} by {} commands", editing_index, removed);
+                        }
+                    }
+                });
+                ui.pad(22, 10.0);
+
                 let mut polyphony_amt = 0;
 
                 if !is_master {
@@ -92,8 +179,16 @@ impl TrackListInterface {
                         self.is_set_instrument = false;
                     }
 
+                    if ui.button(12, "Split by voice").clicked() {
+                        to_split = true;
+                    }
+
+                    if ui.button(16, "Piano Roll...").clicked() {
+                        open_piano_roll = true;
+                    }
+
                     if self.is_edit_voice {
-                        ui.modal(7, true, (600.0, 700.0), |ui| {
+                        ui.modal(7, true, (600.0, 800.0), |ui| {
                             ui.text(0, format!("Voice of '{}'", track.name));
                             ui.pad(1, 20.0);
 
@@ -106,11 +201,11 @@ impl TrackListInterface {
                                     // TODO: add reverby stuff
                                     Command::TrackVoice(a) => {
                                         match voice {
-                                            Some(b) if a == b => false, // Same as current voice
+                                            Some(b) if *a == b => false, // Same as current voice
                                             Some(_) => true, // Voice change D:
                                             None => {
                                                 // We've learned what voice index this track uses!
-                                                voice = Some(a);
+                                                voice = Some(*a);
                                                 false
                                             }
                                         }
@@ -119,9 +214,28 @@ impl TrackListInterface {
                                 })
                                 .collect();
 
-                            if let Some(voice) = voice {
-                                ui.known_size(2, 480.0, 400.0, |ui| {
-                                    self.voice_ui(ui, &mut voices[*voice as usize]);
+                            let mut to_use_voice = None;
+
+                            if let Some(voice) = voice.filter(|voice| (*voice as usize) < voices.len()) {
+                                ui.hbox(10, |ui| {
+                                    ui.text(0, "Uses voice").center_y();
+                                    ui.pad(1, 10.0);
+
+                                    let mut selected = voice as usize;
+                                    let names = voices.iter().enumerate().map(|(i, v)| {
+                                        format!("{}: {}", i, voices::name(v))
+                                    });
+                                    if ui.dropdown(2, &mut selected, names) {
+                                        to_use_voice = Some(selected as u8);
+                                    }
+                                });
+                                ui.pad(11, 10.0);
+
+                                ui.known_size(2, 480.0, 450.0, |ui| {
+                                    let voice = &mut voices[voice as usize];
+                                    if voices::voice_editor(ui, voice, &mut self.is_set_instrument) {
+                                        self.audition = Some(voice.clone());
+                                    }
                                 });
                             } else {
                                 ui.text(3, "This track does not use the voice system.");
@@ -158,6 +272,14 @@ impl TrackListInterface {
                                 ui.pad(9, 20.0);
                             }
 
+                            // Only the first voice command is changed, so later voice changes are kept.
+                            if let Some(new_voice) = to_use_voice {
+                                let first = track.commands.iter_mut().find(|cmd| matches!(cmd, Command::TrackVoice(_)));
+                                if let Some(Command::TrackVoice(voice)) = first {
+                                    *voice = new_voice;
+                                }
+                            }
+
                             if ui.button(99, "Close").clicked() {
                                 self.is_edit_voice = false;
                                 self.is_set_instrument = false;
@@ -166,79 +288,150 @@ impl TrackListInterface {
                     }
                 }
 
-                ui.known_size(8, 300.0, 64.0, |ui| {
-                    if polyphony_amt >= 2 {
-                        ui.text(0, "Warning: game is likely to crash if many tracks use polyphony.")
-                            .center_y();
+                // Sliders for the initial tempo, volume and pan, if the track sets them.
+                let track_idx = editing_index as u8;
+                ui.pad(13, 10.0);
+                if is_master {
+                    let tempo = track.commands.iter_mut().find(|cmd| matches!(cmd, Command::MasterTempo(_)));
+                    if let Some(Command::MasterTempo(bpm)) = tempo {
+                        let mut value = *bpm as f32;
+                        let range = *de::EXPECTED_TEMPO.start() as f32..=*de::EXPECTED_TEMPO.end() as f32;
+                        if labelled_slider(ui, 14, format!("Tempo {}", bpm), &mut value, range) {
+                            *bpm = value.round() as u16;
+                            live_changes.push(LiveChange::Tempo(*bpm));
+                        }
                     }
-                });
 
-                if ui.button(99, "Close").clicked() {
-                    self.editing_index = None;
-                }
-            });
-        }
-    }
-
-    pub fn voice_ui(&mut self, ui: &mut imui_glium::UiFrame<'_>, voice: &mut Voice) {
-        use pm64::bgm::voice::*;
-
-        // The bank u8 is split into two nibbles: "bank" and "staccatoness" (awful names, I know).
-        let mut bank_upper = voice.bank >> 4; // TODO: range 0..=6?
-        let mut bank_lower = voice.bank & 0xF;
+                    let volume = track.commands.iter_mut().find(|cmd| matches!(cmd, Command::MasterVolume(_)));
+                    if let Some(Command::MasterVolume(volume)) = volume {
+                        let mut value = *volume as f32;
+                        if labelled_slider(ui, 15, format!("Volume {}", volume), &mut value, 0.0..=255.0) {
+                            *volume = value.round() as u8;
+                            live_changes.push(LiveChange::MasterVolume(*volume));
+                        }
+                    }
+                } else {
+                    let volume = track.commands.iter_mut().find(|cmd| matches!(cmd, Command::SubTrackVolume(_)));
+                    if let Some(Command::SubTrackVolume(volume)) = volume {
+                        let mut value = *volume as f32;
+                        if labelled_slider(ui, 15, format!("Volume {}", volume), &mut value, 0.0..=255.0) {
+                            *volume = value.round() as u8;
+                            live_changes.push(LiveChange::TrackVolume {
+                                track: track_idx,
+                                volume: *volume,
+                            });
+                        }
+                    }
 
-        ui.vbox(0, |ui| {
-            ui.hbox(0, |ui| {
-                if ui.button(0, "Set instrument...").with_width(210.0).clicked() {
-                    self.is_set_instrument = true;
+                    let pan = track.commands.iter_mut().find(|cmd| matches!(cmd, Command::SubTrackPan(_)));
+                    if let Some(Command::SubTrackPan(pan)) = pan {
+                        // Left = 0, middle = 64, right = 127.
+                        let mut value = *pan as f32;
+                        if labelled_slider(ui, 14, format!("Pan {}", pan), &mut value, 0.0..=127.0) {
+                            *pan = value.round() as i8;
+                            live_changes.push(LiveChange::TrackPan {
+                                track: track_idx,
+                                pan: *pan as u8,
+                            });
+                        }
+                    }
                 }
 
-                ui.pad(1, 20.0);
+                if !track.get_flag(track_flags::DRUM_TRACK) {
+                    ui.pad(10, 10.0);
+                    ui.hbox(11, |ui| {
+                        ui.text(0, "Transpose").center_y();
+                        ui.pad(1, 10.0);
 
-                if let Some(name) = INSTRUMENTS_BY_ID.get(&(bank_upper, voice.patch)) {
-                    ui.text(2, *name).center_y();
-                }
-            });
+                        let mut semitones = 0;
+                        if ui.button(2, "-12").with_width(60.0).clicked() {
+                            semitones = -12;
+                        }
+                        if ui.button(3, "-1").with_width(60.0).clicked() {
+                            semitones = -1;
+                        }
+                        if ui.button(4, "+1").with_width(60.0).clicked() {
+                            semitones = 1;
+                        }
+                        if ui.button(5, "+12").with_width(60.0).clicked() {
+                            semitones = 12;
+                        }
 
-            if self.is_set_instrument {
-                ui.modal(1, true, (600.0, 500.0), |ui| {
-                    ui.hbox(0, |ui| {
-                        let mut i = 0;
-                        for (name, (instr_bank_upper, patch)) in INSTRUMENTS_BY_NAME.iter() {
-                            let approx_width = name.len() as f32 * 10.0; // XXX
+                        if semitones != 0 {
+                            track.transpose(semitones, OutOfRange::Clamp);
+                        }
+                    });
+                }
 
-                            if ui.button(i, *name).with_width(approx_width).clicked() {
-                                bank_upper = *instr_bank_upper;
-                                voice.patch = *patch;
-                                self.is_set_instrument = false;
-                            }
-                            i += 1;
+                if !is_master {
+                    ui.pad(17, 10.0);
+                    ui.hbox(18, |ui| {
+                        ui.text(0, "Velocity").center_y();
+                        ui.pad(1, 10.0);
+
+                        if ui.button(2, "Normalize")
+                            .with_width(140.0)
+                            .tooltip("Scale every note's velocity so the loudest is as loud as can be")
+                            .clicked()
+                        {
+                            track.commands.normalize_velocity(VELOCITY_MAX);
+                        }
+                        if ui.button(3, "Compress")
+                            .with_width(140.0)
+                            .tooltip("Halve how much quieter each note is than the loudest")
+                            .clicked()
+                        {
+                            track.commands.compress_velocity(2.0);
                         }
                     });
-                });
-            }
 
-            ui.pad(2, 10.0);
+                    ui.pad(19, 10.0);
+                    ui.hbox(20, |ui| {
+                        ui.text(0, "Timing").center_y();
+                        ui.pad(1, 10.0);
+
+                        if ui.button(2, "Swing")
+                            .with_width(140.0)
+                            .tooltip("Delay every other eighth note by a third of its length, for a triplet feel")
+                            .clicked()
+                        {
+                            track.commands.apply_swing(TICKS_PER_BEAT as u32 / 2, 1.0 / 3.0);
+                        }
+                        if ui.button(3, "Humanize")
+                            .with_width(140.0)
+                            .tooltip("Nudge each note a little earlier or later at random")
+                            .clicked()
+                        {
+                            let seed = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |since| since.as_nanos() as u64);
+                            track.commands.humanize(HUMANIZE_TICKS, seed);
+                        }
+                    });
+                }
 
-            range_select(ui, 3, 0..=255, 1, &mut bank_upper, |v| format!("Bank {}", v));
-            range_select(ui, 4, 0..=255, 1, &mut voice.patch, |v| format!("Patch {:#04X}", v));
+                ui.known_size(8, 300.0, 64.0, |ui| {
+                    if polyphony_amt >= 2 {
+                        ui.text(0, "Warning: game is likely to crash if many tracks use polyphony.")
+                            .center_y();
+                    }
+                });
 
-            range_select(ui, 5, 0..=3, 1, &mut bank_lower, |v| {
-                match *v {
-                    0 => "Staccato: no".to_string(),
-                    _ => format!("Staccato: {}", v),
+                if ui.button(99, "Close").clicked() {
+                    self.editing_index = None;
                 }
             });
-            range_select(ui, 6, 0..=255, 1, &mut voice.volume, |v| format!("Volume {}", v));
-            range_select(ui, 7, -128..=127, 1, &mut voice.pan, |v| {
-                let a = v.abs();
-                format!("Pan {} ({})", v, if a > 64 { "right" } else if a == 64 { "middle" } else { "left" })
-            });
-            range_select(ui, 8, 0..=255, 1, &mut voice.reverb, |v| format!("Reverb {}", v));
-            range_select(ui, 9, 0..=255, 1, &mut voice.coarse_tune, |v| format!("Coarse tune {}", v));
-            range_select(ui, 10, 0..=255, 1, &mut voice.fine_tune, |v| format!("Fine tune {}", v));
-        });
 
-        voice.bank = (bank_upper << 4) | bank_lower;
+            if to_split && !track_list.split_track_by_voice(editing_index) {
+                log::warn!("not enough empty tracks to split track {} by voice", editing_index);
+            }
+
+            if open_piano_roll {
+                self.editing_index = None;
+                return Some(editing_index);
+            }
+        }
+
+        None
     }
 }
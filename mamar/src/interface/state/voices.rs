@@ -0,0 +1,294 @@
+//! Listing and editing a song's voices, which tracks choose between with [Command::TrackVoice].
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pm64::bank::{self, Instrument};
+use pm64::bgm::*;
+use pm64::bgm::voice::{instruments, INSTRUMENTS_BY_ID};
+
+use crate::interface::form::range_select;
+use crate::interface::settings;
+
+thread_local! {
+    /// The instruments of the ROM chosen to read them from, or `None` to use the built-in list.
+    static ROM_INSTRUMENTS: RefCell<Option<Vec<Instrument>>> = RefCell::new(load_remembered_rom());
+}
+
+/// What a new voice starts as.
+const NEW_VOICE: Voice = Voice {
+    bank: 0x30,
+    patch: 0x02, // Marimba
+    volume: 100,
+    pan: 64,
+    reverb: 0,
+    coarse_tune: 0,
+    fine_tune: 0,
+    unk_07: 0,
+};
+
+#[derive(Clone, Default)]
+pub struct VoiceList {
+    /// Index of the voice we are editing, if any.
+    editing_index: Option<usize>,
+
+    is_set_instrument: bool,
+}
+
+impl VoiceList {
+    /// Shows every voice of the song in a modal. Returns a voice to audition, if one was chosen, and whether the list
+    /// was closed.
+    pub fn update(&mut self, ui: &mut imui_glium::UiFrame<'_>, voices: &mut Vec<Voice>) -> (Option<Voice>, bool) {
+        let mut to_audition = None;
+        let mut is_closed = false;
+
+        ui.modal("voice list", true, (700.0, 600.0), |ui| {
+            ui.text(0, "Voices");
+            ui.pad(1, 20.0);
+
+            ui.known_size(2, 650.0, 420.0, |ui| {
+                ui.scroll_area_with_scrollbar(0, |ui| {
+                    for (i, voice) in voices.iter().enumerate() {
+                        ui.hbox(i, |ui| {
+                            if ui.button(0, format!("{}: {}", i, name(voice))).with_width(300.0).clicked() {
+                                self.editing_index = Some(i);
+                                self.is_set_instrument = false;
+                            }
+
+                            ui.pad(1, 10.0);
+
+                            if ui.button(2, "Play").with_width(80.0).clicked() {
+                                to_audition = Some(voice.clone());
+                            }
+
+                            ui.pad(3, 10.0);
+                            ui.text(4, format!("Vol {} Pan {} Rev {}", voice.volume, voice.pan, voice.reverb))
+                                .center_y();
+                        });
+                    }
+                });
+            });
+
+            ui.pad(3, 10.0);
+
+            ui.hbox(4, |ui| {
+                // Tracks refer to voices by a u8 index
+                if voices.len() <= u8::MAX as usize && ui.button(0, "Add voice").with_width(150.0).clicked() {
+                    voices.push(NEW_VOICE);
+                    self.editing_index = Some(voices.len() - 1);
+                }
+
+                ui.pad(1, 10.0);
+
+                if ui.button(99, "Close").clicked() {
+                    is_closed = true;
+                }
+            });
+
+            if let Some(voice) = self.editing_index.and_then(|i| voices.get_mut(i)) {
+                ui.modal(5, true, (600.0, 600.0), |ui| {
+                    ui.known_size(0, 480.0, 450.0, |ui| {
+                        if voice_editor(ui, voice, &mut self.is_set_instrument) {
+                            to_audition = Some(voice.clone());
+                        }
+                    });
+
+                    if ui.button(99, "Close").clicked() {
+                        self.editing_index = None;
+                        self.is_set_instrument = false;
+                    }
+                });
+            }
+        });
+
+        (to_audition, is_closed)
+    }
+}
+
+/// What to call a voice: the name of its instrument, if we know it.
+pub fn name(voice: &Voice) -> String {
+    match instrument_name(voice) {
+        Some(name) => name,
+        None => format!("Bank {} patch {:#04X}", voice.bank_upper(), voice.patch),
+    }
+}
+
+/// The name of a voice's instrument. If a ROM has been read, only its instruments have names.
+fn instrument_name(voice: &Voice) -> Option<String> {
+    ROM_INSTRUMENTS.with(|rom| match &*rom.borrow() {
+        Some(instruments) => instruments
+            .iter()
+            .find(|instrument| (instrument.bank, instrument.patch) == (voice.bank_upper(), voice.patch))
+            .map(rom_instrument_name),
+        None => voice.instrument_name().map(str::to_owned),
+    })
+}
+
+/// Every instrument that can be chosen, as (name, (upper bank, patch)): the ROM's, if one has been read, or else the
+/// built-in list.
+fn instrument_list() -> Vec<(String, (u8, u8))> {
+    ROM_INSTRUMENTS.with(|rom| match &*rom.borrow() {
+        Some(instruments) => instruments
+            .iter()
+            .map(|instrument| (rom_instrument_name(instrument), (instrument.bank, instrument.patch)))
+            .collect(),
+        None => instruments().into_iter().map(|(name, id)| (name.to_owned(), id)).collect(),
+    })
+}
+
+/// What to call an instrument of a ROM. The ROM only names the bank file it is in, so the name it has in the built-in
+/// list comes first, if it has one.
+fn rom_instrument_name(instrument: &Instrument) -> String {
+    match INSTRUMENTS_BY_ID.get(&(instrument.bank, instrument.patch)) {
+        Some(name) => format!("{} ({})", name, instrument),
+        None => instrument.to_string(),
+    }
+}
+
+fn read_rom_instruments(path: &Path) -> Result<Vec<Instrument>, Box<dyn Error>> {
+    let rom = fs::read(path)?;
+    Ok(bank::instruments_in_rom(&rom)?)
+}
+
+/// The instruments of the ROM chosen last session, if there was one and it can still be read.
+fn load_remembered_rom() -> Option<Vec<Instrument>> {
+    let path = settings::rom_path()?;
+
+    match read_rom_instruments(&path) {
+        Ok(instruments) => Some(instruments),
+        Err(error) => {
+            log::error!("unable to read instruments from {}: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// Asks for a ROM to read instrument names from, and remembers it for next time. Must be run on the main thread.
+fn choose_rom() {
+    let path = match tinyfiledialogs::open_file_dialog("Choose ROM", "", Some((&["*.z64"], ""))) {
+        Some(path) => PathBuf::from(path),
+        None => return,
+    };
+
+    match read_rom_instruments(&path) {
+        Ok(instruments) => {
+            ROM_INSTRUMENTS.with(|rom| *rom.borrow_mut() = Some(instruments));
+
+            if let Err(error) = settings::set_rom_path(&path) {
+                log::error!("unable to remember ROM: {}", error);
+            }
+        }
+        Err(error) => {
+            tinyfiledialogs::message_box_ok(
+                "Cannot read ROM",
+                &format!("Unable to read instruments from {}:\n\n{}", path.display(), error),
+                tinyfiledialogs::MessageBoxIcon::Error,
+            );
+        }
+    }
+}
+
+/// Goes back to the built-in list of instruments, forgetting the ROM.
+fn forget_rom() {
+    ROM_INSTRUMENTS.with(|rom| *rom.borrow_mut() = None);
+
+    if let Err(error) = settings::set_rom_path(Path::new("")) {
+        log::error!("unable to forget ROM: {}", error);
+    }
+}
+
+/// Controls for every parameter of a voice. Returns whether the user asked to hear it.
+pub fn voice_editor(ui: &mut imui_glium::UiFrame<'_>, voice: &mut Voice, is_set_instrument: &mut bool) -> bool {
+    // The bank u8 is split into two nibbles: "bank" and "staccatoness" (awful names, I know).
+    let mut bank_upper = voice.bank_upper(); // TODO: range 0..=6?
+    let mut bank_lower = voice.bank & 0xF;
+    let mut is_audition = false;
+
+    ui.vbox(0, |ui| {
+        ui.hbox(0, |ui| {
+            if ui.button(0, "Set instrument...").with_width(210.0).clicked() {
+                *is_set_instrument = true;
+            }
+
+            ui.pad(1, 10.0);
+
+            if ui.button(2, "Play").with_width(80.0).clicked() {
+                is_audition = true;
+            }
+
+            ui.pad(3, 20.0);
+
+            if let Some(name) = instrument_name(voice) {
+                ui.text(4, name).center_y();
+            }
+        });
+
+        if *is_set_instrument {
+            ui.modal(1, true, (600.0, 560.0), |ui| {
+                ui.hbox(1, |ui| {
+                    let is_from_rom = ROM_INSTRUMENTS.with(|rom| rom.borrow().is_some());
+
+                    if ui.button(0, "Read from ROM...")
+                        .with_width(200.0)
+                        .tooltip("List the instruments a Paper Mario ROM has, such as a romhack's")
+                        .clicked()
+                    {
+                        choose_rom();
+                    }
+
+                    if is_from_rom {
+                        ui.pad(1, 10.0);
+
+                        if ui.button(2, "Use built-in list").with_width(200.0).clicked() {
+                            forget_rom();
+                        }
+                    }
+                });
+                ui.pad(2, 10.0);
+
+                ui.known_size(0, 550.0, 400.0, |ui| {
+                    ui.scroll_area_with_scrollbar(0, |ui| {
+                        for (i, (name, (instr_bank_upper, patch))) in instrument_list().into_iter().enumerate() {
+                            let label = format!("{} (bank {}, patch {:#04X})", name, instr_bank_upper, patch);
+
+                            if ui.button(i, label).with_width(500.0).clicked() {
+                                bank_upper = instr_bank_upper;
+                                voice.patch = patch;
+                                *is_set_instrument = false;
+                            }
+                        }
+                    });
+                });
+
+                if ui.button(99, "Cancel").clicked() {
+                    *is_set_instrument = false;
+                }
+            });
+        }
+
+        ui.pad(2, 10.0);
+
+        range_select(ui, 3, 0..=255, 1, &mut bank_upper, |v| format!("Bank {}", v));
+        range_select(ui, 4, 0..=255, 1, &mut voice.patch, |v| format!("Patch {:#04X}", v));
+
+        range_select(ui, 5, 0..=3, 1, &mut bank_lower, |v| {
+            match *v {
+                0 => "Staccato: no".to_string(),
+                _ => format!("Staccato: {}", v),
+            }
+        });
+        range_select(ui, 6, 0..=255, 1, &mut voice.volume, |v| format!("Volume {}", v));
+        range_select(ui, 7, -128..=127, 1, &mut voice.pan, |v| {
+            let a = v.abs();
+            format!("Pan {} ({})", v, if a > 64 { "right" } else if a == 64 { "middle" } else { "left" })
+        });
+        range_select(ui, 8, 0..=255, 1, &mut voice.reverb, |v| format!("Reverb {}", v));
+        range_select(ui, 9, 0..=255, 1, &mut voice.coarse_tune, |v| format!("Coarse tune {}", v));
+        range_select(ui, 10, 0..=255, 1, &mut voice.fine_tune, |v| format!("Fine tune {}", v));
+    });
+
+    voice.bank = (bank_upper << 4) | bank_lower;
+    is_audition
+}
@@ -0,0 +1,455 @@
+//! Playback without an emulator. The game's instrument samples aren't available, so each voice is played with a
+//! simple waveform, and drums with noise. Songs won't sound quite like they do in the game, but the notes and timing
+//! are the same.
+
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use pm64::bgm::*;
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+
+/// The game mixes at this rate, so there's no point going higher.
+const SAMPLE_RATE: u32 = 32000;
+
+/// Notes playing at once beyond this cut off the oldest.
+const MAX_NOTES: usize = 32;
+
+/// Keeps a loud chord from clipping.
+const HEADROOM: f32 = 0.2;
+
+const ATTACK_SECS: f32 = 0.005;
+const DRUM_DECAY_SECS: f32 = 0.15;
+
+/// The metronome clicks with a short, high blip, so it stands out from the song.
+const CLICK_FREQ: f32 = 1760.0;
+const CLICK_SECS: f32 = 0.03;
+const CLICK_VOLUME: f32 = 0.5;
+
+/// [Synth::seek_to] holds this when there's nowhere to seek to.
+const NO_SEEK: usize = usize::MAX;
+
+/// Plays songs through the computer's speakers.
+pub struct Synth {
+    /// Opened when first needed, since not every computer has an audio device.
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    sink: Option<Sink>,
+
+    /// Whether to click on every beat. Shared with what's playing, so it can be switched while a song plays.
+    metronome: Arc<AtomicBool>,
+
+    /// Where the song playing has got to, in ticks from the start of the variation. Updated as it plays.
+    position: Arc<AtomicUsize>,
+
+    /// Where the song playing should skip to the next time it plays a tick, if anywhere.
+    seek_to: Arc<AtomicUsize>,
+}
+
+impl Synth {
+    pub fn new() -> Self {
+        Synth {
+            output: None,
+            sink: None,
+            metronome: Arc::new(AtomicBool::new(false)),
+            position: Arc::new(AtomicUsize::new(0)),
+            seek_to: Arc::new(AtomicUsize::new(NO_SEEK)),
+        }
+    }
+
+    /// Plays the first variation of `bgm` from the start, stopping whatever was playing. Loops until stopped if the
+    /// variation loops.
+    pub fn play(&mut self, bgm: &Bgm) -> Result<(), Box<dyn Error>> {
+        self.stop();
+
+        if self.output.is_none() {
+            self.output = Some(OutputStream::try_default()?);
+        }
+        let (_, handle) = self.output.as_ref().unwrap();
+
+        // A new counter, so the song that was playing can't overwrite it while it stops.
+        self.position = Arc::new(AtomicUsize::new(0));
+        self.seek_to = Arc::new(AtomicUsize::new(NO_SEEK));
+
+        let sink = Sink::try_new(handle)?;
+        sink.append(Render::new(
+            Sequencer::new(bgm.clone(), 0),
+            self.metronome.clone(),
+            self.position.clone(),
+            self.seek_to.clone(),
+        ));
+        self.sink = Some(sink);
+
+        Ok(())
+    }
+
+    /// Skips the song playing to `time` ticks from the start of the variation, keeping its tempo, volumes and voices
+    /// as they would be by then. Does nothing if nothing is playing.
+    pub fn seek(&mut self, time: usize) {
+        if self.is_playing() {
+            self.seek_to.store(time, Ordering::Relaxed);
+            self.position.store(time, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(sink) = &self.sink {
+            sink.pause();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(sink) = &self.sink {
+            sink.play();
+        }
+    }
+
+    /// Whether a song is playing or paused, rather than stopped or finished.
+    pub fn is_playing(&self) -> bool {
+        matches!(&self.sink, Some(sink) if !sink.empty())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        matches!(&self.sink, Some(sink) if sink.is_paused())
+    }
+
+    /// Where the song playing has got to, in ticks from the start of the variation, or `None` if nothing is playing.
+    /// This jumps back when the variation loops. Sound is mixed a little ahead of being heard, so this may be slightly
+    /// ahead too.
+    pub fn position(&self) -> Option<usize> {
+        if self.is_playing() {
+            Some(self.position.load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    pub fn metronome(&self) -> bool {
+        self.metronome.load(Ordering::Relaxed)
+    }
+
+    /// Switches clicking on every beat on or off, including for the song that is playing. The clicks are only mixed
+    /// in with what is played; the song itself is left alone.
+    pub fn set_metronome(&mut self, enabled: bool) {
+        self.metronome.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// A [Source] of stereo samples, made by following a [Sequencer].
+struct Render {
+    sequencer: Sequencer,
+    events: Vec<SeqEvent>,
+
+    samples_per_tick: f32,
+    /// Samples until the next tick.
+    tick_countdown: f32,
+
+    master_volume: f32,
+    tracks: [TrackState; 16],
+    notes: Vec<PlayingNote>,
+
+    metronome: Arc<AtomicBool>,
+    position: Arc<AtomicUsize>,
+    seek_to: Arc<AtomicUsize>,
+    /// Ticks since the section started. Sections start on a beat, so beats are counted from there.
+    section_ticks: usize,
+    /// Samples since the last metronome click started, while it is sounding.
+    click_age: Option<usize>,
+
+    /// The right channel's sample, to be returned after the left.
+    right: Option<f32>,
+    noise_seed: u32,
+}
+
+#[derive(Clone, Copy)]
+struct TrackState {
+    volume: f32,
+    /// Set by the track itself, overriding the voice's pan.
+    pan: Option<i8>,
+    voice: Option<u8>,
+}
+
+impl Default for TrackState {
+    fn default() -> Self {
+        TrackState {
+            volume: 1.0,
+            pan: None,
+            voice: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Waveform {
+    Square,
+    Saw,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+struct PlayingNote {
+    waveform: Waveform,
+    /// Cycles per sample.
+    step: f32,
+    phase: f32,
+    left: f32,
+    right: f32,
+
+    /// Samples played so far.
+    age: usize,
+    /// Samples until the note is let go.
+    held: usize,
+    /// Samples it takes to fade out once let go.
+    release: usize,
+}
+
+impl Render {
+    fn new(
+        sequencer: Sequencer,
+        metronome: Arc<AtomicBool>,
+        position: Arc<AtomicUsize>,
+        seek_to: Arc<AtomicUsize>,
+    ) -> Self {
+        Render {
+            sequencer,
+            events: Vec::new(),
+            samples_per_tick: samples_per_tick(DEFAULT_TEMPO),
+            tick_countdown: 0.0,
+            master_volume: 1.0,
+            tracks: [TrackState::default(); 16],
+            notes: Vec::new(),
+            metronome,
+            position,
+            seek_to,
+            section_ticks: 0,
+            click_age: None,
+            right: None,
+            noise_seed: 1,
+        }
+    }
+
+    fn tick(&mut self) {
+        let mut events = std::mem::take(&mut self.events);
+
+        let seek_to = self.seek_to.swap(NO_SEEK, Ordering::Relaxed);
+        if seek_to != NO_SEEK {
+            self.seek(seek_to, &mut events);
+        }
+
+        self.sequencer.tick(&mut events);
+        for event in events.drain(..) {
+            self.handle(event);
+        }
+
+        self.events = events;
+
+        if self.section_ticks % TICKS_PER_BEAT == 0 && self.metronome.load(Ordering::Relaxed) {
+            self.click_age = Some(0);
+        }
+        self.section_ticks += 1;
+
+        self.position.store(self.sequencer.position(), Ordering::Relaxed);
+    }
+
+    fn handle(&mut self, event: SeqEvent) {
+        match event {
+            SeqEvent::Note { track, pitch, velocity, length, is_drum } => {
+                self.start_note(track, pitch, velocity, length, is_drum);
+            }
+            SeqEvent::Tempo(bpm) => self.samples_per_tick = samples_per_tick(bpm),
+            SeqEvent::MasterVolume(volume) => self.master_volume = volume as f32 / 255.0,
+            SeqEvent::TrackVolume { track, volume } => self.tracks[track].volume = volume as f32 / 255.0,
+            SeqEvent::TrackPan { track, pan } => self.tracks[track].pan = Some(pan),
+            SeqEvent::TrackVoice { track, voice } => self.tracks[track].voice = Some(voice),
+            SeqEvent::SubsegmentStart => {
+                for track in &mut self.tracks {
+                    track.volume = 1.0;
+                    track.pan = None;
+                }
+                self.section_ticks = 0;
+            }
+        }
+    }
+
+    /// Silences what's playing and skips to `time`, catching up with everything but the notes on the way.
+    fn seek(&mut self, time: usize, events: &mut Vec<SeqEvent>) {
+        self.notes.clear();
+        self.samples_per_tick = samples_per_tick(DEFAULT_TEMPO);
+        self.master_volume = 1.0;
+        self.tracks = [TrackState::default(); 16];
+
+        self.sequencer.seek(time, events);
+        for event in events.drain(..) {
+            self.handle(event);
+        }
+        self.section_ticks = self.sequencer.subsegment_position();
+    }
+
+    fn start_note(&mut self, track_idx: usize, pitch: u8, velocity: u8, length: usize, is_drum: bool) {
+        let bgm = self.sequencer.bgm();
+        let track = self.tracks[track_idx];
+        let mut volume = self.master_volume * track.volume * velocity as f32 / 127.0;
+        let mut pan = track.pan;
+
+        let (waveform, freq, release_secs) = if is_drum {
+            let drum = pitch.checked_sub(PITCH_MIN).and_then(|index| bgm.drums.get(index as usize));
+            if let Some(drum) = drum {
+                volume *= drum.volume as f32 / 255.0;
+                pan = pan.or(Some(drum.pan));
+            }
+
+            (Waveform::Noise, 0.0, DRUM_DECAY_SECS)
+        } else {
+            let voice = track.voice.and_then(|index| bgm.voices.get(index as usize));
+            let waveform = match voice {
+                Some(voice) => {
+                    volume *= voice.volume as f32 / 255.0;
+                    pan = pan.or(Some(voice.pan));
+                    waveform_for(voice)
+                }
+                None => Waveform::Square,
+            };
+
+            // Staccato voices (see the voice editor) let go of notes sooner.
+            let staccato = voice.map_or(0, |voice| voice.bank & 0xF).min(3);
+            let release_secs = [0.2, 0.12, 0.06, 0.03][staccato as usize];
+
+            (waveform, pitch_freq(pitch), release_secs)
+        };
+
+        let pan = pan.unwrap_or(64).clamp(0, 127) as f32 / 127.0;
+        let volume = volume * HEADROOM;
+
+        if self.notes.len() >= MAX_NOTES {
+            self.notes.remove(0);
+        }
+
+        self.notes.push(PlayingNote {
+            waveform,
+            step: freq / SAMPLE_RATE as f32,
+            phase: 0.0,
+            left: volume * (1.0 - pan),
+            right: volume * pan,
+            age: 0,
+            held: if is_drum { 0 } else { (length as f32 * self.samples_per_tick) as usize },
+            release: (release_secs * SAMPLE_RATE as f32) as usize,
+        });
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        // xorshift is plenty random for a snare.
+        self.noise_seed ^= self.noise_seed << 13;
+        self.noise_seed ^= self.noise_seed >> 17;
+        self.noise_seed ^= self.noise_seed << 5;
+        self.noise_seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Iterator for Render {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+
+        while self.tick_countdown <= 0.0 && !self.sequencer.is_finished() {
+            self.tick();
+            self.tick_countdown += self.samples_per_tick;
+        }
+        self.tick_countdown -= 1.0;
+
+        if self.sequencer.is_finished() && self.notes.is_empty() {
+            return None;
+        }
+
+        let (mut left, mut right) = (0.0, 0.0);
+        let attack = ATTACK_SECS * SAMPLE_RATE as f32;
+
+        for i in 0..self.notes.len() {
+            let noise = if self.notes[i].waveform == Waveform::Noise { self.next_noise() } else { 0.0 };
+            let note = &mut self.notes[i];
+
+            let envelope = if note.age < note.held {
+                (note.age as f32 / attack).min(1.0)
+            } else {
+                1.0 - (note.age - note.held) as f32 / note.release as f32
+            };
+
+            let sample = match note.waveform {
+                Waveform::Square => if note.phase < 0.5 { 1.0 } else { -1.0 },
+                Waveform::Saw => note.phase * 2.0 - 1.0,
+                Waveform::Triangle => 1.0 - (note.phase * 4.0 - 2.0).abs(),
+                Waveform::Sine => (note.phase * std::f32::consts::TAU).sin(),
+                Waveform::Noise => noise,
+            } * envelope;
+
+            left += sample * note.left;
+            right += sample * note.right;
+
+            note.phase = (note.phase + note.step).fract();
+            note.age += 1;
+        }
+
+        self.notes.retain(|note| note.age < note.held + note.release);
+
+        if let Some(age) = self.click_age {
+            let click_samples = CLICK_SECS * SAMPLE_RATE as f32;
+            let envelope = 1.0 - age as f32 / click_samples;
+            let sample = (age as f32 * CLICK_FREQ / SAMPLE_RATE as f32 * std::f32::consts::TAU).sin();
+
+            left += sample * envelope * CLICK_VOLUME * HEADROOM;
+            right += sample * envelope * CLICK_VOLUME * HEADROOM;
+
+            self.click_age = Some(age + 1).filter(|age| (*age as f32) < click_samples);
+        }
+
+        self.right = Some(right);
+        Some(left)
+    }
+}
+
+impl Source for Render {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn samples_per_tick(bpm: u16) -> f32 {
+    SAMPLE_RATE as f32 * 60.0 / (bpm.max(1) as f32 * TICKS_PER_BEAT as f32)
+}
+
+/// Frequency in Hz of a note. MIDI key 0 is pitch 104.
+fn pitch_freq(pitch: u8) -> f32 {
+    let key = pitch as f32 - 104.0;
+    440.0 * 2.0_f32.powf((key - 69.0) / 12.0)
+}
+
+/// Picks a waveform for a voice by its bank, so that instruments from different banks sound different.
+fn waveform_for(voice: &Voice) -> Waveform {
+    match (voice.bank >> 4) % 4 {
+        0 => Waveform::Square,
+        1 => Waveform::Triangle,
+        2 => Waveform::Saw,
+        _ => Waveform::Sine,
+    }
+}
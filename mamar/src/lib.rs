@@ -1,7 +1,13 @@
+pub mod cli;
+pub mod crash;
+pub mod formats;
 pub mod history;
 pub mod logger;
 pub mod interface;
 
+#[cfg(test)]
+mod test_util;
+
 pub fn init() {
     #[cfg(target_os = "windows")]
     unsafe {
@@ -11,11 +17,36 @@ pub fn init() {
         wincon::AttachConsole(wincon::ATTACH_PARENT_PROCESS);
     }
 
-    let _ = log::set_logger(&logger::LOGGER);
+    logger::init(build_level());
+}
 
-    if cfg!(debug_assertions) || cfg!(test) {
-        log::set_max_level(log::LevelFilter::Debug);
+/// The default log level for this build.
+fn build_level() -> log::LevelFilter {
+    default_level(cfg!(debug_assertions))
+}
+
+/// The level to log at unless `RUST_LOG` says otherwise. Debug builds log debug messages too.
+fn default_level(debug: bool) -> log::LevelFilter {
+    if debug {
+        log::LevelFilter::Debug
     } else {
-        log::set_max_level(log::LevelFilter::Info);
+        log::LevelFilter::Info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn debug_builds_log_debug_messages() {
+        assert_eq!(default_level(true), log::LevelFilter::Debug);
+        assert_eq!(default_level(false), log::LevelFilter::Info);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn this_build_logs_debug_messages() {
+        assert_eq!(build_level(), log::LevelFilter::Debug);
     }
 }
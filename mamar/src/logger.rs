@@ -1,15 +1,76 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use log::*;
 
-pub static LOGGER: MyLogger = MyLogger;
+use crate::interface::settings;
+
+/// Environment variable that chooses which messages are shown, in the format [Filter::parse] reads.
+const FILTER_VAR: &str = "RUST_LOG";
+
+/// Where logs are written as well as to stdout, so that they can be attached to bug reports.
+const LOG_FILE: &str = "mamar.log";
+
+/// How many older logs are kept, as `mamar.1.log` (the most recent), `mamar.2.log` and so on.
+const OLD_LOG_FILES: usize = 4;
+
+/// Logs are rotated when they reach this size, so that a long session can't fill the disk.
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Where this session's log is written, if anywhere.
+pub fn file_path() -> Option<PathBuf> {
+    settings::path(LOG_FILE)
+}
+
+/// Starts logging messages at `default` level and above, or as `RUST_LOG` says if it is set. Logs from previous
+/// sessions are rotated out of the way, so each session has a file of its own.
+pub fn init(default: LevelFilter) {
+    let filter = match std::env::var(FILTER_VAR) {
+        Ok(spec) => Filter::parse(&spec).unwrap_or_else(|error| {
+            eprintln!("ignoring {}: {}", FILTER_VAR, error);
+            Filter::default()
+        }),
+        Err(_) => Filter::default(),
+    };
+
+    // Tests shouldn't clobber the logs of a real session.
+    let file = match file_path() {
+        Some(path) if !cfg!(test) => match LogFile::open(path) {
+            Ok(file) => Some(Mutex::new(file)),
+            Err(error) => {
+                eprintln!("unable to open log file: {}", error);
+                None
+            }
+        },
+        _ => None,
+    };
 
-pub struct MyLogger;
+    let logger = MyLogger { filter, default, file };
+    let max_level = logger.filter.max_level(default);
+
+    let _ = log::set_logger(Box::leak(Box::new(logger)));
+    log::set_max_level(max_level);
+}
+
+pub struct MyLogger {
+    filter: Filter,
+    default: LevelFilter,
+    file: Option<Mutex<LogFile>>,
+}
 
 impl log::Log for MyLogger {
-    fn enabled(&self, _: &Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target(), self.default)
     }
 
     fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
         // Silence logs from discord-rpc-client because it's really noisy and we don't care about its status
         let is_discord_rpc = record.file().map(|s| s.contains("discord-rpc-client")).unwrap_or_default();
         if is_discord_rpc {
@@ -30,6 +91,191 @@ impl log::Log for MyLogger {
         } else {
             println!("{}", record.args());
         }
+
+        if let Some(file) = &self.file {
+            let time = format_utc(SystemTime::now());
+            let line = format!("{} {:<5} {}: {}", time, record.level(), record.target(), record.args());
+
+            // Logging mustn't take the app down with it, and there's nowhere else to report this.
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_line(&line);
+            }
+        }
     }
     fn flush(&self) {}
 }
+
+/// A log file that is rotated when it gets too big.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    /// Starts a new log at `path`, moving any existing one out of the way.
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        rotate(&path);
+
+        Ok(LogFile {
+            file: File::create(&path)?,
+            path,
+            size: 0,
+        })
+    }
+
+    /// Writes a line without buffering, so nothing is lost if Mamar crashes.
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size >= MAX_LOG_FILE_SIZE {
+            rotate(&self.path);
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+/// Renames `mamar.log` to `mamar.1.log`, `mamar.1.log` to `mamar.2.log` and so on, deleting the oldest.
+fn rotate(path: &Path) {
+    let numbered = |n: usize| path.with_extension(format!("{}.log", n));
+
+    for n in (1..OLD_LOG_FILES).rev() {
+        let _ = fs::rename(numbered(n), numbered(n + 1));
+    }
+    let _ = fs::rename(path, numbered(1));
+}
+
+/// Formats a time as e.g. `2021-05-30 14:02:09Z`.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Days since 1970 to a date in the proleptic Gregorian calendar; see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}-{:02}-{:02} {:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Which messages to show from which modules, e.g. only `pm64::bgm` while looking into a song that doesn't decode.
+#[derive(Debug, Default, PartialEq)]
+pub struct Filter {
+    /// Level for modules that aren't listed.
+    default: Option<LevelFilter>,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Reads a comma-separated list of `module=level` pairs, with a lone `level` for every other module, like
+    /// env_logger does. For example, `pm64::bgm=trace,warn` shows everything from `pm64::bgm` and its submodules, but
+    /// only warnings and errors from elsewhere.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = Filter::default();
+
+        for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            let parse_level = |level: &str| level.parse().map_err(|_| format!("unknown log level `{}`", level));
+
+            match directive.find('=') {
+                Some(i) => {
+                    let level = parse_level(&directive[i + 1..])?;
+                    filter.modules.push((directive[..i].to_owned(), level));
+                }
+                // A lone word is either a level for everything or a module to show everything from
+                None => match parse_level(directive) {
+                    Ok(level) => filter.default = Some(level),
+                    Err(_) => filter.modules.push((directive.to_owned(), LevelFilter::Trace)),
+                },
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// The level that messages logged from `target` must be at or above to be shown.
+    pub fn level(&self, target: &str, default: LevelFilter) -> LevelFilter {
+        let is_within = |module: &str| match target.strip_prefix(module) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        };
+
+        // The most specific module wins
+        self.modules
+            .iter()
+            .filter(|(module, _)| is_within(module))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .or(self.default)
+            .unwrap_or(default)
+    }
+
+    /// The most verbose level any module is shown at.
+    fn max_level(&self, default: LevelFilter) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default.unwrap_or(default), Ord::max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn filter_by_module() {
+        let filter = Filter::parse("pm64::bgm=trace, pm64::bgm::midi=off, warn").unwrap();
+
+        assert_eq!(filter.level("pm64::bgm", LevelFilter::Info), LevelFilter::Trace);
+        assert_eq!(filter.level("pm64::bgm::de", LevelFilter::Info), LevelFilter::Trace);
+        assert_eq!(filter.level("pm64::bgm::midi", LevelFilter::Info), LevelFilter::Off);
+        assert_eq!(filter.level("pm64::bgmx", LevelFilter::Info), LevelFilter::Warn);
+        assert_eq!(filter.level("mamar", LevelFilter::Info), LevelFilter::Warn);
+        assert_eq!(filter.max_level(LevelFilter::Info), LevelFilter::Trace);
+
+        // Unset, everything is shown at the default level
+        let filter = Filter::parse("").unwrap();
+        assert_eq!(filter.level("mamar", LevelFilter::Debug), LevelFilter::Debug);
+        assert_eq!(filter.max_level(LevelFilter::Debug), LevelFilter::Debug);
+
+        assert_eq!(Filter::parse("pm64").unwrap().level("pm64::sbn", LevelFilter::Info), LevelFilter::Trace);
+        assert!(Filter::parse("pm64=loud").is_err());
+    }
+
+    #[test]
+    fn utc_dates() {
+        use std::time::Duration;
+
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00:00Z");
+        assert_eq!(format_utc(UNIX_EPOCH + Duration::from_secs(951_782_400 + 3661)), "2000-02-29 01:01:01Z");
+        assert_eq!(format_utc(UNIX_EPOCH + Duration::from_secs(1_622_383_329)), "2021-05-30 14:02:09Z");
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = TempDir::new("log_rotation");
+        let path = dir.join(LOG_FILE);
+
+        for session in 0..OLD_LOG_FILES + 2 {
+            let mut file = LogFile::open(path.clone()).unwrap();
+            file.write_line(&session.to_string()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", OLD_LOG_FILES + 1));
+        assert_eq!(fs::read_to_string(dir.join("mamar.1.log")).unwrap(), format!("{}\n", OLD_LOG_FILES));
+        assert!(dir.join(format!("mamar.{}.log", OLD_LOG_FILES)).exists());
+        assert!(!dir.join(format!("mamar.{}.log", OLD_LOG_FILES + 1)).exists());
+    }
+}
@@ -9,6 +9,13 @@ const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 fn main() {
     mamar::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if mamar::cli::is_cli(&args) {
+        std::process::exit(mamar::cli::run(&args));
+    }
+
+    mamar::crash::install_handler();
+
     // Discord Rich Presence
     std::thread::spawn(|| {
         let mut discord = DiscordClient::new(832001324035342358);
@@ -0,0 +1,30 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+
+/// An empty directory for a test to write files into, deleted when dropped. The name includes the process id, so
+/// tests running at the same time in different checkouts don't clash.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(test_name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("mamar_{}_{}", process::id(), test_name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use pm64::bgm::Bgm;
+
+fuzz_target!(|data: &[u8]| {
+    // Whatever the input, decoding must return rather than panic. libFuzzer reports it if this takes too long, e.g.
+    // because it is stuck in a loop.
+    let _ = Bgm::from_bytes(data);
+});
@@ -0,0 +1,124 @@
+//! The instrument banks (BK files) in a ROM's [Sbn], for listing the instruments that particular game has.
+//!
+//! The game loads every bank listed in the INIT section of the SBN into a bank set. A [Voice](crate::bgm::Voice)
+//! picks an instrument with the upper nibble of its bank, choosing the set, and its patch, whose upper nibble chooses
+//! a bank in the set and lower nibble an instrument in that bank. Neither the banks nor the instruments have names in
+//! the ROM beyond a four-character one for each bank file, so instruments are identified by bank file and position.
+
+use std::fmt;
+
+use crate::rom::{self, find_sbn};
+use crate::sbn::Sbn;
+
+/// Start of every bank file.
+pub const MAGIC: &str = "BK";
+
+/// Where the bank list's offset and size are in the INIT section header.
+const INIT_BANKS_HEADER: usize = 0x08;
+
+/// Where the offsets of a bank's instruments are in its header.
+const INSTRUMENTS_OFFSET: usize = 0x12;
+
+/// How many instruments a bank can hold.
+pub const INSTRUMENTS_PER_BANK: usize = 16;
+
+/// An instrument that a ROM has.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instrument {
+    /// The upper nibble of the [Voice::bank](crate::bgm::Voice::bank) that selects this instrument.
+    pub bank: u8,
+
+    /// The [Voice::patch](crate::bgm::Voice::patch) that selects this instrument.
+    pub patch: u8,
+
+    /// The name of the bank file it is in.
+    pub file_name: String,
+
+    /// Which of the bank's instruments it is, from 0 to 15.
+    pub index: u8,
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} #{}", self.file_name.trim_end(), self.index)
+    }
+}
+
+/// Lists every instrument in the banks that `sbn` loads, ordered by bank and then patch. Entries that don't refer to
+/// a bank file are skipped.
+///
+/// ```
+/// # use pm64::bank::*;
+/// # use pm64::sbn::*;
+/// let mut bank = vec![0; 0x40];
+/// bank[0..2].copy_from_slice(MAGIC.as_bytes());
+/// bank[0x08..0x0C].copy_from_slice(b"Mari");
+/// bank[0x16..0x18].copy_from_slice(&[0x00, 0x40]); // Instrument 2
+///
+/// let mut sbn = Sbn::default();
+/// sbn.files.push(File { name: "Mari".to_owned(), data: bank, format: 0x30 });
+///
+/// sbn.init = vec![0; INIT_SONGS_OFFSET + 0x10];
+/// sbn.init[0x08..0x0C].copy_from_slice(&[0x00, 0x20, 0x00, 0x04]); // One entry at 0x20
+/// sbn.init[0x20..0x24].copy_from_slice(&[0x00, 0x00, 0x01, 0x03]); // File 0 is bank 1 of set 3
+///
+/// assert_eq!(instruments(&sbn), vec![Instrument {
+///     bank: 3,
+///     patch: 0x12,
+///     file_name: "Mari".to_owned(),
+///     index: 2,
+/// }]);
+/// ```
+pub fn instruments(sbn: &Sbn) -> Vec<Instrument> {
+    let u16_at = |bytes: &[u8], offset: usize| {
+        bytes.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    };
+
+    let list_start = u16_at(&sbn.init, INIT_BANKS_HEADER);
+    let list_size = u16_at(&sbn.init, INIT_BANKS_HEADER + 2);
+    let (list_start, list_size) = match (list_start, list_size) {
+        (Some(start), Some(size)) => (start, size),
+        _ => return Vec::new(),
+    };
+    let list = sbn.init.get(list_start..list_start + list_size).unwrap_or_default();
+
+    let mut instruments = Vec::new();
+
+    // Each entry is the file index, the bank's place in its set, and the set.
+    for entry in list.chunks_exact(4) {
+        let file = match sbn.files.get(u16::from_be_bytes([entry[0], entry[1]]) as usize) {
+            Some(file) if file.data.starts_with(MAGIC.as_bytes()) => file,
+            _ => continue,
+        };
+        let (bank_index, bank_set) = (entry[2], entry[3]);
+
+        if bank_index as usize >= INSTRUMENTS_PER_BANK {
+            continue;
+        }
+
+        for index in 0..INSTRUMENTS_PER_BANK {
+            if let Some(0) | None = u16_at(&file.data, INSTRUMENTS_OFFSET + index * 2) {
+                continue;
+            }
+
+            instruments.push(Instrument {
+                bank: bank_set,
+                patch: (bank_index << 4) | index as u8,
+                file_name: file.name.clone(),
+                index: index as u8,
+            });
+        }
+    }
+
+    // Banks are loaded in order, so where two are loaded into the same place, the later one is what the game has.
+    instruments.reverse();
+    instruments.sort_by_key(|instrument| (instrument.bank, instrument.patch));
+    instruments.dedup_by_key(|instrument| (instrument.bank, instrument.patch));
+    instruments
+}
+
+/// Like [instruments], but for the SBN in a Paper Mario ROM.
+pub fn instruments_in_rom(rom: &[u8]) -> Result<Vec<Instrument>, rom::Error> {
+    let sbn = Sbn::from_bytes(&rom[find_sbn(rom)?])?;
+    Ok(instruments(&sbn))
+}
@@ -0,0 +1,268 @@
+use std::fmt;
+
+use super::*;
+use crate::gzip;
+
+/// How many layers of gzip compression [detect_format] and [read_agnostic] look through. Nothing needs more than one,
+/// but some files decompress to themselves, which would otherwise never stop.
+pub const GZIP_DEPTH_MAX: usize = 4;
+
+/// The format that [detect_format] or [read_agnostic] found its input to be in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceFormat {
+    Bgm,
+    #[cfg(feature = "midly")]
+    Midi,
+    /// A [Bgm] serialized as RON text. Only detected: reading it is up to the caller, through serde.
+    Ron,
+    /// A [Bgm] serialized as JSON text. Only detected: reading it is up to the caller, through serde.
+    Json,
+    /// gzip-compressed data in another format.
+    Gzip(Box<SourceFormat>),
+}
+
+impl SourceFormat {
+    /// The format of the data, once decompressed.
+    pub fn inner(&self) -> &SourceFormat {
+        match self {
+            SourceFormat::Gzip(inner) => inner.inner(),
+            format => format,
+        }
+    }
+
+    /// The format that files with the given extension (without the dot) usually hold, if it is one we know. gzip files
+    /// are not included, as their extension doesn't say what is inside.
+    pub fn from_extension(extension: &str) -> Option<SourceFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "bgm" | "bin" => Some(SourceFormat::Bgm),
+            #[cfg(feature = "midly")]
+            "mid" | "midi" => Some(SourceFormat::Midi),
+            "ron" => Some(SourceFormat::Ron),
+            "json" => Some(SourceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SourceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceFormat::Bgm => write!(f, "BGM"),
+            #[cfg(feature = "midly")]
+            SourceFormat::Midi => write!(f, "MIDI"),
+            SourceFormat::Ron => write!(f, "RON"),
+            SourceFormat::Json => write!(f, "JSON"),
+            SourceFormat::Gzip(inner) => write!(f, "gzip-compressed {}", inner),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    /// The input is not in any format we know how to read.
+    UnknownFormat,
+    /// The input is text, e.g. RON, which has to be deserialized with serde instead.
+    Text(SourceFormat),
+    Decompress(gzip::Error),
+    Bgm(de::Error),
+    #[cfg(feature = "midly")]
+    Midi(Box<dyn std::error::Error>),
+}
+
+impl From<de::Error> for ReadError {
+    fn from(error: de::Error) -> Self {
+        Self::Bgm(error)
+    }
+}
+
+impl From<gzip::Error> for ReadError {
+    fn from(error: gzip::Error) -> Self {
+        Self::Decompress(error)
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::UnknownFormat => write!(f, "Unknown file format"),
+            ReadError::Text(format) => write!(f, "{} files must be read with serde", format),
+            ReadError::Decompress(source) => write!(f, "Unable to decompress: {}", source),
+            ReadError::Bgm(source) => write!(f, "{}", source),
+            #[cfg(feature = "midly")]
+            ReadError::Midi(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReadError::Decompress(source) => Some(source),
+            ReadError::Bgm(source) => Some(source),
+            #[cfg(feature = "midly")]
+            ReadError::Midi(source) => Some(source.as_ref()),
+            ReadError::UnknownFormat | ReadError::Text(_) => None,
+        }
+    }
+}
+
+/// Works out the format of `data` from its contents. The file's `extension`, if there is one, is only used to break
+/// ties: a RON map and a JSON object both start with `{`, for example. gzip-compressed input is decompressed to see
+/// what is inside, through up to [GZIP_DEPTH_MAX] layers.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// let raw = Bgm::new().as_bytes().unwrap();
+/// assert_eq!(detect_format(&raw, Some("ron")), Some(SourceFormat::Bgm));
+///
+/// assert_eq!(detect_format(b"(name: \"Song\")", Some("bin")), Some(SourceFormat::Ron));
+/// assert_eq!(detect_format(b"// Comment\nBgm(name: \"Song\")", None), Some(SourceFormat::Ron));
+/// assert_eq!(detect_format(b"{\"name\": \"Song\"}", None), Some(SourceFormat::Json));
+/// assert_eq!(detect_format(b"{}", Some("ron")), Some(SourceFormat::Ron));
+///
+/// assert_eq!(detect_format(b"RIFF", Some("bgm")), None);
+/// ```
+pub fn detect_format(data: &[u8], extension: Option<&str>) -> Option<SourceFormat> {
+    detect_format_nested(data, extension, 0)
+}
+
+fn detect_format_nested(data: &[u8], extension: Option<&str>, depth: usize) -> Option<SourceFormat> {
+    if gzip::is_gzip(data) {
+        if depth == GZIP_DEPTH_MAX {
+            return None;
+        }
+
+        let decompressed = gzip::decompress(data).ok()?;
+        let format = detect_format_nested(&decompressed, extension, depth + 1)?;
+        return Some(SourceFormat::Gzip(Box::new(format)));
+    }
+
+    if data.starts_with(MAGIC.as_bytes()) {
+        return Some(SourceFormat::Bgm);
+    }
+
+    #[cfg(feature = "midly")]
+    {
+        if data.starts_with(b"MThd") {
+            return Some(SourceFormat::Midi);
+        }
+    }
+
+    let text = std::str::from_utf8(data).ok()?;
+    let mut text = text.trim_start_matches('\u{feff}').trim_start();
+
+    // Skip comments, which only RON allows
+    let mut has_comments = false;
+    loop {
+        if text.starts_with("//") {
+            text = text.find('\n').map_or("", |end| &text[end..]);
+        } else if text.starts_with("/*") {
+            text = text.find("*/").map_or("", |end| &text[end + 2..]);
+        } else {
+            break;
+        }
+        text = text.trim_start();
+        has_comments = true;
+    }
+
+    let is_ron_extension = matches!(extension, Some(ext) if ext.eq_ignore_ascii_case("ron"));
+    let after_name = text.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+
+    if text.starts_with('{') {
+        // A RON map, or a JSON object
+        if has_comments || is_ron_extension {
+            Some(SourceFormat::Ron)
+        } else {
+            Some(SourceFormat::Json)
+        }
+    } else if text.starts_with('(') || text.starts_with("#![") || after_name.trim_start().starts_with('(') {
+        // A struct, named or not, or an attribute like `#![enable(implicit_some)]`
+        Some(SourceFormat::Ron)
+    } else {
+        None
+    }
+}
+
+/// Reads a BGM from a binary BGM or (with the `midly` feature) a MIDI file, working out which it is from its contents.
+/// gzip-compressed input is decompressed first, through up to [GZIP_DEPTH_MAX] layers. Text formats found by
+/// [detect_format] are not read, and give [ReadError::Text]. Anything unexpected in a BGM is returned too, as by
+/// [Bgm::decode_with_warnings]; other formats have no warnings.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// let raw = Bgm::new().as_bytes().unwrap();
+/// let (_, format, warnings) = read_agnostic(&raw).unwrap();
+/// assert_eq!(format, SourceFormat::Bgm);
+/// assert!(warnings.is_empty());
+///
+/// assert!(matches!(read_agnostic(b"RIFF"), Err(ReadError::UnknownFormat)));
+/// assert!(matches!(read_agnostic(b"(name: \"Song\")"), Err(ReadError::Text(SourceFormat::Ron))));
+/// ```
+pub fn read_agnostic(data: &[u8]) -> Result<(Bgm, SourceFormat, Vec<de::Warning>), ReadError> {
+    read_agnostic_nested(data, 0)
+}
+
+fn read_agnostic_nested(data: &[u8], depth: usize) -> Result<(Bgm, SourceFormat, Vec<de::Warning>), ReadError> {
+    if gzip::is_gzip(data) {
+        if depth == GZIP_DEPTH_MAX {
+            return Err(ReadError::UnknownFormat);
+        }
+
+        let decompressed = gzip::decompress(data)?;
+        let (bgm, format, warnings) = read_agnostic_nested(&decompressed, depth + 1)?;
+        return Ok((bgm, SourceFormat::Gzip(Box::new(format)), warnings));
+    }
+
+    match detect_format_nested(data, None, depth) {
+        Some(SourceFormat::Bgm) => {
+            let (bgm, warnings) = Bgm::from_bytes_with_warnings(data)?;
+            Ok((bgm, SourceFormat::Bgm, warnings))
+        }
+        #[cfg(feature = "midly")]
+        Some(SourceFormat::Midi) => {
+            let bgm = midi::to_bgm(data).map_err(ReadError::Midi)?;
+            Ok((bgm, SourceFormat::Midi, Vec::new()))
+        }
+        Some(format) => Err(ReadError::Text(format)),
+        None => Err(ReadError::UnknownFormat),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::crc32;
+
+    /// gzip-compresses `data` with stored (uncompressed) blocks.
+    fn store(data: &[u8]) -> Vec<u8> {
+        let mut gzip = vec![0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        let mut chunks = data.chunks(0xFFFF).peekable();
+        while let Some(chunk) = chunks.next() {
+            let len = chunk.len() as u16;
+            gzip.push(chunks.peek().is_none() as u8);
+            gzip.extend_from_slice(&len.to_le_bytes());
+            gzip.extend_from_slice(&(!len).to_le_bytes());
+            gzip.extend_from_slice(chunk);
+        }
+        gzip.extend_from_slice(&crc32(data).to_le_bytes());
+        gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        gzip
+    }
+
+    #[test]
+    fn gzip_depth() {
+        let mut data = Bgm::new().as_bytes().unwrap();
+        for _ in 0..GZIP_DEPTH_MAX {
+            data = store(&data);
+        }
+
+        let (_, format, _) = read_agnostic(&data).unwrap();
+        assert!(matches!(format, SourceFormat::Gzip(_)));
+        assert_eq!(*format.inner(), SourceFormat::Bgm);
+        assert_eq!(detect_format(&data, None), Some(format));
+
+        let data = store(&data);
+        assert!(matches!(read_agnostic(&data), Err(ReadError::UnknownFormat)));
+        assert_eq!(detect_format(&data, None), None);
+    }
+}
@@ -1,8 +1,10 @@
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::iter;
 use std::ops::Range;
 
-use serde_derive::{Serialize, Deserialize};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 /// A contiguous sequence of [commands](Command) ordered by relative-time.
 /// Insertion and lookup is performed via a relative-time key.
@@ -38,8 +40,8 @@ use serde_derive::{Serialize, Deserialize};
 /// that this collection is not equivalent to [Vec] - in many ways it acts more like a
 /// [HashMap](std::collections::HashMap) (i.e. a dictionary) with relative-time keys and [Command] values (for
 /// example, you cannot lookup by vector index, because ordering is undefined between [Delay] partitions).
-#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
-#[serde(transparent)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct CommandSeq {
     /// List of [Command]s in time order. Sets of [Command]s at the same time value have undefined ordering, so this
     /// is not a public field. Similarly, [CommandSeq] does not [Deref](std::ops::Deref) to the [Vec] it wraps
@@ -219,6 +221,121 @@ impl CommandSeq {
         }
     }
 
+    /// Inserts the given command so that it runs at `tick`, after any commands already there, splitting the
+    /// [Delay] that spans `tick` so that every other command keeps its time. Unlike [CommandSeq::insert], inserting
+    /// several commands at the same tick keeps them in order.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::Delay(48),
+    ///     Command::Delay(48),
+    /// ]);
+    ///
+    /// sequence.insert_at_tick(24, Command::TrackVoice(2));
+    /// sequence.insert_at_tick(48, Command::TrackVoice(3)); // Between the two delays
+    /// sequence.insert_at_tick(0, Command::TrackVoice(4));  // After TrackVoice(1), so it takes effect
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::TrackVoice(4),
+    ///     Command::Delay(24),
+    ///     Command::TrackVoice(2),
+    ///     Command::Delay(24),
+    ///     Command::TrackVoice(3),
+    ///     Command::Delay(48),
+    /// ]));
+    /// ```
+    ///
+    /// Inserting past the end of the sequence pads it with a delay. If the sequence finishes with an
+    /// [End](Command::End), the End is moved to stay after the inserted command:
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// sequence.insert_at_tick(96, Command::TrackVoice(2));
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Delay(48),
+    ///     Command::Delay(48),
+    ///     Command::TrackVoice(2),
+    ///     Command::End,
+    /// ]));
+    /// ```
+    pub fn insert_at_tick(&mut self, tick: usize, command: Command) {
+        let end = match self.vec.last() {
+            Some(Command::End) => self.vec.pop(),
+            _ => None,
+        };
+
+        match self.index_after_commands_at(tick) {
+            Some(index) => self.vec.insert(index, command),
+            None => self.insert(tick, command),
+        }
+
+        if let Some(end) = end {
+            self.vec.push(end);
+        }
+    }
+
+    /// Removes the commands at `tick` that `predicate` returns true for, and returns them in order. [Delays](Delay)
+    /// are never removed, so every other command keeps its time; if nothing is left at `tick`, the delays either side
+    /// of it are merged into one.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(24),
+    ///     Command::TrackVoice(2),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x97, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    /// ]);
+    ///
+    /// let removed = sequence.remove_at_tick(24, |command| matches!(command, Command::Note { .. }));
+    /// assert_eq!(removed, vec![Command::Note { pitch: 0x94, velocity: 100, length: 24 }]);
+    ///
+    /// sequence.remove_at_tick(24, |_| true);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48), // Merged
+    ///     Command::Note { pitch: 0x97, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    /// ]));
+    ///
+    /// // Nothing is at tick 60.
+    /// assert!(sequence.remove_at_tick(60, |_| true).is_empty());
+    /// ```
+    pub fn remove_at_tick<P: FnMut(&Command) -> bool>(&mut self, tick: usize, mut predicate: P) -> Vec<Command> {
+        let indices: Vec<usize> = self
+            .iter_time()
+            .enumerate()
+            .filter(|(_, (time, command))| *time == tick && !matches!(command, Delay(_)) && predicate(command))
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut removed: Vec<Command> = indices.iter().rev().map(|&index| self.vec.remove(index)).collect();
+        removed.reverse();
+
+        if let Some(&index) = indices.first() {
+            if index > 0 {
+                if let (Delay(before), Some(Delay(after))) = (&self.vec[index - 1], self.vec.get(index)) {
+                    self.vec[index - 1] = Delay(before + after);
+                    self.vec.remove(index);
+                }
+            }
+        }
+
+        removed
+    }
+
     /// Returns the commands occurring at the given time, including the terminating Delay command if there is one.
     /// ```
     /// # use pm64::bgm::*;
@@ -253,6 +370,31 @@ impl CommandSeq {
         self.vec.iter()
     }
 
+    /// The commands in this sequence in time-order. Commands at the same time may be in any order.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let sequence = CommandSeq::from(vec![Command::MasterTempo(120), Command::Delay(48), Command::End]);
+    ///
+    /// match sequence.as_slice() {
+    ///     [Command::MasterTempo(bpm), .., Command::End] => assert_eq!(*bpm, 120),
+    ///     _ => unreachable!(),
+    /// }
+    ///
+    /// for command in &sequence {
+    ///     assert_ne!(command.opcode(), None);
+    /// }
+    /// ```
+    pub fn as_slice(&self) -> &[Command] {
+        &self.vec
+    }
+
+    /// Iterates mutably over the commands in this sequence in time-order. Note that changing a [Command::Delay] moves
+    /// every command after it.
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Command> {
+        self.vec.iter_mut()
+    }
+
     /// Iterates over each command in this sequence annotated with its time relative to the start of the sequence.
     pub fn iter_time(&self) -> TimeIter<'_> {
         TimeIter {
@@ -261,128 +403,1234 @@ impl CommandSeq {
         }
     }
 
-    /// Iterates over subsequences of commands that execute at the same time.
-    pub fn iter_time_groups(&self) -> TimeGroupIter<'_> {
-        TimeGroupIter {
-            seq: self.iter_time().peekable(),
+    /// Iterates over subsequences of commands that execute at the same time.
+    pub fn iter_time_groups(&self) -> TimeGroupIter<'_> {
+        TimeGroupIter {
+            seq: self.iter_time().peekable(),
+        }
+    }
+
+    /// Iterates over every [Command::Note] in this sequence, in time-order, annotated with its relative-time.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 80, length: 48 }, // Overlaps the first note
+    ///     Command::Delay(24),
+    /// ]);
+    ///
+    /// let notes: Vec<NoteRef> = sequence.notes().collect();
+    ///
+    /// assert_eq!(notes.len(), 2);
+    /// assert_eq!(notes[0], NoteRef { time: 0, pitch: 0x90, velocity: 100, length: 48 });
+    /// assert_eq!(notes[1], NoteRef { time: 24, pitch: 0x94, velocity: 80, length: 48 });
+    /// ```
+    ///
+    /// A note is allowed to keep sounding after the last [Delay], so its end time may be greater than
+    /// [CommandSeq::len_time]:
+    /// ```
+    /// # use pm64::bgm::*;
+    /// # let sequence = CommandSeq::from(vec![
+    /// #     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    /// #     Command::Delay(24),
+    /// #     Command::Note { pitch: 0x94, velocity: 80, length: 48 },
+    /// #     Command::Delay(24),
+    /// # ]);
+    /// let last = sequence.notes().last().unwrap();
+    /// assert_eq!(last.end_time(), 72);
+    /// assert_eq!(sequence.len_time(), 48);
+    /// ```
+    pub fn notes(&self) -> impl Iterator<Item = NoteRef> + '_ {
+        self.iter_time().filter_map(|(time, command)| match *command {
+            Command::Note {
+                pitch,
+                velocity,
+                length,
+            } => Some(NoteRef {
+                time,
+                pitch,
+                velocity,
+                length,
+            }),
+            _ => None,
+        })
+    }
+
+    /// Like [CommandSeq::notes], but allows the properties of each note to be edited in place. The time of a note
+    /// cannot be changed this way, so the surrounding [Delay]s are never disturbed.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 80, length: 48 },
+    /// ]);
+    ///
+    /// for note in sequence.notes_mut() {
+    ///     *note.velocity = 127;
+    /// }
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 127, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 127, length: 48 },
+    /// ]));
+    /// ```
+    pub fn notes_mut(&mut self) -> impl Iterator<Item = NoteMut<'_>> {
+        let mut current_time = 0;
+
+        self.vec.iter_mut().filter_map(move |command| match command {
+            Delay(delta_time) => {
+                current_time += *delta_time;
+                None
+            }
+            Command::Note {
+                pitch,
+                velocity,
+                length,
+            } => Some(NoteMut {
+                time: current_time,
+                pitch,
+                velocity,
+                length,
+            }),
+            _ => None,
+        })
+    }
+
+    /// Removes the `index`th note, counting from zero in the order of [CommandSeq::notes], and returns it. The times
+    /// of all other commands are unaffected. To move a note, remove it then [insert](CommandSeq::insert) it again.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 80, length: 48 },
+    ///     Command::Delay(48),
+    /// ]);
+    ///
+    /// let note = sequence.remove_note(0).unwrap();
+    /// assert_eq!(note.pitch, 0x90);
+    /// assert_eq!(sequence.notes().next().unwrap().time, 48);
+    /// assert_eq!(sequence.remove_note(1), None);
+    /// ```
+    pub fn remove_note(&mut self, index: usize) -> Option<NoteRef> {
+        let (vec_index, note) = self.vec
+            .iter()
+            .enumerate()
+            .filter(|(_, command)| matches!(command, Command::Note { .. }))
+            .nth(index)?;
+        let time = self.vec[..vec_index].iter().map(|command| match command {
+            Delay(delta_time) => *delta_time,
+            _ => 0,
+        }).sum();
+
+        let note = match *note {
+            Command::Note { pitch, velocity, length } => NoteRef {
+                time,
+                pitch,
+                velocity,
+                length,
+            },
+            _ => unreachable!(),
+        };
+
+        self.vec.remove(vec_index);
+        Some(note)
+    }
+
+    /// Shortens each note that is still playing when another of the same pitch starts, so that it ends there instead.
+    /// The game can only sound one of them at once. A note that starts at the same time as another of the same pitch
+    /// is removed. Returns how many notes were shortened or removed.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 }, // Different pitch, so no overlap
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x90, velocity: 80, length: 48 },
+    /// ]);
+    ///
+    /// assert_eq!(sequence.truncate_overlaps(), 1);
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x90, velocity: 80, length: 48 },
+    /// ]));
+    /// ```
+    pub fn truncate_overlaps(&mut self) -> usize {
+        // Index into notes() and end time of the last note of each pitch.
+        let mut playing: HashMap<u8, (usize, usize)> = HashMap::new();
+        let mut new_lengths = HashMap::new();
+
+        for (i, note) in self.notes().enumerate() {
+            if let Some(&(previous, end)) = playing.get(&note.pitch) {
+                if end > note.time {
+                    new_lengths.insert(previous, end - note.time);
+                }
+            }
+            playing.insert(note.pitch, (i, note.end_time()));
+        }
+
+        let mut to_remove = Vec::new();
+        for (i, note) in self.notes_mut().enumerate() {
+            if let Some(overlap) = new_lengths.get(&i) {
+                *note.length -= *overlap as u16;
+
+                if *note.length == 0 {
+                    to_remove.push(i);
+                }
+            }
+        }
+
+        for i in to_remove.into_iter().rev() {
+            self.remove_note(i);
+        }
+
+        new_lengths.len()
+    }
+
+    /// Removes each note that starts while another of the same pitch is still playing, which the game couldn't sound
+    /// anyway, and returns them in order.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x90, velocity: 80, length: 48 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x90, velocity: 60, length: 48 }, // The first has ended by now
+    /// ]);
+    ///
+    /// let removed = sequence.remove_overlapping();
+    /// assert_eq!(removed, vec![NoteRef { time: 24, pitch: 0x90, velocity: 80, length: 48 }]);
+    /// assert_eq!(sequence.notes().count(), 2);
+    /// ```
+    pub fn remove_overlapping(&mut self) -> Vec<NoteRef> {
+        // End time of the last note kept of each pitch.
+        let mut playing: HashMap<u8, usize> = HashMap::new();
+        let mut to_remove = Vec::new();
+
+        for (i, note) in self.notes().enumerate() {
+            match playing.get(&note.pitch) {
+                Some(end) if *end > note.time => to_remove.push(i),
+                _ => {
+                    playing.insert(note.pitch, note.end_time());
+                }
+            }
+        }
+
+        let mut removed: Vec<NoteRef> = to_remove.into_iter().rev().filter_map(|i| self.remove_note(i)).collect();
+        removed.reverse();
+        removed
+    }
+
+    /// Returns the relative-time after the last [Command]. Does not account for any final command which extends the
+    /// *playback* time (not the relative-time), that is, [Command::Note] (use [CommandSeq::playback_time] to find
+    /// this value).
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::new();
+    /// assert_eq!(sequence.len_time(), 0);
+    ///
+    /// sequence.push(Command::Delay(10));
+    /// assert_eq!(sequence.len_time(), 10);
+    ///
+    /// sequence.push(Command::Delay(10));
+    /// sequence.push(Command::Delay(10));
+    /// assert_eq!(sequence.len_time(), 30);
+    ///
+    /// // Wouldn't make sense to do this in practice, but it is valid.
+    /// sequence.push(Command::Delay(0));
+    /// assert_eq!(sequence.len_time(), 30);
+    /// ```
+    pub fn len_time(&self) -> usize {
+        let mut time = 0;
+
+        for command in self.vec.iter() {
+            if let Command::Delay(delta_time) = command {
+                time += *delta_time as usize;
+            }
+        }
+
+        time
+    }
+
+    /// Calculates the time it takes for this [CommandSeq] to finish in terms of audio playback (i.e. when all
+    /// notes have stopped).
+    ///
+    /// Equivalent to [CommandSeq::len_time] for a sequence with no [Command::Note]s:
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let seq = CommandSeq::from(vec![Command::Delay(1000)]);
+    /// assert_eq!(seq.playback_time(), 1000);
+    /// assert_eq!(seq.len_time(), 1000);
+    /// ```
+    ///
+    /// An empty sequence has a playback time of zero:
+    /// ```
+    /// # use pm64::bgm::*;
+    /// assert_eq!(CommandSeq::new().playback_time(), 0);
+    /// ```
+    pub fn playback_time(&self) -> usize {
+        self.iter_time().last().map_or(0, |(time, command)| match *command {
+            Command::Delay(delta) => time + delta as usize,
+            Command::Note { length, .. } => time + length as usize,
+            _ => time,
+        })
+    }
+
+    /// See [Vec::with_capacity].
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            vec: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Optimises this sequence to take up as little memory as possible whilst still being playback-equivalent (i.e.
+    /// sounds the same).
+    pub fn shrink(&mut self) {
+        // TODO: remove Delay(0) commands
+        // TODO: combine redundant subsequences (e.g. multiple Delay, multiple MasterTempo without a delay between)
+        self.vec.shrink_to_fit();
+    }
+
+    /// Appends the given [Command] to the end of the sequence.
+    pub fn push<C: Into<Command>>(&mut self, command: C) {
+        self.vec.push(command.into())
+    }
+
+    /// Returns the number of commands ("length") in the sequence.
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.len() == 0 || (self.vec.len() == 1 && self.vec[0] == Command::End)
+    }
+
+    /// Returns the number of commands this sequence is encoded as, which [TRACK_COMMANDS_MAX] limits. Unlike
+    /// [CommandSeq::len], [Marker](Command::Marker)s and zero-length [Delay]s aren't counted, and a long [Delay] counts
+    /// as however many delay commands it is split into.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let sequence = CommandSeq::from(vec![
+    ///     Command::Marker("start".to_owned()),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(0),
+    ///     Command::Delay(5000),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// assert_eq!(sequence.len(), 5);
+    /// assert_eq!(sequence.command_count(), 5); // Delay(5000) is three long delays
+    /// ```
+    pub fn command_count(&self) -> usize {
+        self.vec
+            .iter()
+            .map(|command| match command {
+                Delay(delay) => delay_command_count(*delay),
+                Command::Marker(_) => 0,
+                _ => 1,
+            })
+            .sum()
+    }
+
+    pub fn pitch_range(&self) -> Range<u8> {
+        let mut range = 0..0;
+
+        for cmd in self.iter() {
+            if let Command::Note { pitch, .. } = cmd {
+                let pitch = *pitch;
+
+                if pitch < range.start {
+                    range.start = pitch;
+                }
+
+                if pitch >= range.end {
+                    range.end = pitch.saturating_add(1);
+                }
+            }
+        }
+
+        range
+    }
+
+    pub fn clear_command(&mut self, idx: usize) {
+        self.vec[idx] = Command::Delay(0);
+    }
+
+    pub fn zero_all_delays(&mut self) {
+        for cmd in &mut self.vec {
+            if let Command::Delay(_) = cmd {
+                *cmd = Command::Delay(0);
+            }
+        }
+    }
+
+    /// Shifts the pitch of every [Command::Note] in this sequence by the given number of semitones. Notes that would
+    /// leave the range [PITCH_MIN]..=[PITCH_MAX] are handled according to `out_of_range`. Returns the number of notes
+    /// that went out of range.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Note { pitch: PITCH_MAX, velocity: 100, length: 48 },
+    /// ]);
+    ///
+    /// assert_eq!(sequence.transpose(12, OutOfRange::Clamp), 1);
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x9C, velocity: 100, length: 48 },
+    ///     Command::Note { pitch: PITCH_MAX, velocity: 100, length: 48 }, // Clamped
+    /// ]));
+    ///
+    /// assert_eq!(sequence.transpose(-12, OutOfRange::Drop), 0);
+    /// assert_eq!(sequence.transpose(-24, OutOfRange::Drop), 1);
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     // First note dropped, because 0x90 - 24 < PITCH_MIN
+    ///     Command::Note { pitch: PITCH_MAX - 36, velocity: 100, length: 48 },
+    /// ]));
+    /// ```
+    pub fn transpose(&mut self, semitones: i8, out_of_range: OutOfRange) -> usize {
+        let mut num_out_of_range = 0;
+
+        self.vec.retain(|command| match command {
+            Command::Note { pitch, .. } => {
+                let new_pitch = *pitch as isize + semitones as isize;
+                let in_range = new_pitch >= PITCH_MIN as isize && new_pitch <= PITCH_MAX as isize;
+
+                if !in_range {
+                    num_out_of_range += 1;
+                }
+
+                in_range || out_of_range == OutOfRange::Clamp
+            }
+            _ => true,
+        });
+
+        for command in &mut self.vec {
+            if let Command::Note { pitch, .. } = command {
+                let new_pitch = *pitch as isize + semitones as isize;
+                *pitch = new_pitch.clamp(PITCH_MIN as isize, PITCH_MAX as isize) as u8;
+            }
+        }
+
+        num_out_of_range
+    }
+
+    /// Moves the start of every [Command::Note] towards the nearest multiple of `grid_ticks`. `strength` is how far
+    /// each note moves, from `0.0` (not at all) to `1.0` (all the way onto the grid). Commands at the same time as a
+    /// note, such as a [TrackVoice](Command::TrackVoice) for it, move with the note. Other commands, and
+    /// [Command::Marker]s, keep their time. The [len_time](CommandSeq::len_time) of the sequence is preserved unless a
+    /// note is moved past the end of it.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Delay(5),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 40 },
+    ///     Command::Delay(25),
+    ///     Command::Marker("Loop".to_string()),
+    ///     Command::Delay(22),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 40 },
+    ///     Command::Delay(44),
+    /// ]);
+    ///
+    /// sequence.quantize(48, 1.0);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 40 },
+    ///     Command::Delay(30),
+    ///     Command::Marker("Loop".to_string()), // Not moved
+    ///     Command::Delay(18),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 40 },
+    ///     Command::Delay(48),
+    /// ]));
+    /// ```
+    ///
+    /// A voice change just before a note stays before it:
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(22),
+    ///     Command::TrackVoice(2),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(26),
+    /// ]);
+    ///
+    /// sequence.quantize(24, 1.0);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::TrackVoice(2), // Not left behind at 22, where it would change the voice of the first note
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    /// ]));
+    /// ```
+    ///
+    /// Notes of the same pitch which end up at the same time are combined into one:
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Delay(46),
+    ///     Command::Note { pitch: 0x90, velocity: 80, length: 10 },
+    ///     Command::Delay(4),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 5 },
+    /// ]);
+    ///
+    /// sequence.quantize(48, 1.0);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 10 },
+    ///     Command::Delay(2),
+    /// ]));
+    /// ```
+    pub fn quantize(&mut self, grid_ticks: u32, strength: f32) {
+        if grid_ticks == 0 {
+            return;
+        }
+
+        self.move_notes(|time| quantize_time(time, grid_ticks, strength));
+    }
+
+    /// Delays the notes on every other subdivision of `grid_ticks`, the off-beats, for a swung feel. `amount` is how
+    /// far they move, as a fraction of a subdivision: `0.0` leaves them straight and `1.0 / 3.0` gives triplet swing.
+    /// Notes between subdivisions are moved in proportion, so notes stay in order, and notes on the beat don't move.
+    /// The [len_time](CommandSeq::len_time) of the sequence is preserved.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    /// ]);
+    ///
+    /// sequence.apply_swing(24, 1.0 / 3.0);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(32), // Two thirds of the beat
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(16),
+    /// ]));
+    /// ```
+    pub fn apply_swing(&mut self, grid_ticks: u32, amount: f32) {
+        if grid_ticks == 0 {
+            return;
+        }
+
+        let grid = grid_ticks as f32;
+        let pair = grid * 2.0;
+        let swung = grid + grid * amount.clamp(0.0, 1.0);
+        let last = self.len_time().saturating_sub(1);
+
+        self.move_notes(|time| {
+            let pos = time as f32 % pair;
+            let start = time as f32 - pos;
+
+            // Stretch the on-beat half of each pair and squash the off-beat half to fit.
+            let new_pos = if pos < grid {
+                pos * swung / grid
+            } else {
+                swung + (pos - grid) * (pair - swung) / grid
+            };
+
+            ((start + new_pos).round() as usize).min(last.max(time))
+        });
+    }
+
+    /// Nudges the start of every [Command::Note] earlier or later by a random amount, up to `max_jitter_ticks`, so
+    /// that playback sounds less mechanical. The same `seed` always moves the same notes the same way. Notes aren't
+    /// moved before the start or past the end of the sequence, so its [len_time](CommandSeq::len_time) is preserved.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::new();
+    /// for _ in 0..8 {
+    ///     sequence.push(Command::Note { pitch: 0x90, velocity: 100, length: 12 });
+    ///     sequence.push(Command::Delay(48));
+    /// }
+    ///
+    /// let mut humanized = sequence.clone();
+    /// humanized.humanize(4, 1);
+    ///
+    /// assert_ne!(humanized, sequence);
+    /// assert_eq!(humanized.len_time(), sequence.len_time());
+    /// for (before, after) in sequence.notes().zip(humanized.notes()) {
+    ///     assert!((before.time as isize - after.time as isize).abs() <= 4);
+    /// }
+    ///
+    /// let mut again = sequence.clone();
+    /// again.humanize(4, 1);
+    /// assert_eq!(again, humanized);
+    /// ```
+    pub fn humanize(&mut self, max_jitter_ticks: usize, seed: u64) {
+        if max_jitter_ticks == 0 {
+            return;
+        }
+
+        let mut rng = Rng(seed);
+        let last = self.len_time().saturating_sub(1);
+
+        self.move_notes(|time| {
+            let jitter = (rng.next() % (max_jitter_ticks as u64 * 2 + 1)) as usize;
+            (time + jitter).saturating_sub(max_jitter_ticks).min(last.max(time))
+        });
+    }
+
+    /// Moves the start of every [Command::Note] to the time `new_time` gives for it, rebuilding the [Delay]s around
+    /// them. Commands at the same time as a note go wherever the first note at that time goes, except
+    /// [Command::Marker]s and [Command::Subroutine]s, which keep their time like commands away from notes do. Notes of
+    /// the same pitch that end up at the same time are combined into one. The [len_time](CommandSeq::len_time) of the
+    /// sequence is preserved unless a note is moved past the end.
+    fn move_notes<F: FnMut(usize) -> usize>(&mut self, mut new_time: F) {
+        let total_time = self.len_time();
+        let mut has_end = false;
+        let mut events: Vec<(usize, Command)> = Vec::with_capacity(self.vec.len());
+
+        // Where each note goes, and where the first note at each time goes, sorted by the time it was at.
+        let mut note_times = Vec::new();
+        let mut moved: Vec<(usize, usize)> = Vec::new();
+        for (time, command) in self.iter_time() {
+            if let Command::Note { .. } = command {
+                let to = new_time(time);
+                if moved.last().map(|&(from, _)| from) != Some(time) {
+                    moved.push((time, to));
+                }
+                note_times.push(to);
+            }
+        }
+
+        let mut note_times = note_times.into_iter();
+        for (time, command) in self.iter_time() {
+            match command {
+                Command::Delay(_) => {}
+                Command::End => has_end = true,
+                Command::Note { .. } => events.push((note_times.next().unwrap(), command.clone())),
+                Command::Marker(_) | Command::Subroutine(_) => events.push((time, command.clone())),
+                _ => {
+                    let to = moved.binary_search_by_key(&time, |&(from, _)| from).map_or(time, |i| moved[i].1);
+                    events.push((to, command.clone()));
+                }
+            }
+        }
+
+        // Stable, so commands at the same time keep their relative order.
+        events.sort_by_key(|(time, _)| *time);
+
+        self.vec.clear();
+        let mut current_time = 0;
+        let mut group_start = 0;
+
+        for (time, command) in events {
+            if time > current_time {
+                self.vec.push(Command::Delay(time - current_time));
+                current_time = time;
+                group_start = self.vec.len();
+            }
+
+            if let Command::Note { pitch, velocity, length } = command {
+                let duplicate = self.vec[group_start..].iter_mut().find_map(|other| match other {
+                    Command::Note { pitch: p, velocity: v, length: l } if *p == pitch => Some((v, l)),
+                    _ => None,
+                });
+
+                if let Some((other_velocity, other_length)) = duplicate {
+                    *other_velocity = (*other_velocity).max(velocity);
+                    *other_length = (*other_length).max(length);
+                    continue;
+                }
+            }
+
+            self.vec.push(command);
+        }
+
+        if total_time > current_time {
+            self.vec.push(Command::Delay(total_time - current_time));
+        }
+
+        if has_end {
+            self.vec.push(Command::End);
+        }
+    }
+
+    /// Moves the end of every [Command::Note] towards the nearest multiple of `grid_ticks`, like
+    /// [quantize](CommandSeq::quantize) does for note starts. Notes are never shortened below one tick.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 40 },
+    /// ]);
+    ///
+    /// sequence.quantize_lengths(48, 0.5);
+    ///
+    /// // Ends at 92 (halfway between 88 and 96)
+    /// assert_eq!(sequence.notes().next().unwrap().length, 44);
+    /// ```
+    pub fn quantize_lengths(&mut self, grid_ticks: u32, strength: f32) {
+        if grid_ticks == 0 {
+            return;
+        }
+
+        for note in self.notes_mut() {
+            let end_time = quantize_time(note.end_time(), grid_ticks, strength);
+            *note.length = end_time.saturating_sub(note.time).clamp(1, u16::MAX as usize) as u16;
+        }
+    }
+
+    /// Scales the velocity of every [Command::Note] by the same amount, so that the loudest is `target_peak`. Notes
+    /// keep how loud they are compared to each other, as far as rounding allows. Does nothing if every note is silent.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 40, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 20, length: 48 },
+    /// ]);
+    ///
+    /// sequence.normalize_velocity(100);
+    /// let velocities: Vec<u8> = sequence.notes().map(|note| note.velocity).collect();
+    /// assert_eq!(velocities, vec![100, 50]);
+    ///
+    /// sequence.normalize_velocity(255); // Clamped to VELOCITY_MAX
+    /// assert_eq!(sequence.notes().next().unwrap().velocity, VELOCITY_MAX);
+    /// ```
+    pub fn normalize_velocity(&mut self, target_peak: u8) {
+        let peak = self.notes().map(|note| note.velocity).max().unwrap_or(0);
+        if peak == 0 {
+            return;
+        }
+
+        let scale = target_peak.min(VELOCITY_MAX) as f32 / peak as f32;
+        for note in self.notes_mut() {
+            *note.velocity = clamp_velocity(*note.velocity as f32 * scale);
+        }
+    }
+
+    /// Evens out the velocities of every [Command::Note], like a compressor does to audio. The loudest note is left
+    /// as it is, and how far each other note is below it is divided by `ratio`, so `2.0` halves the difference and
+    /// `1.0` changes nothing. A `ratio` below `1.0` spreads velocities further apart instead. Velocities are kept
+    /// within `0..=`[VELOCITY_MAX].
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 40, length: 48 },
+    /// ]);
+    ///
+    /// sequence.compress_velocity(2.0);
+    /// let velocities: Vec<u8> = sequence.notes().map(|note| note.velocity).collect();
+    /// assert_eq!(velocities, vec![100, 70]);
+    ///
+    /// sequence.compress_velocity(0.25);
+    /// let velocities: Vec<u8> = sequence.notes().map(|note| note.velocity).collect();
+    /// assert_eq!(velocities, vec![100, 0]); // 100 - 30 * 4, clamped
+    /// ```
+    pub fn compress_velocity(&mut self, ratio: f32) {
+        if ratio.is_nan() || ratio <= 0.0 {
+            return;
+        }
+
+        let peak = match self.notes().map(|note| note.velocity).max() {
+            Some(peak) => peak as f32,
+            None => return,
+        };
+
+        for note in self.notes_mut() {
+            *note.velocity = clamp_velocity(peak - (peak - *note.velocity as f32) / ratio);
+        }
+    }
+
+    /// Multiplies the volume set by every [Command::MasterVolume] and [Command::MasterVolumeFade] by `scale`. Volumes
+    /// are rounded to the nearest whole number, and clamped to what the command can hold.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::MasterVolume(101),
+    ///     Command::MasterVolumeFade { time: 48, volume: 200 },
+    /// ]);
+    ///
+    /// sequence.scale_master_volume(1.5);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::MasterVolume(152), // 151.5, rounded up
+    ///     Command::MasterVolumeFade { time: 48, volume: 255 }, // 300, clamped
+    /// ]));
+    /// ```
+    pub fn scale_master_volume(&mut self, scale: f32) {
+        for command in self.iter_mut() {
+            match command {
+                Command::MasterVolume(volume) | Command::MasterVolumeFade { volume, .. } => {
+                    *volume = (*volume as f32 * scale).round().clamp(0.0, u8::MAX as f32) as u8;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Multiplies the tempo set by every [Command::MasterTempo] and [Command::MasterTempoFade] by `scale`. Tempos are
+    /// rounded to the nearest whole BPM, and are never scaled below 1 BPM, which would stop the song.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::MasterTempo(125),
+    ///     Command::MasterTempoFade { time: 48, bpm: 1 },
+    /// ]);
+    ///
+    /// sequence.scale_tempo(0.9);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::MasterTempo(113), // 112.5, rounded up
+    ///     Command::MasterTempoFade { time: 48, bpm: 1 },
+    /// ]));
+    /// ```
+    pub fn scale_tempo(&mut self, scale: f32) {
+        for command in self.iter_mut() {
+            match command {
+                Command::MasterTempo(bpm) | Command::MasterTempoFade { bpm, .. } => {
+                    *bpm = (*bpm as f32 * scale).round().clamp(1.0, u16::MAX as f32) as u16;
+                }
+                _ => {}
+            }
         }
     }
 
-    /// Returns the relative-time after the last [Command]. Does not account for any final command which extends the
-    /// *playback* time (not the relative-time), that is, [Command::Note] (use [CommandSeq::playback_time] to find
-    /// this value).
+    /// Removes commands that make no difference to how this sequence sounds, so that it encodes to fewer
+    /// [commands](CommandSeq::command_count). These are:
     ///
-    /// ```
-    /// # use pm64::bgm::*;
-    /// let mut sequence = CommandSeq::new();
-    /// assert_eq!(sequence.len_time(), 0);
+    /// - a controller, like [SubTrackPan](Command::SubTrackPan), that sets what is already set;
+    /// - a controller that is set again before any note plays or time passes;
+    /// - a zero-length [Delay], or a [Delay] directly after another, which is merged into it.
     ///
-    /// sequence.push(Command::Delay(10));
-    /// assert_eq!(sequence.len_time(), 10);
+    /// What a controller is set to is forgotten at any command that could change it some other way, such as a fade,
+    /// a voice change, or a [Marker](Command::Marker) that a subroutine could jump to. Returns how many fewer commands
+    /// the sequence encodes to.
     ///
-    /// sequence.push(Command::Delay(10));
-    /// sequence.push(Command::Delay(10));
-    /// assert_eq!(sequence.len_time(), 30);
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::SubTrackPan(64),
+    ///     Command::SubTrackPan(32), // Replaces the first before it is heard
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(24),
+    ///     Command::SubTrackPan(32), // Already panned there
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    /// ]);
     ///
-    /// // Wouldn't make sense to do this in practice, but it is valid.
-    /// sequence.push(Command::Delay(0));
-    /// assert_eq!(sequence.len_time(), 30);
+    /// assert_eq!(sequence.remove_redundant(), 3);
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::SubTrackPan(32),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    /// ]));
     /// ```
-    pub fn len_time(&self) -> usize {
-        let mut time = 0;
+    pub fn remove_redundant(&mut self) -> usize {
+        let count_before = self.command_count();
+
+        // The last command to set each controller, by opcode, and whether it has been heard yet.
+        let mut controllers: HashMap<u8, (usize, bool)> = HashMap::new();
+        let mut keep = vec![true; self.vec.len()];
+
+        for (i, command) in self.vec.iter().enumerate() {
+            match command {
+                Delay(0) => keep[i] = false,
+                Delay(_) | Command::Note { .. } => {
+                    for (_, heard) in controllers.values_mut() {
+                        *heard = true;
+                    }
+                }
+                _ => match controller_opcode(command) {
+                    Some(opcode) => {
+                        if let Some(&(last, heard)) = controllers.get(&opcode) {
+                            if self.vec[last] == *command {
+                                keep[i] = false;
+                                continue;
+                            }
+                            if !heard {
+                                keep[last] = false;
+                            }
+                        }
+                        controllers.insert(opcode, (i, false));
+                    }
+                    None => controllers.clear(),
+                },
+            }
+        }
 
-        for command in self.vec.iter() {
-            if let Command::Delay(delta_time) = command {
-                time += *delta_time as usize;
+        let mut vec: Vec<Command> = Vec::with_capacity(self.vec.len());
+        for (command, keep) in self.vec.drain(..).zip(keep) {
+            if !keep {
+                continue;
+            }
+            match (vec.last_mut(), command) {
+                (Some(Delay(last)), Delay(delay)) => *last += delay,
+                (_, command) => vec.push(command),
             }
         }
+        self.vec = vec;
 
-        time
+        count_before.saturating_sub(self.command_count())
     }
 
-    /// Calculates the time it takes for this [CommandSeq] to finish in terms of audio playback (i.e. when all
-    /// notes have stopped).
+    /// Partitions this sequence into one sequence per voice, where each contains only the [Command::Note]s played
+    /// with that voice (as selected by [Command::TrackVoice]). Every other command, including [Delay]s, is copied into
+    /// every resulting sequence, so each plays back exactly like the original minus the other voices' notes. Each
+    /// result begins with the [Command::TrackVoice] it uses, and has no other voice changes.
+    ///
+    /// Results are ordered by the first note played with each voice. Notes played before any [Command::TrackVoice]
+    /// get a sequence of their own with no [Command::TrackVoice] at all.
     ///
-    /// Equivalent to [CommandSeq::len_time] for a sequence with no [Command::Note]s:
     /// ```
     /// # use pm64::bgm::*;
-    /// let seq = CommandSeq::from(vec![Command::Delay(1000)]);
-    /// assert_eq!(seq.playback_time(), 1000);
-    /// assert_eq!(seq.len_time(), 1000);
+    /// let sequence = CommandSeq::from(vec![
+    ///     Command::TrackVoice(2),
+    ///     Command::SubTrackPan(64),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::TrackVoice(5),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    /// ]);
+    ///
+    /// assert_eq!(sequence.split_by_voice(), vec![
+    ///     CommandSeq::from(vec![
+    ///         Command::TrackVoice(2),
+    ///         Command::SubTrackPan(64),
+    ///         Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///         Command::Delay(48),
+    ///         Command::Delay(48),
+    ///     ]),
+    ///     CommandSeq::from(vec![
+    ///         Command::TrackVoice(5),
+    ///         Command::SubTrackPan(64),
+    ///         Command::Delay(48),
+    ///         Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///         Command::Delay(48),
+    ///     ]),
+    /// ]);
     /// ```
+    pub fn split_by_voice(&self) -> Vec<CommandSeq> {
+        let mut voices = Vec::new();
+        let mut voice = None;
+
+        for command in self.iter() {
+            match command {
+                Command::TrackVoice(v) => voice = Some(*v),
+                Command::Note { .. } if !voices.contains(&voice) => voices.push(voice),
+                _ => {}
+            }
+        }
+
+        voices
+            .into_iter()
+            .map(|wanted_voice| {
+                let mut seq = CommandSeq::with_capacity(self.len());
+                let mut voice = None;
+
+                if let Some(v) = wanted_voice {
+                    seq.push(Command::TrackVoice(v));
+                }
+
+                for command in self.iter() {
+                    match command {
+                        Command::TrackVoice(v) => voice = Some(*v),
+                        Command::Note { .. } => {
+                            if voice == wanted_voice {
+                                seq.push(command.clone());
+                            }
+                        }
+                        _ => seq.push(command.clone()),
+                    }
+                }
+
+                seq
+            })
+            .collect()
+    }
+
+    /// Copies the commands in the relative-time range `range`, re-anchored so that the copy starts at time zero and
+    /// ends with a [Delay] up to the end of the range. The copy can be put back with [CommandSeq::paste], in this
+    /// sequence or another one.
+    ///
+    /// [Markers](Command::Marker), [subroutines](Command::Subroutine) and [End](Command::End) are left out, as they
+    /// only make sense where they are.
     ///
-    /// An empty sequence has a playback time of zero:
     /// ```
     /// # use pm64::bgm::*;
-    /// assert_eq!(CommandSeq::new().playback_time(), 0);
+    /// let sequence = CommandSeq::from(vec![
+    ///     Command::Delay(24),
+    ///     Command::TrackVoice(3),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// assert_eq!(sequence.copy_range(24..96), vec![
+    ///     Command::TrackVoice(3),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(48),
+    /// ]);
     /// ```
-    pub fn playback_time(&self) -> usize {
-        self.iter_time().last().map_or(0, |(time, command)| match *command {
-            Command::Delay(delta) => time + delta as usize,
-            Command::Note { length, .. } => time + length as usize,
-            _ => time,
-        })
-    }
+    pub fn copy_range(&self, range: Range<usize>) -> Vec<Command> {
+        let mut clip = Vec::new();
+        let mut clip_time = range.start;
+
+        for (time, command) in self.iter_time() {
+            if range.contains(&time) && is_copyable(command) {
+                if time > clip_time {
+                    clip.push(Delay(time - clip_time));
+                    clip_time = time;
+                }
 
-    /// See [Vec::with_capacity].
-    pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            vec: Vec::with_capacity(capacity),
+                clip.push(command.clone());
+            }
         }
-    }
 
-    /// Optimises this sequence to take up as little memory as possible whilst still being playback-equivalent (i.e.
-    /// sounds the same).
-    pub fn shrink(&mut self) {
-        // TODO: remove Delay(0) commands
-        // TODO: combine redundant subsequences (e.g. multiple Delay, multiple MasterTempo without a delay between)
-        self.vec.shrink_to_fit();
-    }
+        if range.end > clip_time {
+            clip.push(Delay(range.end - clip_time));
+        }
 
-    /// Appends the given [Command] to the end of the sequence.
-    pub fn push<C: Into<Command>>(&mut self, command: C) {
-        self.vec.push(command.into())
+        clip
     }
 
-    /// Returns the number of commands ("length") in the sequence.
-    pub fn len(&self) -> usize {
-        self.vec.len()
-    }
+    /// Removes the commands that [CommandSeq::copy_range] would copy, and returns the copy. [Delays](Delay) are kept,
+    /// so the commands after `range` stay where they are.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// assert_eq!(sequence.cut_range(48..96), vec![
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    /// ]);
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]));
+    /// ```
+    pub fn cut_range(&mut self, range: Range<usize>) -> Vec<Command> {
+        let clip = self.copy_range(range.clone());
+        let mut time = 0;
 
-    pub fn is_empty(&self) -> bool {
-        self.vec.len() == 0 || (self.vec.len() == 1 && self.vec[0] == Command::End)
+        self.vec.retain(|command| match command {
+            Delay(delta_time) => {
+                time += delta_time;
+                true
+            }
+            _ => !(range.contains(&time) && is_copyable(command)),
+        });
+
+        clip
     }
 
-    pub fn pitch_range(&self) -> Range<u8> {
-        let mut range = 0..0;
+    /// Inserts a copy made by [CommandSeq::copy_range] so that it starts at `time`, keeping the timing between its
+    /// commands. Where this sequence already has commands at the same time, the pasted ones go after them, so that
+    /// e.g. a pasted [TrackVoice](Command::TrackVoice) is the one that takes effect.
+    ///
+    /// Commands that would land at or after the [End](Command::End) of the sequence are left out, since they would
+    /// never play. Returns how many were left out.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let clip = vec![
+    ///     Command::TrackVoice(3),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    /// ];
+    ///
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::Delay(96),
+    ///     Command::TrackVoice(2),
+    ///     Command::Delay(96),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// assert_eq!(sequence.paste(96, &clip), 0);
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::Delay(96),
+    ///     Command::TrackVoice(2),
+    ///     Command::TrackVoice(3), // After the voice that was already here, so it takes effect
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 },
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(72),
+    ///     Command::End,
+    /// ]));
+    ///
+    /// // The second note would come after the End.
+    /// assert_eq!(sequence.paste(180, &clip), 1);
+    /// ```
+    pub fn paste(&mut self, time: usize, clip: &[Command]) -> usize {
+        let end_time = self.iter_time().find(|(_, command)| **command == Command::End).map(|(time, _)| time);
+
+        // Group the commands by when they will be, so each group can be inserted in one go.
+        let mut groups: Vec<(usize, Vec<Command>)> = Vec::new();
+        let mut clip_time = time;
+        for command in clip {
+            match command {
+                Delay(delta_time) => clip_time += delta_time,
+                _ => match groups.last_mut() {
+                    Some((group_time, group)) if *group_time == clip_time => group.push(command.clone()),
+                    _ => groups.push((clip_time, vec![command.clone()])),
+                },
+            }
+        }
 
-        for cmd in self.iter() {
-            if let Command::Note { pitch, .. } = cmd {
-                let pitch = *pitch;
+        let mut num_left_out = 0;
 
-                if pitch < range.start {
-                    range.start = pitch;
-                }
+        for (time, group) in groups {
+            if matches!(end_time, Some(end_time) if time >= end_time) {
+                num_left_out += group.len();
+                continue;
+            }
 
-                if pitch >= range.end {
-                    range.end = pitch.saturating_add(1);
+            match self.index_after_commands_at(time) {
+                Some(index) => {
+                    self.vec.splice(index..index, group);
                 }
+                None => self.insert_many(time, group),
             }
         }
 
-        range
+        num_left_out
     }
 
-    pub fn clear_command(&mut self, idx: usize) {
-        self.vec[idx] = Command::Delay(0);
+    /// Returns the index just after the last command at `time`, ignoring any [End](Command::End), or None if there are
+    /// no commands at that time.
+    fn index_after_commands_at(&self, time: usize) -> Option<usize> {
+        let mut current_time = 0;
+        let mut index = None;
+
+        for (i, command) in self.vec.iter().enumerate() {
+            match command {
+                Delay(delta_time) => {
+                    current_time += delta_time;
+                    if current_time > time {
+                        break;
+                    }
+                }
+                Command::End if current_time == time => break,
+                _ if current_time == time => index = Some(i + 1),
+                _ => {}
+            }
+        }
+
+        index
     }
 
-    pub fn zero_all_delays(&mut self) {
-        for cmd in &mut self.vec {
-            if let Command::Delay(_) = cmd {
-                *cmd = Command::Delay(0);
+    /// Removes the first `time` ticks of the sequence, so that it starts playing from part-way through. Commands
+    /// that set things up, like [TrackVoice](Command::TrackVoice), are kept and run straight away, but notes in the
+    /// removed part are dropped. Notes still sounding at `time` are shortened to their remaining length.
+    ///
+    /// [Subroutines](Command::Subroutine) in the removed part are dropped too, as they could contain notes.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut sequence = CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 96 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 24 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x97, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// sequence.skip_to(72);
+    ///
+    /// assert_eq!(sequence, CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 24 }, // Shortened
+    ///     Command::Delay(24),
+    ///     Command::Note { pitch: 0x97, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]));
+    /// ```
+    pub fn skip_to(&mut self, time: usize) {
+        let len_time = self.len_time();
+        let mut vec = Vec::with_capacity(self.vec.len());
+        let mut held_notes = Vec::new();
+        let mut rest = Vec::new();
+
+        for (t, command) in self.iter_time() {
+            match *command {
+                Delay(_) => {}
+                Command::Note { pitch, velocity, length } if t < time => {
+                    let end_time = t + length as usize;
+                    if end_time > time {
+                        held_notes.push(Command::Note {
+                            pitch,
+                            velocity,
+                            length: (end_time - time) as u16,
+                        });
+                    }
+                }
+                Command::Subroutine(_) if t < time => {}
+                _ if t < time => vec.push(command.clone()),
+                _ => rest.push((t - time, command.clone())),
+            }
+        }
+
+        vec.append(&mut held_notes);
+
+        let mut current_time = 0;
+        for (t, command) in rest {
+            if t > current_time {
+                vec.push(Delay(t - current_time));
+                current_time = t;
             }
+            vec.push(command);
+        }
+
+        let end_time = len_time.saturating_sub(time);
+        if end_time > current_time {
+            vec.push(Delay(end_time - current_time));
         }
+
+        self.vec = vec;
     }
 
     // TODO
@@ -533,6 +1781,21 @@ impl From<Vec<Command>> for CommandSeq {
     }
 }
 
+impl AsRef<[Command]> for CommandSeq {
+    fn as_ref(&self) -> &[Command] {
+        &self.vec
+    }
+}
+
+impl<'a> IntoIterator for &'a CommandSeq {
+    type Item = &'a Command;
+    type IntoIter = std::slice::Iter<'a, Command>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.vec.iter()
+    }
+}
+
 impl iter::FromIterator<Command> for CommandSeq {
     fn from_iter<T: IntoIterator<Item = Command>>(iter: T) -> Self {
         Self {
@@ -541,6 +1804,34 @@ impl iter::FromIterator<Command> for CommandSeq {
     }
 }
 
+/// Moves `time` towards the nearest multiple of `grid_ticks` by `strength` (0.0..=1.0).
+fn quantize_time(time: usize, grid_ticks: u32, strength: f32) -> usize {
+    let grid_ticks = grid_ticks as usize;
+    let nearest = ((time + grid_ticks / 2) / grid_ticks) * grid_ticks;
+    let strength = strength.clamp(0.0, 1.0);
+
+    (time as f32 + (nearest as f32 - time as f32) * strength).round() as usize
+}
+
+/// A small pseudo-random number generator (splitmix64), so that [CommandSeq::humanize] gives the same results for the
+/// same seed everywhere.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Rounds a scaled velocity to the nearest one a note can have.
+fn clamp_velocity(velocity: f32) -> u8 {
+    velocity.round().clamp(0.0, VELOCITY_MAX as f32) as u8
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum DelayLookup {
     /// The index of the [Delay] that introduces the time being looked up. That is, the command (if any) immediately
@@ -552,86 +1843,123 @@ enum DelayLookup {
 }
 
 /// A Command (or in MIDI terms, an event) describes operations performed at a particular time and on a particular track
-/// during playback, like playing a note or changing the track instrument. CommandSeq are independent of (and therefore
+/// during playback, like playing a note or changing the track instrument. Commands are independent of (and therefore
 /// do not know of) any likely - but not required - parent structs (such as [CommandSeq] and its parent
 /// [Track](crate::Track)) and by extension any properties known only by them, such as the command's absolute and
 /// relative time positioning.
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+///
+/// ## Units
+///
+/// - Times and lengths are in ticks. There are [TICKS_PER_BEAT] ticks in a beat, so how long a tick lasts depends on
+///   the tempo.
+/// - Tempos are in beats per minute.
+/// - Volumes go from 0 (silent) to 127 (full) unless noted otherwise.
+///
+/// ## Encoding
+///
+/// Each variant notes the opcode it is encoded as, i.e. the first byte, which says what kind of command it is; see
+/// also [Command::opcode]. Any arguments follow, in the order of the variant's fields, with 16-bit values big-endian.
+///
+/// There are no loop commands: where a segment loops is set by [Subsegment](crate::Subsegment)s, see
+/// [Bgm::set_loop](crate::Bgm::set_loop).
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Command {
-    /// Sleeps for however many ticks before continuing playback on this track.
+    /// Sleeps for however many ticks before continuing playback on this track. Every command between two delays runs
+    /// at the same time.
+    ///
+    /// Opcodes `0x01..=0x77` are a delay of that many ticks, and `0x78..=0x7F` a longer delay with another byte
+    /// following. Delays too long for one command are encoded as several.
     Delay(usize),
 
-    /// Plays a note or drum sound. Non-blocking(?), i.e. does **not** act like a [`Delay(length)`](Command::Delay) and
+    /// Plays a note or drum sound. Non-blocking, i.e. does **not** act like a [`Delay(length)`](Command::Delay) and
     /// only continue execution once the note has finished playing.
+    ///
+    /// Opcode `0x80..=0xD3`, the pitch. On a drum track, the pitch picks a [Drum](crate::Drum) instead.
     Note {
+        /// From [PITCH_MIN] (a C) to [PITCH_MAX], a semitone apart.
         // TODO: make Pitch type
         pitch: u8,
 
+        /// How hard the note is played, from 0 to 127.
         // TODO: determine bounds etc and use newtype
         velocity: u8,
 
-        // TODO: determine max value (it's not u16::MAX)
-        // I think it's 0xD3FF? TODO use newtype
+        /// In ticks, up to `0xD3FF`.
+        // TODO: use newtype
         length: u16,
     },
 
-    /// Sets the beats-per-minute of the composition.
+    /// Sets the tempo of the composition in beats per minute. Opcode `0xE0`.
     MasterTempo(u16),
 
-    /// Fades the tempo to `bpm` across `time` ticks.
+    /// Fades the tempo to `bpm` across `time` ticks. Opcode `0xE4`.
     MasterTempoFade {
         time: u16,
         bpm: u16,
     },
 
-    /// Sets the composition volume.
+    /// Sets the composition volume. Opcode `0xE1`.
     MasterVolume(u8),
 
-    /// Fades the volume to `volume` across `time` ticks.
+    /// Fades the volume to `volume` across `time` ticks. Opcode `0xE5`.
     MasterVolumeFade {
         time: u16,
         volume: u8,
     },
 
-    /// Sets the volume for this track only. Resets at the end of the [super::Subsegment].
+    /// Sets the volume for this track only. Resets at the end of the [super::Subsegment]. Opcode `0xE9`.
     SubTrackVolume(u8),
 
-    /// Sets the volume for this track only. Resets at the end of the [super::Segment].
+    /// Sets the volume for this track only. Resets at the end of the [super::Segment]. Opcode `0xEC`.
     SegTrackVolume(u8),
 
+    /// Fades the volume of this track to `volume` across `time` ticks. Opcode `0xF6`.
     // TODO: figure out whether Seg or Sub
     TrackVolumeFade {
         time: u16,
         volume: u8,
     },
 
-    /// Sets the composition transpose value. It is currently unknown exactly how this adjusts pitch.
+    /// Sets the composition transpose value. It is currently unknown exactly how this adjusts pitch. Opcode `0xE2`.
     MasterTranspose(i8),
 
-    /// Applies the given effect to the entire composition.
+    /// Applies the given effect to the entire composition. Opcode `0xE6`.
     MasterEffect(u8, u8), // TODO: enum for field
 
-    /// Sets the bank/patch of this track, overriding its [super::Voice].
+    /// Sets the bank/patch of this track, overriding its [super::Voice], like a MIDI program change. Opcode `0xE8`.
     TrackOverridePatch {
         bank: u8,
         patch: u8,
     },
 
+    /// Sets where this track is in the stereo field. Opcode `0xEA`.
+    ///
     /// Left = (+/-)0.
     /// Middle = (+/-)64.
     /// Right = (+/-)127.
     SubTrackPan(i8),
 
+    /// Sets how much reverb this track has. Opcode `0xEB`.
     SubTrackReverb(u8),
+
+    /// Opcode `0xF7`.
     SubTrackReverbType(u8), // TODO: enum for field
 
+    /// Shifts this track's pitch by semitones. Opcode `0xED`.
     SubTrackCoarseTune(u8),
+
+    /// Shifts this track's pitch by fractions of a semitone. Opcode `0xEE`.
     SubTrackFineTune(u8),
+
+    /// Both [SubTrackCoarseTune](Command::SubTrackCoarseTune) and [SubTrackFineTune](Command::SubTrackFineTune), but
+    /// until the end of the [super::Segment]. Opcode `0xEF`.
     SegTrackTune {
         coarse: u8,
         fine: u8,
     },
 
+    /// Makes this track waver in pitch. Opcode `0xF0`.
     // TODO: figure out whether Seg or Sub
     TrackTremolo {
         amount: u8,
@@ -639,33 +1967,153 @@ pub enum Command {
         unknown: u8,
     },
 
+    /// Stops a [TrackTremolo](Command::TrackTremolo). Opcode `0xF3`.
     // XXX: Angry_Bowser_67 doesn't have any TrackTremolo but does have TrackTremoloStop.
     // It may reset SubTrackVolume also?
     TrackTremoloStop,
 
+    /// Sets the track's voice (instrument). Field is an index into [super::Bgm::voices]. Opcode `0xF5`.
     // TODO: figure out whether Seg or Sub
-    /// Sets the track's voice. Field is an index into [super::Bgm::voices].
     TrackVoice(u8),
 
     /// Markers don't actually exist in the BGM binary format (rather, it uses command offsets); we use this
-    /// abstraction rather than [CommandSeq] indices because they stay stable during mutation.
+    /// abstraction rather than [CommandSeq] indices because they stay stable during mutation. Not encoded, so it has
+    /// no opcode.
     Marker(MarkerId),
 
-    /// Jumps to the start label and executes until the end label is found.
+    /// Jumps to the start label and executes until the end label is found. Opcode `0xFE`.
     Subroutine(CommandRange),
 
-    /// An unknown/unimplemented command.
+    /// An unknown/unimplemented command, as the bytes it is encoded as, opcode first.
     Unknown(Vec<u8>),
 
     /// Stops playback on this track. Note that it is valid to have commands after an `End`; they can be executed
-    /// via a [`Subroutine`](Command::Subroutine) jump.
+    /// via a [`Subroutine`](Command::Subroutine) jump. Opcode `0x00`.
     End,
 }
 
+impl Command {
+    /// The first byte this command is encoded as, which says what kind of command it is. `None` for commands that
+    /// aren't encoded, i.e. [Marker](Command::Marker)s and zero-length [Delay]s.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// assert_eq!(Command::MasterTempo(120).opcode(), Some(0xE0));
+    /// assert_eq!(Command::Note { pitch: 0x90, velocity: 100, length: 48 }.opcode(), Some(0x90));
+    /// assert_eq!(Command::Delay(48).opcode(), Some(48));
+    /// assert_eq!(Command::Marker("start".to_owned()).opcode(), None);
+    ///
+    /// // It is the first byte the command encodes to
+    /// let mut encoded = std::io::Cursor::new(Vec::new());
+    /// CommandSeq::from(vec![Command::Delay(1000)]).encode(&mut encoded, false).unwrap();
+    /// assert_eq!(Command::Delay(1000).opcode(), Some(encoded.into_inner()[0]));
+    /// ```
+    pub fn opcode(&self) -> Option<u8> {
+        Some(match self {
+            Delay(0) => return None,
+            Delay(delay) if *delay < DELAY_MAX as usize => *delay as u8,
+            Delay(delay) => DELAY_MAX | ((delay - DELAY_MAX as usize) >> 8).min(7) as u8,
+            Command::Note { pitch, .. } => *pitch,
+            Command::MasterTempo(_) => 0xE0,
+            Command::MasterVolume(_) => 0xE1,
+            Command::MasterTranspose(_) => 0xE2,
+            Command::MasterTempoFade { .. } => 0xE4,
+            Command::MasterVolumeFade { .. } => 0xE5,
+            Command::MasterEffect(..) => 0xE6,
+            Command::TrackOverridePatch { .. } => 0xE8,
+            Command::SubTrackVolume(_) => 0xE9,
+            Command::SubTrackPan(_) => 0xEA,
+            Command::SubTrackReverb(_) => 0xEB,
+            Command::SegTrackVolume(_) => 0xEC,
+            Command::SubTrackCoarseTune(_) => 0xED,
+            Command::SubTrackFineTune(_) => 0xEE,
+            Command::SegTrackTune { .. } => 0xEF,
+            Command::TrackTremolo { .. } => 0xF0,
+            Command::TrackTremoloStop => 0xF3,
+            Command::TrackVoice(_) => 0xF5,
+            Command::TrackVolumeFade { .. } => 0xF6,
+            Command::SubTrackReverbType(_) => 0xF7,
+            Command::Subroutine(_) => 0xFE,
+            Command::Unknown(bytes) => return bytes.first().copied(),
+            Command::Marker(_) => return None,
+            Command::End => 0x00,
+        })
+    }
+}
+
 use Command::Delay;
 
+/// Whether [CommandSeq::copy_range] copies `command`.
+fn is_copyable(command: &Command) -> bool {
+    !matches!(command, Delay(_) | Command::Marker(_) | Command::Subroutine(_) | Command::End)
+}
+
+/// The opcode of `command` if it only sets one thing to a value, so that setting it to the same value again does
+/// nothing. Used by [CommandSeq::remove_redundant].
+fn controller_opcode(command: &Command) -> Option<u8> {
+    match command {
+        Command::MasterTempo(_)
+        | Command::MasterVolume(_)
+        | Command::MasterTranspose(_)
+        | Command::SubTrackVolume(_)
+        | Command::SubTrackPan(_)
+        | Command::SubTrackReverb(_)
+        | Command::SubTrackReverbType(_)
+        | Command::SubTrackCoarseTune(_)
+        | Command::SubTrackFineTune(_) => command.opcode(),
+        _ => None,
+    }
+}
+
+/// How many delay commands [CommandSeq::encode] splits a [Delay] of `delay` ticks into.
+fn delay_command_count(mut delay: usize) -> usize {
+    let mut count = 0;
+
+    while delay > 0 {
+        count += 1;
+
+        if delay < DELAY_MAX as usize {
+            delay = 0;
+        } else {
+            // A long delay has some of its length in its opcode and the rest in the byte after.
+            delay -= DELAY_MAX as usize;
+            delay -= (delay >> 8).min(7) << 8;
+            delay -= delay.min(DELAY_MAX as usize);
+        }
+    }
+
+    count
+}
+
 pub const DELAY_MAX: u8 = 0x78;
 
+/// How many ticks there are in a beat (a quarter note). [Delay]s and note lengths are in ticks.
+pub const TICKS_PER_BEAT: usize = 48;
+
+/// The lowest pitch a [Command::Note] can have. Lower values are interpreted as other commands.
+pub const PITCH_MIN: u8 = 0x80;
+
+/// The highest pitch a [Command::Note] can have. Higher values are interpreted as other commands.
+pub const PITCH_MAX: u8 = 0xD3;
+
+/// The highest velocity a [Command::Note] can have.
+pub const VELOCITY_MAX: u8 = 127;
+
+/// The most [commands](CommandSeq::command_count) Mamar lets one track have. The engine reads a track until its
+/// [Command::End] rather than counting, but a whole song must fit in `0x8A90` bytes, and a track this long leaves
+/// too little room for anything else. Usually it means notes were imported with more precision than the game needs.
+pub const TRACK_COMMANDS_MAX: usize = 4096;
+
+/// What to do with notes that would be moved outside of [PITCH_MIN]..=[PITCH_MAX], e.g. by [CommandSeq::transpose].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// Keep the note, but move it to the nearest valid pitch.
+    Clamp,
+
+    /// Remove the note from the sequence.
+    Drop,
+}
+
 impl Default for Command {
     /// Returns a no-op command. Cannot be encoded.
     fn default() -> Self {
@@ -780,9 +2228,44 @@ impl<'a> Iterator for TimeGroupIter<'a> {
     }
 }
 
+/// A copy of a [Command::Note] and the relative-time it starts at. See [CommandSeq::notes].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteRef {
+    /// Relative-time at which the note starts.
+    pub time: usize,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub length: u16,
+}
+
+impl NoteRef {
+    /// Relative-time at which the note stops sounding. This may be past the end of the sequence.
+    pub fn end_time(&self) -> usize {
+        self.time + self.length as usize
+    }
+}
+
+/// Mutable view of a [Command::Note] within a [CommandSeq]. See [CommandSeq::notes_mut].
+#[derive(Debug)]
+pub struct NoteMut<'a> {
+    /// Relative-time at which the note starts. Read-only; changing it would require moving [Delay]s around.
+    pub time: usize,
+    pub pitch: &'a mut u8,
+    pub velocity: &'a mut u8,
+    pub length: &'a mut u16,
+}
+
+impl NoteMut<'_> {
+    /// Relative-time at which the note stops sounding. This may be past the end of the sequence.
+    pub fn end_time(&self) -> usize {
+        self.time + *self.length as usize
+    }
+}
+
 pub type MarkerId = String;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CommandRange {
     pub name: String,
 
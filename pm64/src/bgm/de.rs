@@ -2,7 +2,7 @@ use std::collections::btree_map::{BTreeMap, Entry};
 use std::fmt;
 use std::io::prelude::*;
 use std::io::{self, SeekFrom};
-use std::mem::MaybeUninit;
+use std::ops::RangeInclusive;
 
 use log::{debug, warn};
 
@@ -14,6 +14,13 @@ pub enum Error {
     InvalidMagic,
     SizeMismatch { true_size: u32, internal_size: u32 },
     InvalidNumSegments(u8),
+
+    /// A subroutine command at `pos` points to `ptr`, which is outside its track.
+    InvalidSubroutine { pos: u64, ptr: u64 },
+
+    /// The file ends part-way through something, which starts at or before `offset`; it was probably cut short.
+    UnexpectedEof { offset: u64 },
+
     Io(io::Error),
 }
 
@@ -40,6 +47,16 @@ impl fmt::Display for Error {
                 "Exactly 4 segment slots are supported, but this file has {}",
                 num_segments
             ),
+            Error::InvalidSubroutine { pos, ptr } => write!(
+                f,
+                "Subroutine at {:#X} points to {:#X}, which is outside its track",
+                pos, ptr
+            ),
+            Error::UnexpectedEof { offset } => write!(
+                f,
+                "The file ends early: data at {:#X} is missing, so it may have been cut short",
+                offset
+            ),
             Error::Io(source) => {
                 if let io::ErrorKind::UnexpectedEof = source.kind() {
                     write!(f, "Unexpected end-of-file")
@@ -60,6 +77,43 @@ impl std::error::Error for Error {
     }
 }
 
+/// Something unexpected in the input that didn't stop it from being decoded. See [Bgm::decode_with_warnings].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The size in the header doesn't match the size of the input.
+    SizeMismatch { true_size: u32, internal_size: u32 },
+    /// Bytes which should be padding (zero) aren't.
+    NonZeroPadding { pos: u64 },
+    /// There is data after everything that was decoded.
+    UnusedData { pos: u64 },
+    /// A command byte we don't know how to decode. It was kept as a [Command::Unknown].
+    UnknownCommand { pos: u64, byte: u8 },
+    /// A tempo outside of [EXPECTED_TEMPO], which probably means the command was misread.
+    UnusualTempo { pos: u64, bpm: u16 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::SizeMismatch {
+                true_size,
+                internal_size,
+            } => write!(
+                f,
+                "The file says it is {}B, but it is actually {}B",
+                internal_size, true_size
+            ),
+            Warning::NonZeroPadding { pos } => write!(f, "Expected padding at {:#X}", pos),
+            Warning::UnusedData { pos } => write!(f, "Unused data at {:#X}", pos),
+            Warning::UnknownCommand { pos, byte } => write!(f, "Unknown command {:#04X} at {:#X}", byte, pos),
+            Warning::UnusualTempo { pos, bpm } => write!(f, "Unusual tempo of {} BPM at {:#X}", bpm, pos),
+        }
+    }
+}
+
+/// Range of tempos (in beats per minute) that songs are expected to use.
+pub const EXPECTED_TEMPO: RangeInclusive<u16> = 20..=400;
+
 trait CollectArray<T, E, U: Default + AsMut<[T]>>: Sized + Iterator<Item = Result<T, E>> {
     /// Doesn't panic if the iterator is too large or too small for the output array. If the iterator is too short,
     /// the remaining elements have their default value.
@@ -100,7 +154,33 @@ impl Bgm {
         Self::decode(&mut std::io::Cursor::new(f))
     }
 
+    pub fn from_bytes_with_warnings(f: &[u8]) -> Result<(Self, Vec<Warning>), Error> {
+        Self::decode_with_warnings(&mut std::io::Cursor::new(f))
+    }
+
     pub fn decode<R: Read + Seek>(f: &mut R) -> Result<Self, Error> {
+        Self::decode_with_warnings(f).map(|(bgm, _)| bgm)
+    }
+
+    /// Decodes a BGM, also returning anything unexpected that was found in it. Problems that stop the BGM from being
+    /// decoded at all are still returned as an [Error].
+    pub fn decode_with_warnings<R: Read + Seek>(f: &mut R) -> Result<(Self, Vec<Warning>), Error> {
+        let mut f = Reader {
+            inner: f,
+            read_start: 0,
+        };
+
+        match Self::decode_unchecked(&mut f) {
+            Err(Error::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof => Err(Error::UnexpectedEof {
+                offset: f.read_start,
+            }),
+            result => result,
+        }
+    }
+
+    fn decode_unchecked<R: Read + Seek>(f: &mut R) -> Result<(Self, Vec<Warning>), Error> {
+        let mut warnings = Vec::new();
+
         f.seek(SeekFrom::Start(0))?;
         let mut magic = [0; 4];
         f.read_exact(&mut magic)?;
@@ -113,24 +193,25 @@ impl Bgm {
         let true_size = f.seek(SeekFrom::End(0))? as u32;
         if internal_size == true_size {
             // Ok
-        } else if align(internal_size, 16) == true_size {
+        } else if internal_size < true_size && align(internal_size, 16) == true_size {
             // Make sure the trailing bytes are all zero
             f.seek(SeekFrom::Start(internal_size as u64))?;
-            f.read_padding(true_size - internal_size)?;
+            read_padding(f, true_size - internal_size, &mut warnings)?;
         } else {
-            warn!(
-                "size mismatch! BGM says it is {:#X} B but the input is {:#X} B",
-                internal_size, true_size
-            );
+            warnings.push(Warning::SizeMismatch {
+                true_size,
+                internal_size,
+            });
         }
 
         let mut bgm = Bgm::new();
 
         f.seek(SeekFrom::Start(0x08))?;
         bgm.name = f.read_cstring(4)?;
+        f.seek(SeekFrom::Start(0x0C))?; // read_cstring stops short at the end of the file
 
         debug_assert!(f.pos()? == 0x0C);
-        f.read_padding(4)?;
+        read_padding(f, 4, &mut warnings)?;
 
         debug_assert!(f.pos()? == 0x10);
         let num_segments = f.read_u8()?;
@@ -139,7 +220,7 @@ impl Bgm {
         }
 
         debug_assert!(f.pos()? == 0x11);
-        f.read_padding(3)?;
+        read_padding(f, 3, &mut warnings)?;
 
         debug_assert!(f.pos()? == 0x14);
         let segment_offsets: Vec<u16> = (0..4)
@@ -155,7 +236,7 @@ impl Bgm {
 
         debug_assert!(f.pos()? == 0x24); // End of struct
 
-        let mut furthest_read_pos = 0; // TODO: have `f` track this (i.e. a wrapper over f)
+        let mut furthest_read_pos = f.pos()?; // TODO: have `f` track this (i.e. a wrapper over f)
 
         // Special cases to get problematic BGMs to match
         bgm.unknowns = match bgm.name.as_str() {
@@ -190,17 +271,19 @@ impl Bgm {
                         f.seek(SeekFrom::Current(-4))?;
                         word != 0
                     } {
-                        subsegments.push(Subsegment::decode(f, &mut bgm, pos, &mut furthest_read_pos)?);
+                        subsegments.push(Subsegment::decode(f, &mut bgm, pos, &mut furthest_read_pos, &mut warnings)?);
 
                         i += 1;
                     }
 
+                    // Past the terminator
+                    furthest_read_pos = furthest_read_pos.max(pos + i * 4 + 4);
+
                     debug!("segment end {:#X}", f.pos()?);
 
-                    Ok(Some(Segment {
-                        name: format!("Variation {:#06X}", pos),
-                        subsegments,
-                    }))
+                    let mut segment = Segment::new(format!("Variation {:#06X}", pos));
+                    segment.subsegments = subsegments;
+                    Ok(Some(segment))
                 }
             })
             .collect_array_pedantic()?;
@@ -211,6 +294,7 @@ impl Bgm {
                 .into_iter()
                 .map(|_| Drum::decode(f))
                 .collect::<Result<_, _>>()?;
+            furthest_read_pos = furthest_read_pos.max(f.pos()?);
         }
 
         if voices_offset != 0 {
@@ -219,17 +303,21 @@ impl Bgm {
                 .into_iter()
                 .map(|_| Voice::decode(f))
                 .collect::<Result<_, _>>()?;
+            furthest_read_pos = furthest_read_pos.max(f.pos()?);
         };
 
         // TODO: check that all the data between furthest_read_pos and align(furthest_read_pos) is padding (zero)
 
         let eof_pos = f.seek(SeekFrom::End(0))?;
         if align(furthest_read_pos as u32, 16) < eof_pos as u32 {
-            // TODO: make this an error
-            warn!("unused data at {:#X}", furthest_read_pos);
+            warnings.push(Warning::UnusedData { pos: furthest_read_pos });
         }
 
-        Ok(bgm)
+        for warning in &warnings {
+            warn!("{}", warning);
+        }
+
+        Ok((bgm, warnings))
     }
 }
 
@@ -239,12 +327,13 @@ impl Subsegment {
         bgm: &mut Bgm,
         start: u64,
         furthest_read_pos: &mut u64,
+        warnings: &mut Vec<Warning>,
     ) -> Result<Self, Error> {
         debug!("subsegment {:#X}", f.pos()?);
         let flags = f.read_u8()?;
 
         if flags & 0x70 == 0x10 {
-            f.read_padding(1)?;
+            read_padding(f, 1, warnings)?;
 
             let offset = (f.read_u16_be()? as u64) << 2;
             let track_list_pos = start + offset;
@@ -255,33 +344,26 @@ impl Subsegment {
             let track_list = match bgm.find_track_list_with_pos(track_list_pos) {
                 Some(id) => id,
                 None => {
-                    let mut tracks: [MaybeUninit<Track>; 16] = unsafe {
-                        // SAFETY: this is an array of uninitialised elements, so the array overall does not require
-                        // initialisation.
-                        MaybeUninit::uninit().assume_init()
-                    };
-
-                    for (track_no, track) in tracks.iter_mut().enumerate() {
-                        let track_no = track_no as u64;
-
-                        f.seek(SeekFrom::Start(track_list_pos + track_no * 4))?;
-                        *track = MaybeUninit::new(Track::decode(f, track_list_pos)?);
-
-                        let pos = f.pos()?;
-                        if pos > *furthest_read_pos {
-                            *furthest_read_pos = pos;
-                        }
-                    }
+                    // Collected rather than written into place, so that tracks already read are dropped if a
+                    // later one can't be.
+                    let tracks = (0..16)
+                        .map(|track_no| -> Result<Track, Error> {
+                            f.seek(SeekFrom::Start(track_list_pos + track_no * 4))?;
+                            let track = Track::decode(f, track_list_pos, warnings)?;
+
+                            let pos = f.pos()?;
+                            if pos > *furthest_read_pos {
+                                *furthest_read_pos = pos;
+                            }
+
+                            Ok(track)
+                        })
+                        .collect_array_pedantic()?;
 
                     bgm.add_track_list(TrackList {
                         name: format!("Section {:#06X}", track_list_pos),
                         pos: Some(track_list_pos),
-                        tracks: unsafe {
-                            // SAFETY: the for loop above has initialised the array. This is also how the std
-                            // documentation suggests initialising an array element-by-element:
-                            // https://doc.rust-lang.org/std/mem/union.MaybeUninit.html#initializing-an-array-element-by-element
-                            std::mem::transmute(tracks)
-                        },
+                        tracks,
                     })
                 }
             };
@@ -297,7 +379,7 @@ impl Subsegment {
 }
 
 impl Track {
-    fn decode<R: Read + Seek>(f: &mut R, segment_start: u64) -> Result<Self, Error> {
+    fn decode<R: Read + Seek>(f: &mut R, segment_start: u64, warnings: &mut Vec<Warning>) -> Result<Self, Error> {
         let commands_offset = f.read_u16_be()?;
         let flags = f.read_u16_be()?;
 
@@ -312,7 +394,7 @@ impl Track {
                 CommandSeq::with_capacity(0)
             } else {
                 f.seek(SeekFrom::Start(segment_start + commands_offset as u64))?;
-                let seq = CommandSeq::decode(f)?;
+                let seq = CommandSeq::decode(f, warnings)?;
 
                 // Assumption; structure will need changing if false for matching.
                 // Maybe use command "groups" which can be represented in UI also
@@ -327,7 +409,7 @@ impl Track {
 }
 
 impl CommandSeq {
-    fn decode<R: Read + Seek>(f: &mut R) -> Result<Self, Error> {
+    fn decode<R: Read + Seek>(f: &mut R, warnings: &mut Vec<Warning>) -> Result<Self, Error> {
         let start = f.pos()? as usize;
 
         // A binary tree mapping input offset -> Command. This is then trivially converted to a
@@ -336,6 +418,9 @@ impl CommandSeq {
 
         let mut seen_terminator = false;
 
+        // The error to give for each subroutine if it turns out to end past the end of the sequence.
+        let mut subroutine_ends = Vec::new();
+
         loop {
             let cmd_offset = (f.pos()? as usize) - start;
 
@@ -356,7 +441,16 @@ impl CommandSeq {
                 }
             }
 
-            let cmd_byte = f.read_u8()?;
+            let cmd_byte = match f.read_u8() {
+                // Past the terminator, only what subroutines point to is read, so one of them points past the end.
+                Err(error) if seen_terminator && error.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(subroutine_ends
+                        .into_iter()
+                        .find(|(end_offset, _)| *end_offset > cmd_offset)
+                        .map_or(Error::Io(error), |(_, invalid)| invalid));
+                }
+                result => result?,
+            };
 
             let command = match cmd_byte {
                 // Sentinel (zero-terminator)
@@ -409,7 +503,16 @@ impl CommandSeq {
                     }
                 }
 
-                0xE0 => Command::MasterTempo(f.read_u16_be()?),
+                0xE0 => {
+                    let bpm = f.read_u16_be()?;
+                    if !EXPECTED_TEMPO.contains(&bpm) {
+                        warnings.push(Warning::UnusualTempo {
+                            pos: (start + cmd_offset) as u64,
+                            bpm,
+                        });
+                    }
+                    Command::MasterTempo(bpm)
+                }
                 0xE1 => Command::MasterVolume(f.read_u8()?),
                 0xE2 => Command::MasterTranspose(f.read_i8()?),
                 0xE3 => Command::Unknown(vec![0xE3, f.read_u8()?]),
@@ -467,20 +570,41 @@ impl CommandSeq {
                 */
                 0xFD => Command::Unknown(vec![0xFD, f.read_u8()?, f.read_u8()?, f.read_u8()?]),
                 0xFE => {
-                    let start_offset = f.read_u16_be()? as usize - start;
+                    let ptr = f.read_u16_be()? as usize;
+                    let invalid = Error::InvalidSubroutine {
+                        pos: (start + cmd_offset) as u64,
+                        ptr: ptr as u64,
+                    };
+                    let start_offset = match ptr.checked_sub(start) {
+                        Some(offset) => offset,
+                        None => return Err(invalid),
+                    };
                     let end_offset = start_offset + (f.read_u8()? as usize);
 
                     //debug!("subroutine @ {:#X} (start = {:#X}; end = {:#X})", cmd_offset, start_offset, end_offset);
 
+                    let markers = (commands.upsert_marker(start_offset), commands.upsert_marker(end_offset));
+                    let (start_marker, end_marker) = match markers {
+                        (Some(start_marker), Some(end_marker)) => (start_marker, end_marker),
+                        _ => return Err(invalid),
+                    };
+                    subroutine_ends.push((end_offset, invalid));
+
                     Command::Subroutine(CommandRange {
                         name: format!("Subroutine {:#X}", cmd_offset),
-                        start: commands.upsert_marker(start_offset),
-                        end: commands.upsert_marker(end_offset),
+                        start: start_marker,
+                        end: end_marker,
                     })
                 }
                 0xFF => Command::Unknown(vec![0xFF, f.read_u8()?, f.read_u8()?, f.read_u8()?]),
 
-                _ => Command::Unknown(vec![cmd_byte]),
+                _ => {
+                    warnings.push(Warning::UnknownCommand {
+                        pos: (start + cmd_offset) as u64,
+                        byte: cmd_byte,
+                    });
+                    Command::Unknown(vec![cmd_byte])
+                }
             };
 
             commands.insert(cmd_offset, command);
@@ -489,15 +613,39 @@ impl CommandSeq {
         let size = f.pos()? as usize - start;
         //debug!("end commandseq {:#X}", f.pos()?);
 
-        // Explode if there are no commands (must be markers) past the end of the file
-        for (offset, command) in commands.0.split_off(&OffsetCommandMap::atob(size)).into_iter() {
-            panic!("command after end of parsed sequence {:?} @ {:#X}", command, offset);
+        // Reading stops once every marker has been passed, but make sure no subroutine runs off the end regardless.
+        if let Some((_, invalid)) = subroutine_ends.into_iter().find(|(end_offset, _)| *end_offset > size) {
+            return Err(invalid);
         }
 
         Ok(commands.into())
     }
 }
 
+/// Remembers where the last read started, so that running out of data can say where it was expected.
+struct Reader<'a, R> {
+    inner: &'a mut R,
+    read_start: u64,
+}
+
+impl<R: Read + Seek> Read for Reader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    // The reads here and in crate::rw that can run out of data use this.
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.read_start = self.inner.pos()?;
+        self.inner.read_exact(buf)
+    }
+}
+
+impl<R: Seek> Seek for Reader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
 /// Temporary struct for [CommandSeq::decode].
 #[derive(Debug)]
 struct OffsetCommandMap(pub(self) BTreeMap<usize, Command>);
@@ -522,8 +670,9 @@ impl OffsetCommandMap {
         self.0.insert(Self::atob(offset), command);
     }
 
-    /// Finds a marker at `offset`, or inserts it if it cannot be found.
-    pub fn upsert_marker(&mut self, offset: usize) -> MarkerId {
+    /// Finds a marker at `offset`, or inserts it if it cannot be found. Returns `None` if something other than a
+    /// marker is there.
+    pub fn upsert_marker(&mut self, offset: usize) -> Option<MarkerId> {
         let shifted_offset = Self::atob(offset) - 1;
 
         match self.0.entry(shifted_offset) {
@@ -531,14 +680,11 @@ impl OffsetCommandMap {
                 // Insert the new marker here.
                 let id: MarkerId = format!("Offset {:#X}", offset);
                 entry.insert(Command::Marker(id.clone()));
-                id
+                Some(id)
             }
             Entry::Occupied(entry) => match entry.get() {
-                Command::Marker(id) => id.clone(),
-                other_command => panic!(
-                    "non-marker command {:?} found in label range (shifted_offset = {:#X})",
-                    other_command, shifted_offset,
-                ),
+                Command::Marker(id) => Some(id.clone()),
+                _ => None,
             },
         }
     }
@@ -621,6 +767,23 @@ impl Unknown {
     }
 }
 
+/// Like [ReadExt::read_padding], but non-zero padding is a [Warning] rather than an error.
+fn read_padding<R: Read + Seek>(f: &mut R, num_bytes: u32, warnings: &mut Vec<Warning>) -> io::Result<()> {
+    let pos = f.pos()?;
+    let mut is_zero = true;
+
+    // Read all of it even if some isn't zero, so that whatever follows is read from the right place
+    for _ in 0..num_bytes {
+        is_zero &= f.read_u8()? == 0;
+    }
+
+    if !is_zero {
+        warnings.push(Warning::NonZeroPadding { pos });
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::io::Cursor;
@@ -646,7 +809,7 @@ mod test {
             0x00, // End - at offset 15
         ];
 
-        let seq = CommandSeq::decode(&mut Cursor::new(bytecode)).unwrap();
+        let seq = CommandSeq::decode(&mut Cursor::new(bytecode), &mut Vec::new()).unwrap();
         dbg!(&seq);
 
         let start_labels: Vec<&MarkerId> = seq
@@ -691,4 +854,89 @@ mod test {
         assert_eq!(subroutine_labels[1].1, end_labels[0]);
         assert_eq!(subroutine_labels[2].1, end_labels[0]);
     }
+
+    #[test]
+    fn decode_warnings() {
+        let bytecode: Vec<u8> = vec![
+            0xE0, 0x00, 0x78, // MasterTempo(120)
+            0xE0, 0x27, 0x10, // MasterTempo(10000)
+            0xD4, // Not a command
+            0x00, // End
+        ];
+
+        let mut warnings = Vec::new();
+        CommandSeq::decode(&mut Cursor::new(bytecode), &mut warnings).unwrap();
+
+        assert_eq!(warnings, vec![
+            Warning::UnusualTempo { pos: 3, bpm: 10000 },
+            Warning::UnknownCommand { pos: 6, byte: 0xD4 },
+        ]);
+    }
+
+    /// Inputs that used to panic, found by fuzzing (see fuzz/readme.md).
+    #[test]
+    fn malformed() {
+        // Subroutine pointing before the start of its track
+        let mut f = Cursor::new(vec![0x00, 0x00, 0xFE, 0x00, 0x00, 0x04, 0x00]);
+        f.seek(SeekFrom::Start(2)).unwrap();
+        let result = CommandSeq::decode(&mut f, &mut Vec::new());
+        assert!(matches!(result, Err(Error::InvalidSubroutine { pos: 2, ptr: 0 })), "{:?}", result);
+
+        // Subroutine pointing past the end of its track
+        let mut f = Cursor::new(vec![0xFE, 0x00, 0x10, 0x05, 0x00]);
+        let result = CommandSeq::decode(&mut f, &mut Vec::new());
+        assert!(matches!(result, Err(Error::InvalidSubroutine { pos: 0, ptr: 0x10 })), "{:?}", result);
+
+        // Subroutine ending past the end of its track
+        let mut f = Cursor::new(vec![0xFE, 0x00, 0x04, 0x05, 0x00, 0x01]);
+        let result = CommandSeq::decode(&mut f, &mut Vec::new());
+        assert!(matches!(result, Err(Error::InvalidSubroutine { pos: 0, ptr: 0x04 })), "{:?}", result);
+
+        let bin = Bgm::new().as_bytes().unwrap();
+
+        // Cut short in the middle of the name
+        assert!(Bgm::from_bytes(&bin[..10]).is_err());
+
+        // Size so big it can't be aligned
+        let mut huge = bin.clone();
+        huge[0x04..0x08].copy_from_slice(&0xFFFF_FFF8u32.to_be_bytes());
+        assert!(Bgm::from_bytes(&huge).is_ok());
+
+        // Padding that isn't zero is still skipped over
+        let mut padding = bin;
+        padding[0x0C] = 0xFF;
+        let (_, warnings) = Bgm::from_bytes_with_warnings(&padding).unwrap();
+        assert!(warnings.contains(&Warning::NonZeroPadding { pos: 0x0C }));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+        bgm.voices.push(Voice::default());
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+        bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = CommandSeq::from(vec![
+            Command::MasterTempo(120),
+            Command::Note { pitch: 0x90, velocity: 100, length: 0x1000 },
+            Command::Delay(0x200),
+            Command::End,
+        ]);
+
+        let bin = bgm.as_bytes().unwrap();
+        let whole = Bgm::from_bytes(&bin).unwrap();
+
+        for len in 0..bin.len() {
+            match Bgm::from_bytes(&bin[..len]) {
+                // Nothing reads more than 4 bytes at a time, so the read that ran out can't start earlier than that
+                Err(Error::UnexpectedEof { offset }) => {
+                    assert!(offset + 4 > len as u64, "{:#X} cut at {:#X}", offset, len)
+                }
+                // Only padding was cut off
+                Ok(decoded) => assert_eq!(decoded, whole, "cut at {:#X}", len),
+                Err(error) => panic!("cut at {:#X}: {}", len, error),
+            }
+        }
+    }
 }
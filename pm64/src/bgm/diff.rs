@@ -0,0 +1,571 @@
+use std::fmt;
+use std::io::{self, Cursor, SeekFrom};
+use std::io::prelude::*;
+use std::mem::discriminant;
+
+use super::*;
+use crate::rw::*;
+
+/// How many bytes either side of a difference [ByteDiff] shows.
+const CONTEXT_BYTES: usize = 8;
+
+/// One step in turning one sequence of commands into another. Times are in ticks, as given by
+/// [CommandSeq::iter_time].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffOp {
+    /// The command is in both sequences, at the same time.
+    Same {
+        time: usize,
+        command: Command,
+    },
+
+    /// The command is only in the second sequence.
+    Insert {
+        time: usize,
+        command: Command,
+    },
+
+    /// The command is only in the first sequence.
+    Delete {
+        time: usize,
+        command: Command,
+    },
+
+    /// Both sequences have a command of this kind at this time, but with different arguments, e.g. a note with a
+    /// different velocity.
+    Change {
+        time: usize,
+        from: Command,
+        to: Command,
+    },
+}
+
+impl DiffOp {
+    pub fn time(&self) -> usize {
+        match self {
+            DiffOp::Same { time, .. }
+            | DiffOp::Insert { time, .. }
+            | DiffOp::Delete { time, .. }
+            | DiffOp::Change { time, .. } => *time,
+        }
+    }
+
+    pub fn is_same(&self) -> bool {
+        matches!(self, DiffOp::Same { .. })
+    }
+}
+
+/// Compares two sequences command by command, returning how to turn `a` into `b`. Commands are aligned by the tick
+/// they run at rather than their position in the sequence, so a delay split in two doesn't count as a difference
+/// (delays themselves are left out).
+///
+/// ```
+/// # use pm64::bgm::*;
+/// # use pm64::bgm::diff::*;
+/// let a = CommandSeq::from(vec![
+///     Command::MasterTempo(120),
+///     Command::Delay(48),
+///     Command::MasterVolume(100),
+/// ]);
+/// let b = CommandSeq::from(vec![
+///     Command::MasterTempo(120),
+///     Command::Delay(24),
+///     Command::Delay(24),
+///     Command::MasterVolume(80),
+///     Command::End,
+/// ]);
+///
+/// assert_eq!(diff_seq(&a, &b), vec![
+///     DiffOp::Same { time: 0, command: Command::MasterTempo(120) },
+///     DiffOp::Change { time: 48, from: Command::MasterVolume(100), to: Command::MasterVolume(80) },
+///     DiffOp::Insert { time: 48, command: Command::End },
+/// ]);
+/// ```
+pub fn diff_seq(a: &CommandSeq, b: &CommandSeq) -> Vec<DiffOp> {
+    diff_timed(&without_delays(a.iter_time()), &without_delays(b.iter_time()))
+}
+
+/// Compares each track of a segment in one song with the same track of a segment in another (or the same) song, with
+/// sections laid end to end, so times are in ticks from the start of the segment. Returns the operations for each of
+/// the 16 tracks. A missing segment counts as empty.
+pub fn diff_segments(a: &Bgm, a_segment: usize, b: &Bgm, b_segment: usize) -> Vec<Vec<DiffOp>> {
+    (0..16)
+        .map(|track_idx| diff_timed(&segment_track(a, a_segment, track_idx), &segment_track(b, b_segment, track_idx)))
+        .collect()
+}
+
+fn without_delays<'a, I: Iterator<Item = (usize, &'a Command)>>(commands: I) -> Vec<(usize, &'a Command)> {
+    commands.filter(|(_, command)| !matches!(command, Command::Delay(_))).collect()
+}
+
+/// The commands of one track across every section of a segment, in ticks from the start of the segment.
+fn segment_track(bgm: &Bgm, segment_idx: usize, track_idx: usize) -> Vec<(usize, &Command)> {
+    let segment = match bgm.segments.get(segment_idx) {
+        Some(Some(segment)) => segment,
+        _ => return Vec::new(),
+    };
+
+    let mut commands = Vec::new();
+    let mut offset = 0;
+
+    for subsegment in &segment.subsegments {
+        if let Subsegment::Tracks { track_list, .. } = subsegment {
+            if let Some(track_list) = bgm.track_lists.get(track_list) {
+                let track = &track_list.tracks[track_idx].commands;
+                commands.extend(without_delays(track.iter_time()).into_iter().map(|(time, c)| (offset + time, c)));
+                offset += track_list.len_time();
+            }
+        }
+    }
+
+    commands
+}
+
+fn diff_timed(a: &[(usize, &Command)], b: &[(usize, &Command)]) -> Vec<DiffOp> {
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    // Both are in time order, so they can be walked together a tick at a time. Only commands at the same tick need
+    // lining up, which keeps this fast for long tracks.
+    while i < a.len() || j < b.len() {
+        let time = match (a.get(i), b.get(j)) {
+            (Some((a_time, _)), Some((b_time, _))) => *a_time.min(b_time),
+            (Some((time, _)), None) | (None, Some((time, _))) => *time,
+            (None, None) => unreachable!(),
+        };
+
+        let a_len = a[i..].iter().take_while(|(t, _)| *t == time).count();
+        let b_len = b[j..].iter().take_while(|(t, _)| *t == time).count();
+
+        let a_group: Vec<&Command> = a[i..i + a_len].iter().map(|(_, command)| *command).collect();
+        let b_group: Vec<&Command> = b[j..j + b_len].iter().map(|(_, command)| *command).collect();
+        diff_group(time, &a_group, &b_group, &mut ops);
+
+        i += a_len;
+        j += b_len;
+    }
+
+    ops
+}
+
+/// Lines up the commands at one tick, keeping their order: the longest common subsequence is kept the same, and
+/// whatever is left between is paired up into changes where the commands are of the same kind.
+fn diff_group(time: usize, a: &[&Command], b: &[&Command], ops: &mut Vec<DiffOp>) {
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut deleted = Vec::new();
+    let mut inserted = Vec::new();
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            pair_changes(time, &mut deleted, &mut inserted, ops);
+            ops.push(DiffOp::Same { time, command: a[i].clone() });
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            inserted.push(b[j]);
+            j += 1;
+        } else {
+            deleted.push(a[i]);
+            i += 1;
+        }
+    }
+
+    pair_changes(time, &mut deleted, &mut inserted, ops);
+}
+
+fn pair_changes(time: usize, deleted: &mut Vec<&Command>, inserted: &mut Vec<&Command>, ops: &mut Vec<DiffOp>) {
+    for from in deleted.drain(..) {
+        match inserted.iter().position(|to| discriminant(*to) == discriminant(from)) {
+            Some(k) => ops.push(DiffOp::Change { time, from: from.clone(), to: inserted.remove(k).clone() }),
+            None => ops.push(DiffOp::Delete { time, command: from.clone() }),
+        }
+    }
+
+    for command in inserted.drain(..) {
+        ops.push(DiffOp::Insert { time, command: command.clone() });
+    }
+}
+
+/// Where two encoded songs first differ. Its [Display](fmt::Display) says where, what part of the song that is, and
+/// the bytes around it in each song.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteDiff {
+    pub offset: usize,
+
+    /// The part of `original` that `offset` falls in, and where that part starts. See [locate].
+    pub location: Option<(Location, FilePos)>,
+
+    /// The bytes around `offset` in each song, starting at `context_start`. Shorter near the end of a song.
+    pub original: Vec<u8>,
+    pub encoded: Vec<u8>,
+    pub context_start: usize,
+}
+
+/// A part of an encoded song, as found by [locate]. Indices count from zero, as in [Bgm::segments].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Header,
+
+    /// The list of subsegments of a segment.
+    Segment {
+        segment: usize,
+    },
+
+    /// The table of tracks of a subsegment. If several subsegments share it, this is the first.
+    TrackList {
+        segment: usize,
+        subsegment: usize,
+    },
+
+    /// The commands of a track.
+    Track {
+        segment: usize,
+        subsegment: usize,
+        track: usize,
+    },
+
+    Drums,
+    Voices,
+}
+
+/// Compares two encoded songs, e.g. a song from the game and what it encodes to after decoding it, returning where
+/// they first differ, or `None` if they are the same.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// # use pm64::bgm::diff::*;
+/// let mut bgm = Bgm::new();
+/// bgm.add_segment().unwrap();
+///
+/// let original = bgm.as_bytes().unwrap();
+/// let mut encoded = original.clone();
+/// encoded[0x09] = b'X';
+///
+/// let diff = diff_bytes(&original, &encoded).unwrap();
+/// assert_eq!(diff.offset, 0x09);
+/// assert_eq!(diff.location, Some((Location::Header, 0)));
+/// assert!(diff_bytes(&original, &original).is_none());
+/// ```
+pub fn diff_bytes(original: &[u8], encoded: &[u8]) -> Option<ByteDiff> {
+    let offset = match original.iter().zip(encoded).position(|(a, b)| a != b) {
+        Some(offset) => offset,
+        None if original.len() == encoded.len() => return None,
+        // One is the other with more on the end
+        None => original.len().min(encoded.len()),
+    };
+
+    let context_start = offset.saturating_sub(CONTEXT_BYTES);
+    let context = |bin: &[u8]| bin[context_start.min(bin.len())..(offset + CONTEXT_BYTES + 1).min(bin.len())].to_vec();
+
+    Some(ByteDiff {
+        offset,
+        location: locate(original, offset as FilePos),
+        original: context(original),
+        encoded: context(encoded),
+        context_start,
+    })
+}
+
+/// Finds which part of an encoded song `offset` falls in, returning it and where it starts. See [layout]. `None` if
+/// `offset` is before anything that could be found, e.g. because the header is cut short.
+pub fn locate(bin: &[u8], offset: FilePos) -> Option<(Location, FilePos)> {
+    layout(bin)
+        .into_iter()
+        .take_while(|(_, range)| range.start <= offset)
+        .last()
+        .map(|(location, range)| (location, range.start))
+}
+
+/// Every part of an encoded song, in the order they appear, with the bytes each takes up. Parts are found by following
+/// the offsets in the song from its header, in the same way [Bgm::decode] does, and each is taken to run until the
+/// next one starts.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// # use pm64::bgm::diff::*;
+/// let mut bgm = Bgm::new();
+/// bgm.add_segment().unwrap();
+/// let segment = bgm.segments[0].as_mut().unwrap();
+/// let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+/// let commands = CommandSeq::from(vec![Command::Delay(48), Command::End]);
+/// bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = commands;
+///
+/// let bin = bgm.as_bytes().unwrap();
+/// let layout = layout(&bin);
+/// assert_eq!(layout[0].0, Location::Header);
+/// assert!(layout.iter().any(|(location, _)| *location == Location::Track { segment: 0, subsegment: 0, track: 0 }));
+/// assert_eq!(layout.last().unwrap().1.end, bin.len() as FilePos);
+/// ```
+pub fn layout(bin: &[u8]) -> Vec<(Location, Range<FilePos>)> {
+    let mut parts = Vec::new();
+
+    // Whatever was found before a read fails is still worth knowing.
+    let _ = find_parts(&mut Cursor::new(bin), &mut parts);
+
+    // Where parts start at the same place, e.g. tracks sharing their commands, the first found is kept.
+    parts.sort_by_key(|(start, _)| *start);
+    parts.dedup_by_key(|(start, _)| *start);
+
+    let ends: Vec<FilePos> = parts
+        .iter()
+        .skip(1)
+        .map(|(start, _)| *start)
+        .chain(std::iter::once(bin.len() as FilePos))
+        .collect();
+
+    // A part can start past the end if the song is cut short.
+    parts
+        .into_iter()
+        .zip(ends)
+        .map(|((start, location), end)| (location, start..end.max(start)))
+        .collect()
+}
+
+fn find_parts(f: &mut Cursor<&[u8]>, parts: &mut Vec<(FilePos, Location)>) -> io::Result<()> {
+    let bin_len = f.get_ref().len() as FilePos;
+    parts.push((0, Location::Header));
+
+    f.seek(SeekFrom::Start(0x14))?;
+    let mut segment_offsets = [0; 4];
+    for offset in segment_offsets.iter_mut() {
+        *offset = (f.read_u16_be()? as FilePos) << 2;
+    }
+
+    let drums_offset = (f.read_u16_be()? as FilePos) << 2;
+    let _drums_count = f.read_u16_be()?;
+    let voices_offset = (f.read_u16_be()? as FilePos) << 2;
+
+    if drums_offset != 0 {
+        parts.push((drums_offset, Location::Drums));
+    }
+    if voices_offset != 0 {
+        parts.push((voices_offset, Location::Voices));
+    }
+
+    let mut track_lists = Vec::new();
+
+    for (segment, &start) in segment_offsets.iter().enumerate().filter(|(_, start)| **start != 0) {
+        parts.push((start, Location::Segment { segment }));
+
+        // Stop at the null terminator, or the end of the file if there isn't one
+        for subsegment in 0.. {
+            let pos = start + subsegment as FilePos * 4;
+            if pos >= bin_len {
+                break;
+            }
+
+            f.seek(SeekFrom::Start(pos))?;
+            let word = f.read_u32_be()?;
+            if word == 0 {
+                break;
+            }
+
+            let flags = (word >> 24) as u8;
+            let track_list_pos = start + (((word & 0xFFFF) as FilePos) << 2);
+            if flags & 0x70 != 0x10 || track_lists.contains(&track_list_pos) {
+                continue;
+            }
+
+            track_lists.push(track_list_pos);
+            parts.push((track_list_pos, Location::TrackList { segment, subsegment }));
+
+            for track in 0..16 {
+                f.seek(SeekFrom::Start(track_list_pos + track as FilePos * 4))?;
+                let commands_offset = f.read_u16_be()?;
+
+                if commands_offset != 0 {
+                    parts.push((track_list_pos + commands_offset as FilePos, Location::Track {
+                        segment,
+                        subsegment,
+                        track,
+                    }));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Header => write!(f, "the header"),
+            Location::Segment { segment } => write!(f, "the subsegments of segment {}", segment),
+            Location::TrackList { segment, subsegment } => {
+                write!(f, "the track list of segment {} subsegment {}", segment, subsegment)
+            }
+            Location::Track { segment, subsegment, track } => {
+                write!(f, "segment {} subsegment {} track {}", segment, subsegment, track)
+            }
+            Location::Drums => write!(f, "the drums"),
+            Location::Voices => write!(f, "the voices"),
+        }
+    }
+}
+
+impl fmt::Display for ByteDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "First difference at {:#X}", self.offset)?;
+        match self.location {
+            Some((location, start)) => writeln!(f, ", in {} (from {:#X})", location, start)?,
+            None => writeln!(f)?,
+        }
+
+        for (name, bytes) in [("original", &self.original), ("encoded", &self.encoded)] {
+            write!(f, "    {:<8} {:#06X}:", name, self.context_start)?;
+
+            for i in 0..=self.offset + CONTEXT_BYTES - self.context_start {
+                match bytes.get(i) {
+                    Some(byte) if self.context_start + i == self.offset => write!(f, " [{:02X}]", byte)?,
+                    Some(byte) => write!(f, " {:02X}", byte)?,
+                    None => {
+                        if self.context_start + i <= self.offset {
+                            write!(f, " [end]")?;
+                        }
+                        break;
+                    }
+                }
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn note(pitch: u8) -> Command {
+        Command::Note {
+            pitch,
+            velocity: 100,
+            length: 48,
+        }
+    }
+
+    #[test]
+    fn identical() {
+        let seq = CommandSeq::from(vec![note(60), Command::Delay(48), note(62), Command::End]);
+        assert!(diff_seq(&seq, &seq).iter().all(DiffOp::is_same));
+        assert!(diff_seq(&CommandSeq::new(), &CommandSeq::new()).is_empty());
+    }
+
+    #[test]
+    fn aligned_by_tick() {
+        let a = CommandSeq::from(vec![note(60), Command::Delay(48), note(62), Command::Delay(48), note(64)]);
+        let b = CommandSeq::from(vec![note(60), Command::Delay(96), note(64), Command::Delay(48), note(65)]);
+
+        assert_eq!(diff_seq(&a, &b), vec![
+            DiffOp::Same { time: 0, command: note(60) },
+            DiffOp::Delete { time: 48, command: note(62) },
+            DiffOp::Same { time: 96, command: note(64) },
+            DiffOp::Insert { time: 144, command: note(65) },
+        ]);
+    }
+
+    #[test]
+    fn changes_within_a_tick() {
+        let a = CommandSeq::from(vec![Command::MasterTempo(120), note(60), note(64), Command::MasterVolume(100)]);
+        let b = CommandSeq::from(vec![note(61), note(64), Command::MasterVolume(90)]);
+
+        assert_eq!(diff_seq(&a, &b), vec![
+            DiffOp::Delete { time: 0, command: Command::MasterTempo(120) },
+            DiffOp::Change { time: 0, from: note(60), to: note(61) },
+            DiffOp::Same { time: 0, command: note(64) },
+            DiffOp::Change { time: 0, from: Command::MasterVolume(100), to: Command::MasterVolume(90) },
+        ]);
+    }
+
+    #[test]
+    fn segments_across_sections() {
+        let mut a = Bgm::new();
+        a.add_segment().unwrap();
+
+        let segment = a.segments[0].as_mut().unwrap();
+        for pitch in [60, 62] {
+            let track_list = segment.add_tracks_subsegment(&mut a.track_lists, 0x10);
+            a.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+                note(pitch),
+                Command::Delay(96),
+            ]);
+        }
+
+        let mut b = a.clone();
+        b.segments[1] = b.segments[0].clone();
+
+        assert!(diff_segments(&a, 0, &b, 1).iter().flatten().all(DiffOp::is_same));
+
+        let diff = diff_segments(&a, 0, &b, 2);
+        assert_eq!(diff[1], vec![
+            DiffOp::Delete { time: 0, command: note(60) },
+            DiffOp::Delete { time: 96, command: note(62) },
+        ]);
+        assert!(diff[0].is_empty());
+    }
+
+    #[test]
+    fn locate_differences() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+        bgm.add_segment().unwrap();
+
+        for segment_idx in 0..2 {
+            let segment = bgm.segments[segment_idx].as_mut().unwrap();
+            let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+            bgm.track_lists.get_mut(&track_list).unwrap().tracks[3].commands = CommandSeq::from(vec![
+                note(60 + segment_idx as u8),
+                Command::Delay(48),
+                Command::End,
+            ]);
+        }
+
+        let original = bgm.as_bytes().unwrap();
+
+        // Find the second segment's track 3 the long way round
+        let track_list_pos = Bgm::from_bytes(&original)
+            .unwrap()
+            .track_lists
+            .values()
+            .filter_map(|track_list| track_list.pos)
+            .max()
+            .unwrap() as usize;
+        let commands_offset = u16::from_be_bytes([original[track_list_pos + 12], original[track_list_pos + 13]]);
+        let track_pos = track_list_pos + commands_offset as usize;
+
+        let mut encoded = original.clone();
+        encoded[track_pos + 1] ^= 0xFF;
+
+        let diff = diff_bytes(&original, &encoded).unwrap();
+        assert_eq!(diff.offset, track_pos + 1);
+        let track = Location::Track {
+            segment: 1,
+            subsegment: 0,
+            track: 3,
+        };
+        assert_eq!(diff.location, Some((track, track_pos as FilePos)));
+        assert_eq!(diff.original.len(), CONTEXT_BYTES * 2 + 1);
+        assert!(diff.to_string().contains("segment 1 subsegment 0 track 3"));
+
+        // Cut short
+        let diff = diff_bytes(&original, &original[..track_pos]).unwrap();
+        assert_eq!(diff.offset, track_pos);
+        assert!(diff.to_string().contains("[end]"));
+        assert_eq!(locate(&original[..4], 2), Some((Location::Header, 0)));
+    }
+}
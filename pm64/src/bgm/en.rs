@@ -60,6 +60,11 @@ impl Bgm {
     }
 
     pub fn encode<W: Write + Seek>(&self, f: &mut W) -> Result<(), Error> {
+        // Scaling isn't part of the format, so it is done to the commands instead.
+        if self.segments.iter().flatten().any(|segment| !segment.is_unscaled()) {
+            return self.with_scales_applied().encode(f);
+        }
+
         f.seek(SeekFrom::Start(0))?;
 
         f.write_all(MAGIC.as_bytes())?;
@@ -221,19 +226,10 @@ impl Bgm {
                     f.write_u16_be_at(pos, SeekFrom::Start(tracks_pos))?;
                     encoded_tracks.insert(track_list_id, track_data_start);
 
-                    let any_solo = track_list.tracks.iter().any(|t| t.solo);
-
                     // Write flags
                     let mut todo_commands = Vec::new();
-                    for Track { flags, commands, mute, solo, .. } in track_list.tracks.iter() {
-                        let is_silent;
-                        if *mute {
-                            is_silent = true;
-                        } else if any_solo {
-                            is_silent = !solo;
-                        } else {
-                            is_silent = false;
-                        }
+                    for (i, Track { flags, commands, .. }) in track_list.tracks.iter().enumerate() {
+                        let is_silent = track_list.is_track_silent(i);
 
                         if !commands.is_empty() {
                             // Need to write command data after the track
@@ -0,0 +1,270 @@
+use std::fmt;
+
+use super::*;
+
+/// [Subsegment::Unknown] flags marking where a segment starts looping.
+pub const LOOP_START_FLAGS: u8 = 0x30;
+
+/// [Subsegment::Unknown] flags marking where a segment jumps back to the loop start.
+pub const LOOP_END_FLAGS: u8 = 0x50;
+
+/// A reason [Bgm::set_loop] couldn't set a loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopError {
+    /// There is no segment at this index.
+    NoSegment(usize),
+
+    /// The loop would be empty, or end before it starts.
+    EndBeforeStart {
+        start: usize,
+        end: usize,
+    },
+
+    /// The loop would end after the segment does.
+    PastEnd {
+        end: usize,
+        length: usize,
+    },
+}
+
+impl fmt::Display for LoopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoopError::NoSegment(segment) => write!(f, "There is no variation {}", segment + 1),
+            LoopError::EndBeforeStart { start, end } => {
+                write!(f, "The loop must end after it starts (it starts at {} and ends at {})", start, end)
+            }
+            LoopError::PastEnd { end, length } => {
+                write!(f, "The loop ends at {}, but the variation is only {} ticks long", end, length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoopError {}
+
+impl Subsegment {
+    pub fn is_loop_start(&self) -> bool {
+        matches!(self, Subsegment::Unknown { flags: LOOP_START_FLAGS, .. })
+    }
+
+    pub fn is_loop_end(&self) -> bool {
+        matches!(self, Subsegment::Unknown { flags: LOOP_END_FLAGS, .. })
+    }
+}
+
+impl Segment {
+    /// Returns the length of this segment in ticks. Each [Subsegment::Tracks] plays until its longest track finishes.
+    pub fn len_time(&self, track_lists: &HashMap<TrackListId, TrackList>) -> usize {
+        self.subsegment_time(track_lists, self.subsegments.len())
+    }
+
+    /// Returns the time, in ticks from the start of the segment, at which the subsegment at `subsegment_idx` starts.
+    pub fn subsegment_time(&self, track_lists: &HashMap<TrackListId, TrackList>, subsegment_idx: usize) -> usize {
+        self.subsegments[..subsegment_idx]
+            .iter()
+            .map(|subsegment| match subsegment {
+                Subsegment::Tracks { track_list, .. } => track_lists.get(track_list).map_or(0, TrackList::len_time),
+                Subsegment::Unknown { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Returns the ticks between the loop start and loop end markers, if this segment has both.
+    pub fn loop_range(&self, track_lists: &HashMap<TrackListId, TrackList>) -> Option<Range<usize>> {
+        let start_idx = self.subsegments.iter().position(Subsegment::is_loop_start)?;
+        let end_idx = start_idx + self.subsegments[start_idx..].iter().position(Subsegment::is_loop_end)?;
+
+        Some(self.subsegment_time(track_lists, start_idx)..self.subsegment_time(track_lists, end_idx))
+    }
+}
+
+impl Bgm {
+    /// Makes segment `segment_idx` loop over the given range of ticks, replacing any loop it already had. Sections
+    /// are split where the loop starts and ends if need be, since the game can only loop between whole sections.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(192),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// assert_eq!(bgm.set_loop(0, 96..48), Err(LoopError::EndBeforeStart { start: 96, end: 48 }));
+    /// assert_eq!(bgm.set_loop(0, 0..200), Err(LoopError::PastEnd { end: 200, length: 192 }));
+    ///
+    /// bgm.set_loop(0, 48..192).unwrap();
+    /// let segment = bgm.segments[0].as_ref().unwrap();
+    /// assert_eq!(segment.loop_range(&bgm.track_lists), Some(48..192));
+    /// assert_eq!(segment.subsegments.len(), 4); // Intro, loop start, loop body, loop end
+    /// ```
+    pub fn set_loop(&mut self, segment_idx: usize, range: Range<usize>) -> Result<(), LoopError> {
+        let segment = self.segments
+            .get(segment_idx)
+            .and_then(Option::as_ref)
+            .ok_or(LoopError::NoSegment(segment_idx))?;
+
+        let length = segment.len_time(&self.track_lists);
+        if range.end <= range.start {
+            return Err(LoopError::EndBeforeStart {
+                start: range.start,
+                end: range.end,
+            });
+        }
+        if range.end > length {
+            return Err(LoopError::PastEnd {
+                end: range.end,
+                length,
+            });
+        }
+
+        self.split_subsegment_at(segment_idx, range.start);
+        self.split_subsegment_at(segment_idx, range.end);
+
+        let track_lists = &self.track_lists;
+        let segment = self.segments[segment_idx].as_mut().unwrap();
+        segment.subsegments.retain(|subsegment| !subsegment.is_loop_start() && !subsegment.is_loop_end());
+
+        // Markers go before the first section starting at or after their time.
+        let index_at = |time: usize| {
+            (0..segment.subsegments.len())
+                .find(|i| {
+                    matches!(segment.subsegments[*i], Subsegment::Tracks { .. })
+                        && segment.subsegment_time(track_lists, *i) >= time
+                })
+                .unwrap_or(segment.subsegments.len())
+        };
+        let (start_idx, end_idx) = (index_at(range.start), index_at(range.end));
+
+        segment.subsegments.insert(end_idx, Subsegment::Unknown {
+            flags: LOOP_END_FLAGS,
+            data: [0, 0, 0],
+        });
+        segment.subsegments.insert(start_idx, Subsegment::Unknown {
+            flags: LOOP_START_FLAGS,
+            data: [0, 0, 0],
+        });
+
+        Ok(())
+    }
+
+    /// Splits the [Subsegment::Tracks] playing at `time` (in ticks from the start of the segment) in two, so that a
+    /// subsegment starts exactly at `time`. Does nothing if one already does.
+    fn split_subsegment_at(&mut self, segment_idx: usize, time: usize) {
+        let segment = match &self.segments[segment_idx] {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        let mut start = 0;
+        let mut found = None;
+
+        for (i, subsegment) in segment.subsegments.iter().enumerate() {
+            if let Subsegment::Tracks { flags, track_list } = subsegment {
+                let length = self.track_lists.get(track_list).map_or(0, TrackList::len_time);
+
+                if start < time && time < start + length {
+                    found = Some((i, *flags, *track_list, time - start, length));
+                    break;
+                }
+
+                start += length;
+            }
+        }
+
+        let (i, flags, id, split_time, length) = match found {
+            Some(found) => found,
+            None => return,
+        };
+
+        let track_list = &self.track_lists[&id];
+        let before = track_list.slice(0..split_time, "part 1");
+        let after = track_list.slice(split_time..length, "part 2");
+        let before = self.add_track_list(before);
+        let after = self.add_track_list(after);
+
+        let subsegments = &mut self.segments[segment_idx].as_mut().unwrap().subsegments;
+        subsegments.splice(i..=i, vec![
+            Subsegment::Tracks { flags, track_list: before },
+            Subsegment::Tracks { flags, track_list: after },
+        ]);
+
+        // The original may still be used elsewhere, e.g. by another variation.
+        if !self.is_track_list_used(id) {
+            self.track_lists.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_loop_replaces_old_loop() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        for _ in 0..2 {
+            let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+            bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = CommandSeq::from(vec![
+                Command::Delay(96),
+                Command::End,
+            ]);
+        }
+
+        // Loop points on section boundaries don't split anything.
+        bgm.set_loop(0, 0..192).unwrap();
+        assert_eq!(bgm.track_lists.len(), 2);
+
+        bgm.set_loop(0, 96..144).unwrap();
+        let segment = bgm.segments[0].as_ref().unwrap();
+        assert_eq!(segment.loop_range(&bgm.track_lists), Some(96..144));
+        assert_eq!(segment.len_time(&bgm.track_lists), 192);
+        assert_eq!(segment.subsegments.iter().filter(|s| s.is_loop_start()).count(), 1);
+        assert_eq!(segment.subsegments.iter().filter(|s| s.is_loop_end()).count(), 1);
+        assert_eq!(bgm.track_lists.len(), 3); // The second section was split in two
+    }
+
+    #[test]
+    fn split_keeps_voice() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+        bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+            Command::TrackVoice(3), // In the intro
+            Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+            Command::Delay(96),
+            Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+            Command::Delay(96),
+            Command::End,
+        ]);
+
+        bgm.set_loop(0, 96..192).unwrap();
+
+        let segment = bgm.segments[0].as_ref().unwrap();
+        let track_list = segment
+            .subsegments
+            .iter()
+            .filter_map(|subsegment| match subsegment {
+                Subsegment::Tracks { track_list, .. } => Some(&bgm.track_lists[track_list]),
+                _ => None,
+            })
+            .last()
+            .unwrap();
+        assert_eq!(track_list.tracks[1].commands, CommandSeq::from(vec![
+            Command::TrackVoice(3),
+            Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+            Command::Delay(96),
+            Command::End,
+        ]));
+    }
+}
@@ -1,8 +1,10 @@
+use std::collections::HashSet;
 use std::error::Error;
+use std::fmt;
 use std::io::prelude::*;
 use std::io::SeekFrom;
 
-use midly::{MetaMessage, Smf};
+use midly::{MetaMessage, Smf, TrackEventKind};
 
 use crate::bgm::*;
 use crate::rw::*;
@@ -18,18 +20,362 @@ pub fn is_midi<R: Read + Seek>(file: &mut R) -> Result<bool, std::io::Error> {
     Ok(is_midi)
 }
 
+/// Options for [to_bgm_with_options].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOptions {
+    /// Limits how many volume and pan changes are imported per second of music, per track. Sweeps of these
+    /// controllers are usually sent as a continuous stream of events, which would otherwise bloat the track. `None`
+    /// imports every event.
+    pub max_controller_events_per_second: Option<f32>,
+
+    /// Which drum to use for each note of a percussion track.
+    pub drum_map: DrumMap,
+
+    /// What to do when a note starts while another of the same pitch is still playing on the same track.
+    pub overlapping_notes: OverlapPolicy,
+
+    /// Which track and voice to put each MIDI channel on, instead of the usual ones.
+    pub channel_map: ChannelMap,
+
+    /// How many ticks of the BGM a beat of the MIDI file becomes. Times are rounded to the nearest tick, so a lower
+    /// resolution than [TICKS_PER_BEAT] snaps notes to a coarser grid, and a higher one keeps them closer to where they
+    /// were. Tempos are scaled to match, so the song plays at the same speed either way.
+    pub ticks_per_beat: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            max_controller_events_per_second: Some(20.0),
+            drum_map: DrumMap::default(),
+            overlapping_notes: OverlapPolicy::Truncate,
+            channel_map: ChannelMap::default(),
+            ticks_per_beat: TICKS_PER_BEAT,
+        }
+    }
+}
+
+/// How to import two notes of the same pitch that overlap on one track. The game can only sound one of them at once,
+/// so leaving them be cuts one off unpredictably.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// End the earlier note when the later one starts. See [CommandSeq::truncate_overlaps].
+    Truncate,
+
+    /// Skip the later note. See [CommandSeq::remove_overlapping].
+    DropLater,
+
+    /// Put the later notes on an empty track of their own, which has the same voice and controllers. If there is no
+    /// empty track, the earlier notes are truncated instead.
+    MoveToTrack,
+}
+
+/// Maps the keys of percussion notes (MIDI channel 10, or tracks named like "Drums") to the [Drum] that plays them.
+/// The default map covers the General MIDI percussion keys, 35 (Acoustic Bass Drum) to 81 (Open Triangle).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrumMap {
+    pub drums: HashMap<u8, Drum>,
+}
+
+impl DrumMap {
+    /// A map with no drums in it, so every percussion note is reported as [ImportWarning::UnmappedDrum].
+    pub fn empty() -> Self {
+        Self {
+            drums: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: u8) -> Option<&Drum> {
+        self.drums.get(&key)
+    }
+
+    pub fn insert(&mut self, key: u8, drum: Drum) {
+        self.drums.insert(key, drum);
+    }
+}
+
+impl Default for DrumMap {
+    fn default() -> Self {
+        // (key, patch, coarse tune, pan)
+        // XXX: These patches are guesses at the closest-sounding instruments in bank 3. Only the woodblock is known.
+        const GENERAL_MIDI: [(u8, u8, u8, i8); 47] = [
+            (35, 0xE0, 0x2A, 64), // Acoustic Bass Drum
+            (36, 0xE0, 0x30, 64), // Bass Drum 1
+            (37, 0xE1, 0x30, 64), // Side Stick
+            (38, 0xE2, 0x30, 64), // Acoustic Snare
+            (39, 0xE3, 0x30, 56), // Hand Clap
+            (40, 0xE2, 0x34, 64), // Electric Snare
+            (41, 0xE5, 0x24, 44), // Low Floor Tom
+            (42, 0xE6, 0x30, 80), // Closed Hi-Hat
+            (43, 0xE5, 0x28, 48), // High Floor Tom
+            (44, 0xE6, 0x2C, 80), // Pedal Hi-Hat
+            (45, 0xE5, 0x2C, 56), // Low Tom
+            (46, 0xE7, 0x30, 80), // Open Hi-Hat
+            (47, 0xE5, 0x30, 64), // Low-Mid Tom
+            (48, 0xE5, 0x34, 72), // Hi-Mid Tom
+            (49, 0xE8, 0x30, 48), // Crash Cymbal 1
+            (50, 0xE5, 0x38, 80), // High Tom
+            (51, 0xE9, 0x30, 84), // Ride Cymbal 1
+            (52, 0xE8, 0x2C, 84), // Chinese Cymbal
+            (53, 0xE9, 0x34, 84), // Ride Bell
+            (54, 0xEA, 0x30, 72), // Tambourine
+            (55, 0xE8, 0x36, 48), // Splash Cymbal
+            (56, 0xEB, 0x30, 72), // Cowbell
+            (57, 0xE8, 0x32, 80), // Crash Cymbal 2
+            (58, 0xEC, 0x30, 64), // Vibraslap
+            (59, 0xE9, 0x2E, 84), // Ride Cymbal 2
+            (60, 0xED, 0x36, 72), // Hi Bongo
+            (61, 0xED, 0x30, 72), // Low Bongo
+            (62, 0xEE, 0x34, 56), // Mute Hi Conga
+            (63, 0xEE, 0x32, 56), // Open Hi Conga
+            (64, 0xEE, 0x2C, 56), // Low Conga
+            (65, 0xEF, 0x34, 64), // High Timbale
+            (66, 0xEF, 0x2E, 64), // Low Timbale
+            (67, 0xEB, 0x36, 72), // High Agogo
+            (68, 0xEB, 0x32, 72), // Low Agogo
+            (69, 0xF0, 0x30, 80), // Cabasa
+            (70, 0xF0, 0x34, 80), // Maracas
+            (71, 0xF1, 0x36, 48), // Short Whistle
+            (72, 0xF1, 0x30, 48), // Long Whistle
+            (73, 0xF2, 0x34, 72), // Short Guiro
+            (74, 0xF2, 0x30, 72), // Long Guiro
+            (75, 0xE4, 0x3C, 64), // Claves
+            (76, 0xE4, 0x36, 72), // Hi Wood Block
+            (77, 0xE4, 0x30, 72), // Low Wood Block
+            (78, 0xF3, 0x34, 56), // Mute Cuica
+            (79, 0xF3, 0x30, 56), // Open Cuica
+            (80, 0xF4, 0x30, 88), // Mute Triangle
+            (81, 0xF4, 0x30, 88), // Open Triangle
+        ];
+
+        let mut map = Self::empty();
+
+        for &(key, patch, coarse_tune, pan) in GENERAL_MIDI.iter() {
+            map.insert(key, Drum {
+                bank: 0x30,
+                patch,
+                coarse_tune,
+                volume: 100,
+                pan,
+                ..Default::default()
+            });
+        }
+
+        map
+    }
+}
+
+/// Chooses the track and voice for MIDI channels, overriding where [to_bgm_with_options] would put them. Channels are
+/// numbered from 0, as they are in MIDI messages, so MIDI channel 10 (percussion) is 9.
+///
+/// ```
+/// # use pm64::bgm::midi::*;
+/// let mut options = ImportOptions::default();
+/// options.channel_map.insert(2, 5, 12); // MIDI channel 3 plays on track 5, using voice 12
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelMap {
+    pub channels: HashMap<u8, ChannelTarget>,
+}
+
+impl ChannelMap {
+    pub fn get(&self, channel: u8) -> Option<&ChannelTarget> {
+        self.channels.get(&channel)
+    }
+
+    pub fn insert(&mut self, channel: u8, track: usize, voice: u8) {
+        self.channels.insert(channel, ChannelTarget { track, voice });
+    }
+}
+
+/// Where a [ChannelMap] puts a MIDI channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelTarget {
+    /// From 1 to 15, as track 0 is the master track. Channels mapped to other tracks are placed as if unmapped.
+    pub track: usize,
+
+    /// Index into [Bgm::voices]. If several channels are mapped to one track, the track uses the first one's voice.
+    pub voice: u8,
+}
+
+/// Something that could not be imported exactly, but which didn't stop the rest of the MIDI file from importing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportWarning {
+    /// A percussion note's key isn't in [ImportOptions::drum_map], so notes with that key were skipped.
+    UnmappedDrum { track: usize, key: u8 },
+
+    /// The song uses more distinct drums than a BGM can play, so notes with that key were skipped.
+    TooManyDrums { track: usize, key: u8 },
+
+    /// Notes overlapped others of the same pitch, and `resolved` says what was done about it.
+    OverlappingNotes { track: usize, count: usize, resolved: OverlapPolicy },
+
+    /// Every track was taken by the time this channel of this MIDI track was reached, so it was skipped. The channel
+    /// is numbered from 0.
+    NoTrackForChannel { midi_track: usize, channel: u8 },
+}
+
+impl fmt::Display for ImportWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportWarning::UnmappedDrum { track, key } => {
+                write!(f, "Track {}: no drum for percussion key {}; notes skipped", track, key)
+            }
+            ImportWarning::TooManyDrums { track, key } => {
+                write!(f, "Track {}: too many drums to add one for key {}; notes skipped", track, key)
+            }
+            ImportWarning::OverlappingNotes { track, count, resolved } => {
+                let resolution = match resolved {
+                    OverlapPolicy::Truncate => "earlier notes shortened",
+                    OverlapPolicy::DropLater => "later notes skipped",
+                    OverlapPolicy::MoveToTrack => "later notes moved to a track of their own",
+                };
+                write!(f, "Track {}: {} notes overlapped others of the same pitch; {}", track, count, resolution)
+            }
+            ImportWarning::NoTrackForChannel { midi_track, channel } => {
+                write!(f, "MIDI track {}: no track left for channel {}; skipped", midi_track, channel + 1)
+            }
+        }
+    }
+}
+
+/// What importing a MIDI file does to it, for checking before importing it. See [import_report].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The tracks of the song that play notes.
+    pub tracks: Vec<ImportedTrack>,
+
+    /// How many notes the MIDI file has.
+    pub midi_notes: usize,
+
+    /// How many notes the song has. This is fewer than [ImportReport::midi_notes] where notes were skipped or
+    /// dropped; [ImportReport::warnings] says why.
+    pub notes: usize,
+
+    /// How many volume, pan and pitch bend events the MIDI file has.
+    pub midi_controller_events: usize,
+
+    /// How many volume, pan and pitch bend events the song has, after
+    /// [ImportOptions::max_controller_events_per_second] thinned them out.
+    pub controller_events: usize,
+
+    /// How many times the tempo is set, including at the start.
+    pub tempo_changes: usize,
+
+    pub warnings: Vec<ImportWarning>,
+}
+
+/// A track of an imported song, as [ImportReport] describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedTrack {
+    pub track: usize,
+    pub name: String,
+    pub notes: usize,
+    pub is_drum: bool,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Tracks:")?;
+        for track in &self.tracks {
+            let kind = if track.is_drum { "drum notes" } else { "notes" };
+            writeln!(f, "  {}. {} ({} {})", track.track, track.name, track.notes, kind)?;
+        }
+
+        writeln!(f, "Notes: {} of {} imported", self.notes, self.midi_notes)?;
+        writeln!(
+            f,
+            "Volume, pan and pitch bend events: {} of {} kept",
+            self.controller_events,
+            self.midi_controller_events,
+        )?;
+        write!(f, "Tempo changes: {}", self.tempo_changes)?;
+
+        for warning in &self.warnings {
+            write!(f, "\n{}", warning)?;
+        }
+
+        Ok(())
+    }
+}
+
 pub fn to_bgm(raw: &[u8]) -> Result<Bgm, Box<dyn Error>> {
+    let (bgm, warnings) = to_bgm_with_options(raw, &ImportOptions::default())?;
+
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+
+    Ok(bgm)
+}
+
+/// Imports a MIDI file, also returning anything that could not be imported exactly.
+pub fn to_bgm_with_options(
+    raw: &[u8],
+    options: &ImportOptions,
+) -> Result<(Bgm, Vec<ImportWarning>), Box<dyn Error>> {
+    let (bgm, report) = to_bgm_with_report(raw, options)?;
+    Ok((bgm, report.warnings))
+}
+
+/// Works out what importing a MIDI file would do, without keeping the song, so the import can be checked first.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// let mut bgm = Bgm::new();
+/// bgm.add_segment().unwrap();
+/// let smf = bgm.to_midi(0).unwrap();
+///
+/// let report = midi::import_report(&smf, &midi::ImportOptions::default()).unwrap();
+/// assert_eq!(report.notes, 0);
+/// assert_eq!(report.tempo_changes, 1);
+/// ```
+pub fn import_report(raw: &[u8], options: &ImportOptions) -> Result<ImportReport, Box<dyn Error>> {
+    let (_, report) = to_bgm_with_report(raw, options)?;
+    Ok(report)
+}
+
+/// Imports a MIDI file, also returning a report of what was imported and what could not be imported exactly.
+///
+/// Each channel that a MIDI track plays on becomes a track of the BGM:
+/// - Track 0 is the master track. It has the tempo, and the markers and names from MIDI track 0 and from MIDI tracks
+///   that play on no channel.
+/// - Channels in [ImportOptions::channel_map] go where it says.
+/// - Every other channel, in order of MIDI track and then channel, goes on the first free track numbered at least its
+///   MIDI track (or, failing that, the first free track at all). MIDI track 3 playing on one channel is therefore
+///   track 3, as long as the tracks before it play on one channel each; a MIDI track playing on two channels takes
+///   two tracks, and a format 0 file, which has only MIDI track 0, has its channels on tracks 1, 2, 3 and so on.
+///   Once all 15 tracks are taken, further channels are skipped with [ImportWarning::NoTrackForChannel].
+/// - The markers and names of a MIDI track go with its lowest channel, except those after a MIDI Channel Prefix, which
+///   go with that channel.
+/// - Track N uses voice N, unless its channel is mapped to another voice.
+pub fn to_bgm_with_report(raw: &[u8], options: &ImportOptions) -> Result<(Bgm, ImportReport), Box<dyn Error>> {
     let smf = Smf::parse(raw)?;
     let mut bgm = Bgm::new();
 
-    // Timing information (ticks per beat, aka "division"). MIDI files can use what they want, but the game always(?)
-    // uses 48 ticks per beat - so we have to convert the MIDI timescale to the BGM timescale.
-    let ticks_per_beat = match smf.header.timing {
-        midly::Timing::Metrical(tpb) => tpb.as_int() as f32,
-        midly::Timing::Timecode(fps, subframe) => 1.0 / fps.as_f32() / subframe as f32, // Uncommon, untested
+    match smf.header.format {
+        // Split up by channel like any other track; see below.
+        midly::Format::SingleTrack => log::debug!("format 0: every channel is in one track"),
+        midly::Format::Parallel => log::debug!("format 1"),
+        midly::Format::Sequential => log::warn!("format 2 MIDI files hold separate songs; importing them as one"),
+    }
+
+    // Timing information (ticks per beat, aka "division"). MIDI files can use what they want, but the game plays
+    // TICKS_PER_BEAT ticks per beat - so we have to convert the MIDI timescale to the BGM timescale.
+    let bgm_ticks_per_beat = options.ticks_per_beat.max(1) as u64;
+    let time_scale = match smf.header.timing {
+        midly::Timing::Metrical(tpb) => TimeScale {
+            from: tpb.as_int().max(1) as u64,
+            to: bgm_ticks_per_beat,
+        },
+        // Ticks are a fraction of a second rather than of a beat, so pretend the song is at the default 120 BPM, i.e.
+        // two beats per second. Uncommon, untested
+        midly::Timing::Timecode(fps, subframe) => TimeScale {
+            from: (fps.as_f32() * subframe as f32).round().max(1.0) as u64,
+            to: bgm_ticks_per_beat * 2,
+        },
     };
-    log::debug!("original ticks/beat: {}", ticks_per_beat);
-    let time_divisor = ticks_per_beat / 48.0; // Divide all MIDI times by this value to convert to BGM timescale!
+    log::debug!("time scale: {:?}", time_scale);
 
     bgm.name = "New Song".to_string();
 
@@ -37,11 +383,7 @@ pub fn to_bgm(raw: &[u8]) -> Result<Bgm, Box<dyn Error>> {
         let mut max = 0;
 
         for track in &smf.tracks {
-            let mut length = 0;
-
-            for event in track {
-                length += convert_time(event.delta.as_int() as usize, time_divisor);
-            }
+            let length = convert_time(track.iter().map(|event| event.delta.as_int() as usize).sum(), time_scale);
 
             if length > max {
                 max = length;
@@ -51,66 +393,468 @@ pub fn to_bgm(raw: &[u8]) -> Result<Bgm, Box<dyn Error>> {
         max
     };
 
-    log::debug!("song length: {} ticks (48 ticks/beat)", total_song_length);
+    log::debug!("song length: {} ticks ({} ticks/beat)", total_song_length, bgm_ticks_per_beat);
+
+    let tempo_map = tempo_map(&smf, time_scale, bgm_ticks_per_beat as usize);
+
+    // Minimum number of ticks between controller events, based on the initial tempo.
+    let controller_interval = options.max_controller_events_per_second.map_or(0, |max_per_second| {
+        let beats_per_minute = tempo_map[0].1 as f32;
+        let ticks_per_second = TICKS_PER_BEAT as f32 * beats_per_minute / 60.0;
+
+        (ticks_per_second / max_per_second).ceil() as usize
+    });
+
+    // In format 1 files, the name of the first track is the name of the whole sequence.
+    let sequence_name = smf.tracks
+        .first()
+        .and_then(|events| {
+            events.iter().find_map(|event| match event.kind {
+                midly::TrackEventKind::Meta(MetaMessage::TrackName(s)) => Some(meta_text(s)),
+                _ => None,
+            })
+        })
+        .filter(|name| !name.is_empty());
+
+    if let Some(name) = &sequence_name {
+        bgm.name = to_bgm_name(name);
+    }
+
+    let mut context = Context {
+        options,
+        total_song_length,
+        time_scale,
+        controller_interval,
+        tempo_map,
+        voices: Vec::new(),
+        drums: Vec::new(),
+        warnings: Vec::new(),
+        overlaps_to_move: Vec::new(),
+        patched_voices: HashSet::new(),
+        controller_events: (0, 0),
+    };
+
+    // The events of every MIDI track, at their time since the start of the song.
+    let midi_tracks: Vec<Vec<(usize, TrackEventKind)>> = smf.tracks
+        .iter()
+        .map(|events| {
+            let mut time = 0;
+            events.iter().map(|event| {
+                time += event.delta.as_int() as usize;
+                (time, event.kind)
+            }).collect()
+        })
+        .collect();
+
+    // Every channel of every MIDI track, in order, and where each goes.
+    let mut parts = Vec::new();
+    for (midi_track, events) in midi_tracks.iter().enumerate() {
+        let mut channels: Vec<u8> = events.iter().filter_map(|(_, kind)| event_channel(kind)).collect();
+        channels.sort_unstable();
+        channels.dedup();
+        parts.extend(channels.into_iter().map(|channel| (midi_track, channel)));
+    }
+    let targets = assign_tracks(&parts, &options.channel_map);
+
+    let mut track_events: [Option<Vec<(usize, TrackEventKind)>>; 16] = Default::default();
+    let mut track_voices = [0; 16];
+
+    for (midi_track, events) in midi_tracks.iter().enumerate() {
+        let first_part = parts.iter().position(|(t, _)| *t == midi_track);
+        let channels: Vec<Option<u8>> = event_channels(events)
+            .into_iter()
+            .map(|channel| channel.filter(|channel| parts.contains(&(midi_track, *channel))))
+            .collect();
+
+        if midi_track == 0 || first_part.is_none() {
+            // Meta events of tracks playing nothing are kept for their markers, which might say where to loop.
+            let master = track_events[0].get_or_insert_with(Vec::new);
+            master.extend(events.iter().zip(&channels).filter_map(|(event, channel)| match event.1 {
+                _ if channel.is_some() => None,
+                TrackEventKind::Meta(MetaMessage::CuePoint(_)) | TrackEventKind::Meta(MetaMessage::Marker(_)) => {
+                    Some(event)
+                }
+                TrackEventKind::Meta(_) if midi_track == 0 => Some(event),
+                _ => None,
+            }));
+        }
+
+        for (i, &(_, channel)) in parts.iter().enumerate().filter(|(_, (t, _))| *t == midi_track) {
+            let target = match targets[i] {
+                Some(target) => target,
+                None => {
+                    context.warn(ImportWarning::NoTrackForChannel { midi_track, channel });
+                    continue;
+                }
+            };
+
+            if track_events[target.track].is_none() {
+                track_voices[target.track] = target.voice;
+            }
+
+            let has_meta = midi_track != 0 && first_part == Some(i);
+            let events = events.iter().zip(&channels).filter_map(|(event, c)| match c {
+                Some(c) if *c == channel => Some(event),
+                None if has_meta => Some(event),
+                _ => None,
+            });
+            track_events[target.track].get_or_insert_with(Vec::new).extend(events);
+        }
+    }
+
+    for events in track_events.iter_mut().flatten() {
+        // Stable, so that events at the same time stay in order.
+        events.sort_by_key(|(time, _)| *time);
+    }
 
-    let track_list = TrackList {
-        name: "Imported from MIDI".to_owned(),
+    let mut track = |i: usize| midi_track_to_bgm_track(track_events[i].as_deref(), i, track_voices[i], &mut context);
+
+    let mut track_list = TrackList {
+        name: sequence_name.clone().unwrap_or_else(|| "Imported from MIDI".to_owned()),
         pos: None,
         tracks: [
-            midi_track_to_bgm_track(smf.tracks.get(0), total_song_length, 0, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(1), total_song_length, 1, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(2), total_song_length, 2, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(3), total_song_length, 3, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(4), total_song_length, 4, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(5), total_song_length, 5, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(6), total_song_length, 6, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(7), total_song_length, 7, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(8), total_song_length, 8, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(9), total_song_length, 9, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(10), total_song_length, 10, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(11), total_song_length, 11, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(12), total_song_length, 12, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(13), total_song_length, 13, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(14), total_song_length, 14, time_divisor, &mut bgm.voices),
-            midi_track_to_bgm_track(smf.tracks.get(15), total_song_length, 15, time_divisor, &mut bgm.voices),
+            track(0),
+            track(1),
+            track(2),
+            track(3),
+            track(4),
+            track(5),
+            track(6),
+            track(7),
+            track(8),
+            track(9),
+            track(10),
+            track(11),
+            track(12),
+            track(13),
+            track(14),
+            track(15),
         ],
     };
-    let track_list_id = bgm.add_track_list(track_list);
 
-    let (_, segment) = bgm.add_segment().unwrap();
-    segment.subsegments = vec![
-        Subsegment::Unknown { // loop start
-            flags: 0x30,
-            data: [0, 0, 0],
-        },
-        Subsegment::Tracks {
+    for (track_number, notes) in std::mem::take(&mut context.overlaps_to_move) {
+        let count = notes.len();
+        let empty_track = (1..track_list.tracks.len()).find(|i| track_list.tracks[*i].commands.is_empty());
+
+        let resolved = match empty_track {
+            Some(empty_track) => {
+                track_list.tracks[empty_track] = track_for_overlaps(&track_list.tracks[track_number], notes);
+                OverlapPolicy::MoveToTrack
+            }
+            None => {
+                let commands = &mut track_list.tracks[track_number].commands;
+                for note in notes {
+                    commands.insert(note.time, note_command(&note));
+                }
+                commands.truncate_overlaps();
+                OverlapPolicy::Truncate
+            }
+        };
+
+        context.warn(ImportWarning::OverlappingNotes { track: track_number, count, resolved });
+    }
+
+    let midi_notes = midi_tracks
+        .iter()
+        .flatten()
+        .filter(|(_, kind)| matches!(
+            kind,
+            TrackEventKind::Midi { message: midly::MidiMessage::NoteOn { vel, .. }, .. } if vel.as_int() > 0
+        ))
+        .count();
+    let tracks: Vec<ImportedTrack> = track_list.tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| ImportedTrack {
+            track: i,
+            name: track.name.clone(),
+            notes: track.commands.notes().count(),
+            is_drum: track.get_flag(track_flags::DRUM_TRACK),
+        })
+        .filter(|track| track.notes > 0)
+        .collect();
+    let report = ImportReport {
+        midi_notes,
+        notes: tracks.iter().map(|track| track.notes).sum(),
+        tracks,
+        midi_controller_events: context.controller_events.0,
+        controller_events: context.controller_events.1,
+        tempo_changes: context.tempo_map.len(),
+        warnings: context.warnings,
+    };
+
+    bgm.voices = context.voices;
+    bgm.drums = context.drums;
+
+    let loop_range = find_loop(&track_list, total_song_length);
+
+    let mut subsegments = Vec::new();
+
+    if loop_range.start > 0 {
+        let intro = track_list.slice(0..loop_range.start, "intro");
+        subsegments.push(Subsegment::Tracks {
             flags: 0x10,
-            track_list: track_list_id,
-        },
-        Subsegment::Unknown { // loop end
-            flags: 0x50,
-            data: [0, 0, 0],
-        },
-    ];
+            track_list: bgm.add_track_list(intro),
+        });
+    }
 
-    Ok(bgm)
+    let body = if loop_range == (0..total_song_length) {
+        track_list.clone()
+    } else {
+        track_list.slice(loop_range.clone(), "loop")
+    };
+    subsegments.push(Subsegment::Unknown {
+        flags: LOOP_START_FLAGS,
+        data: [0, 0, 0],
+    });
+    subsegments.push(Subsegment::Tracks {
+        flags: 0x10,
+        track_list: bgm.add_track_list(body),
+    });
+    subsegments.push(Subsegment::Unknown {
+        flags: LOOP_END_FLAGS,
+        data: [0, 0, 0],
+    });
+
+    if loop_range.end < total_song_length {
+        let outro = track_list.slice(loop_range.end..total_song_length, "outro");
+        subsegments.push(Subsegment::Tracks {
+            flags: 0x10,
+            track_list: bgm.add_track_list(outro),
+        });
+    }
+
+    let (_, segment) = bgm.add_segment().unwrap();
+    if let Some(name) = sequence_name {
+        segment.name = name;
+    }
+    segment.subsegments = subsegments;
+
+    Ok((bgm, report))
 }
 
-fn midi_track_to_bgm_track(
-    events: Option<&Vec<midly::TrackEvent>>,
+/// Finds the range of time to loop, as given by markers named "loop" / "loopend" (or similar). If there are no such
+/// markers, the whole song loops.
+fn find_loop(track_list: &TrackList, total_song_length: usize) -> Range<usize> {
+    let mut starts = Vec::new();
+    let mut ends = Vec::new();
+
+    for track in track_list.tracks.iter() {
+        for (time, command) in track.commands.iter_time() {
+            if let Command::Marker(name) = command {
+                let name: String = name
+                    .to_lowercase()
+                    .chars()
+                    .filter(|ch| !matches!(*ch, ' ' | '_' | '-'))
+                    .collect();
+
+                match name.as_str() {
+                    "loop" | "loopstart" | "[" => starts.push(time),
+                    "loopend" | "]" => ends.push(time),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let start = starts.iter().copied().min().unwrap_or(0);
+    let end = ends
+        .iter()
+        .copied()
+        .filter(|end| *end > start)
+        .min()
+        .unwrap_or(total_song_length)
+        .min(total_song_length);
+
+    if starts.len() > 1 || ends.len() > 1 {
+        log::warn!("multiple loop markers; looping {}..{}", start, end);
+    }
+
+    start..end
+}
+
+/// Decodes the text of a meta event. MIDI doesn't specify a text encoding, so anything that isn't valid UTF-8 is
+/// replaced.
+fn meta_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim().to_owned()
+}
+
+/// Makes a name fit the 4-byte name field of the BGM header.
+fn to_bgm_name(name: &str) -> String {
+    name.chars()
+        .filter(|ch| ch.is_ascii() && !ch.is_ascii_control())
+        .take(4)
+        .collect()
+}
+
+/// State shared by every track of the MIDI file being imported.
+struct Context<'a> {
+    options: &'a ImportOptions,
     total_song_length: usize,
+    time_scale: TimeScale,
+    controller_interval: usize,
+    tempo_map: Vec<(usize, u16)>,
+    voices: Vec<Voice>,
+    drums: Vec<Drum>,
+    warnings: Vec<ImportWarning>,
+    /// Notes taken off each track by [OverlapPolicy::MoveToTrack], to be put on an empty track once every track is
+    /// imported.
+    overlaps_to_move: Vec<(usize, Vec<NoteRef>)>,
+    /// Voices whose patch has been set by a program change.
+    patched_voices: HashSet<usize>,
+    /// Volume, pan and pitch bend events read, and how many of them were kept.
+    controller_events: (usize, usize),
+}
+
+impl Context<'_> {
+    /// Returns the pitch that plays the drum mapped to `key`, adding the drum to the BGM if needed. Drum tracks select
+    /// the drum to play by note pitch, starting from [PITCH_MIN].
+    fn drum_pitch(&mut self, track_number: usize, key: u8) -> Option<u8> {
+        let options = self.options;
+        let drum = match options.drum_map.get(key) {
+            Some(drum) => drum,
+            None => {
+                self.warn(ImportWarning::UnmappedDrum { track: track_number, key });
+                return None;
+            }
+        };
+
+        let index = match self.drums.iter().position(|d| d == drum) {
+            Some(index) => index,
+            None => {
+                if self.drums.len() > (PITCH_MAX - PITCH_MIN) as usize {
+                    self.warn(ImportWarning::TooManyDrums { track: track_number, key });
+                    return None;
+                }
+
+                self.drums.push(drum.clone());
+                self.drums.len() - 1
+            }
+        };
+
+        Some(PITCH_MIN + index as u8)
+    }
+
+    /// Records a warning, unless the same one has already been recorded.
+    fn warn(&mut self, warning: ImportWarning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+}
+
+/// Whether a track is percussion: either it plays notes on MIDI channel 10 (as General MIDI specifies), or its name
+/// contains 'drums' or 'percussion' (excluding 'steel drums').
+fn is_percussion(events: &[(usize, TrackEventKind)]) -> bool {
+    use midly::MidiMessage;
+
+    events.iter().any(|(_, kind)| match *kind {
+        TrackEventKind::Midi { channel, message: MidiMessage::NoteOn { .. } } => channel.as_int() == 9,
+        TrackEventKind::Meta(MetaMessage::TrackName(s)) | TrackEventKind::Meta(MetaMessage::InstrumentName(s)) => {
+            let name = meta_text(s).to_lowercase();
+            (name.contains("drum") && !name.contains("steel")) || name.contains("percussion")
+        }
+        _ => false,
+    })
+}
+
+/// The channel of a MIDI message, or `None` for other events.
+fn event_channel(kind: &TrackEventKind) -> Option<u8> {
+    match kind {
+        TrackEventKind::Midi { channel, .. } => Some(channel.as_int()),
+        _ => None,
+    }
+}
+
+/// The channel each event is for: that of MIDI messages, and for meta events, the one chosen by the last MIDI Channel
+/// Prefix if no MIDI message has come since. This is how format 0 files, where every channel shares one MIDI track, say
+/// which channel an instrument name is for.
+fn event_channels(events: &[(usize, TrackEventKind)]) -> Vec<Option<u8>> {
+    let mut prefix = None;
+
+    events
+        .iter()
+        .map(|(_, kind)| match kind {
+            TrackEventKind::Midi { channel, .. } => {
+                prefix = None;
+                Some(channel.as_int())
+            }
+            TrackEventKind::Meta(MetaMessage::MidiChannel(channel)) => {
+                prefix = Some(channel.as_int());
+                None
+            }
+            TrackEventKind::Meta(_) => prefix,
+            _ => None,
+        })
+        .collect()
+}
+
+/// Chooses the track and voice of every `(MIDI track, channel)` in `parts`, as [to_bgm_with_options] describes.
+/// `None` means every track was taken.
+fn assign_tracks(parts: &[(usize, u8)], channel_map: &ChannelMap) -> Vec<Option<ChannelTarget>> {
+    let mapped = |channel| channel_map.get(channel).filter(|target| (1..16).contains(&target.track)).copied();
+
+    let mut taken = [false; 16];
+    taken[0] = true;
+    for &(_, channel) in parts {
+        if let Some(target) = mapped(channel) {
+            taken[target.track] = true;
+        }
+    }
+
+    parts
+        .iter()
+        .map(|&(midi_track, channel)| {
+            if let Some(target) = mapped(channel) {
+                return Some(target);
+            }
+
+            let track = (midi_track.max(1)..16).chain(1..16).find(|track| !taken[*track])?;
+            taken[track] = true;
+            Some(ChannelTarget { track, voice: track as u8 })
+        })
+        .collect()
+}
+
+fn midi_track_to_bgm_track(
+    events: Option<&[(usize, TrackEventKind)]>,
     track_number: usize,
-    time_divisor: f32,
-    voices: &mut Vec<Voice>,
+    voice_idx: u8,
+    context: &mut Context,
 ) -> Track {
-    use midly::{MidiMessage, TrackEventKind};
+    use midly::MidiMessage;
 
     /// NoteOn data
     struct Note {
         time: usize,
         vel: u8,
+        pitch: u8,
+    }
+
+    /// Adds a note that started at `start` and ended at `end`, in MIDI ticks. The end is converted rather than the
+    /// length, so that a note ending where another starts still does after rounding.
+    fn insert_note(commands: &mut CommandSeq, start: Note, end: usize, time_scale: TimeScale) {
+        let time = convert_time(start.time, time_scale);
+        let length = convert_time(end, time_scale) - time;
+        commands.insert(time, Command::Note {
+            pitch: start.pitch,
+            velocity: start.vel,
+            length: length as u16,
+        });
+    }
+
+    /// Ends the earliest unfinished note of `key`.
+    fn end_note(started_notes: &mut HashMap<u8, Vec<Note>>, key: u8) -> Option<Note> {
+        let notes = started_notes.get_mut(&key)?;
+        if notes.is_empty() {
+            None
+        } else {
+            Some(notes.remove(0))
+        }
     }
 
+    let time_scale = context.time_scale;
+
     match events {
         None => Track {
             name: String::from("Empty track"),
@@ -131,55 +875,50 @@ fn midi_track_to_bgm_track(
                 solo: false,
             };
 
-            let voice_idx = voices.len();
-            voices.push(Voice {
-                bank: 0x30,
-                pan: 64,
-                patch: 1,
-                volume: 100,
-                ..Default::default()
-            });
-            let mut set_bank_patch = false;
+            let is_percussion = track_number != 0 && is_percussion(events);
+            if is_percussion {
+                track.flags = track_flags::DRUM_TRACK;
+            }
 
-            let mut time = 0;
-            let mut started_notes: HashMap<u8, Note> = HashMap::new(); // Maps key to notes that have not finished yet
+            // The master track plays no notes, so it needs no voice.
+            let voice_idx = voice_idx as usize;
+            if track_number != 0 {
+                while context.voices.len() <= voice_idx {
+                    context.voices.push(Voice {
+                        bank: 0x30,
+                        pan: 64,
+                        patch: 1,
+                        volume: 100,
+                        ..Default::default()
+                    });
+                }
+            }
+
+            // Maps key to notes that have not finished yet, earliest first. A key can be pressed again before it is
+            // released, and each release ends the earliest.
+            let mut started_notes: HashMap<u8, Vec<Note>> = HashMap::new();
 
             let mut instrument_name = None;
             let mut track_name = None;
 
-            let mut is_pitch_bent = false;
+            // Controller sweeps are thinned out after reading the whole track.
+            let mut volume_events = Vec::new();
+            let mut pan_events = Vec::new();
+            let mut bend_events = Vec::new();
 
-            for event in events {
-                time += event.delta.as_int() as usize;
+            // Registered parameter number last selected by CC 101 and 100, and the pitch bend range it may set.
+            let mut rpn = (None, None);
+            let mut bend_range = DEFAULT_BEND_RANGE;
 
-                match event.kind {
+            for &(time, kind) in events {
+                match kind {
                     TrackEventKind::Midi { channel: _, message } => {
                         match message {
                             MidiMessage::NoteOff { key, vel: _ } => {
                                 let key = key.as_int();
 
-                                if let Some(start) = started_notes.remove(&key) {
-                                    let length = time - start.time;
-
-                                    track.commands.insert(
-                                        convert_time(start.time, time_divisor),
-                                        Command::Note {
-                                            pitch: key + 104,
-                                            velocity: start.vel,
-                                            length: convert_time(length as usize, time_divisor) as u16,
-                                        },
-                                    );
-
-                                    if is_pitch_bent {
-                                        is_pitch_bent = false;
-                                        track.commands.insert(
-                                            convert_time(time, time_divisor),
-                                            Command::SegTrackTune {
-                                                coarse: 0,
-                                                fine: 0,
-                                            }
-                                        );
-                                    }
+                                if let Some(start) = end_note(&mut started_notes, key) {
+                                    insert_note(&mut track.commands, start, time, time_scale);
                                 } else {
                                     log::warn!("found NoteOff {} but saw no NoteOn", key);
                                 }
@@ -189,52 +928,40 @@ fn midi_track_to_bgm_track(
                                 let vel = vel.as_int();
 
                                 if vel == 0 {
-                                    if let Some(start) = started_notes.remove(&key) {
-                                        let length = time - start.time;
-
-                                        track.commands.insert(
-                                            convert_time(start.time, time_divisor),
-                                            Command::Note {
-                                                pitch: key + 104,
-                                                velocity: start.vel,
-                                                length: length as u16,
-                                            },
-                                        );
+                                    if let Some(start) = end_note(&mut started_notes, key) {
+                                        insert_note(&mut track.commands, start, time, time_scale);
                                     } else {
                                         log::warn!("found NoteOn(vel=0) {} but saw no NoteOn(vel>0)", key);
                                     }
                                 } else {
-                                    started_notes.insert(key, Note { time, vel });
+                                    let pitch = if is_percussion {
+                                        context.drum_pitch(track_number, key)
+                                    } else {
+                                        Some(key + 104)
+                                    };
+
+                                    if let Some(pitch) = pitch {
+                                        started_notes.entry(key).or_default().push(Note { time, vel, pitch });
+                                    }
                                 }
                             }
                             MidiMessage::PitchBend { bend } => {
-                                // FIXME commands should use signed types?
-                                let bend: u16 = unsafe { std::mem::transmute(bend.as_int()) }; // bleh
-                                let bend_lower = (bend & 0xFF) as u8;
-                                let bend_upper = (bend >> 8) as u8;
-
-                                track.commands.insert(
-                                    convert_time(time, time_divisor),
-                                    Command::SegTrackTune {
-                                        coarse: bend_upper,
-                                        fine: bend_lower,
-                                    }
-                                );
-                                is_pitch_bent = true;
+                                let cents = bend.as_f32() * bend_range as f32;
+                                bend_events.push((convert_time(time, time_scale), cents.round() as i16));
                             }
                             MidiMessage::Aftertouch { key: _, vel } | MidiMessage::ChannelAftertouch { vel } => {
                                 track.commands.insert(
-                                    convert_time(time, time_divisor),
+                                    convert_time(time, time_scale),
                                     Command::SubTrackVolume(vel.as_int()),
                                 );
                             }
                             MidiMessage::ProgramChange { program } => {
-                                if !set_bank_patch {
-                                    voices[voice_idx].patch = program.as_int();
-                                    set_bank_patch = true;
+                                // The first program change of a voice sets its patch, and later ones override it.
+                                if context.patched_voices.insert(voice_idx) {
+                                    context.voices[voice_idx].patch = program.as_int();
                                 } else {
                                     track.commands.insert(
-                                        convert_time(time, time_divisor),
+                                        convert_time(time, time_scale),
                                         Command::TrackOverridePatch { bank: 48, patch: program.as_int() },
                                     );
                                 }
@@ -256,7 +983,7 @@ fn midi_track_to_bgm_track(
                                         //
                                         // I declare it...tremolo!
                                         track.commands.insert(
-                                            convert_time(time, time_divisor),
+                                            convert_time(time, time_scale),
                                             Command::TrackTremolo {
                                                 // XXX: These values are total guesses. Investigate!
                                                 amount: 8,
@@ -266,18 +993,22 @@ fn midi_track_to_bgm_track(
                                         );
                                     }
                                     // Channel Volume
-                                    7 | 39 => track.commands.insert(
-                                        convert_time(time, time_divisor),
-                                        Command::SubTrackVolume(value),
-                                    ),
+                                    7 | 39 => volume_events.push((convert_time(time, time_scale), value)),
                                     // Pan
-                                    10 | 42 | 8 | 40 => track.commands.insert(
-                                        convert_time(time, time_divisor),
-                                        Command::SubTrackPan(value as i8),
-                                    ),
+                                    10 | 42 | 8 | 40 => pan_events.push((convert_time(time, time_scale), value)),
+                                    // RPN LSB / MSB
+                                    100 => rpn.0 = Some(value),
+                                    101 => rpn.1 = Some(value),
+                                    // Data entry MSB / LSB. RPN 0 is the pitch bend range, in semitones and cents.
+                                    6 if rpn == (Some(0), Some(0)) => {
+                                        bend_range = value as u16 * 100 + bend_range % 100;
+                                    }
+                                    38 if rpn == (Some(0), Some(0)) => {
+                                        bend_range = bend_range / 100 * 100 + value as u16;
+                                    }
                                     // Effect control 1
                                     12 | 44 => track.commands.insert(
-                                        convert_time(time, time_divisor),
+                                        convert_time(time, time_scale),
                                         Command::SubTrackReverb(value),
                                     ),
                                     // Damper pedal on/off (sustain)
@@ -291,10 +1022,10 @@ fn midi_track_to_bgm_track(
                                         };
 
                                         track.commands.insert(
-                                            convert_time(time, time_divisor),
+                                            convert_time(time, time_scale),
                                             Command::TrackOverridePatch {
-                                                bank: voices[voice_idx].bank & 0xF0 | sustain,
-                                                patch: voices[voice_idx].bank,
+                                                bank: context.voices[voice_idx].bank & 0xF0 | sustain,
+                                                patch: context.voices[voice_idx].bank,
                                             },
                                         );
                                     }
@@ -314,24 +1045,16 @@ fn midi_track_to_bgm_track(
                                         };
 
                                         track.commands.insert(
-                                            convert_time(time, time_divisor),
+                                            convert_time(time, time_scale),
                                             Command::TrackOverridePatch {
-                                                bank: voices[voice_idx].bank & 0xF0 | sustain,
-                                                patch: voices[voice_idx].bank,
+                                                bank: context.voices[voice_idx].bank & 0xF0 | sustain,
+                                                patch: context.voices[voice_idx].bank,
                                             },
                                         );
                                     }
                                     // All notes off / All sound off
-                                    123 | 120 => for (key, start) in started_notes.drain() {
-                                        let length = time - start.time;
-                                        track.commands.insert(
-                                            convert_time(start.time, time_divisor),
-                                            Command::Note {
-                                                pitch: key + 104,
-                                                velocity: start.vel,
-                                                length: convert_time(length as usize, time_divisor) as u16,
-                                            },
-                                        );
+                                    123 | 120 => for start in started_notes.drain().flat_map(|(_, notes)| notes) {
+                                        insert_note(&mut track.commands, start, time, time_scale);
                                     }
                                     // Poly[phonic] mode on/off
                                     126 => {
@@ -361,43 +1084,84 @@ fn midi_track_to_bgm_track(
                             }
                         }
                     }
-                    TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => if track_number == 0 {
-                        let microseconds_per_beat = tempo.as_int() as f32;
-                        let beats_per_minute = (60_000_000.0 / microseconds_per_beat).round() as u16;
-                        track.commands.insert(
-                            convert_time(time, time_divisor),
-                            Command::MasterTempo(beats_per_minute),
-                        );
-                        log::debug!("bpm: {}", beats_per_minute);
-                    } else {
-                        log::warn!("ignoring non-master tempo change");
-                    }
+                    // Tempo changes are read separately; see tempo_map().
                     TrackEventKind::Meta(MetaMessage::InstrumentName(s)) => {
-                        instrument_name = String::from_utf8(s.to_owned()).ok();
+                        instrument_name = Some(meta_text(s));
                     }
                     TrackEventKind::Meta(MetaMessage::TrackName(s)) => {
-                        track_name = String::from_utf8(s.to_owned()).ok();
+                        track_name = Some(meta_text(s));
                     }
                     TrackEventKind::Meta(MetaMessage::CuePoint(s))
                     | TrackEventKind::Meta(MetaMessage::Marker(s)) => {
-                        if let Ok(s) = String::from_utf8(s.to_owned()) {
-                            track.commands.insert(
-                                convert_time(time, time_divisor),
-                                Command::Marker(s),
-                            );
-                        }
+                        track.commands.insert(
+                            convert_time(time, time_scale),
+                            Command::Marker(meta_text(s)),
+                        );
                     }
                     _ => {}
                 }
             }
 
-            if !started_notes.is_empty() {
-                log::warn!("{} unended notes", started_notes.len());
+            let unended: usize = started_notes.values().map(Vec::len).sum();
+            if unended > 0 {
+                log::warn!("{} unended notes", unended);
+            }
+
+            match context.options.overlapping_notes {
+                OverlapPolicy::Truncate => {
+                    let count = track.commands.truncate_overlaps();
+                    if count > 0 {
+                        context.warn(ImportWarning::OverlappingNotes {
+                            track: track_number,
+                            count,
+                            resolved: OverlapPolicy::Truncate,
+                        });
+                    }
+                }
+                OverlapPolicy::DropLater => {
+                    let count = track.commands.remove_overlapping().len();
+                    if count > 0 {
+                        context.warn(ImportWarning::OverlappingNotes {
+                            track: track_number,
+                            count,
+                            resolved: OverlapPolicy::DropLater,
+                        });
+                    }
+                }
+                OverlapPolicy::MoveToTrack => {
+                    let notes = track.commands.remove_overlapping();
+                    if !notes.is_empty() {
+                        context.overlaps_to_move.push((track_number, notes));
+                    }
+                }
+            }
+
+            let volume_events_kept = thin_controller_events(&volume_events, context.controller_interval);
+            let pan_events_kept = thin_controller_events(&pan_events, context.controller_interval);
+            let bend_events_kept = thin_controller_events(&bend_events, context.controller_interval);
+
+            context.controller_events.0 += volume_events.len() + pan_events.len() + bend_events.len();
+            context.controller_events.1 += volume_events_kept.len() + pan_events_kept.len() + bend_events_kept.len();
+
+            for (time, value) in volume_events_kept {
+                track.commands.insert(time, Command::SubTrackVolume(value));
+            }
+            for (time, value) in pan_events_kept {
+                track.commands.insert(time, Command::SubTrackPan(value as i8));
+            }
+            for (time, cents) in bend_events_kept {
+                track.commands.insert(time, tune_command(cents));
             }
 
             if track_number == 0 {
+                for &(time, beats_per_minute) in &context.tempo_map {
+                    if time > 0 {
+                        track.commands.insert(time, Command::MasterTempo(beats_per_minute));
+                    }
+                }
+
                 track.commands.insert_many(0, vec![
-                    Command::MasterTempo(120),
+                    Command::MasterTempo(context.tempo_map[0].1),
                     Command::MasterVolume(100),
                     Command::MasterEffect(0, 1),
                 ]);
@@ -407,7 +1171,7 @@ fn midi_track_to_bgm_track(
                 return Track::default();
             }
 
-            track.commands.insert(total_song_length, Command::End);
+            track.commands.insert(context.total_song_length, Command::End);
 
             if track_number != 0 {
                 // Required else the game crashes D:
@@ -429,19 +1193,818 @@ fn midi_track_to_bgm_track(
                 }
             }
 
-            // There's not a very good way to detect MIDI drum tracks, so we'll just make a best guess by seeing if the
-            // designated track title contains 'drums' or 'percussion' (excluding 'steel drums').
-            let name_lower = track.name.to_lowercase();
-            if (name_lower.contains("drum") && !name_lower.contains("steel")) | name_lower.contains("percussion") {
-                // TODO: insert voice instead of drum
-                track.flags = track_flags::DRUM_TRACK;
+            track
+        }
+    }
+}
+
+/// A track that plays `notes` with the same voice and controllers as `source`, for [OverlapPolicy::MoveToTrack].
+fn track_for_overlaps(source: &Track, notes: Vec<NoteRef>) -> Track {
+    let mut commands = CommandSeq::new();
+
+    for note in &notes {
+        commands.insert(note.time, note_command(note));
+    }
+
+    // Inserted after the notes so that, like on the source track, they come before any note at the same time.
+    for (time, group) in source.commands.iter_time_groups() {
+        let others: Vec<Command> = group
+            .into_iter()
+            .filter(|command| !matches!(command, Command::Note { .. } | Command::Delay(_)))
+            .cloned()
+            .collect();
+
+        if !others.is_empty() {
+            commands.insert_many(time, others);
+        }
+    }
+
+    Track {
+        name: format!("{} (overlaps)", source.name),
+        flags: source.flags,
+        commands,
+        ..Default::default()
+    }
+}
+
+fn note_command(note: &NoteRef) -> Command {
+    Command::Note {
+        pitch: note.pitch,
+        velocity: note.velocity,
+        length: note.length,
+    }
+}
+
+/// Collects the Set Tempo events of every track into a list of `(time, beats per minute)` changes, ordered by time and
+/// starting at time 0. Tempo events are meant to be in the first track, but some files put them elsewhere. Tempos are
+/// scaled by `ticks_per_beat` / [TICKS_PER_BEAT], since the game plays [TICKS_PER_BEAT] ticks per beat whatever the
+/// song was imported at, so that the song plays at the same speed.
+fn tempo_map(smf: &Smf, time_scale: TimeScale, ticks_per_beat: usize) -> Vec<(usize, u16)> {
+    // MIDI files with no tempo events play at 120 BPM.
+    let mut tempo_map = vec![(0, 120.0)];
+
+    for events in &smf.tracks {
+        let mut time = 0;
+
+        for event in events {
+            time += event.delta.as_int() as usize;
+
+            if let midly::TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                // MIDI tempo is in microseconds per beat, but the game uses beats per minute.
+                let microseconds_per_beat = tempo.as_int() as f32;
+                tempo_map.push((convert_time(time, time_scale), 60_000_000.0 / microseconds_per_beat));
             }
+        }
+    }
 
-            track
+    // Stable, so when there are multiple changes at the same time, the last one read wins.
+    tempo_map.sort_by_key(|(time, _)| *time);
+    tempo_map.reverse();
+    tempo_map.dedup_by_key(|(time, _)| *time);
+    tempo_map.reverse();
+
+    let scale = ticks_per_beat as f32 / TICKS_PER_BEAT as f32;
+    let tempo_map: Vec<(usize, u16)> = tempo_map
+        .into_iter()
+        .map(|(time, beats_per_minute)| (time, (beats_per_minute * scale).round().min(u16::MAX as f32) as u16))
+        .collect();
+
+    log::debug!("tempo map: {:?}", tempo_map);
+    tempo_map
+}
+
+/// Pitch bend range, in cents, until a file sets it with RPN 0. This is the General MIDI default of ±2 semitones.
+const DEFAULT_BEND_RANGE: u16 = 200;
+
+/// Converts a pitch bend into a [Command::SegTrackTune], which the game applies to every note the track plays until it
+/// is changed again. The game tunes in whole cents, which is coarser than the 14-bit precision of MIDI pitch bends
+/// when the bend range is over ±0.82 semitones. There is no command that glides between pitches, so a smooth bend is
+/// approximated by a series of steps, which are at most as frequent as
+/// [ImportOptions::max_controller_events_per_second] allows.
+fn tune_command(cents: i16) -> Command {
+    let cents = cents.clamp(i8::MIN as i16 * 100, i8::MAX as i16 * 100);
+
+    Command::SegTrackTune {
+        coarse: (cents / 100) as i8 as u8,
+        fine: (cents % 100) as i8 as u8,
+    }
+}
+
+/// Reduces a stream of `(time, value)` controller events so there are at least `min_interval` ticks between them.
+/// Events that don't change the value are dropped, and the last value of a sweep is always kept.
+fn thin_controller_events<T: Copy + PartialEq>(events: &[(usize, T)], min_interval: usize) -> Vec<(usize, T)> {
+    let mut thinned: Vec<(usize, T)> = Vec::with_capacity(events.len());
+    let mut pending = None;
+
+    for &(time, value) in events {
+        if let Some(&(last_time, last_value)) = thinned.last() {
+            if time < last_time + min_interval {
+                pending = Some(value);
+                continue;
+            }
+
+            // The sweep paused, so make sure it ends at the right value.
+            if let Some(pending_value) = pending.take() {
+                let flush_time = last_time + min_interval;
+                if flush_time < time && pending_value != last_value {
+                    thinned.push((flush_time, pending_value));
+                }
+            }
+
+            if thinned.last().map(|&(_, v)| v) == Some(value) {
+                continue;
+            }
+        }
+
+        thinned.push((time, value));
+    }
+
+    if let (Some(pending_value), Some(&(_, last_value))) = (pending, thinned.last()) {
+        if pending_value != last_value {
+            let last_time = events.last().map_or(0, |&(time, _)| time);
+            thinned.push((last_time, pending_value));
+        }
+    }
+
+    thinned
+}
+
+/// An event in a track being exported, at an absolute time in ticks.
+type ExportEvent = (usize, Vec<u8>);
+
+impl Bgm {
+    /// Exports a segment ("variation") as a standard MIDI file, with one MIDI track per track of the BGM. Each section
+    /// is played once, in order, and markers named `loop` and `loopend` are placed where the game would loop, so the
+    /// file can be imported again with [to_bgm]. Returns `None` if there is no segment at `segment_idx`.
+    pub fn to_midi(&self, segment_idx: usize) -> Option<Vec<u8>> {
+        self.to_midi_with_ticks_per_beat(segment_idx, TICKS_PER_BEAT as u16)
+    }
+
+    /// Like [Bgm::to_midi], but with `ticks_per_beat` (at most 0x7FFF) ticks per beat rather than the game's
+    /// [TICKS_PER_BEAT], for programs that expect a particular resolution. Times are rounded to the nearest tick.
+    pub fn to_midi_with_ticks_per_beat(&self, segment_idx: usize, ticks_per_beat: u16) -> Option<Vec<u8>> {
+        let segment = self.segments.get(segment_idx)?.as_ref()?;
+        let drum_map = DrumMap::default();
+
+        let mut tracks: Vec<Vec<ExportEvent>> = vec![Vec::new(); 16];
+        let mut names: [Option<&str>; 16] = [None; 16];
+        let mut time = 0;
+
+        // In format 1 files, the name of the first track is the name of the whole sequence.
+        names[0] = Some(&segment.name);
+
+        for subsegment in &segment.subsegments {
+            let track_list = match subsegment {
+                Subsegment::Tracks { track_list, .. } => match self.track_lists.get(track_list) {
+                    Some(track_list) => track_list,
+                    None => continue,
+                },
+                Subsegment::Unknown { flags: LOOP_START_FLAGS, .. } => {
+                    tracks[0].push((time, meta_event(0x06, b"loop")));
+                    continue;
+                }
+                Subsegment::Unknown { flags: LOOP_END_FLAGS, .. } => {
+                    tracks[0].push((time, meta_event(0x06, b"loopend")));
+                    continue;
+                }
+                Subsegment::Unknown { .. } => continue,
+            };
+
+            // The next section starts once every track of this one has finished.
+            let mut length = 0;
+
+            for (i, track) in track_list.tracks.iter().enumerate() {
+                if track.commands.is_empty() {
+                    continue;
+                }
+
+                names[i].get_or_insert(&track.name);
+                length = length.max(track.commands.len_time());
+                self.export_track(track, i, time, &drum_map, &mut tracks[i]);
+            }
+
+            time += length;
+        }
+
+        // The top bit would make it a timecode instead.
+        let ticks_per_beat = ticks_per_beat.clamp(1, 0x7FFF);
+        let time_scale = TimeScale {
+            from: TICKS_PER_BEAT as u64,
+            to: ticks_per_beat as u64,
+        };
+
+        let [tpb_high, tpb_low] = ticks_per_beat.to_be_bytes();
+        let mut midi = chunk(b"MThd", &[0, 1, 0, tracks.len() as u8, tpb_high, tpb_low]); // Format 1
+
+        for (mut events, name) in tracks.into_iter().zip(names.iter()) {
+            if let Some(name) = name {
+                events.insert(0, (0, meta_event(0x03, name.as_bytes())));
+            }
+
+            // Stable, so events at the same time stay in order, except that notes end before new ones start.
+            events.sort_by_key(|(time, event)| (*time, event[0] & 0xF0 != 0x80));
+
+            let end_time = events.last().map_or(0, |(time, _)| *time);
+            events.push((end_time, meta_event(0x2F, &[])));
+
+            let mut data = Vec::new();
+            let mut previous_time = 0;
+
+            for (time, event) in events {
+                // Converted from the start of the song, so rounding doesn't add up.
+                let time = convert_time(time, time_scale);
+                write_variable_length(&mut data, (time - previous_time) as u32);
+                data.extend_from_slice(&event);
+                previous_time = time;
+            }
+
+            midi.extend(chunk(b"MTrk", &data));
         }
+
+        Some(midi)
     }
+
+    fn export_track(
+        &self,
+        track: &Track,
+        track_number: usize,
+        start_time: usize,
+        drum_map: &DrumMap,
+        events: &mut Vec<ExportEvent>,
+    ) {
+        let is_drum = track.get_flag(track_flags::DRUM_TRACK);
+
+        // Channel 10 is for percussion, so the 15 other tracks use the other 15 channels.
+        let channel = match track_number {
+            _ if is_drum => 9,
+            0..=9 => track_number.saturating_sub(1) as u8,
+            _ => track_number as u8,
+        };
+
+        for (time, command) in track.commands.iter_time() {
+            let time = start_time + time;
+
+            match *command {
+                Command::Note { pitch, velocity, length } => {
+                    let key = if is_drum {
+                        self.drum_key(pitch, drum_map)
+                    } else {
+                        pitch.checked_sub(104).filter(|key| *key <= 127)
+                    };
+
+                    if let Some(key) = key {
+                        // A NoteOn with zero velocity would be read as a NoteOff.
+                        events.push((time, vec![0x90 | channel, key, velocity.clamp(1, 127)]));
+                        events.push((time + length as usize, vec![0x80 | channel, key, 0]));
+                    }
+                }
+                Command::MasterTempo(beats_per_minute) if beats_per_minute > 0 => {
+                    // MIDI tempo is in microseconds per beat.
+                    let microseconds_per_beat = 60_000_000 / beats_per_minute as u32;
+                    events.push((time, meta_event(0x51, &microseconds_per_beat.to_be_bytes()[1..])));
+                }
+                Command::SubTrackVolume(volume) | Command::SegTrackVolume(volume) => {
+                    events.push((time, vec![0xB0 | channel, 7, volume.min(127)]));
+                }
+                Command::SubTrackPan(pan) => {
+                    events.push((time, vec![0xB0 | channel, 10, (pan as u8).min(127)]));
+                }
+                Command::TrackVoice(voice) => {
+                    if let Some(voice) = self.voices.get(voice as usize) {
+                        events.push((time, vec![0xC0 | channel, voice.patch & 0x7F]));
+                    }
+                }
+                Command::TrackOverridePatch { patch, .. } => {
+                    events.push((time, vec![0xC0 | channel, patch & 0x7F]));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The General MIDI percussion key of the drum that `pitch` plays on a drum track, if it is one [DrumMap] knows.
+    fn drum_key(&self, pitch: u8, drum_map: &DrumMap) -> Option<u8> {
+        let drum = self.drums.get(pitch.checked_sub(PITCH_MIN)? as usize)?;
+
+        // Some keys share a drum, so pick the lowest to be consistent.
+        drum_map.drums
+            .iter()
+            .filter(|(_, d)| *d == drum)
+            .map(|(key, _)| *key)
+            .min()
+    }
+}
+
+fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(data);
+    chunk
 }
 
-fn convert_time(t: usize, time_divisor: f32) -> usize {
-    (t as f32 / time_divisor).round() as usize
+fn meta_event(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut event = vec![0xFF, kind];
+    write_variable_length(&mut event, data.len() as u32);
+    event.extend_from_slice(data);
+    event
+}
+
+/// Writes a MIDI variable-length quantity: 7 bits per byte, most significant first, with the top bit set on every
+/// byte but the last.
+fn write_variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.extend(bytes.iter().rev());
+}
+
+/// Converts between timing resolutions, e.g. from MIDI ticks to BGM ones: `from` ticks take as long as `to` ticks.
+#[derive(Debug, Clone, Copy)]
+struct TimeScale {
+    from: u64,
+    to: u64,
+}
+
+/// Converts a time to the nearest tick. Convert times from the start of the song rather than from the event before, so
+/// that rounding errors don't add up over a long song.
+fn convert_time(t: usize, time_scale: TimeScale) -> usize {
+    ((t as u64 * time_scale.to + time_scale.from / 2) / time_scale.from) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn controller_sweep_thinning() {
+        let sweep: Vec<(usize, u8)> = (0..50).map(|i| (i, i as u8)).collect();
+        assert_eq!(
+            thin_controller_events(&sweep, 10),
+            vec![(0, 0), (10, 10), (20, 20), (30, 30), (40, 40), (49, 49)],
+        );
+
+        // The end of a sweep is kept when the next event is far away
+        assert_eq!(
+            thin_controller_events(&[(0, 1), (1, 2), (100, 2), (101, 5)], 10),
+            vec![(0, 1), (10, 2), (101, 5)],
+        );
+
+        // Events that don't change anything are dropped
+        assert_eq!(thin_controller_events(&[(0, 1), (1, 1), (2, 1)], 0), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn names_and_loop_markers() {
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 2, 0, 48]); // Format 1, 2 tracks, 48 ticks/beat
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x03, 10, b'S', b'o', b'n', b'g', b' ', b'T', b'i', b't', b'l', b'e',
+            0x30, 0xFF, 0x06, 4, b'l', b'o', b'o', b'p',
+            0x30, 0xFF, 0x06, 7, b'l', b'o', b'o', b'p', b'e', b'n', b'd',
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x03, 5, b'P', b'i', b'a', b'n', b'o',
+            0x00, 0x90, 0x3C, 0x64,
+            0x60, 0x80, 0x3C, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let bgm = to_bgm(&midi).unwrap();
+        assert_eq!(bgm.name, "Song");
+
+        let segment = bgm.segments[0].as_ref().unwrap();
+        assert_eq!(segment.name, "Song Title");
+
+        // Intro, loop start, loop body, loop end
+        assert_eq!(segment.subsegments.len(), 4);
+        assert_eq!(segment.subsegments[1].flags(), 0x30);
+        assert_eq!(segment.subsegments[3].flags(), 0x50);
+
+        for (i, suffix) in [(0, "intro"), (2, "loop")].iter() {
+            if let Subsegment::Tracks { track_list, .. } = &segment.subsegments[*i] {
+                let track_list = &bgm.track_lists[track_list];
+                assert_eq!(track_list.name, format!("Song Title ({})", suffix));
+                assert_eq!(track_list.tracks[1].name, "Piano");
+                assert_eq!(track_list.tracks[1].commands.len_time(), 48);
+            } else {
+                panic!("expected Subsegment::Tracks");
+            }
+        }
+    }
+
+    #[test]
+    fn tempo_changes() {
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 1, 0, 96]); // 96 ticks/beat, to check conversion to 48 ticks/beat
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20, // 500000 us/beat = 120 BPM
+            0x60, 0xFF, 0x51, 3, 0x09, 0x27, 0xC0, // 600000 us/beat = 100 BPM
+            0x60, 0xFF, 0x51, 3, 0x0B, 0x71, 0xB0, // 750000 us/beat = 80 BPM
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let bgm = to_bgm(&midi).unwrap();
+        let track_list = bgm.track_lists.values().next().unwrap();
+        let tempos: Vec<(usize, u16)> = track_list.tracks[0]
+            .commands
+            .iter_time()
+            .filter_map(|(time, command)| match command {
+                Command::MasterTempo(bpm) => Some((time, *bpm)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(tempos, vec![(0, 120), (48, 100), (96, 80)]);
+    }
+
+    #[test]
+    fn percussion_uses_drum_map() {
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 2, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[0x00, 0xFF, 0x2F, 0]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0x99, 36, 0x64, // Bass Drum 1, channel 10
+            0x00, 0x99, 20, 0x64, // Not a General MIDI drum
+            0x30, 0x89, 36, 0x40,
+            0x00, 0x89, 20, 0x40,
+            0x00, 0x99, 36, 0x64, // Same drum again
+            0x30, 0x89, 36, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let options = ImportOptions::default();
+        let (bgm, warnings) = to_bgm_with_options(&midi, &options).unwrap();
+
+        assert_eq!(warnings, vec![ImportWarning::UnmappedDrum { track: 1, key: 20 }]);
+        assert_eq!(bgm.drums, vec![options.drum_map.get(36).unwrap().clone()]);
+
+        let track = &bgm.track_lists.values().next().unwrap().tracks[1];
+        assert!(track.get_flag(track_flags::DRUM_TRACK));
+
+        let pitches: Vec<u8> = track.commands
+            .iter()
+            .filter_map(|command| match command {
+                Command::Note { pitch, .. } => Some(*pitch),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(pitches, vec![PITCH_MIN, PITCH_MIN]);
+    }
+
+    #[test]
+    fn report() {
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 2, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x51, 3, 0x07, 0xA1, 0x20, // 120 BPM
+            0x60, 0xFF, 0x51, 3, 0x09, 0x27, 0xC0, // 100 BPM
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x03, 5, b'D', b'r', b'u', b'm', b's',
+            0x00, 0x99, 36, 0x64,
+            0x00, 0x99, 20, 0x64, // Not a General MIDI drum
+            0x00, 0xB9, 7, 0x40, // A volume sweep, of which only the ends are kept
+            0x01, 0xB9, 7, 0x41,
+            0x01, 0xB9, 7, 0x42,
+            0x2E, 0x89, 36, 0x40,
+            0x00, 0x89, 20, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let report = import_report(&midi, &ImportOptions::default()).unwrap();
+        assert_eq!(report, ImportReport {
+            tracks: vec![ImportedTrack { track: 1, name: "Drums".to_owned(), notes: 1, is_drum: true }],
+            midi_notes: 2,
+            notes: 1,
+            midi_controller_events: 3,
+            controller_events: 2,
+            tempo_changes: 2,
+            warnings: vec![ImportWarning::UnmappedDrum { track: 1, key: 20 }],
+        });
+        assert_eq!(report.to_string(), [
+            "Tracks:",
+            "  1. Drums (1 drum notes)",
+            "Notes: 1 of 2 imported",
+            "Volume, pan and pitch bend events: 2 of 3 kept",
+            "Tempo changes: 2",
+            "Track 1: no drum for percussion key 20; notes skipped",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn overlapping_notes() {
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 2, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[0x00, 0xFF, 0x2F, 0]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0x90, 0x3C, 0x64,
+            0x18, 0x90, 0x3C, 0x50, // Pressed again before being released
+            0x18, 0x80, 0x3C, 0x40,
+            0x18, 0x80, 0x3C, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let import = |overlapping_notes| {
+            let options = ImportOptions { overlapping_notes, ..Default::default() };
+            let (bgm, warnings) = to_bgm_with_options(&midi, &options).unwrap();
+            let track_list = bgm.track_lists.values().next().unwrap().clone();
+            let notes = |track: usize| -> Vec<(usize, u8, u16)> {
+                track_list.tracks[track].commands.notes().map(|note| (note.time, note.velocity, note.length)).collect()
+            };
+            (notes(1), notes(2), warnings)
+        };
+
+        let warning = |resolved| vec![ImportWarning::OverlappingNotes { track: 1, count: 1, resolved }];
+
+        assert_eq!(
+            import(OverlapPolicy::Truncate),
+            (vec![(0, 0x64, 24), (24, 0x50, 48)], vec![], warning(OverlapPolicy::Truncate)),
+        );
+        assert_eq!(
+            import(OverlapPolicy::DropLater),
+            (vec![(0, 0x64, 48)], vec![], warning(OverlapPolicy::DropLater)),
+        );
+
+        let (track_1, track_2, warnings) = import(OverlapPolicy::MoveToTrack);
+        assert_eq!((track_1, track_2), (vec![(0, 0x64, 48)], vec![(24, 0x50, 48)]));
+        assert_eq!(warnings, warning(OverlapPolicy::MoveToTrack));
+    }
+
+    #[test]
+    fn channel_layout() {
+        // Format 0: every channel is in MIDI track 0.
+        let mut midi = chunk(b"MThd", &[0, 0, 0, 1, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x03, 4, b'B', b'a', b'n', b'd',
+            0x00, 0xC0, 5, // Channel 1: program 5
+            0x00, 0xC1, 33, // Channel 2: program 33
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0x91, 0x30, 0x64,
+            0x00, 0x99, 36, 0x64, // Channel 10: Bass Drum 1
+            0x30, 0x80, 0x3C, 0x40,
+            0x00, 0x81, 0x30, 0x40,
+            0x00, 0x89, 36, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        // (track, voice, drum track, keys of its notes) for every track that plays notes
+        let layout = |bgm: &Bgm| -> Vec<(usize, u8, bool, Vec<u8>)> {
+            let track_list = bgm.track_lists.values().next().unwrap();
+            track_list.tracks
+                .iter()
+                .enumerate()
+                .filter(|(_, track)| track.commands.notes().next().is_some())
+                .map(|(i, track)| {
+                    let voice = track.commands.iter().find_map(|command| match command {
+                        Command::TrackVoice(voice) => Some(*voice),
+                        _ => None,
+                    });
+                    let keys = track.commands.notes().map(|note| note.pitch.wrapping_sub(104)).collect();
+                    (i, voice.unwrap(), track.get_flag(track_flags::DRUM_TRACK), keys)
+                })
+                .collect()
+        };
+
+        let bgm = to_bgm(&midi).unwrap();
+        let drum_key = PITCH_MIN.wrapping_sub(104);
+        assert_eq!(layout(&bgm), vec![
+            (1, 1, false, vec![0x3C]),
+            (2, 2, false, vec![0x30]),
+            (3, 3, true, vec![drum_key]),
+        ]);
+        assert_eq!((bgm.voices[1].patch, bgm.voices[2].patch), (5, 33));
+        assert_eq!(bgm.track_lists.values().next().unwrap().tracks[0].name, "Band");
+
+        // Format 1: a MIDI track playing on two channels takes two tracks, pushing the next MIDI track along.
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 3, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[0x00, 0xFF, 0x2F, 0]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0x91, 0x30, 0x64,
+            0x30, 0x80, 0x3C, 0x40,
+            0x00, 0x81, 0x30, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x03, 4, b'B', b'a', b's', b's',
+            0x00, 0xC2, 40,
+            0x00, 0x92, 0x24, 0x64,
+            0x30, 0x82, 0x24, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let bgm = to_bgm(&midi).unwrap();
+        assert_eq!(layout(&bgm), vec![
+            (1, 1, false, vec![0x3C]),
+            (2, 2, false, vec![0x30]),
+            (3, 3, false, vec![0x24]),
+        ]);
+        assert_eq!(bgm.track_lists.values().next().unwrap().tracks[3].name, "Bass");
+
+        // MIDI channel 3 to track 5, voice 12
+        let mut options = ImportOptions::default();
+        options.channel_map.insert(2, 5, 12);
+
+        let (bgm, warnings) = to_bgm_with_options(&midi, &options).unwrap();
+        assert_eq!(warnings, vec![]);
+        assert_eq!(layout(&bgm), vec![
+            (1, 1, false, vec![0x3C]),
+            (2, 2, false, vec![0x30]),
+            (5, 12, false, vec![0x24]),
+        ]);
+        assert_eq!(bgm.voices[12].patch, 40);
+        assert_eq!(bgm.track_lists.values().next().unwrap().tracks[5].name, "Bass");
+    }
+
+    #[test]
+    fn format_0() {
+        let mut midi = chunk(b"MThd", &[0, 0, 0, 1, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xFF, 0x03, 4, b'S', b'o', b'n', b'g',
+            0x00, 0xFF, 0x20, 1, 1, // Channel prefix: the name is for channel 2
+            0x00, 0xFF, 0x04, 7, b'S', b't', b'r', b'i', b'n', b'g', b's',
+            0x00, 0xFF, 0x20, 1, 0,
+            0x00, 0xFF, 0x04, 5, b'P', b'i', b'a', b'n', b'o',
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0x91, 0x30, 0x64,
+            0x00, 0xB1, 7, 0x50, // Channel 2 volume
+            0x30, 0x80, 0x3C, 0x40,
+            0x30, 0x81, 0x30, 0x40,
+            0x00, 0xFF, 0x06, 4, b'l', b'o', b'o', b'p',
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let bgm = to_bgm(&midi).unwrap();
+        assert_eq!(bgm.name, "Song");
+
+        // The loop marker is on the master track, so the song has an intro before the loop.
+        let segment = bgm.segments[0].as_ref().unwrap();
+        assert_eq!(segment.subsegments.len(), 4);
+        let track_list = match &segment.subsegments[0] {
+            Subsegment::Tracks { track_list, .. } => &bgm.track_lists[track_list],
+            _ => panic!("expected Subsegment::Tracks"),
+        };
+
+        let tracks = &track_list.tracks;
+        assert_eq!(
+            [tracks[0].name.as_str(), tracks[1].name.as_str(), tracks[2].name.as_str()],
+            ["Song", "Piano", "Strings"],
+        );
+
+        // Each channel's notes and controllers are on its own track, and the master track plays nothing.
+        assert_eq!(tracks[0].commands.notes().count(), 0);
+        let notes = |track: usize| -> Vec<(usize, u16)> {
+            tracks[track].commands.notes().map(|note| (note.time, note.length)).collect()
+        };
+        assert_eq!((notes(1), notes(2)), (vec![(0, 48)], vec![(0, 96)]));
+        assert!(tracks[2].commands.iter().any(|command| *command == Command::SubTrackVolume(0x50)));
+        assert!(!tracks[1].commands.iter().any(|command| *command == Command::SubTrackVolume(0x50)));
+        assert!(tracks[3..].iter().all(|track| track.commands.is_empty()));
+    }
+
+    #[test]
+    fn pitch_bend() {
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 3, 0, 48]);
+        midi.extend(chunk(b"MTrk", &[0x00, 0xFF, 0x2F, 0]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0xE0, 0x7F, 0x7F, // Full bend up
+            0x30, 0xE0, 0x00, 0x60, // Halfway up
+            0x30, 0xE0, 0x00, 0x20, // Halfway down
+            0x30, 0xE0, 0x00, 0x40, // Center
+            0x30, 0x80, 0x3C, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+        midi.extend(chunk(b"MTrk", &[
+            0x00, 0xB0, 101, 0, // RPN 0: pitch bend range
+            0x00, 0xB0, 100, 0,
+            0x00, 0xB0, 6, 12, // 12 semitones
+            0x00, 0xB0, 38, 0,
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0xE0, 0x7F, 0x7F,
+            0x30, 0xE0, 0x00, 0x40,
+            0x30, 0x80, 0x3C, 0x40,
+            0x00, 0xFF, 0x2F, 0,
+        ]));
+
+        let bgm = to_bgm(&midi).unwrap();
+        let track_list = bgm.track_lists.values().next().unwrap();
+        let tunes = |track: usize| -> Vec<(usize, i8, i8)> {
+            track_list.tracks[track]
+                .commands
+                .iter_time()
+                .filter_map(|(time, command)| match command {
+                    Command::SegTrackTune { coarse, fine } => Some((time, *coarse as i8, *fine as i8)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(tunes(1), vec![(0, 2, 0), (48, 1, 0), (96, -1, 0), (144, 0, 0)]);
+        assert_eq!(tunes(2), vec![(0, 12, 0), (48, 0, 0)]);
+    }
+
+    #[test]
+    fn resolution_round_trip() {
+        // 480 ticks per beat, so each tick of the BGM is 10 of the MIDI file. Notes are 37 ticks apart, which would
+        // drift by 0.3 ticks per note if each gap were rounded separately.
+        let mut track = Vec::new();
+        let mut onsets = Vec::new();
+        for i in 0..500 {
+            let key = 0x3C + (i % 2) as u8;
+            write_variable_length(&mut track, if i == 0 { 0 } else { 12 });
+            track.extend_from_slice(&[0x90, key, 0x64]);
+            write_variable_length(&mut track, 25);
+            track.extend_from_slice(&[0x80, key, 0x40]);
+            onsets.push(i * 37);
+        }
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0]);
+
+        let mut midi = chunk(b"MThd", &[0, 1, 0, 2, 0x01, 0xE0]);
+        midi.extend(chunk(b"MTrk", &[0x00, 0xFF, 0x2F, 0]));
+        midi.extend(chunk(b"MTrk", &track));
+
+        let bgm = to_bgm(&midi).unwrap();
+        let exported = bgm.to_midi_with_ticks_per_beat(0, 480).unwrap();
+        let smf = Smf::parse(&exported).unwrap();
+        assert_eq!(smf.header.timing, midly::Timing::Metrical(480.into()));
+
+        let mut exported_onsets = Vec::new();
+        for events in &smf.tracks {
+            let mut time = 0;
+            for event in events {
+                time += event.delta.as_int() as usize;
+                if let TrackEventKind::Midi { message: midly::MidiMessage::NoteOn { vel, .. }, .. } = event.kind {
+                    if vel.as_int() > 0 {
+                        exported_onsets.push(time);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(exported_onsets.len(), onsets.len());
+        for (exported, original) in exported_onsets.iter().zip(&onsets) {
+            assert!((*exported as isize - *original as isize).abs() <= 10, "{} vs {}", exported, original);
+        }
+
+        // Importing at a finer resolution than the game's scales the tempo to match.
+        let options = ImportOptions { ticks_per_beat: 480, ..Default::default() };
+        let (bgm, _) = to_bgm_with_options(&midi, &options).unwrap();
+        let track_list = bgm.track_lists.values().next().unwrap();
+        let notes: Vec<usize> = track_list.tracks[1].commands.notes().map(|note| note.time).collect();
+        assert_eq!(notes, onsets);
+        assert!(track_list.tracks[0].commands.iter().any(|command| *command == Command::MasterTempo(1200)));
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let mut bgm = Bgm::new();
+        bgm.voices.push(Voice { patch: 5, ..Default::default() });
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        segment.name = "Export".to_owned();
+        segment.subsegments.push(Subsegment::Unknown { flags: 0x30, data: [0, 0, 0] });
+        let id = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+        segment.subsegments.push(Subsegment::Unknown { flags: 0x50, data: [0, 0, 0] });
+
+        let track_list = bgm.track_lists.get_mut(&id).unwrap();
+        track_list.tracks[0].commands = CommandSeq::from(vec![
+            Command::MasterTempo(100),
+            Command::Delay(96),
+            Command::End,
+        ]);
+        track_list.tracks[1].name = "Lead".to_owned();
+        track_list.tracks[1].commands = CommandSeq::from(vec![
+            Command::TrackVoice(0),
+            Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+            Command::Delay(48),
+            Command::Note { pitch: 0x94, velocity: 80, length: 24 },
+            Command::Delay(48),
+            Command::End,
+        ]);
+
+        assert_eq!(bgm.to_midi(1), None);
+        let midi = bgm.to_midi(0).unwrap();
+
+        let imported = to_bgm(&midi).unwrap();
+        let segment = imported.segments[0].as_ref().unwrap();
+        assert_eq!(segment.name, "Export");
+
+        // Loop start, body, loop end; the loop covers the whole song so there's no intro or outro.
+        assert_eq!(segment.subsegments.len(), 3);
+        let track_list = match &segment.subsegments[1] {
+            Subsegment::Tracks { track_list, .. } => &imported.track_lists[track_list],
+            _ => panic!("expected Subsegment::Tracks"),
+        };
+
+        let original = &bgm.track_lists[&id].tracks[1];
+        let track = &track_list.tracks[1];
+        assert_eq!(track.name, "Lead");
+        assert_eq!(track.commands.notes().collect::<Vec<_>>(), original.commands.notes().collect::<Vec<_>>());
+        assert_eq!(imported.voices[1].patch, 5);
+        assert!(track_list.tracks[0].commands.iter().any(|command| *command == Command::MasterTempo(100)));
+    }
 }
@@ -6,17 +6,47 @@ pub mod en;
 /// Decoder (.bin -> [Bgm])
 pub mod de;
 
+/// Comparing songs command by command ([diff::diff_seq]), e.g. to see why a song doesn't encode to match the original.
+pub mod diff;
+
+/// MIDI import ([midi::to_bgm]) and export ([Bgm::to_midi]). Needs the `midly` feature.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// let mut bgm = Bgm::new();
+/// bgm.add_segment().unwrap();
+///
+/// let smf = bgm.to_midi(0).unwrap();
+/// let imported = midi::to_bgm(&smf).unwrap();
+/// assert!(imported.segments[0].is_some());
+/// ```
 #[cfg(feature = "midly")]
 pub mod midi;
 
 use std::ops::Range;
 use std::collections::HashMap;
 
-use serde_derive::{Serialize, Deserialize};
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
 
 mod cmd;
 pub use cmd::*;
 
+mod validate;
+pub use validate::*;
+
+mod agnostic;
+pub use agnostic::*;
+
+mod looping;
+pub use looping::*;
+
+mod sequencer;
+pub use sequencer::*;
+
+mod tempo;
+pub use tempo::*;
+
 /// Constant signature string which appears at the start of every binary BGM file.
 pub const MAGIC: &str = "BGM ";
 
@@ -25,26 +55,41 @@ pub type FilePos = u64;
 
 pub type TrackListId = u64;
 
-#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Bgm {
     pub name: String,
 
-    #[serde(rename = "variations")]
-    pub segments: [Option<Segment>; 4],
+    #[cfg_attr(feature = "serde", serde(rename = "variations"))]
+    pub segments: [Option<Segment>; SEGMENTS_MAX],
 
     pub drums: Vec<Drum>,
     pub voices: Vec<Voice>,
 
     pub track_lists: HashMap<TrackListId, TrackList>,
 
-    #[serde(skip)]
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub unknowns: Vec<Unknown>,
 }
 
 #[derive(Clone, Default, Copy, PartialEq, Eq, Debug)]
 pub struct NoSpace;
 
+/// A segment copied from another song by [Bgm::import_segment_from].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ImportedSegment {
+    /// Where the copy is in [Bgm::segments].
+    pub segment_idx: usize,
+
+    /// Voices of the other song that the copy uses but which couldn't be brought over, because the other song doesn't
+    /// have them or this one has no room for more. Commands using them are left as they were.
+    pub unmapped_voices: Vec<u8>,
+
+    /// Like `unmapped_voices`, but for drums of the other song, by their index in its [Bgm::drums]. Notes playing
+    /// them are left as they were.
+    pub unmapped_drums: Vec<u8>,
+}
+
 impl Bgm {
     pub fn new() -> Bgm {
         Bgm {
@@ -66,10 +111,7 @@ impl Bgm {
         match empty_seg {
             None => Err(NoSpace),
             Some((idx, slot)) => {
-                *slot = Some(Segment {
-                    name: format!("Variation {}", idx + 1),
-                    subsegments: Default::default(),
-                });
+                *slot = Some(Segment::new(format!("Variation {}", idx + 1)));
                 Ok((idx, slot.as_mut().unwrap()))
             }
         }
@@ -83,34 +125,717 @@ impl Bgm {
     }
 
     pub fn add_track_list(&mut self, track_list: TrackList) -> TrackListId {
-        let mut max_id = 0;
+        insert_track_list(&mut self.track_lists, track_list)
+    }
+
+    /// Returns whether any subsegment of any segment refers to the given track list.
+    pub fn is_track_list_used(&self, id: TrackListId) -> bool {
+        self.segments
+            .iter()
+            .flatten()
+            .flat_map(|segment| segment.subsegments.iter())
+            .any(|subsegment| matches!(subsegment, Subsegment::Tracks { track_list, .. } if *track_list == id))
+    }
+
+    /// A copy of this song with each segment's [volume_scale](Segment::volume_scale) and
+    /// [tempo_scale](Segment::tempo_scale) folded into its commands, and reset to 1. This is what is
+    /// [encoded](Bgm::encode), since the game has no such scaling. Track lists that a scaled segment shares with other
+    /// segments are copied first, so the other segments aren't scaled too.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let track_list = bgm.segments[0].as_mut().unwrap().add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = CommandSeq::from(vec![
+    ///     Command::MasterTempo(120),
+    ///     Command::MasterVolume(100),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// // Another variation plays the same section, unscaled.
+    /// let (_, other) = bgm.add_segment().unwrap();
+    /// other.subsegments.push(Subsegment::Tracks { flags: 0x10, track_list });
+    ///
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// segment.volume_scale = 0.5;
+    /// segment.tempo_scale = 1.25;
+    ///
+    /// let scaled = bgm.with_scales_applied();
+    /// assert!(scaled.segments[0].as_ref().unwrap().is_unscaled());
+    ///
+    /// let scaled_list = match scaled.segments[0].as_ref().unwrap().subsegments[0] {
+    ///     Subsegment::Tracks { track_list, .. } => track_list,
+    ///     _ => unreachable!(),
+    /// };
+    /// assert_ne!(scaled_list, track_list);
+    /// assert_eq!(scaled.track_lists[&scaled_list].tracks[0].commands, CommandSeq::from(vec![
+    ///     Command::MasterTempo(150),
+    ///     Command::MasterVolume(50),
+    ///     Command::End,
+    /// ]));
+    ///
+    /// // The other variation, and the song itself, are left alone.
+    /// assert_eq!(scaled.track_lists[&track_list], bgm.track_lists[&track_list]);
+    /// ```
+    pub fn with_scales_applied(&self) -> Bgm {
+        let mut bgm = self.clone();
+
+        for segment_idx in 0..bgm.segments.len() {
+            let (volume_scale, tempo_scale) = match &mut bgm.segments[segment_idx] {
+                Some(segment) if !segment.is_unscaled() => {
+                    let scales = (segment.volume_scale, segment.tempo_scale);
+                    segment.volume_scale = 1.0;
+                    segment.tempo_scale = 1.0;
+                    scales
+                }
+                _ => continue,
+            };
+
+            let mut ids = Vec::new();
+            for subsegment in &bgm.segments[segment_idx].as_ref().unwrap().subsegments {
+                if let Subsegment::Tracks { track_list, .. } = subsegment {
+                    if !ids.contains(track_list) {
+                        ids.push(*track_list);
+                    }
+                }
+            }
+
+            for shared in ids {
+                let uses_elsewhere = bgm.segments
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _)| *idx != segment_idx)
+                    .filter_map(|(_, segment)| segment.as_ref())
+                    .flat_map(|segment| segment.subsegments.iter())
+                    .any(|subsegment| {
+                        matches!(subsegment, Subsegment::Tracks { track_list, .. } if *track_list == shared)
+                    });
+
+                let id = if uses_elsewhere {
+                    let copy = match bgm.track_lists.get(&shared).cloned() {
+                        Some(track_list) => insert_track_list(&mut bgm.track_lists, track_list),
+                        None => continue,
+                    };
+
+                    for subsegment in &mut bgm.segments[segment_idx].as_mut().unwrap().subsegments {
+                        if let Subsegment::Tracks { track_list, .. } = subsegment {
+                            if *track_list == shared {
+                                *track_list = copy;
+                            }
+                        }
+                    }
+
+                    copy
+                } else {
+                    shared
+                };
+
+                if let Some(track_list) = bgm.track_lists.get_mut(&id) {
+                    for track in &mut track_list.tracks {
+                        track.commands.scale_master_volume(volume_scale);
+                        track.commands.scale_tempo(tempo_scale);
+                    }
+                }
+            }
+        }
+
+        bgm
+    }
+
+    /// How many subsegments, across every segment, refer to the given track list. If more than one does, editing the
+    /// track list changes all of them.
+    pub fn track_list_uses(&self, id: TrackListId) -> usize {
+        self.segments
+            .iter()
+            .flatten()
+            .flat_map(|segment| segment.subsegments.iter())
+            .filter(|subsegment| matches!(subsegment, Subsegment::Tracks { track_list, .. } if *track_list == id))
+            .count()
+    }
+
+    /// Points a [Subsegment::Tracks] at another track list, which it then shares with every other subsegment that
+    /// refers to it. The track list it referred to before is removed if nothing else uses it. Returns `None` if there
+    /// is no such subsegment, or no track list `id`.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let first = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// let second = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    ///
+    /// assert_eq!(bgm.share_track_list(0, 1, first), Some(()));
+    /// assert_eq!(bgm.track_list_uses(first), 2);
+    /// assert!(!bgm.track_lists.contains_key(&second));
+    ///
+    /// assert_eq!(bgm.share_track_list(0, 1, second), None); // No longer exists
+    /// ```
+    pub fn share_track_list(&mut self, segment_idx: usize, subsegment_idx: usize, id: TrackListId) -> Option<()> {
+        if !self.track_lists.contains_key(&id) {
+            return None;
+        }
+
+        let segment = self.segments.get_mut(segment_idx)?.as_mut()?;
+        let previous = match segment.subsegments.get_mut(subsegment_idx)? {
+            Subsegment::Tracks { track_list, .. } => std::mem::replace(track_list, id),
+            Subsegment::Unknown { .. } => return None,
+        };
+
+        if !self.is_track_list_used(previous) {
+            self.track_lists.remove(&previous);
+        }
+
+        Some(())
+    }
+
+    /// Gives a [Subsegment::Tracks] its own copy of the track list it refers to, if it is shared with other
+    /// subsegments, so that editing it leaves the others alone. Returns the id of the track list the subsegment now
+    /// refers to, or `None` if there is no such subsegment.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let shared = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// segment.subsegments.push(Subsegment::Tracks { flags: 0x10, track_list: shared });
+    ///
+    /// let copy = bgm.unshare_track_list(0, 1).unwrap();
+    /// assert_ne!(copy, shared);
+    /// assert_eq!(bgm.track_list_uses(shared), 1);
+    /// assert_eq!(bgm.track_list_uses(copy), 1);
+    ///
+    /// assert_eq!(bgm.unshare_track_list(0, 1), Some(copy)); // Already its own
+    /// ```
+    pub fn unshare_track_list(&mut self, segment_idx: usize, subsegment_idx: usize) -> Option<TrackListId> {
+        let shared = match self.segments.get(segment_idx)?.as_ref()?.subsegments.get(subsegment_idx)? {
+            Subsegment::Tracks { track_list, .. } => *track_list,
+            Subsegment::Unknown { .. } => return None,
+        };
+
+        if self.track_list_uses(shared) <= 1 {
+            return Some(shared);
+        }
+
+        let mut copy = self.track_lists.get(&shared)?.clone();
+        copy.name = format!("{} (copy)", copy.name);
+        copy.pos = None;
+        let copy = insert_track_list(&mut self.track_lists, copy);
+
+        if let Some(Subsegment::Tracks { track_list, .. }) = self.segments[segment_idx]
+            .as_mut()
+            .and_then(|segment| segment.subsegments.get_mut(subsegment_idx))
+        {
+            *track_list = copy;
+        }
+
+        Some(copy)
+    }
+
+    /// Makes this BGM encode to fewer bytes without changing how it sounds:
+    ///
+    /// - every track has its [redundant commands](CommandSeq::remove_redundant) removed;
+    /// - track lists that encode the same are merged into one, shared by every subsegment that referred to either;
+    /// - track lists forget their [position](TrackList::pos) in the file they were read from, so they are packed
+    ///   together. Songs with [unknown](Bgm::unknowns) data keep their layout, since that data can't be moved.
+    ///
+    /// The result won't match the file it was read from, so this is best left alone when matching.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let first = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// let second = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    ///
+    /// let note = Command::Note { pitch: 0x90, velocity: 100, length: 48 };
+    /// bgm.track_lists.get_mut(&first).unwrap().tracks[1].commands =
+    ///     CommandSeq::from(vec![Command::SubTrackPan(64), Command::SubTrackPan(64), note.clone()]);
+    /// bgm.track_lists.get_mut(&second).unwrap().tracks[1].commands =
+    ///     CommandSeq::from(vec![Command::SubTrackPan(64), note]);
+    ///
+    /// let size = bgm.as_bytes().unwrap().len();
+    /// bgm.optimize();
+    ///
+    /// assert_eq!(bgm.track_list_uses(first), 2);
+    /// assert!(!bgm.track_lists.contains_key(&second));
+    /// assert!(bgm.as_bytes().unwrap().len() < size);
+    /// ```
+    pub fn optimize(&mut self) {
+        for track_list in self.track_lists.values_mut() {
+            for track in track_list.tracks.iter_mut() {
+                track.commands.remove_redundant();
+            }
+
+            if self.unknowns.is_empty() {
+                track_list.pos = None;
+            }
+        }
 
-        for id in self.track_lists.keys() {
-            if *id > max_id {
-                max_id = *id;
+        // Sorted so the track list that is kept is the same every time.
+        let mut ids: Vec<TrackListId> = self.track_lists.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut kept: Vec<TrackListId> = Vec::new();
+        for id in ids {
+            let same = kept
+                .iter()
+                .copied()
+                .find(|other| self.track_lists[other].encodes_same_as(&self.track_lists[&id]));
+
+            let same = match same {
+                Some(same) => same,
+                None => {
+                    kept.push(id);
+                    continue;
+                }
+            };
+
+            for subsegment in self.segments.iter_mut().flatten().flat_map(|segment| segment.subsegments.iter_mut()) {
+                if let Subsegment::Tracks { track_list, .. } = subsegment {
+                    if *track_list == id {
+                        *track_list = same;
+                    }
+                }
+            }
+            self.track_lists.remove(&id);
+        }
+    }
+
+    /// Removes a segment, leaving its slot empty. Track lists that none of the remaining segments refer to are removed
+    /// too.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let own = bgm.segments[0].as_mut().unwrap().add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// let shared = bgm.segments[0].as_mut().unwrap().add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    ///
+    /// let (other_idx, other) = bgm.add_segment().unwrap();
+    /// other.subsegments.push(Subsegment::Tracks { flags: 0x10, track_list: shared });
+    ///
+    /// assert!(bgm.remove_segment(0).is_some());
+    /// assert!(bgm.segments[0].is_none());
+    /// assert!(!bgm.track_lists.contains_key(&own));
+    /// assert!(bgm.track_lists.contains_key(&shared)); // Still used by the other segment
+    ///
+    /// assert!(bgm.remove_segment(0).is_none());
+    /// assert!(bgm.remove_segment(other_idx).is_some());
+    /// assert!(bgm.track_lists.is_empty());
+    /// ```
+    pub fn remove_segment(&mut self, idx: usize) -> Option<Segment> {
+        let segment = self.segments.get_mut(idx)?.take()?;
+
+        for subsegment in &segment.subsegments {
+            if let Subsegment::Tracks { track_list, .. } = subsegment {
+                if !self.is_track_list_used(*track_list) {
+                    self.track_lists.remove(track_list);
+                }
+            }
+        }
+
+        Some(segment)
+    }
+
+    /// Removes a subsegment from a segment. If the subsegment referred to a track list that is no longer referred to
+    /// by any other subsegment (track lists can be shared, even between segments), the track list is removed too.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    ///
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let shared = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// segment.subsegments.push(Subsegment::Tracks { flags: 0x10, track_list: shared });
+    ///
+    /// bgm.remove_subsegment(0, 0);
+    /// assert!(bgm.track_lists.contains_key(&shared)); // Still used by the other subsegment
+    ///
+    /// bgm.remove_subsegment(0, 0);
+    /// assert!(bgm.track_lists.is_empty());
+    /// ```
+    pub fn remove_subsegment(&mut self, segment_idx: usize, subsegment_idx: usize) -> Option<Subsegment> {
+        let segment = self.segments.get_mut(segment_idx)?.as_mut()?;
+
+        if subsegment_idx >= segment.subsegments.len() {
+            return None;
+        }
+
+        let subsegment = segment.subsegments.remove(subsegment_idx);
+
+        if let Subsegment::Tracks { track_list, .. } = &subsegment {
+            if !self.is_track_list_used(*track_list) {
+                self.track_lists.remove(track_list);
+            }
+        }
+
+        Some(subsegment)
+    }
+
+    /// Copies a segment into the first empty segment slot, returning the index of the copy. The track lists it refers
+    /// to are copied too, so editing one segment doesn't change the other. Returns `None` if there is no segment at
+    /// `idx` or every slot is full.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let original = bgm.segments[0].as_mut().unwrap().add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    ///
+    /// assert_eq!(bgm.duplicate_segment(0), Some(1));
+    /// match &bgm.segments[1].as_ref().unwrap().subsegments[0] {
+    ///     Subsegment::Tracks { track_list, .. } => assert_ne!(*track_list, original),
+    ///     _ => unreachable!(),
+    /// }
+    /// assert_eq!(bgm.track_lists.len(), 2);
+    ///
+    /// bgm.duplicate_segment(0);
+    /// bgm.duplicate_segment(0);
+    /// assert_eq!(bgm.duplicate_segment(0), None); // Full
+    /// ```
+    pub fn duplicate_segment(&mut self, idx: usize) -> Option<usize> {
+        let mut segment = self.segments.get(idx)?.clone()?;
+        let new_idx = self.segments.iter().position(Option::is_none)?;
+
+        // Track lists shared between subsegments of the original are shared between subsegments of the copy.
+        let mut copies: HashMap<TrackListId, TrackListId> = HashMap::new();
+
+        for subsegment in &mut segment.subsegments {
+            if let Subsegment::Tracks { track_list, .. } = subsegment {
+                if let Some(copy) = copies.get(track_list) {
+                    *track_list = *copy;
+                } else if let Some(original) = self.track_lists.get(track_list) {
+                    let mut copy = original.clone();
+                    copy.pos = None;
+
+                    let copy = insert_track_list(&mut self.track_lists, copy);
+                    copies.insert(*track_list, copy);
+                    *track_list = copy;
+                }
             }
         }
 
-        let id = max_id.wrapping_add(1);
+        segment.name = format!("{} (copy)", segment.name);
+        self.segments[new_idx] = Some(segment);
+        Some(new_idx)
+    }
+
+    /// Copies segment `idx` of another song into the first empty segment slot of this one, along with the track lists
+    /// it refers to. Voices the copied tracks use are matched up with identical voices in this song, or added to it if
+    /// there aren't any, and the tracks' [Command::TrackVoice]s are changed to suit. Drums are brought over the same
+    /// way, with the pitches of notes on drum tracks changed to pick them. Returns `None` if `other` has no segment at
+    /// `idx` or every slot is full.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let marimba = Voice { bank: 0x30, patch: 0x02, ..Default::default() };
+    /// let flute = Voice { bank: 0x30, patch: 0x8A, ..Default::default() };
+    ///
+    /// let mut other = Bgm::new();
+    /// other.voices = vec![marimba.clone(), flute.clone()];
+    /// other.add_segment().unwrap();
+    /// let segment = other.segments[0].as_mut().unwrap();
+    /// let track_list = segment.add_tracks_subsegment(&mut other.track_lists, 0x10);
+    /// other.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+    ///     Command::TrackVoice(1),
+    ///     Command::TrackVoice(0),
+    ///     Command::TrackVoice(5), // No such voice
+    ///     Command::End,
+    /// ]);
+    ///
+    /// let mut bgm = Bgm::new();
+    /// bgm.voices = vec![flute.clone()];
+    /// bgm.add_segment().unwrap();
+    ///
+    /// let imported = bgm.import_segment_from(&other, 0).unwrap();
+    /// assert_eq!(imported.segment_idx, 1);
+    /// assert_eq!(imported.unmapped_voices, vec![5]);
+    /// assert_eq!(imported.unmapped_drums, Vec::<u8>::new());
+    /// assert_eq!(bgm.voices, vec![flute, marimba]);
+    ///
+    /// let track_list = match &bgm.segments[1].as_ref().unwrap().subsegments[0] {
+    ///     Subsegment::Tracks { track_list, .. } => *track_list,
+    ///     _ => unreachable!(),
+    /// };
+    /// assert_eq!(bgm.track_lists[&track_list].tracks[1].commands, CommandSeq::from(vec![
+    ///     Command::TrackVoice(0),
+    ///     Command::TrackVoice(1),
+    ///     Command::TrackVoice(5),
+    ///     Command::End,
+    /// ]));
+    /// ```
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let kick = Drum { bank: 0x30, patch: 0x00, ..Default::default() };
+    /// let snare = Drum { bank: 0x30, patch: 0x01, ..Default::default() };
+    ///
+    /// let mut other = Bgm::new();
+    /// other.drums = vec![kick.clone(), snare.clone()];
+    /// other.add_segment().unwrap();
+    /// let segment = other.segments[0].as_mut().unwrap();
+    /// let track_list = segment.add_tracks_subsegment(&mut other.track_lists, 0x10);
+    /// let track = &mut other.track_lists.get_mut(&track_list).unwrap().tracks[1];
+    /// track.set_flag(track_flags::DRUM_TRACK, true);
+    /// track.commands = CommandSeq::from(vec![
+    ///     Command::Note { pitch: PITCH_MIN + 1, velocity: 100, length: 24 },
+    ///     Command::Note { pitch: PITCH_MIN + 9, velocity: 100, length: 24 }, // No such drum
+    ///     Command::End,
+    /// ]);
+    ///
+    /// let mut bgm = Bgm::new();
+    /// bgm.drums = vec![kick, snare.clone()];
+    /// bgm.drums.swap(0, 1);
+    /// bgm.add_segment().unwrap();
+    ///
+    /// let imported = bgm.import_segment_from(&other, 0).unwrap();
+    /// assert_eq!(imported.unmapped_drums, vec![9]);
+    ///
+    /// let track_list = match &bgm.segments[1].as_ref().unwrap().subsegments[0] {
+    ///     Subsegment::Tracks { track_list, .. } => *track_list,
+    ///     _ => unreachable!(),
+    /// };
+    /// assert_eq!(bgm.track_lists[&track_list].tracks[1].commands, CommandSeq::from(vec![
+    ///     Command::Note { pitch: PITCH_MIN, velocity: 100, length: 24 }, // The snare is first in this song
+    ///     Command::Note { pitch: PITCH_MIN + 9, velocity: 100, length: 24 },
+    ///     Command::End,
+    /// ]));
+    /// ```
+    pub fn import_segment_from(&mut self, other: &Bgm, idx: usize) -> Option<ImportedSegment> {
+        let mut segment = other.segments.get(idx)?.clone()?;
+        let segment_idx = self.segments.iter().position(Option::is_none)?;
+
+        // Track lists shared between subsegments of the original are shared between subsegments of the copy.
+        let mut copies: HashMap<TrackListId, TrackListId> = HashMap::new();
+        let mut voices: HashMap<u8, u8> = HashMap::new();
+        let mut unmapped_voices = Vec::new();
+        let mut drums: HashMap<u8, u8> = HashMap::new();
+        let mut unmapped_drums = Vec::new();
+
+        for subsegment in &mut segment.subsegments {
+            if let Subsegment::Tracks { track_list, .. } = subsegment {
+                if let Some(copy) = copies.get(track_list) {
+                    *track_list = *copy;
+                    continue;
+                }
+
+                let mut copy = match other.track_lists.get(track_list) {
+                    Some(original) => original.clone(),
+                    None => continue,
+                };
+                copy.pos = None;
+
+                for track in &mut copy.tracks {
+                    let is_drum = track.get_flag(track_flags::DRUM_TRACK);
+
+                    for command in track.commands.iter_mut() {
+                        match command {
+                            Command::TrackVoice(voice) => {
+                                let mapped = match voices.get(voice) {
+                                    Some(mapped) => Some(*mapped),
+                                    None => self.import_voice(other, *voice),
+                                };
 
-        debug_assert!(self.track_lists.get(&id).is_none());
+                                match mapped {
+                                    Some(mapped) => {
+                                        voices.insert(*voice, mapped);
+                                        *voice = mapped;
+                                    }
+                                    None if !unmapped_voices.contains(voice) => unmapped_voices.push(*voice),
+                                    None => {}
+                                }
+                            }
+                            // Drum tracks pick the drum to play by pitch.
+                            Command::Note { pitch, .. } if is_drum => {
+                                let drum = pitch.wrapping_sub(PITCH_MIN);
+                                let mapped = match drums.get(&drum) {
+                                    Some(mapped) => Some(*mapped),
+                                    None => self.import_drum(other, drum),
+                                };
 
-        self.track_lists.insert(id, track_list);
-        id
+                                match mapped {
+                                    Some(mapped) => {
+                                        drums.insert(drum, mapped);
+                                        *pitch = PITCH_MIN + mapped;
+                                    }
+                                    None if !unmapped_drums.contains(&drum) => unmapped_drums.push(drum),
+                                    None => {}
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                let copy = insert_track_list(&mut self.track_lists, copy);
+                copies.insert(*track_list, copy);
+                *track_list = copy;
+            }
+        }
+
+        self.segments[segment_idx] = Some(segment);
+
+        Some(ImportedSegment {
+            segment_idx,
+            unmapped_voices,
+            unmapped_drums,
+        })
+    }
+
+    /// Finds or adds a voice the same as voice `idx` of `other`, returning its index in this song.
+    fn import_voice(&mut self, other: &Bgm, idx: u8) -> Option<u8> {
+        let voice = other.voices.get(idx as usize)?;
+
+        match self.voices.iter().position(|mine| mine == voice) {
+            Some(mine) => Some(mine as u8),
+            // Tracks refer to voices by a u8 index
+            None if self.voices.len() <= u8::MAX as usize => {
+                self.voices.push(voice.clone());
+                Some((self.voices.len() - 1) as u8)
+            }
+            None => None,
+        }
+    }
+
+    /// Finds or adds a drum the same as drum `idx` of `other`, returning its index in this song.
+    fn import_drum(&mut self, other: &Bgm, idx: u8) -> Option<u8> {
+        let drum = other.drums.get(idx as usize)?;
+
+        match self.drums.iter().position(|mine| mine == drum) {
+            Some(mine) if mine <= (PITCH_MAX - PITCH_MIN) as usize => Some(mine as u8),
+            Some(_) => None,
+            // Drum tracks pick drums by pitch, so only as many as there are pitches can be played
+            None if self.drums.len() <= (PITCH_MAX - PITCH_MIN) as usize => {
+                self.drums.push(drum.clone());
+                Some((self.drums.len() - 1) as u8)
+            }
+            None => None,
+        }
+    }
+
+    /// Returns a copy of this BGM in which segment `segment_idx` starts playing `time` ticks in, for previewing part
+    /// of a song. If `time` is within the segment's loop, the whole loop still plays once the segment loops back.
+    /// Returns `None` if there is no segment at `segment_idx`.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = CommandSeq::from(vec![
+    ///     Command::Delay(192),
+    ///     Command::End,
+    /// ]);
+    /// bgm.set_loop(0, 96..192).unwrap();
+    ///
+    /// let preview = bgm.starting_at(0, 144).unwrap();
+    /// let segment = preview.segments[0].as_ref().unwrap();
+    /// assert_eq!(segment.len_time(&preview.track_lists), 48 + 96); // The rest of the loop, then all of it
+    /// assert_eq!(segment.loop_range(&preview.track_lists), Some(48..144));
+    /// ```
+    pub fn starting_at(&self, segment_idx: usize, time: usize) -> Option<Bgm> {
+        let segment = self.segments.get(segment_idx)?.as_ref()?;
+        let mut bgm = self.clone();
+
+        let loop_start = segment.subsegments.iter().position(Subsegment::is_loop_start);
+        let loop_end = segment.subsegments.iter().position(Subsegment::is_loop_end);
+
+        // Sections before `time` are copied with everything before `time` skipped. The rest are used as they are.
+        let mut subsegments = Vec::new();
+        let mut resume_idx = segment.subsegments.len();
+        let mut start = 0;
+
+        for (i, subsegment) in segment.subsegments.iter().enumerate() {
+            if let Subsegment::Tracks { flags, track_list } = subsegment {
+                let length = self.track_lists.get(track_list).map_or(0, TrackList::len_time);
+
+                if let Some(track_list) = self.track_lists.get(track_list) {
+                    let mut skipped = track_list.clone();
+                    skipped.pos = None;
+                    skipped.skip_to(time.saturating_sub(start).min(length));
+                    subsegments.push(Subsegment::Tracks {
+                        flags: *flags,
+                        track_list: bgm.add_track_list(skipped),
+                    });
+                }
+
+                if start + length > time {
+                    resume_idx = i + 1;
+                    break;
+                }
+
+                start += length;
+            }
+        }
+
+        match (loop_start, loop_end) {
+            // Starting part-way through the loop: play the rest of it, then loop the whole thing.
+            (Some(loop_start), Some(loop_end)) if loop_start < resume_idx && resume_idx <= loop_end => {
+                subsegments.extend(segment.subsegments[resume_idx..loop_end].iter().cloned());
+                subsegments.extend(segment.subsegments[loop_start..].iter().cloned());
+            }
+            _ => subsegments.extend(segment.subsegments[resume_idx..].iter().cloned()),
+        }
+
+        bgm.segments[segment_idx].as_mut()?.subsegments = subsegments;
+        Some(bgm)
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+fn insert_track_list(track_lists: &mut HashMap<TrackListId, TrackList>, track_list: TrackList) -> TrackListId {
+    let mut max_id = 0;
+
+    for id in track_lists.keys() {
+        if *id > max_id {
+            max_id = *id;
+        }
+    }
+
+    let id = max_id.wrapping_add(1);
+
+    debug_assert!(track_lists.get(&id).is_none());
+
+    track_lists.insert(id, track_list);
+    id
+}
+
+/// How many segments a [Bgm] has room for. The header has an offset for each, so [Bgm::segments] is this long.
+pub const SEGMENTS_MAX: usize = 4;
+
+/// The most [subsegments](Segment::subsegments) Mamar lets one segment have. The engine reads them until a terminator
+/// rather than counting, but each takes space in a song that must fit in `0x8A90` bytes, and no song in the game has
+/// more than a few dozen. More than this usually means a section was added over and over by mistake.
+pub const SUBSEGMENTS_MAX: usize = 128;
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Segment {
     /// Not encoded in BGM data.
     pub name: String,
 
-    #[serde(rename = "sections")]
+    #[cfg_attr(feature = "serde", serde(rename = "sections"))]
     pub subsegments: Vec<Subsegment>,
+
+    /// Multiplies the volume of every [Command::MasterVolume] in this segment when it is encoded, leaving the
+    /// commands themselves as they are. See [Bgm::with_scales_applied].
+    #[cfg_attr(feature = "serde", serde(default = "unscaled", skip_serializing_if = "is_unscaled"))]
+    pub volume_scale: f32,
+
+    /// Multiplies the tempo of every [Command::MasterTempo] in this segment when it is encoded, like
+    /// [volume_scale](Segment::volume_scale).
+    #[cfg_attr(feature = "serde", serde(default = "unscaled", skip_serializing_if = "is_unscaled"))]
+    pub tempo_scale: f32,
 }
 
 // TODO: better representation for `flags`
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Subsegment {
     Tracks {
         flags: u8,
@@ -122,34 +847,91 @@ pub enum Subsegment {
     },
 }
 
-#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct TrackList {
     /// Not encoded in BGM data.
     pub name: String,
 
     /// Encode/decode file position.
-    #[serde(skip_serializing_if="Option::is_none")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="Option::is_none"))]
     pub pos: Option<FilePos>,
 
     pub tracks: [Track; 16],
 }
 
-#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Track {
     pub name: String,
 
     pub flags: u16, // TODO: better representation
     pub commands: CommandSeq,
 
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub mute: bool,
 
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub solo: bool,
 }
 
+impl Segment {
+    /// An empty segment, with no subsegments and no scaling.
+    pub fn new(name: String) -> Segment {
+        Segment {
+            name,
+            subsegments: Vec::new(),
+            volume_scale: 1.0,
+            tempo_scale: 1.0,
+        }
+    }
+
+    /// Whether [volume_scale](Segment::volume_scale) and [tempo_scale](Segment::tempo_scale) leave the segment as it
+    /// is.
+    pub fn is_unscaled(&self) -> bool {
+        self.volume_scale == 1.0 && self.tempo_scale == 1.0
+    }
+
+    /// Appends a [Subsegment::Tracks] with a new, empty track list, which is added to `track_lists`.
+    pub fn add_tracks_subsegment(
+        &mut self,
+        track_lists: &mut HashMap<TrackListId, TrackList>,
+        flags: u8,
+    ) -> TrackListId {
+        let track_list = insert_track_list(track_lists, TrackList::default());
+        self.subsegments.push(Subsegment::Tracks { flags, track_list });
+        track_list
+    }
+
+    /// Transposes every track list referenced by this segment. Track lists shared between multiple subsegments are
+    /// only transposed once. Returns the number of notes that went out of range.
+    /// See [TrackList::transpose].
+    pub fn transpose(
+        &self,
+        track_lists: &mut HashMap<TrackListId, TrackList>,
+        semitones: i8,
+        out_of_range: OutOfRange,
+    ) -> usize {
+        let mut transposed = Vec::new();
+        let mut num_out_of_range = 0;
+
+        for subsegment in &self.subsegments {
+            if let Subsegment::Tracks { track_list, .. } = subsegment {
+                if transposed.contains(track_list) {
+                    continue;
+                }
+                transposed.push(*track_list);
+
+                if let Some(track_list) = track_lists.get_mut(track_list) {
+                    num_out_of_range += track_list.transpose(semitones, out_of_range);
+                }
+            }
+        }
+
+        num_out_of_range
+    }
+}
+
 impl Subsegment {
     pub fn flags(&self) -> u8 {
         match *self {
@@ -159,8 +941,8 @@ impl Subsegment {
     }
 }
 
-#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Drum {
     pub bank: u8,
     pub patch: u8,
@@ -175,22 +957,22 @@ pub struct Drum {
 
     pub reverb: u8,
 
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub unk_07: u8,
 
     // The following are possibly just padding, or they just have unused uses. Needs testing
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub unk_08: u8, // Unused; zero in all original songs
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub unk_09: u8, // Unused
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub unk_0a: u8, // Unused
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub unk_0b: u8, // Unused
 }
 
-#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
 pub struct Voice {
     /// Upper nibble = bank. (0..=6 are valid?)
     /// Lower nibble = staccatoness mod 3 (0 = sustain, 3 = staccato).
@@ -209,7 +991,7 @@ pub struct Voice {
     pub coarse_tune: u8,
     pub fine_tune: u8,
 
-    #[serde(skip_serializing_if="is_default")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if="is_default"))]
     pub unk_07: u8,
 }
 
@@ -224,6 +1006,12 @@ impl Track {
         (self.flags & flag) != 0
     }
 
+    /// Shifts the pitch of every note in this track. Returns the number of notes that went out of range.
+    /// See [CommandSeq::transpose].
+    pub fn transpose(&mut self, semitones: i8, out_of_range: OutOfRange) -> usize {
+        self.commands.transpose(semitones, out_of_range)
+    }
+
     pub fn set_flag(&mut self, flag: u16, enable: bool) {
         if enable {
             self.flags |= flag;
@@ -253,11 +1041,229 @@ pub mod track_flags {
     pub const POLYPHONY_3: u16 = 0x8000;
 }
 
+#[cfg(feature = "serde")]
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
 }
 
+#[cfg(feature = "serde")]
+fn unscaled() -> f32 {
+    1.0
+}
+
+#[cfg(feature = "serde")]
+fn is_unscaled(scale: &f32) -> bool {
+    *scale == 1.0
+}
+
 impl TrackList {
+    /// Returns the length of this track list in ticks, i.e. that of its longest track.
+    pub fn len_time(&self) -> usize {
+        self.tracks.iter().map(|track| track.commands.len_time()).max().unwrap_or(0)
+    }
+
+    /// Removes the first `time` ticks of every track. See [CommandSeq::skip_to].
+    pub fn skip_to(&mut self, time: usize) {
+        for track in self.tracks.iter_mut() {
+            if !track.commands.is_empty() {
+                track.commands.skip_to(time);
+            }
+        }
+    }
+
+    /// Copies the part of every track within `range` into a new track list, with `suffix` added to its name.
+    ///
+    /// The slice plays as its own [Subsegment], so the last tempo, voice, volume, pan and so on that each track sets
+    /// before `range.start` is copied to the start of the slice, where it runs straight away. Fades are finished
+    /// early, i.e. replaced with the value they fade to, except [TrackVolumeFade](Command::TrackVolumeFade).
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut track_list = TrackList::default();
+    /// track_list.tracks[0].commands = CommandSeq::from(vec![
+    ///     Command::MasterTempo(120),
+    ///     Command::TrackVoice(1),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::TrackVoice(2),
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// let slice = track_list.slice(96..144, "loop");
+    ///
+    /// assert_eq!(slice.tracks[0].commands, CommandSeq::from(vec![
+    ///     Command::MasterTempo(120),
+    ///     Command::TrackVoice(2), // Not 1
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]));
+    /// ```
+    pub fn slice(&self, range: Range<usize>, suffix: &str) -> TrackList {
+        let mut slice = self.clone();
+        slice.name = format!("{} ({})", self.name, suffix);
+        slice.pos = None;
+
+        for track in slice.tracks.iter_mut() {
+            if track.commands.is_empty() {
+                continue;
+            }
+
+            let mut state: Vec<Command> = Vec::new();
+            for (_, command) in track.commands.iter_time().take_while(|(t, _)| *t < range.start) {
+                if let Some(command) = state_before_slice(command) {
+                    state.retain(|other| other.opcode() != command.opcode());
+                    state.push(command);
+                }
+            }
+
+            let mut commands = CommandSeq::from(state);
+            let mut time = range.start;
+
+            for (t, command) in track.commands.iter_time() {
+                if matches!(command, Command::Delay(_) | Command::End) || !range.contains(&t) {
+                    continue;
+                }
+
+                if t > time {
+                    commands.push(Command::Delay(t - time));
+                    time = t;
+                }
+                commands.push(command.clone());
+            }
+
+            if range.end > time {
+                commands.push(Command::Delay(range.end - time));
+            }
+            commands.push(Command::End);
+
+            track.commands = commands;
+        }
+
+        slice
+    }
+
+    /// Transposes every track in this list, except for drum tracks (where pitch selects the drum sound rather than
+    /// the note to play). Returns the number of notes that went out of range.
+    pub fn transpose(&mut self, semitones: i8, out_of_range: OutOfRange) -> usize {
+        self.tracks
+            .iter_mut()
+            .filter(|track| !track.get_flag(track_flags::DRUM_TRACK))
+            .map(|track| track.transpose(semitones, out_of_range))
+            .sum()
+    }
+
+    /// Splits every track into one sequence per voice it uses. The master track (the first track) and drum tracks are
+    /// skipped, as their notes don't use voices. Results are ordered by track; see [CommandSeq::split_by_voice] for
+    /// what each sequence contains.
+    pub fn split_by_voice(&self) -> Vec<CommandSeq> {
+        self.tracks
+            .iter()
+            .skip(1)
+            .filter(|track| !track.get_flag(track_flags::DRUM_TRACK))
+            .flat_map(|track| track.commands.split_by_voice())
+            .collect()
+    }
+
+    /// Copies the track at `index` into the first empty track in this list, returning where it went. Returns `None`
+    /// if there is no empty track to copy it into.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut track_list = TrackList::default();
+    /// track_list.tracks[1].name = "Bass".to_owned();
+    /// track_list.tracks[1].commands = CommandSeq::from(vec![Command::Delay(10), Command::End]);
+    ///
+    /// assert_eq!(track_list.duplicate_track(1), Some(2));
+    /// assert_eq!(track_list.tracks[2].name, "Bass (copy)");
+    /// ```
+    pub fn duplicate_track(&mut self, index: usize) -> Option<usize> {
+        let empty = self.empty_tracks(index).next()?;
+
+        let mut track = self.tracks[index].clone();
+        track.name = format!("{} (copy)", track.name);
+        self.tracks[empty] = track;
+
+        Some(empty)
+    }
+
+    /// Moves the notes of each voice used by the track at `index` onto a track of its own, filling empty tracks in
+    /// this list. Does nothing and returns false if there aren't enough empty tracks.
+    pub fn split_track_by_voice(&mut self, index: usize) -> bool {
+        let mut seqs = self.tracks[index].commands.split_by_voice().into_iter();
+
+        let first = match seqs.next() {
+            Some(seq) => seq,
+            None => return true,
+        };
+
+        let empty_tracks: Vec<usize> = self.empty_tracks(index).collect();
+        if empty_tracks.len() < seqs.len() {
+            return false;
+        }
+
+        let flags = self.tracks[index].flags;
+        let name = self.tracks[index].name.clone();
+        self.tracks[index].commands = first;
+
+        for (n, (i, seq)) in empty_tracks.into_iter().zip(seqs).enumerate() {
+            let track = &mut self.tracks[i];
+            track.name = format!("{} ({})", name, n + 2);
+            track.flags = flags;
+            track.commands = seq;
+        }
+
+        true
+    }
+
+    /// Indices of tracks with no commands, other than the master track and the track at `except`.
+    fn empty_tracks(&self, except: usize) -> impl Iterator<Item = usize> + '_ {
+        self.tracks
+            .iter()
+            .enumerate()
+            .skip(1) // Master track
+            .filter(move |(i, track)| *i != except && track.commands.is_empty())
+            .map(|(i, _)| i)
+    }
+
+    /// Whether this track list and `other` encode to the same data, though their names and positions may differ.
+    fn encodes_same_as(&self, other: &TrackList) -> bool {
+        (0..self.tracks.len()).all(|i| {
+            let (a, b) = (&self.tracks[i], &other.tracks[i]);
+            a.flags == b.flags && a.commands == b.commands && self.is_track_silent(i) == other.is_track_silent(i)
+        })
+    }
+
+    /// Whether the track at `index` should be encoded without sound: either it is muted, or another track in this list
+    /// is soloed and it isn't. Silent tracks still play all of their other commands, so tempo and timing are kept.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut track_list = TrackList::default();
+    /// track_list.tracks[2].mute = true;
+    /// assert!(!track_list.is_track_silent(1));
+    /// assert!(track_list.is_track_silent(2));
+    ///
+    /// track_list.tracks[3].solo = true;
+    /// assert!(track_list.is_track_silent(1));
+    /// assert!(!track_list.is_track_silent(3));
+    ///
+    /// track_list.tracks[3].mute = true; // Mute wins over solo
+    /// assert!(track_list.is_track_silent(3));
+    /// ```
+    pub fn is_track_silent(&self, index: usize) -> bool {
+        let track = &self.tracks[index];
+
+        if track.mute {
+            true
+        } else {
+            self.tracks.iter().any(|t| t.solo) && !track.solo
+        }
+    }
+
     pub fn silence_skip(&mut self) {
         for track in &mut self.tracks {
             track.mute = true;
@@ -266,3 +1272,20 @@ impl TrackList {
         }
     }
 }
+
+/// The command to run at the start of a [TrackList::slice] so that it sounds as it did after `command` ran earlier
+/// in the track, or `None` if the slice doesn't need it. Only the last command with each opcode is kept, and they
+/// stay in order, so e.g. a [TrackTremoloStop](Command::TrackTremoloStop) still comes after the tremolo it stops.
+fn state_before_slice(command: &Command) -> Option<Command> {
+    match *command {
+        Command::MasterTempoFade { bpm, .. } => Some(Command::MasterTempo(bpm)),
+        Command::MasterVolumeFade { volume, .. } => Some(Command::MasterVolume(volume)),
+        Command::Delay(_)
+        | Command::Note { .. }
+        | Command::Marker(_)
+        | Command::Subroutine(_)
+        | Command::Unknown(_)
+        | Command::End => None,
+        _ => Some(command.clone()),
+    }
+}
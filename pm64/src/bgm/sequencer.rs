@@ -0,0 +1,454 @@
+use super::*;
+
+/// Something that happens on a tick of playback, for a synthesizer to act on. Tracks are numbered by their index in
+/// the [TrackList] playing, so 0 is the master track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeqEvent {
+    /// A note starts, lasting `length` ticks. On drum tracks, `pitch` picks a [Drum] starting from [PITCH_MIN].
+    Note {
+        track: usize,
+        pitch: u8,
+        velocity: u8,
+        length: usize,
+        is_drum: bool,
+    },
+
+    /// Beats per minute. Ticks are 1/48 of a beat.
+    Tempo(u16),
+
+    MasterVolume(u8),
+
+    TrackVolume {
+        track: usize,
+        volume: u8,
+    },
+
+    /// Left = 0, middle = 64, right = 127.
+    TrackPan {
+        track: usize,
+        pan: i8,
+    },
+
+    /// The track's notes use [Bgm::voices] at this index from now on.
+    TrackVoice {
+        track: usize,
+        voice: u8,
+    },
+
+    /// A new section starts, so volume and pan set by the previous one no longer apply.
+    SubsegmentStart,
+}
+
+/// Steps through a segment of a [Bgm] tick by tick, roughly as the game plays it, so that it can be played without
+/// the game. Loops forever if the segment has a loop, and [subroutines](Command::Subroutine) are followed.
+///
+/// Fades happen all at once, at the start of the fade. Commands without a [SeqEvent], like reverb, are ignored.
+///
+/// ```
+/// # use pm64::bgm::*;
+/// let mut bgm = Bgm::new();
+/// bgm.add_segment().unwrap();
+/// let segment = bgm.segments[0].as_mut().unwrap();
+/// let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+/// bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+///     Command::Delay(10),
+///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+///     Command::Delay(48),
+///     Command::End,
+/// ]);
+///
+/// let mut sequencer = Sequencer::new(bgm, 0);
+/// let mut events = Vec::new();
+/// for _ in 0..10 {
+///     sequencer.tick(&mut events);
+/// }
+/// assert_eq!(events, vec![SeqEvent::SubsegmentStart]);
+///
+/// events.clear();
+/// sequencer.tick(&mut events);
+/// assert!(matches!(events[..], [SeqEvent::Note { track: 1, pitch: 0x90, length: 48, .. }]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sequencer {
+    bgm: Bgm,
+    segment_idx: usize,
+
+    /// The subsegment playing, unless it hasn't started yet.
+    subsegment_idx: Option<usize>,
+    /// Where the segment's loop starts, once it has been reached.
+    loop_start_idx: Option<usize>,
+    /// Ticks since the loop last started. Loops that take no time are not repeated, or they would hang.
+    loop_ticks: usize,
+    /// Where the subsegment playing starts, in ticks from the start of the segment.
+    subsegment_start: usize,
+    /// Ticks since the subsegment playing started.
+    subsegment_ticks: usize,
+
+    tracks: Vec<TrackCursor>,
+    is_finished: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrackCursor {
+    /// Index of the next command to run.
+    pos: usize,
+    /// Ticks until the next command runs.
+    wait: usize,
+    /// The subroutines being run, innermost last: where each returns to and the index of its end marker.
+    calls: Vec<(usize, usize)>,
+    is_finished: bool,
+}
+
+impl Sequencer {
+    /// Starts playing the segment at `segment_idx`, which would be 0 to play a song from the start.
+    pub fn new(bgm: Bgm, segment_idx: usize) -> Self {
+        Sequencer {
+            bgm,
+            segment_idx,
+            subsegment_idx: None,
+            loop_start_idx: None,
+            loop_ticks: 0,
+            subsegment_start: 0,
+            subsegment_ticks: 0,
+            tracks: Vec::new(),
+            is_finished: false,
+        }
+    }
+
+    /// The song being played, e.g. to look up voices and drums.
+    pub fn bgm(&self) -> &Bgm {
+        &self.bgm
+    }
+
+    /// Whether the segment has ended. Looping segments never end.
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    /// The time of the next tick to play, in ticks from the start of the segment. This jumps back when the segment
+    /// loops, so it is always within [Segment::len_time].
+    pub fn position(&self) -> usize {
+        self.subsegment_start + self.subsegment_ticks
+    }
+
+    /// Ticks since the subsegment playing started. Subsegments start on a beat, so beats can be counted from here.
+    pub fn subsegment_position(&self) -> usize {
+        self.subsegment_ticks
+    }
+
+    /// Starts the segment again, then skips `time` ticks into it without playing notes, so that the next tick played
+    /// is at that [position](Sequencer::position). Everything else that happens on the way, like tempo, volume and
+    /// voice changes, is added to `events` so that a synthesizer can catch up with it. Notes that would still be
+    /// sounding at `time` are skipped too. Seeking past the end of the segment finishes it.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+    ///     Command::TrackVoice(2),
+    ///     Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::Note { pitch: 0x94, velocity: 100, length: 48 },
+    ///     Command::Delay(48),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// let mut sequencer = Sequencer::new(bgm, 0);
+    /// let mut events = Vec::new();
+    /// sequencer.seek(48, &mut events);
+    /// assert_eq!(events, vec![SeqEvent::SubsegmentStart, SeqEvent::TrackVoice { track: 1, voice: 2 }]);
+    /// assert_eq!(sequencer.position(), 48);
+    ///
+    /// events.clear();
+    /// sequencer.tick(&mut events);
+    /// assert!(matches!(events[..], [SeqEvent::Note { track: 1, pitch: 0x94, .. }]));
+    /// ```
+    pub fn seek(&mut self, time: usize, events: &mut Vec<SeqEvent>) {
+        *self = Sequencer::new(std::mem::take(&mut self.bgm), self.segment_idx);
+
+        let mut skipped = Vec::new();
+        for _ in 0..time {
+            if self.is_finished {
+                break;
+            }
+
+            self.tick(&mut skipped);
+            events.extend(skipped.drain(..).filter(|event| !matches!(event, SeqEvent::Note { .. })));
+        }
+    }
+
+    /// Plays one tick, adding what happens on it to `events`.
+    pub fn tick(&mut self, events: &mut Vec<SeqEvent>) {
+        while !self.is_finished {
+            if self.subsegment_idx.is_some() {
+                self.run_tracks(events);
+
+                if self.tracks.iter().any(|track| !track.is_finished) {
+                    break;
+                }
+            }
+
+            self.next_subsegment(events);
+        }
+
+        for track in &mut self.tracks {
+            track.wait = track.wait.saturating_sub(1);
+        }
+        self.loop_ticks += 1;
+        self.subsegment_ticks += 1;
+    }
+
+    /// Moves on to the next [Subsegment::Tracks], following the loop if there is one.
+    fn next_subsegment(&mut self, events: &mut Vec<SeqEvent>) {
+        let segment = match &self.bgm.segments[self.segment_idx] {
+            Some(segment) => segment,
+            None => {
+                self.is_finished = true;
+                return;
+            }
+        };
+
+        let subsegments = &segment.subsegments;
+        let mut idx = self.subsegment_idx.map_or(0, |idx| idx + 1);
+
+        loop {
+            match subsegments.get(idx) {
+                None => {
+                    self.is_finished = true;
+                    return;
+                }
+                Some(Subsegment::Tracks { .. }) => break,
+                Some(subsegment) if subsegment.is_loop_start() => {
+                    self.loop_start_idx = Some(idx + 1);
+                    self.loop_ticks = 0;
+                    idx += 1;
+                }
+                Some(subsegment) if subsegment.is_loop_end() => match self.loop_start_idx {
+                    Some(start_idx) if self.loop_ticks > 0 => {
+                        self.loop_ticks = 0;
+                        idx = start_idx;
+                    }
+                    _ => idx += 1,
+                },
+                Some(Subsegment::Unknown { .. }) => idx += 1,
+            }
+        }
+
+        self.subsegment_idx = Some(idx);
+        self.subsegment_start = segment.subsegment_time(&self.bgm.track_lists, idx);
+        self.subsegment_ticks = 0;
+        self.tracks = vec![TrackCursor::default(); 16];
+        events.push(SeqEvent::SubsegmentStart);
+    }
+
+    /// Runs the commands of every track that is due to run some.
+    fn run_tracks(&mut self, events: &mut Vec<SeqEvent>) {
+        let (bgm, segment_idx) = (&self.bgm, self.segment_idx);
+        let track_list = match self.subsegment_idx.and_then(|idx| track_list_at(bgm, segment_idx, idx)) {
+            Some(track_list) => track_list,
+            None => {
+                for cursor in &mut self.tracks {
+                    cursor.is_finished = true;
+                }
+                return;
+            }
+        };
+
+        for (i, cursor) in self.tracks.iter_mut().enumerate() {
+            let track = &track_list.tracks[i];
+            let is_silent = track_list.is_track_silent(i);
+            let is_drum = track.get_flag(track_flags::DRUM_TRACK);
+            let commands = track.commands.iter().as_slice();
+
+            while !cursor.is_finished && cursor.wait == 0 {
+                // Return from subroutines that have reached their end marker.
+                while let Some((return_pos, end_pos)) = cursor.calls.last().copied() {
+                    if cursor.pos < end_pos {
+                        break;
+                    }
+                    cursor.calls.pop();
+                    cursor.pos = return_pos;
+                }
+
+                let command = match commands.get(cursor.pos) {
+                    Some(command) => command,
+                    None => {
+                        cursor.is_finished = true;
+                        break;
+                    }
+                };
+                cursor.pos += 1;
+
+                let event = match command {
+                    Command::Delay(delta_time) => {
+                        cursor.wait = *delta_time;
+                        None
+                    }
+                    Command::End if cursor.calls.is_empty() => {
+                        cursor.is_finished = true;
+                        None
+                    }
+                    Command::Subroutine(range) => {
+                        let find = |id: &MarkerId| {
+                            commands.iter().position(|command| matches!(command, Command::Marker(m) if m == id))
+                        };
+
+                        // Subroutines can't call themselves in the game, so neither can they here.
+                        if let (Some(start), Some(end)) = (find(&range.start), find(&range.end)) {
+                            if cursor.calls.len() < MAX_SUBROUTINE_DEPTH && start < end {
+                                cursor.calls.push((cursor.pos, end));
+                                cursor.pos = start;
+                            }
+                        }
+                        None
+                    }
+                    Command::Note { pitch, velocity, length } if !is_silent => Some(SeqEvent::Note {
+                        track: i,
+                        pitch: *pitch,
+                        velocity: *velocity,
+                        length: *length as usize,
+                        is_drum,
+                    }),
+                    Command::MasterTempo(bpm) | Command::MasterTempoFade { bpm, .. } => Some(SeqEvent::Tempo(*bpm)),
+                    Command::MasterVolume(volume) | Command::MasterVolumeFade { volume, .. } => {
+                        Some(SeqEvent::MasterVolume(*volume))
+                    }
+                    Command::SubTrackVolume(volume)
+                    | Command::SegTrackVolume(volume)
+                    | Command::TrackVolumeFade { volume, .. } => Some(SeqEvent::TrackVolume {
+                        track: i,
+                        volume: *volume,
+                    }),
+                    Command::SubTrackPan(pan) => Some(SeqEvent::TrackPan { track: i, pan: *pan }),
+                    Command::TrackVoice(voice) => Some(SeqEvent::TrackVoice { track: i, voice: *voice }),
+                    _ => None,
+                };
+
+                events.extend(event);
+            }
+        }
+    }
+}
+
+fn track_list_at(bgm: &Bgm, segment_idx: usize, subsegment_idx: usize) -> Option<&TrackList> {
+    let segment = bgm.segments[segment_idx].as_ref()?;
+
+    match segment.subsegments.get(subsegment_idx)? {
+        Subsegment::Tracks { track_list, .. } => bgm.track_lists.get(track_list),
+        Subsegment::Unknown { .. } => None,
+    }
+}
+
+/// How deeply subroutines can be nested before calls are ignored.
+const MAX_SUBROUTINE_DEPTH: usize = 4;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notes(sequencer: &mut Sequencer, ticks: usize) -> Vec<(usize, u8)> {
+        let mut notes = Vec::new();
+        let mut events = Vec::new();
+
+        for time in 0..ticks {
+            sequencer.tick(&mut events);
+
+            for event in events.drain(..) {
+                if let SeqEvent::Note { pitch, .. } = event {
+                    notes.push((time, pitch));
+                }
+            }
+        }
+
+        notes
+    }
+
+    #[test]
+    fn loops_forever() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        for pitch in &[0x90, 0x94] {
+            let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+            bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+                Command::Note { pitch: *pitch, velocity: 100, length: 10 },
+                Command::Delay(10),
+                Command::End,
+            ]);
+        }
+        bgm.set_loop(0, 10..20).unwrap();
+
+        let mut sequencer = Sequencer::new(bgm, 0);
+        assert_eq!(notes(&mut sequencer, 35), vec![(0, 0x90), (10, 0x94), (20, 0x94), (30, 0x94)]);
+        assert!(!sequencer.is_finished());
+
+        // The position jumps back to the loop start each time around.
+        assert_eq!(sequencer.position(), 15);
+        notes(&mut sequencer, 5);
+        assert_eq!(sequencer.position(), 20);
+        notes(&mut sequencer, 1);
+        assert_eq!(sequencer.position(), 11);
+    }
+
+    #[test]
+    fn seeks_into_loop() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        for pitch in &[0x90, 0x94] {
+            let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+            bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+                Command::Note { pitch: *pitch, velocity: 100, length: 10 },
+                Command::Delay(10),
+                Command::End,
+            ]);
+        }
+        bgm.set_loop(0, 10..20).unwrap();
+
+        let mut sequencer = Sequencer::new(bgm, 0);
+        notes(&mut sequencer, 3);
+
+        // Seeking goes back to the start first, and carries on around the loop from wherever it lands.
+        let mut events = Vec::new();
+        sequencer.seek(15, &mut events);
+        assert_eq!(events, vec![SeqEvent::SubsegmentStart, SeqEvent::SubsegmentStart]);
+        assert_eq!(sequencer.position(), 15);
+        assert_eq!(sequencer.subsegment_position(), 5);
+        assert_eq!(notes(&mut sequencer, 10), vec![(5, 0x94)]);
+
+        sequencer.seek(100, &mut events);
+        assert!(!sequencer.is_finished());
+    }
+
+    #[test]
+    fn runs_subroutines() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+        bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+            Command::Subroutine(CommandRange {
+                name: "riff".to_owned(),
+                start: "start".to_owned(),
+                end: "end".to_owned(),
+            }),
+            Command::Note { pitch: 0x98, velocity: 100, length: 5 },
+            Command::Delay(5),
+            Command::End,
+            Command::Marker("start".to_owned()),
+            Command::Note { pitch: 0x90, velocity: 100, length: 5 },
+            Command::Delay(5),
+            Command::Marker("end".to_owned()),
+        ]);
+
+        let mut sequencer = Sequencer::new(bgm, 0);
+        assert_eq!(notes(&mut sequencer, 20), vec![(0, 0x90), (5, 0x98)]);
+        assert!(sequencer.is_finished());
+    }
+}
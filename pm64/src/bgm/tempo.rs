@@ -0,0 +1,197 @@
+use super::*;
+
+/// Tempo, in beats per minute, until a song sets one.
+pub const DEFAULT_TEMPO: u16 = 120;
+
+/// A [Command::MasterTempo] or [Command::MasterTempoFade], at a time in ticks from the start of its segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TempoChange {
+    time: usize,
+    bpm: u16,
+    /// How many ticks the tempo takes to reach `bpm`; 0 to change straight away.
+    fade_time: usize,
+}
+
+impl Segment {
+    /// Converts `time`, in ticks from the start of this segment, to seconds. The tempo is followed as it changes,
+    /// including fades, starting from [DEFAULT_TEMPO].
+    ///
+    /// Like [Segment::len_time], each section is counted once, so loops aren't followed; a tempo change within a
+    /// loop carries on into what comes after it. Tempo changes within [subroutines](Command::Subroutine) are ignored.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// bgm.add_segment().unwrap();
+    /// let segment = bgm.segments[0].as_mut().unwrap();
+    /// let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+    /// bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = CommandSeq::from(vec![
+    ///     Command::MasterTempo(60),
+    ///     Command::Delay(TICKS_PER_BEAT * 4),
+    ///     Command::MasterTempo(120),
+    ///     Command::Delay(TICKS_PER_BEAT * 4),
+    ///     Command::End,
+    /// ]);
+    ///
+    /// let segment = bgm.segments[0].as_ref().unwrap();
+    /// assert_eq!(segment.ticks_to_secs(&bgm.track_lists, TICKS_PER_BEAT * 4), 4.0); // 4 beats at 60 BPM
+    /// assert_eq!(segment.len_secs(&bgm.track_lists), 4.0 + 2.0); // Then 4 beats at 120 BPM
+    /// ```
+    pub fn ticks_to_secs(&self, track_lists: &HashMap<TrackListId, TrackList>, time: usize) -> f64 {
+        let changes = self.tempo_changes(track_lists);
+        let mut changes = changes.iter().peekable();
+
+        let mut secs = 0.0;
+        let mut tick = 0;
+        let mut bpm = DEFAULT_TEMPO as f64;
+        let mut fade_step = 0.0;
+        let mut fade_ticks = 0;
+
+        while tick < time {
+            while let Some(change) = changes.next_if(|change| change.time <= tick) {
+                if change.fade_time == 0 {
+                    bpm = change.bpm as f64;
+                    fade_ticks = 0;
+                } else {
+                    fade_step = (change.bpm as f64 - bpm) / change.fade_time as f64;
+                    fade_ticks = change.fade_time;
+                }
+            }
+
+            if fade_ticks > 0 {
+                // The game changes the tempo a little every tick of a fade.
+                secs += secs_per_tick(bpm);
+                bpm += fade_step;
+                fade_ticks -= 1;
+                tick += 1;
+            } else {
+                let next = changes.peek().map_or(time, |change| change.time.min(time));
+                secs += (next - tick) as f64 * secs_per_tick(bpm);
+                tick = next;
+            }
+        }
+
+        secs
+    }
+
+    /// How long this segment takes to play once through, in seconds. If it loops, this is how long it takes to reach
+    /// the end of the loop the first time. See [Segment::ticks_to_secs].
+    pub fn len_secs(&self, track_lists: &HashMap<TrackListId, TrackList>) -> f64 {
+        self.ticks_to_secs(track_lists, self.len_time(track_lists))
+    }
+
+    /// Every tempo change in this segment, in time order. Changes at the same time are in track order, so the last
+    /// one takes effect.
+    fn tempo_changes(&self, track_lists: &HashMap<TrackListId, TrackList>) -> Vec<TempoChange> {
+        let mut changes = Vec::new();
+        let mut start = 0;
+
+        for subsegment in &self.subsegments {
+            let track_list = match subsegment {
+                Subsegment::Tracks { track_list, .. } => match track_lists.get(track_list) {
+                    Some(track_list) => track_list,
+                    None => continue,
+                },
+                Subsegment::Unknown { .. } => continue,
+            };
+
+            for track in &track_list.tracks {
+                for (time, command) in track.commands.iter_time() {
+                    let (bpm, fade_time) = match *command {
+                        Command::MasterTempo(bpm) => (bpm, 0),
+                        Command::MasterTempoFade { time, bpm } => (bpm, time as usize),
+                        _ => continue,
+                    };
+
+                    changes.push(TempoChange {
+                        time: start + time,
+                        bpm,
+                        fade_time,
+                    });
+                }
+            }
+
+            start += track_list.len_time();
+        }
+
+        // Stable, so changes at the same time stay in track order.
+        changes.sort_by_key(|change| change.time);
+        changes
+    }
+}
+
+fn secs_per_tick(bpm: f64) -> f64 {
+    60.0 / (bpm.max(1.0) * TICKS_PER_BEAT as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segment(sections: Vec<Vec<Command>>) -> Bgm {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+
+        let segment = bgm.segments[0].as_mut().unwrap();
+        for commands in sections {
+            let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+            bgm.track_lists.get_mut(&track_list).unwrap().tracks[0].commands = CommandSeq::from(commands);
+        }
+
+        bgm
+    }
+
+    #[test]
+    fn tempo_carries_across_sections() {
+        let bgm = segment(vec![
+            vec![Command::MasterTempo(60), Command::Delay(TICKS_PER_BEAT), Command::End],
+            vec![Command::Delay(TICKS_PER_BEAT), Command::End],
+            vec![Command::MasterTempo(240), Command::Delay(TICKS_PER_BEAT * 2), Command::End],
+        ]);
+        let segment = bgm.segments[0].as_ref().unwrap();
+
+        assert_eq!(segment.ticks_to_secs(&bgm.track_lists, 0), 0.0);
+        assert_eq!(segment.ticks_to_secs(&bgm.track_lists, TICKS_PER_BEAT * 2), 2.0);
+        assert_eq!(segment.len_secs(&bgm.track_lists), 2.5);
+    }
+
+    #[test]
+    fn default_tempo() {
+        let bgm = segment(vec![vec![Command::Delay(TICKS_PER_BEAT * 2), Command::End]]);
+        let segment = bgm.segments[0].as_ref().unwrap();
+
+        assert_eq!(segment.len_secs(&bgm.track_lists), 2.0 * 60.0 / DEFAULT_TEMPO as f64);
+    }
+
+    #[test]
+    fn fades() {
+        // Fading evenly from 60 to 120 BPM over 10 beats takes 10 ln(2) seconds.
+        let bgm = segment(vec![vec![
+            Command::MasterTempo(60),
+            Command::MasterTempoFade { time: 480, bpm: 120 },
+            Command::Delay(480),
+            Command::Delay(TICKS_PER_BEAT),
+            Command::End,
+        ]]);
+        let segment = bgm.segments[0].as_ref().unwrap();
+
+        let fade_secs = segment.ticks_to_secs(&bgm.track_lists, 480);
+        assert!((fade_secs - 10.0 * 2.0_f64.ln()).abs() < 0.01, "{}", fade_secs);
+
+        // Once the fade is over, the tempo stays where it ended up.
+        assert!((segment.len_secs(&bgm.track_lists) - fade_secs - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn loops_counted_once() {
+        let mut bgm = segment(vec![vec![
+            Command::MasterTempo(120),
+            Command::Delay(TICKS_PER_BEAT * 4),
+            Command::End,
+        ]]);
+        bgm.set_loop(0, TICKS_PER_BEAT..TICKS_PER_BEAT * 4).unwrap();
+        let segment = bgm.segments[0].as_ref().unwrap();
+
+        assert_eq!(segment.len_secs(&bgm.track_lists), 2.0);
+    }
+}
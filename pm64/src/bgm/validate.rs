@@ -0,0 +1,208 @@
+use std::fmt;
+
+use super::*;
+
+/// A structural problem with a [Bgm] that would cause [Bgm::encode] to fail or produce data the game can't play.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Every segment slot is empty, so there is nothing to play. There can't be more than [SEGMENTS_MAX] segments, as
+    /// that's how many slots there are.
+    NoSegments,
+
+    /// A segment has no [Subsegment::Tracks], so it plays nothing.
+    EmptySegment {
+        segment: usize,
+    },
+
+    /// A segment has more subsegments than [SUBSEGMENTS_MAX].
+    TooManySubsegments {
+        segment: usize,
+        subsegments: usize,
+    },
+
+    /// A [Subsegment::Tracks] refers to a [TrackListId] that isn't in [Bgm::track_lists].
+    DanglingTrackList {
+        segment: usize,
+        subsegment: usize,
+        track_list: TrackListId,
+    },
+
+    /// A [Command::TrackVoice] refers to a voice index that isn't in [Bgm::voices].
+    VoiceOutOfRange {
+        track_list: TrackListId,
+        track: usize,
+        voice: u8,
+    },
+
+    /// A track has more [commands](CommandSeq::command_count) than [TRACK_COMMANDS_MAX]. Some may be removed with
+    /// [CommandSeq::remove_redundant].
+    TrackTooLong {
+        track_list: TrackListId,
+        track: usize,
+        commands: usize,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::NoSegments => write!(f, "Song has no variations"),
+            ValidationError::EmptySegment { segment } => {
+                write!(f, "Variation {} has no sections with tracks", segment + 1)
+            }
+            ValidationError::TooManySubsegments { segment, subsegments } => write!(
+                f,
+                "Variation {} has {} sections, more than the limit of {}",
+                segment + 1,
+                subsegments,
+                SUBSEGMENTS_MAX
+            ),
+            ValidationError::DanglingTrackList { segment, subsegment, track_list } => write!(
+                f,
+                "Section {} of variation {} refers to missing track list {}",
+                subsegment + 1,
+                segment + 1,
+                track_list
+            ),
+            ValidationError::VoiceOutOfRange { track_list, track, voice } => write!(
+                f,
+                "Track {} of track list {} uses voice {}, which does not exist",
+                track,
+                track_list,
+                voice
+            ),
+            ValidationError::TrackTooLong { track_list, track, commands } => write!(
+                f,
+                "Track {} of track list {} has {} commands, more than the limit of {}",
+                track,
+                track_list,
+                commands,
+                TRACK_COMMANDS_MAX
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl Bgm {
+    /// Checks this BGM for structural problems that [Bgm::encode] does not catch. An empty list means the BGM is
+    /// safe to encode (although it may still be [too big](en::Error::TooBig)).
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let mut bgm = Bgm::new();
+    /// assert_eq!(bgm.validate(), vec![ValidationError::NoSegments]);
+    ///
+    /// bgm.add_segment().unwrap().1.subsegments.push(Subsegment::Tracks {
+    ///     flags: 0x10,
+    ///     track_list: 1,
+    /// });
+    /// assert_eq!(bgm.validate(), vec![ValidationError::DanglingTrackList {
+    ///     segment: 0,
+    ///     subsegment: 0,
+    ///     track_list: 1,
+    /// }]);
+    ///
+    /// bgm.add_track_list(TrackList::default());
+    /// assert!(bgm.validate().is_empty());
+    /// ```
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+
+        if self.segments.iter().all(Option::is_none) {
+            errors.push(ValidationError::NoSegments);
+        }
+
+        for (segment_idx, segment) in self.segments.iter().enumerate() {
+            let segment = match segment {
+                Some(segment) => segment,
+                None => continue,
+            };
+
+            if segment.subsegments.len() > SUBSEGMENTS_MAX {
+                errors.push(ValidationError::TooManySubsegments {
+                    segment: segment_idx,
+                    subsegments: segment.subsegments.len(),
+                });
+            }
+
+            let mut has_tracks = false;
+
+            for (subsegment_idx, subsegment) in segment.subsegments.iter().enumerate() {
+                if let Subsegment::Tracks { track_list, .. } = subsegment {
+                    has_tracks = true;
+
+                    if !self.track_lists.contains_key(track_list) {
+                        errors.push(ValidationError::DanglingTrackList {
+                            segment: segment_idx,
+                            subsegment: subsegment_idx,
+                            track_list: *track_list,
+                        });
+                    }
+                }
+            }
+
+            if !has_tracks {
+                errors.push(ValidationError::EmptySegment {
+                    segment: segment_idx,
+                });
+            }
+        }
+
+        // Sorted so the order of errors is stable between calls.
+        let mut track_list_ids: Vec<&TrackListId> = self.track_lists.keys().collect();
+        track_list_ids.sort();
+
+        for id in track_list_ids {
+            for (track_idx, track) in self.track_lists[id].tracks.iter().enumerate() {
+                let commands = track.commands.command_count();
+                if commands > TRACK_COMMANDS_MAX {
+                    errors.push(ValidationError::TrackTooLong {
+                        track_list: *id,
+                        track: track_idx,
+                        commands,
+                    });
+                }
+
+                for command in track.commands.iter() {
+                    if let Command::TrackVoice(voice) = command {
+                        if *voice as usize >= self.voices.len() {
+                            errors.push(ValidationError::VoiceOutOfRange {
+                                track_list: *id,
+                                track: track_idx,
+                                voice: *voice,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn too_many_subsegments() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+        let segment = bgm.segments[0].as_mut().unwrap();
+        segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+
+        // Sharing one track list keeps the song small, but there are still too many sections.
+        let repeat = segment.subsegments[0].clone();
+        segment.subsegments.resize(SUBSEGMENTS_MAX, repeat.clone());
+        assert!(bgm.validate().is_empty());
+
+        bgm.segments[0].as_mut().unwrap().subsegments.push(repeat);
+        assert_eq!(bgm.validate(), vec![ValidationError::TooManySubsegments {
+            segment: 0,
+            subsegments: SUBSEGMENTS_MAX + 1,
+        }]);
+    }
+}
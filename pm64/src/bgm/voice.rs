@@ -2,6 +2,8 @@ use std::collections::HashMap;
 
 use lazy_static::lazy_static;
 
+use super::*;
+
 lazy_static! {
     /// name -> (upper bank, patch)
     pub static ref INSTRUMENTS_BY_NAME: HashMap<&'static str, (u8, u8)> = {
@@ -77,3 +79,79 @@ lazy_static! {
         m
     };
 }
+
+/// Middle C, which [Voice::audition] plays around.
+const AUDITION_ROOT: u8 = 164;
+
+/// Every instrument we know the name of, as (name, (upper bank, patch)), ordered by bank and then patch so that the
+/// list reads the same every time it is shown.
+pub fn instruments() -> Vec<(&'static str, (u8, u8))> {
+    let mut instruments: Vec<_> = INSTRUMENTS_BY_NAME.iter().map(|(name, id)| (*name, *id)).collect();
+    instruments.sort_by_key(|(name, id)| (*id, *name));
+    instruments
+}
+
+impl Voice {
+    /// The upper nibble of [Voice::bank], which chooses the bank that [Voice::patch] is in.
+    pub fn bank_upper(&self) -> u8 {
+        self.bank >> 4
+    }
+
+    /// The name of this voice's instrument, if it is one in [INSTRUMENTS_BY_ID].
+    pub fn instrument_name(&self) -> Option<&'static str> {
+        INSTRUMENTS_BY_ID.get(&(self.bank_upper(), self.patch)).copied()
+    }
+
+    /// A short song that plays a few notes with this voice and nothing else, for hearing what it sounds like.
+    ///
+    /// ```
+    /// # use pm64::bgm::*;
+    /// let voice = Voice { bank: 0x30, patch: 0x02, volume: 100, pan: 64, ..Default::default() };
+    /// let bgm = voice.audition();
+    ///
+    /// assert_eq!(bgm.voices, vec![voice]);
+    /// assert!(bgm.validate().is_empty());
+    /// ```
+    pub fn audition(&self) -> Bgm {
+        let mut bgm = Bgm::new();
+        bgm.name = "Audition".to_owned();
+        bgm.voices.push(self.clone());
+
+        let (segment_idx, _) = bgm.add_segment().unwrap();
+        let segment = bgm.segments[segment_idx].as_mut().unwrap();
+        let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+        let tracks = &mut bgm.track_lists.get_mut(&track_list).unwrap().tracks;
+
+        // (semitones above the root, beats): an arpeggio up an octave
+        let notes = [(0, 1), (4, 1), (7, 1), (12, 3)];
+
+        let mut commands = vec![
+            Command::SubTrackVolume(100),
+            Command::SubTrackPan(64),
+            Command::TrackVoice(0),
+        ];
+        for (interval, beats) in notes {
+            let length = TICKS_PER_BEAT * beats;
+            commands.push(Command::Note {
+                pitch: AUDITION_ROOT + interval,
+                velocity: 100,
+                length: length as u16,
+            });
+            commands.push(Command::Delay(length));
+        }
+        commands.push(Command::End);
+
+        let len = TICKS_PER_BEAT * notes.iter().map(|(_, beats)| beats).sum::<usize>();
+        tracks[0].commands = CommandSeq::from(vec![
+            Command::MasterTempo(DEFAULT_TEMPO),
+            Command::MasterVolume(100),
+            Command::MasterEffect(0, 1),
+            Command::Delay(len),
+            Command::End,
+        ]);
+        tracks[1].flags = track_flags::POLYPHONY_1;
+        tracks[1].commands = CommandSeq::from(commands);
+
+        bgm
+    }
+}
@@ -0,0 +1,394 @@
+//! gzip decompression (RFC 1951 and RFC 1952). Only decompression is supported, as we never need to write gzip files.
+
+use std::fmt;
+
+use crate::rom::crc32;
+
+/// The first two bytes of every gzip file.
+pub const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// The most bytes [decompress] will write. Far more than any song needs, but small enough that a few bytes crafted to
+/// decompress to gigabytes give an error rather than using up all the memory.
+pub const DECOMPRESSED_SIZE_MAX: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The header is invalid, or uses a compression method other than deflate.
+    InvalidHeader,
+    /// The compressed data is malformed.
+    InvalidData,
+    UnexpectedEof,
+    /// The decompressed data doesn't match the checksum or length stored after it.
+    ChecksumMismatch,
+    /// The data decompresses to more than [DECOMPRESSED_SIZE_MAX] bytes.
+    TooLarge,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidHeader => write!(f, "Invalid gzip header"),
+            Error::InvalidData => write!(f, "Invalid compressed data"),
+            Error::UnexpectedEof => write!(f, "Unexpected end-of-file in compressed data"),
+            Error::ChecksumMismatch => write!(f, "Compressed data is corrupt (checksum mismatch)"),
+            Error::TooLarge => write!(f, "Compressed data is too large when decompressed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Decompresses the first member of a gzip file, of up to [DECOMPRESSED_SIZE_MAX] bytes.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    decompress_limited(data, DECOMPRESSED_SIZE_MAX)
+}
+
+fn decompress_limited(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if !is_gzip(data) || data.get(2) != Some(&8) {
+        return Err(Error::InvalidHeader);
+    }
+
+    let flags = *data.get(3).ok_or(Error::InvalidHeader)?;
+    let mut pos = 10; // Skip mtime, extra flags, and OS
+
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or(Error::UnexpectedEof)?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            let len = data.get(pos..).and_then(|s| s.iter().position(|b| *b == 0)).ok_or(Error::UnexpectedEof)?;
+            pos += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let mut bits = BitReader::new(data.get(pos..).ok_or(Error::UnexpectedEof)?);
+    let decompressed = inflate(&mut bits, limit)?;
+
+    let pos = pos + bits.byte_pos();
+    let trailer = data.get(pos..pos + 8).ok_or(Error::UnexpectedEof)?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+    if crc != crc32(&decompressed) || size != decompressed.len() as u32 {
+        return Err(Error::ChecksumMismatch);
+    }
+
+    Ok(decompressed)
+}
+
+/// Reads bits least-significant first, as deflate requires.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(Error::UnexpectedEof)?;
+            self.pos += 1;
+            self.buf |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Discards any bits left in the current byte.
+    fn align(&mut self) {
+        let partial = self.count % 8;
+        self.buf >>= partial;
+        self.count -= partial;
+    }
+
+    /// Skips to a byte position, discarding any buffered bits.
+    fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+        self.buf = 0;
+        self.count = 0;
+    }
+
+    /// The position of the next unread byte.
+    fn byte_pos(&self) -> usize {
+        self.pos - (self.count / 8) as usize
+    }
+}
+
+/// Canonical Huffman code, stored as the number of codes of each length and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+
+        let mut offsets = [0; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        let mut code = 0; // Code read so far
+        let mut first = 0; // First code of the current length
+        let mut index = 0; // Index of the first code of the current length in `symbols`
+
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::InvalidData)
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// Order in which code length code lengths are stored in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompresses deflate data, failing with [Error::TooLarge] rather than writing more than `limit` bytes.
+fn inflate(bits: &mut BitReader, limit: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    loop {
+        let is_last = bits.bits(1)? == 1;
+
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let start = bits.byte_pos();
+                let header = bits.data.get(start..start + 4).ok_or(Error::UnexpectedEof)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(Error::InvalidData);
+                }
+
+                let stored = bits.data.get(start + 4..start + 4 + len as usize).ok_or(Error::UnexpectedEof)?;
+                if out.len() + stored.len() > limit {
+                    return Err(Error::TooLarge);
+                }
+                out.extend_from_slice(stored);
+                bits.seek(start + 4 + len as usize);
+            }
+            1 => {
+                let mut lengths = [0; 288 + 30];
+                lengths[0..144].iter_mut().for_each(|l| *l = 8);
+                lengths[144..256].iter_mut().for_each(|l| *l = 9);
+                lengths[256..280].iter_mut().for_each(|l| *l = 7);
+                lengths[280..288].iter_mut().for_each(|l| *l = 8);
+                lengths[288..].iter_mut().for_each(|l| *l = 5);
+
+                let literals = Huffman::new(&lengths[..288]);
+                let distances = Huffman::new(&lengths[288..]);
+                inflate_block(bits, &mut out, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(bits)?;
+                inflate_block(bits, &mut out, limit, &literals, &distances)?;
+            }
+            _ => return Err(Error::InvalidData),
+        }
+
+        if is_last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let num_literals = bits.bits(5)? as usize + 257;
+    let num_distances = bits.bits(5)? as usize + 1;
+    let num_code_lengths = bits.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for i in CODE_LENGTH_ORDER.iter().take(num_code_lengths) {
+        code_lengths[*i] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let (length, repeat) = match code_lengths.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or(Error::InvalidData)?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            18 => (0, 11 + bits.bits(7)?),
+            _ => return Err(Error::InvalidData),
+        };
+
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+
+    if lengths.len() != num_literals + num_distances {
+        return Err(Error::InvalidData);
+    }
+
+    Ok((Huffman::new(&lengths[..num_literals]), Huffman::new(&lengths[num_literals..])))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+
+        match symbol {
+            0..=255 if out.len() >= limit => return Err(Error::TooLarge),
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(Error::InvalidData);
+                }
+                let length = LENGTH_BASE[symbol] as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = distances.decode(bits)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err(Error::InvalidData);
+                }
+                let distance = DIST_BASE[symbol] as usize + bits.bits(DIST_EXTRA[symbol] as u32)? as usize;
+
+                if distance > out.len() {
+                    return Err(Error::InvalidData);
+                }
+                if out.len() + length > limit {
+                    return Err(Error::TooLarge);
+                }
+
+                // Byte-by-byte, because the copy may overlap the bytes being written.
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        // gzip.compress(b"BGM BGM BGM hello hello hello", mtime=0), which uses a fixed Huffman block
+        let compressed = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x73, 0x72, 0xF7, 0x55, 0x70, 0x82, 0xE2, 0x8C,
+            0xD4, 0x9C, 0x9C, 0x7C, 0x64, 0x12, 0x00, 0x67, 0x44, 0x03, 0xD7, 0x1D, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(decompress(&compressed).unwrap(), b"BGM BGM BGM hello hello hello");
+
+        let mut corrupt = compressed;
+        corrupt[compressed.len() - 8] ^= 1;
+        assert_eq!(decompress(&corrupt), Err(Error::ChecksumMismatch));
+
+        // The limit is checked for both literals and copies
+        assert_eq!(decompress_limited(&compressed, 2), Err(Error::TooLarge));
+        assert_eq!(decompress_limited(&compressed, 28), Err(Error::TooLarge));
+
+        assert_eq!(decompress(b"BGM "), Err(Error::InvalidHeader));
+    }
+
+    #[test]
+    fn stored_block() {
+        // gzip.compress(b"BGM hello", compresslevel=0, mtime=0)
+        let compressed = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x09, 0x00, 0xF6, 0xFF, 0x42, 0x47, 0x4D,
+            0x20, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0xCF, 0xB4, 0x77, 0xE7, 0x09, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(decompress(&compressed).unwrap(), b"BGM hello");
+
+        // LEN and NLEN don't match
+        let mut corrupt = compressed;
+        corrupt[13] ^= 1;
+        assert_eq!(decompress(&corrupt), Err(Error::InvalidData));
+
+        assert_eq!(decompress_limited(&compressed, 9).unwrap(), b"BGM hello");
+        assert_eq!(decompress_limited(&compressed, 8), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn dynamic_block() {
+        // gzip.compress(b"G B MB GMGGGMBB  G  BBG", compresslevel=9, mtime=0), the shortest string of these letters
+        // that zlib gives a dynamic Huffman block
+        let compressed = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x05, 0xC1, 0x21, 0x01, 0x00, 0x00, 0x00, 0x83,
+            0xB0, 0x2A, 0x64, 0xC1, 0xA0, 0xDE, 0xBF, 0xCE, 0xB7, 0x90, 0x49, 0xAB, 0xA6, 0x10, 0x68, 0x07, 0x19, 0x04,
+            0x34, 0x46, 0x17, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(compressed[10] >> 1 & 3, 2); // Block type
+        assert_eq!(decompress(&compressed).unwrap(), b"G B MB GMGGGMBB  G  BBG");
+        assert_eq!(decompress_limited(&compressed, 22), Err(Error::TooLarge));
+    }
+}
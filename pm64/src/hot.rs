@@ -1,5 +1,6 @@
 // TODO: migrate this to its own module probably, shouldn't live in pm64
 
+use std::fmt;
 use std::io::prelude::*;
 use std::io::{Cursor, ErrorKind};
 use std::net::{TcpListener, TcpStream};
@@ -20,6 +21,13 @@ impl<'a> Packet<'a> {
         }
     }
 
+    const fn hot_live(data: &'a [u8]) -> Packet<'a> {
+        Packet {
+            message: "HOT_LIVE",
+            data,
+        }
+    }
+
     fn send(&self, stream: &mut TcpStream) -> Result<(), std::io::Error>  {
         // We have to write to an in-memory buffer because the client expects the head of the packet to be
         // send in a single TCP packet, not spread across multiple (which can happen if we don't write in a
@@ -46,22 +54,194 @@ const PONG: Packet = Packet {
     data: &[1, 0, 0],
 };
 
+/// Longest description we accept from a client's handshake, so a misbehaving one can't make us allocate a lot.
+const MAX_HANDSHAKE_DATA: u32 = 0x1000;
+
+/// Something for the server to send to the connected emulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Encoded BGM to play from the start.
+    Bgm(Vec<u8>),
+
+    /// A change to the song that is playing. Only sent to emulators that [support it](ConnectionInfo::supports_live).
+    Live(LiveChange),
+}
+
+/// A change to one parameter of the song that is playing, applied without reloading it. Sent as a `HOT_LIVE`
+/// packet, whose data is 4 bytes: the parameter (the number in brackets below), the track index (0 for parameters of
+/// the whole song), then the new value as a big-endian u16.
+///
+/// Only these parameters can be changed live. Anything else, like notes, means reloading the song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveChange {
+    /// (0) Tempo in beats per minute.
+    Tempo(u16),
+
+    /// (1) Volume of the whole song.
+    MasterVolume(u8),
+
+    /// (2) Volume of one track.
+    TrackVolume { track: u8, volume: u8 },
+
+    /// (3) Pan of one track. Left = 0, middle = 64, right = 127.
+    TrackPan { track: u8, pan: u8 },
+}
+
+impl LiveChange {
+    /// Which parameter of which track this changes.
+    fn param(&self) -> (u8, u8) {
+        match self {
+            LiveChange::Tempo(_) => (0, 0),
+            LiveChange::MasterVolume(_) => (1, 0),
+            LiveChange::TrackVolume { track, .. } => (2, *track),
+            LiveChange::TrackPan { track, .. } => (3, *track),
+        }
+    }
+
+    fn value(&self) -> u16 {
+        match self {
+            LiveChange::Tempo(bpm) => *bpm,
+            LiveChange::MasterVolume(volume) => *volume as u16,
+            LiveChange::TrackVolume { volume, .. } => *volume as u16,
+            LiveChange::TrackPan { pan, .. } => *pan as u16,
+        }
+    }
+
+    /// Whether `self` and `other` change the same parameter, so that only the later one needs sending.
+    ///
+    /// ```
+    /// # use pm64::hot::LiveChange;
+    /// assert!(LiveChange::Tempo(120).replaces(&LiveChange::Tempo(90)));
+    /// assert!(!LiveChange::TrackPan { track: 1, pan: 0 }.replaces(&LiveChange::TrackPan { track: 2, pan: 0 }));
+    /// ```
+    pub fn replaces(&self, other: &LiveChange) -> bool {
+        self.param() == other.param()
+    }
+
+    fn to_bytes(self) -> [u8; 4] {
+        let (param, track) = self.param();
+        let [value_hi, value_lo] = self.value().to_be_bytes();
+        [param, track, value_hi, value_lo]
+    }
+}
+
+/// What the server is doing. Sent to the main thread whenever it changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Waiting for an emulator to connect. `reason` says why the last connection failed or ended, if one did.
+    Listening { reason: Option<String> },
+
+    Connected(ConnectionInfo),
+
+    /// The server couldn't start, e.g. because another program is using its port.
+    Unavailable(String),
+}
+
+/// An emulator connected to the server, as described by its hot-reload script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Name of the emulator, e.g. "Project64".
+    pub emulator: Option<String>,
+
+    /// Game code from the ROM header, e.g. "NMQE" for Paper Mario (US).
+    pub game_code: Option<String>,
+
+    /// Optional parts of the protocol the hot-reload script understands, e.g. "live".
+    pub features: Vec<String>,
+}
+
+impl ConnectionInfo {
+    /// Reads the data a client may send with its handshake PING: lines of `key=value`, where the keys are
+    /// `emulator`, `game` and `features` (a comma-separated list). Older clients send nothing, and unknown keys are
+    /// ignored.
+    ///
+    /// ```
+    /// # use pm64::hot::ConnectionInfo;
+    /// let info = ConnectionInfo::from_handshake(b"emulator=Project64\ngame=NMQE\nfeatures=live\n");
+    /// assert_eq!(info.to_string(), "Project64 (US)");
+    /// assert!(info.supports_live());
+    ///
+    /// assert_eq!(ConnectionInfo::from_handshake(b"").to_string(), "an unknown emulator");
+    /// ```
+    pub fn from_handshake(data: &[u8]) -> Self {
+        let mut info = ConnectionInfo::default();
+
+        for line in String::from_utf8_lossy(data).lines() {
+            let mut parts = line.splitn(2, '=').map(str::trim);
+
+            match (parts.next(), parts.next()) {
+                (Some("emulator"), Some(name)) if !name.is_empty() => info.emulator = Some(name.to_owned()),
+                (Some("game"), Some(code)) if !code.is_empty() => info.game_code = Some(code.to_owned()),
+                (Some("features"), Some(features)) => {
+                    info.features = features
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|feature| !feature.is_empty())
+                        .map(str::to_owned)
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+
+        info
+    }
+
+    /// The region of the game, if it is a version of Paper Mario we know.
+    pub fn region(&self) -> Option<&'static str> {
+        match self.game_code.as_deref()? {
+            "NMQE" => Some("US"),
+            "NMQJ" => Some("JP"),
+            "NMQP" => Some("EU"),
+            _ => None,
+        }
+    }
+
+    /// Whether the emulator can take [LiveChange]s.
+    pub fn supports_live(&self) -> bool {
+        self.features.iter().any(|feature| feature == "live")
+    }
+}
+
+impl fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.emulator {
+            Some(emulator) => write!(f, "{}", emulator)?,
+            None => write!(f, "an unknown emulator")?,
+        }
+
+        match (self.region(), &self.game_code) {
+            (Some(region), _) => write!(f, " ({})", region),
+            (None, Some(game_code)) => write!(f, " (unknown game {})", game_code),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
 /// Server implementation of https://github.com/pmret/hot-reload/blob/main/protocol.md. Allows sending
 /// BGM data to an emulator for it to be played back.
 ///
-/// - `state_sender`: A sender of `true` when a client connects or `false` when a client disconnects.
-/// - `bgm_receiver`: A receiver of encoded BGM bytes to upload to an emulator.
+/// - `state_sender`: A sender of the server's [Status] whenever it changes, e.g. when a client connects.
+/// - `message_receiver`: A receiver of [Message]s to send to an emulator.
 ///
-/// This function **blocks the thread** until the `bgm_receiver` channel is closed. Run it in a thread.
-pub fn run(state_sender: Sender<bool>, bgm_receiver: Receiver<Vec<u8>>) -> Result<(), std::io::Error> {
-    let listener = TcpListener::bind("127.0.0.1:65432")?;
+/// This function **blocks the thread** until the `message_receiver` channel is closed. Run it in a thread.
+pub fn run(state_sender: Sender<Status>, message_receiver: Receiver<Message>) -> Result<(), std::io::Error> {
+    let listener = match TcpListener::bind("127.0.0.1:65432") {
+        Ok(listener) => listener,
+        Err(error) => {
+            let _ = state_sender.send(Status::Unavailable(format!("unable to listen on port 65432: {}", error)));
+            return Err(error);
+        }
+    };
+
+    let mut reason = None;
 
     // Client-handling loop. We only need to support one client at once.
     'listen: loop {
         log::info!("listening for new client");
 
         // Tell the main thread that there are no connected clients.
-        let _ = state_sender.send(false);
+        let _ = state_sender.send(Status::Listening { reason: reason.take() });
 
         // Block until we receieve a new connection.
         let (mut stream, _) = listener.accept()?;
@@ -69,30 +249,32 @@ pub fn run(state_sender: Sender<bool>, bgm_receiver: Receiver<Vec<u8>>) -> Resul
         log::info!("client connected");
 
         // Send handshake PING.
-        if let Err(_) = PING.send(&mut stream) {
+        if PING.send(&mut stream).is_err() {
+            reason = Some("the emulator closed the connection".to_owned());
             continue;
         }
 
-        // Respond to handshake PING sent by client.
-        loop {
-            match stream.read_cstring(16) {
-                Ok(s) if s == "PING" => {
-                    if let Err(_) = PONG.send(&mut stream) {
-                        continue 'listen;
-                    }
-
-                    break;
-                }
-                _ => {
-                    continue 'listen;
-                }
+        // Respond to handshake PING sent by client, which may describe the emulator.
+        let info = match read_handshake(&mut stream) {
+            Ok(info) => info,
+            Err(error) => {
+                reason = Some(format!("handshake failed: {}", error));
+                continue 'listen;
             }
+        };
+
+        if PONG.send(&mut stream).is_err() {
+            reason = Some("the emulator closed the connection".to_owned());
+            continue 'listen;
         }
 
         log::trace!("handshake ok");
+        log::info!("connected to {}", info);
 
         // Tell the main thread that a connection has been made.
-        let _ = state_sender.send(true);
+        let supports_live = info.supports_live();
+        let _ = state_sender.send(Status::Connected(info));
+        reason = Some("the emulator disconnected".to_owned());
 
         stream.set_nonblocking(true).unwrap();
 
@@ -106,19 +288,28 @@ pub fn run(state_sender: Sender<bool>, bgm_receiver: Receiver<Vec<u8>>) -> Resul
                 Err(_) => break,
             }
 
-            // Wait for some BGM data on the channel, and send it.
-            match bgm_receiver.recv_timeout(std::time::Duration::from_millis(500)) {
-                Ok(bgm_data) => {
+            // Wait for a message on the channel, and send it.
+            let result = match message_receiver.recv_timeout(std::time::Duration::from_millis(500)) {
+                Ok(Message::Bgm(bgm_data)) => {
                     log::info!("sending BGM to client");
-
-                    match Packet::hot_bgm(&bgm_data).send(&mut stream) {
-                        Ok(_) => (),
-                        Err(error) if error.kind() == ErrorKind::Interrupted => (),
-                        Err(_) => break,
-                    }
-                },
+                    Packet::hot_bgm(&bgm_data).send(&mut stream)
+                }
+                Ok(Message::Live(change)) if supports_live => {
+                    log::debug!("sending live change to client: {:?}", change);
+                    Packet::hot_live(&change.to_bytes()).send(&mut stream)
+                }
+                Ok(Message::Live(change)) => {
+                    log::warn!("client does not support live changes, dropping {:?}", change);
+                    Ok(())
+                }
                 Err(RecvTimeoutError::Disconnected) => break 'listen, // Channel was closed, so we'll return
-                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Timeout) => Ok(()),
+            };
+
+            match result {
+                Ok(_) => (),
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(_) => break,
             }
 
             // Don't eat the CPU!!
@@ -126,7 +317,28 @@ pub fn run(state_sender: Sender<bool>, bgm_receiver: Receiver<Vec<u8>>) -> Resul
         }
     }
 
-    let _ = state_sender.send(false);
+    let _ = state_sender.send(Status::Listening { reason: None });
 
     Ok(())
 }
+
+/// Reads the client's handshake PING, returning what it says about the emulator.
+fn read_handshake(stream: &mut TcpStream) -> Result<ConnectionInfo, std::io::Error> {
+    let message = stream.read_cstring(16)?;
+    if message != "PING" {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("expected PING, got {:?}", message),
+        ));
+    }
+
+    let len = stream.read_u32_be()?;
+    if len > MAX_HANDSHAKE_DATA {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "PING data is too long"));
+    }
+
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data)?;
+
+    Ok(ConnectionInfo::from_handshake(&data))
+}
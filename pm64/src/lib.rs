@@ -1,4 +1,39 @@
+//! Reading and writing the music formats of Paper Mario (N64), without any of the Mamar editor.
+//!
+//! - [bgm] is a single song: [Bgm::from_bytes] decodes one and [Bgm::as_bytes] encodes it again. With the `midly`
+//!   feature, songs can also be converted to and from MIDI. With the `serde` feature, songs can be serialized, e.g. to
+//!   RON; track lists played by more than one section are kept once in [Bgm::track_lists] and referred to by id.
+//! - [sbn] is the archive in the ROM that holds every song, and [rom] patches songs into a ROM directly.
+//! - [bank] lists the instruments in the ROM's instrument banks.
+//! - [hot] sends songs to an emulator as they are edited.
+//!
+//! Decoding a song, editing it, and encoding it again:
+//!
+//! ```
+//! use pm64::bgm::*;
+//!
+//! # let mut song = Bgm::new();
+//! # song.add_segment().unwrap();
+//! # let bytes = song.as_bytes().unwrap();
+//! // `bytes` would usually be read from a .bgm file, or from a song in the ROM's Sbn.
+//! let mut bgm = Bgm::from_bytes(&bytes).unwrap();
+//!
+//! bgm.name = "My song".to_owned();
+//! for track_list in bgm.track_lists.values_mut() {
+//!     track_list.transpose(2, OutOfRange::Clamp);
+//! }
+//!
+//! let bytes = bgm.as_bytes().unwrap();
+//! assert_eq!(&bytes[..4], MAGIC.as_bytes());
+//! ```
+
 pub mod bgm;
 pub mod sbn;
 pub mod hot;
+pub mod rom;
+pub mod bank;
+pub mod gzip;
 mod rw;
+
+pub use bgm::Bgm;
+pub use sbn::Sbn;
@@ -0,0 +1,250 @@
+//! Paper Mario ROM images.
+
+use std::fmt;
+use std::io::Cursor;
+
+use crate::bgm::{self, Bgm};
+use crate::rw::*;
+use crate::sbn::{self, Sbn, SBN_START};
+
+/// First word of a big-endian (.z64) N64 ROM.
+const Z64_MAGIC: u32 = 0x80371240;
+
+/// Region of the ROM covered by the checksum stored in its header.
+const CHECKSUM_START: usize = 0x1000;
+const CHECKSUM_LENGTH: usize = 0x100000;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not a big-endian (.z64) N64 ROM.
+    NotARom,
+    /// There is no SBN at [SBN_START], so this is probably not a Paper Mario (U) ROM.
+    SbnNotFound,
+    InvalidSongIndex(usize),
+    /// The patched SBN is larger than the space the original one takes up in the ROM.
+    SbnTooBig { size: usize, max_size: usize },
+    /// The boot code is not one we know how to calculate the checksum for.
+    UnknownCic,
+    Sbn(sbn::de::Error),
+    SbnEncode(sbn::en::Error),
+    Bgm(bgm::en::Error),
+}
+
+impl From<sbn::de::Error> for Error {
+    fn from(error: sbn::de::Error) -> Self {
+        Self::Sbn(error)
+    }
+}
+
+impl From<sbn::en::Error> for Error {
+    fn from(error: sbn::en::Error) -> Self {
+        Self::SbnEncode(error)
+    }
+}
+
+impl From<bgm::en::Error> for Error {
+    fn from(error: bgm::en::Error) -> Self {
+        Self::Bgm(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotARom => write!(f, "Not an N64 ROM (only big-endian .z64 ROMs are supported)"),
+            Error::SbnNotFound => write!(f, "Unable to find the soundbank in this ROM. Is it Paper Mario (U)?"),
+            Error::InvalidSongIndex(index) => write!(f, "There is no song {:#X} in this ROM", index),
+            Error::SbnTooBig { size, max_size } => write!(
+                f,
+                "Patched soundbank is too large ({:#X} bytes, but only {:#X} are available)",
+                size, max_size
+            ),
+            Error::UnknownCic => write!(f, "Unknown boot code; unable to update the ROM checksum"),
+            Error::Sbn(source) => write!(f, "{}", source),
+            Error::SbnEncode(source) => write!(f, "{}", source),
+            Error::Bgm(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Sbn(source) => Some(source),
+            Error::SbnEncode(source) => Some(source),
+            Error::Bgm(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces the BGM used by song `song_index` with `bgm`, then updates the ROM checksum so the game still boots.
+pub fn patch_bgm(rom: &mut [u8], song_index: usize, bgm: &Bgm) -> Result<(), Error> {
+    let sbn_range = find_sbn(rom)?;
+    let mut sbn = Sbn::from_bytes(&rom[sbn_range.clone()])?;
+
+    let file_index = sbn.songs
+        .get(song_index)
+        .ok_or(Error::InvalidSongIndex(song_index))?
+        .bgm_file as usize;
+    let file = sbn.files.get_mut(file_index).ok_or(Error::InvalidSongIndex(song_index))?;
+    file.data = bgm.as_bytes()?;
+
+    let encoded = sbn.as_bytes()?;
+    if encoded.len() > sbn_range.len() {
+        return Err(Error::SbnTooBig {
+            size: encoded.len(),
+            max_size: sbn_range.len(),
+        });
+    }
+
+    // Check we can update the checksum before modifying anything.
+    let cic = Cic::detect(rom)?;
+
+    let region = &mut rom[sbn_range];
+    region[..encoded.len()].copy_from_slice(&encoded);
+    for byte in &mut region[encoded.len()..] {
+        *byte = 0;
+    }
+
+    cic.update_checksum(rom);
+    Ok(())
+}
+
+/// Recalculates the checksum in the ROM header. Required after any change to the first 1 MiB of game data, or the
+/// boot code will refuse to start the game.
+pub fn update_checksum(rom: &mut [u8]) -> Result<(), Error> {
+    Cic::detect(rom)?.update_checksum(rom);
+    Ok(())
+}
+
+/// Returns the range of bytes in the ROM that the SBN occupies.
+pub(crate) fn find_sbn(rom: &[u8]) -> Result<std::ops::Range<usize>, Error> {
+    let mut cursor = Cursor::new(rom);
+
+    if rom.len() < CHECKSUM_START + CHECKSUM_LENGTH || cursor.read_u32_be().ok() != Some(Z64_MAGIC) {
+        return Err(Error::NotARom);
+    }
+
+    let start = SBN_START as usize;
+    let header = rom.get(start..start + 8).ok_or(Error::SbnNotFound)?;
+    if &header[0..4] != sbn::MAGIC.as_bytes() {
+        return Err(Error::SbnNotFound);
+    }
+
+    let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let end = start + ((size + 15) & !15); // Padded to 16 bytes
+    if size == 0 || end > rom.len() {
+        return Err(Error::SbnNotFound);
+    }
+
+    Ok(start..end)
+}
+
+/// The N64 boot chip variant, which determines how the ROM checksum is calculated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cic {
+    Cic6101,
+    Cic6102,
+    Cic6103,
+    Cic6105,
+    Cic6106,
+}
+
+impl Cic {
+    /// Identifies the CIC by the CRC32 of the boot code.
+    fn detect(rom: &[u8]) -> Result<Self, Error> {
+        let boot_code = rom.get(0x40..CHECKSUM_START).ok_or(Error::NotARom)?;
+
+        match crc32(boot_code) {
+            0x6170A4A1 => Ok(Cic::Cic6101),
+            0x90BB6CB5 => Ok(Cic::Cic6102),
+            0x0B050EE0 => Ok(Cic::Cic6103),
+            0x98BC2C86 => Ok(Cic::Cic6105),
+            0xACC8580A => Ok(Cic::Cic6106),
+            _ => Err(Error::UnknownCic),
+        }
+    }
+
+    fn seed(self) -> u32 {
+        match self {
+            Cic::Cic6101 | Cic::Cic6102 => 0xF8CA4DDC,
+            Cic::Cic6103 => 0xA3886759,
+            Cic::Cic6105 => 0xDF26F436,
+            Cic::Cic6106 => 0x1FEA617A,
+        }
+    }
+
+    fn update_checksum(self, rom: &mut [u8]) {
+        let word = |i: usize| u32::from_be_bytes([rom[i], rom[i + 1], rom[i + 2], rom[i + 3]]);
+
+        let seed = self.seed();
+        let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+
+        for i in (CHECKSUM_START..CHECKSUM_START + CHECKSUM_LENGTH).step_by(4) {
+            let d = word(i);
+
+            if t6.wrapping_add(d) < t6 {
+                t4 = t4.wrapping_add(1);
+            }
+            t6 = t6.wrapping_add(d);
+            t3 ^= d;
+
+            let r = d.rotate_left(d & 0x1F);
+            t5 = t5.wrapping_add(r);
+
+            if t2 > d {
+                t2 ^= r;
+            } else {
+                t2 ^= t6 ^ d;
+            }
+
+            if self == Cic::Cic6105 {
+                t1 = t1.wrapping_add(word(0x40 + 0x0710 + (i & 0xFF)) ^ d);
+            } else {
+                t1 = t1.wrapping_add(t5 ^ d);
+            }
+        }
+
+        let (crc1, crc2) = match self {
+            Cic::Cic6103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+            Cic::Cic6106 => (t6.wrapping_mul(t4).wrapping_add(t3), t5.wrapping_mul(t2).wrapping_add(t1)),
+            _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+        };
+
+        rom[0x10..0x14].copy_from_slice(&crc1.to_be_bytes());
+        rom[0x14..0x18].copy_from_slice(&crc2.to_be_bytes());
+    }
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_reject_non_rom() {
+        let mut rom = vec![0; CHECKSUM_START + CHECKSUM_LENGTH];
+        assert!(matches!(patch_bgm(&mut rom, 0, &Bgm::new()), Err(Error::NotARom)));
+
+        rom[0..4].copy_from_slice(&Z64_MAGIC.to_be_bytes());
+        assert!(matches!(patch_bgm(&mut rom, 0, &Bgm::new()), Err(Error::SbnNotFound)));
+    }
+}
@@ -10,6 +10,10 @@ use super::*;
 #[derive(Debug)]
 pub enum Error {
     InvalidMagic,
+
+    /// Something at `offset` claims to be `size` bytes long, which runs past the end of the input.
+    OutOfBounds { offset: u64, size: u64 },
+
     Io(io::Error),
 }
 
@@ -25,6 +29,11 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::InvalidMagic => write!(f, "Missing 'SBN' signature at start"),
+            Error::OutOfBounds { offset, size } => write!(
+                f,
+                "Data at {:#X} says it is {:#X} B long, which is past the end of the file",
+                offset, size
+            ),
             Error::Io(source) => write!(f, "{}", source),
         }
     }
@@ -44,17 +53,42 @@ impl Sbn {
         Self::decode(&mut std::io::Cursor::new(f))
     }
 
+    /// Like [Sbn::from_bytes], but calls `progress(done, total)` as decoding goes on. See [Sbn::decode_with_progress].
+    pub fn from_bytes_with_progress(f: &[u8], progress: impl FnMut(usize, usize)) -> Result<Self> {
+        Self::decode_with_progress(&mut std::io::Cursor::new(f), progress)
+    }
+
     pub fn decode<R: Read + Seek>(f: &mut R) -> Result<Self> {
+        Self::decode_with_progress(f, |_, _| {})
+    }
+
+    /// Decodes an SBN, calling `progress(done, total)` after each file is read and once more when the song list has
+    /// been read, so `done == total` means decoding is complete. Useful for showing progress when decoding a whole
+    /// soundbank.
+    ///
+    /// ```
+    /// # use pm64::sbn::*;
+    /// let mut sbn = Sbn::default();
+    /// sbn.init = vec![0; INIT_SONGS_OFFSET + 0x10];
+    /// sbn.files.push(File { name: "ABCD".to_owned(), data: b"BGM \0\0\0\x10ABCD\0\0\0\0".to_vec(), format: 0x10 });
+    /// sbn.files.push(sbn.files[0].clone());
+    ///
+    /// let mut calls = Vec::new();
+    /// Sbn::from_bytes_with_progress(&sbn.as_bytes().unwrap(), |done, total| calls.push((done, total))).unwrap();
+    /// assert_eq!(calls, vec![(1, 3), (2, 3), (3, 3)]);
+    /// ```
+    pub fn decode_with_progress<R: Read + Seek>(f: &mut R, mut progress: impl FnMut(usize, usize)) -> Result<Self> {
         if f.read_cstring(4)? != MAGIC {
             return Err(Error::InvalidMagic);
         }
 
         debug_assert!(f.pos()? == 0x04);
         let internal_size = f.read_u32_be()?;
-        let true_size = f.seek(SeekFrom::End(0))? as u32;
+        let input_len = f.seek(SeekFrom::End(0))?;
+        let true_size = input_len as u32;
         if internal_size == true_size {
             // Ok
-        } else if align(internal_size, 16) == true_size {
+        } else if internal_size < true_size && align(internal_size, 16) == true_size {
             // Make sure the trailing bytes are all zero
             f.seek(SeekFrom::Start(internal_size as u64))?;
             f.read_padding(true_size - internal_size)?;
@@ -71,24 +105,47 @@ impl Sbn {
         let files_start = f.read_u32_be()?;
         let num_files = f.read_u32_be()?;
 
-        f.seek(SeekFrom::Current(12))?; // TODO: what is this data
-
+        let _file_size = f.read_u32_be()?; // Same as internal_size
+        let version_start = f.read_u32_be()?;
+        let unk_20 = f.read_u32_be()?;
         let songs_start = f.read_u32_be()?;
 
+        // Sizes come straight from the file, so make sure they fit before allocating anything for them.
+        let check_fits = |offset: u32, size: u64| {
+            if offset as u64 + size > input_len {
+                Err(Error::OutOfBounds {
+                    offset: offset as u64,
+                    size,
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        check_fits(files_start, num_files as u64 * 8)?;
+
         let mut sbn = Self {
             files: Vec::with_capacity(num_files as usize),
             songs: Vec::new(),
+            version: Vec::new(),
+            init: Vec::new(),
+            unk_20,
         };
 
+        // One step per file, plus one for the song list.
+        let total = num_files as usize + 1;
+
         for i in 0..num_files {
-            f.seek(SeekFrom::Start((files_start + i * 8) as u64))?;
+            f.seek(SeekFrom::Start(files_start as u64 + i as u64 * 8))?;
 
             let file_start = f.read_u32_be()?;
+            let format = (f.read_u32_be()? >> 24) as u8;
 
             f.seek(SeekFrom::Start(file_start as u64))?;
 
             let _file_magic = f.read_cstring(4)?;
             let file_size = f.read_u32_be()?;
+            check_fits(file_start, file_size as u64)?;
 
             sbn.files.push(File {
                 name: f.read_cstring(4)?,
@@ -100,11 +157,28 @@ impl Sbn {
 
                     bytes
                 },
+                format,
             });
+
+            progress(i as usize + 1, total);
         }
 
+        if version_start != 0 && version_start < songs_start {
+            check_fits(version_start, (songs_start - version_start) as u64)?;
+            f.seek(SeekFrom::Start(version_start as u64))?;
+            sbn.version = vec![0; (songs_start - version_start) as usize];
+            f.read_exact(&mut sbn.version)?;
+        }
+
+        f.seek(SeekFrom::Start(songs_start as u64 + 4))?;
+        let init_size = f.read_u32_be()?;
+        check_fits(songs_start, init_size as u64)?;
+        f.seek(SeekFrom::Start(songs_start as u64))?;
+        sbn.init = vec![0; init_size as usize];
+        f.read_exact(&mut sbn.init)?;
+
         // Q: why +0x130? what is the data here?
-        f.seek(SeekFrom::Start(songs_start as u64 + 0x130))?;
+        f.seek(SeekFrom::Start(songs_start as u64 + INIT_SONGS_OFFSET as u64))?;
 
         loop {
             sbn.songs.push(Song {
@@ -122,6 +196,40 @@ impl Sbn {
             });
         }
 
+        progress(total, total);
+
         Ok(sbn)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn huge_sizes_are_errors() {
+        let mut sbn = Sbn::default();
+        sbn.init = vec![0; INIT_SONGS_OFFSET + 0x10];
+        sbn.files.push(File { name: "ABCD".to_owned(), data: b"BGM \0\0\0\x10ABCD\0\0\0\0".to_vec(), format: 0x10 });
+        let bytes = sbn.as_bytes().unwrap();
+        assert!(Sbn::from_bytes(&bytes).is_ok());
+
+        // The init data's size
+        let mut init = bytes.clone();
+        let songs_start = u32::from_be_bytes(init[0x24..0x28].try_into().unwrap()) as usize;
+        init[songs_start + 4..songs_start + 8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(Sbn::from_bytes(&init), Err(Error::OutOfBounds { .. })));
+
+        // The first file's size
+        let mut file = bytes.clone();
+        let files_start = u32::from_be_bytes(file[0x10..0x14].try_into().unwrap()) as usize;
+        let file_start = u32::from_be_bytes(file[files_start..files_start + 4].try_into().unwrap()) as usize;
+        file[file_start + 4..file_start + 8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(Sbn::from_bytes(&file), Err(Error::OutOfBounds { .. })));
+
+        // The number of files
+        let mut num_files = bytes;
+        num_files[0x14..0x18].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(Sbn::from_bytes(&num_files), Err(Error::OutOfBounds { .. })));
+    }
+}
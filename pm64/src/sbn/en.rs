@@ -1,12 +1,44 @@
-use std::io;
+use std::fmt;
 use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+
+use log::debug;
 
 use super::*;
 
-type Error = io::Error;
+#[derive(Debug)]
+pub enum Error {
+    /// The song list doesn't fit in the INIT section.
+    TooManySongs,
+    Io(io::Error),
+}
 
 type Result<T> = std::result::Result<T, Error>;
 
+impl From<io::Error> for Error {
+    fn from(io: io::Error) -> Self {
+        Self::Io(io)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooManySongs => write!(f, "Too many songs to fit in the INIT section"),
+            Error::Io(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
 impl Sbn {
     pub fn as_bytes(&self) -> Result<Vec<u8>> {
         let mut encoded = io::Cursor::new(Vec::new());
@@ -14,7 +46,107 @@ impl Sbn {
         Ok(encoded.into_inner())
     }
 
-    pub fn encode<W: Write + Seek>(&self, _: &mut W) -> Result<()> {
-        todo!("SBN encoding") // TODO
+    /// Layout:
+    ///
+    /// ```text
+    /// header     0x40 bytes
+    /// file table 8 bytes per file
+    /// files      each aligned to 16 bytes
+    /// version
+    /// INIT
+    /// ```
+    pub fn encode<W: Write + Seek>(&self, f: &mut W) -> Result<()> {
+        let init = self.init_with_songs()?;
+
+        f.seek(SeekFrom::Start(0))?;
+
+        f.write_all(MAGIC.as_bytes())?;
+        let size_offset = SeekFrom::Start(f.pos()?);
+        f.write_u32_be(0)?;
+        f.write_all(&[0; 8])?;
+
+        debug_assert_eq!(f.pos()?, 0x10);
+        f.write_u32_be(0x40)?; // File table, immediately after the header
+        f.write_u32_be(self.files.len() as u32)?;
+        let file_size_offset = SeekFrom::Start(f.pos()?);
+        f.write_u32_be(0)?;
+        let version_offset = SeekFrom::Start(f.pos()?);
+        f.write_u32_be(0)?;
+        f.write_u32_be(self.unk_20)?;
+        let init_offset = SeekFrom::Start(f.pos()?);
+        f.write_u32_be(0)?;
+        f.write_all(&[0; 0x18])?;
+
+        // Write file table
+        debug_assert_eq!(f.pos()?, 0x40);
+        let file_table_start = f.pos()?;
+        for _ in &self.files {
+            f.write_u32_be(0)?; // Replaced later
+            f.write_u32_be(0)?;
+        }
+
+        // Write files
+        for (i, file) in self.files.iter().enumerate() {
+            f.align(16)?;
+
+            let pos = f.pos()?;
+            debug!("file {} {} @ {:#X}", i, file.name, pos);
+
+            let entry = SeekFrom::Start(file_table_start + i as u64 * 8);
+            f.write_u32_be_at(pos as u32, entry)?;
+            f.write_u32_be_at(
+                ((file.format as u32) << 24) | (file.data.len() as u32 & 0xFFFFFF),
+                SeekFrom::Start(file_table_start + i as u64 * 8 + 4),
+            )?;
+
+            f.write_all(&file.data)?;
+        }
+
+        // Write version
+        if !self.version.is_empty() {
+            f.align(16)?;
+            let pos = f.pos()? as u32;
+            f.write_u32_be_at(pos, version_offset)?;
+            f.write_all(&self.version)?;
+        }
+
+        // Write INIT
+        f.align(16)?;
+        let pos = f.pos()? as u32;
+        f.write_u32_be_at(pos, init_offset)?;
+        f.write_all(&init)?;
+
+        // Write file size
+        let size = f.pos()? as u32;
+        f.write_u32_be_at(size, size_offset)?;
+        f.write_u32_be_at(size, file_size_offset)?;
+
+        f.align(16)?;
+
+        Ok(())
+    }
+
+    /// Returns [Sbn::init] with its song list replaced by [Sbn::songs].
+    fn init_with_songs(&self) -> Result<Vec<u8>> {
+        let mut song_list = io::Cursor::new(Vec::new());
+
+        for song in &self.songs {
+            song_list.write_u16_be(song.bgm_file)?;
+            song_list.write_u16_be(song.bk_a_file.map_or(0, |n| n.get()))?;
+            song_list.write_u16_be(song.bk_b_file.map_or(0, |n| n.get()))?;
+            song_list.write_u16_be(song.unk_file.map_or(0, |n| n.get()))?;
+        }
+        song_list.write_u16_be(u16::MAX)?; // Terminator
+
+        let song_list = song_list.into_inner();
+        let range = INIT_SONGS_OFFSET..INIT_SONGS_OFFSET + song_list.len();
+
+        let mut init = self.init.clone();
+        if range.end > init.len() {
+            return Err(Error::TooManySongs);
+        }
+        init[range].copy_from_slice(&song_list);
+
+        Ok(init)
     }
 }
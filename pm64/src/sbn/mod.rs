@@ -9,16 +9,32 @@ pub mod en;
 pub const MAGIC: &str = "SBN ";
 pub const SBN_START: u64 = 0xF00000;
 
+/// Offset of the song list within the INIT section.
+pub const INIT_SONGS_OFFSET: usize = 0x130;
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Sbn {
     pub files: Vec<File>,
     pub songs: Vec<Song>,
+
+    /// Data between the last file and the INIT section. Not understood, so it is kept as-is.
+    pub version: Vec<u8>,
+
+    /// The raw INIT section. The song list inside it is overwritten with [Sbn::songs] when encoding; the rest is not
+    /// understood, so it is kept as-is.
+    pub init: Vec<u8>,
+
+    /// Header field with unknown purpose.
+    pub unk_20: u32,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct File {
     pub name: String,
     pub data: Vec<u8>,
+
+    /// Upper byte of this file's entry in the file table. Appears to describe the file type.
+    pub format: u8,
 }
 
 impl File {
@@ -35,6 +35,9 @@ macro_rules! test_matching {
             if encoded != original {
                 log::error!("non-matching!! D:");
 
+                let difference = diff::diff_bytes(original, &encoded).unwrap();
+                println!("{}", difference);
+
                 println!("original tracks allocation:");
                 for (seg_no, seg) in bgm.segments.iter().enumerate() {
                     if let Some(seg) = seg {
@@ -83,8 +86,8 @@ macro_rules! test_matching {
 
                 // ...and fail the test.
                 panic!(
-                    "Re-encoded song did not match original. Wrote non-matching output to tests/bin/{}",
-                    nonmatching_bin
+                    "Re-encoded song did not match original. Wrote non-matching output to tests/bin/{}\n{}",
+                    nonmatching_bin, difference
                 );
             }
         }
@@ -117,6 +120,42 @@ macro_rules! test_non_matching {
     };
 }
 
+/// Each test generated by this macro tests for the following property:
+///
+///     encode(from_ron(to_ron(decode(bin)))) == bin
+///
+/// That is, saving a song as RON and loading it back must not lose anything needed for matching, including the raw
+/// data of [Subsegment::Unknown]s.
+#[cfg(feature = "serde")]
+macro_rules! test_ron_matching {
+    ($song:ident) => {
+        #[allow(non_snake_case)]
+        #[test]
+        fn $song() {
+            let original = include_bytes!(concat!("bin/", stringify!($song), ".bin"));
+            let bgm = Bgm::decode(&mut Cursor::new(original)).expect("decode error");
+
+            let has_unknown_subsegment = bgm.segments
+                .iter()
+                .flatten()
+                .flat_map(|seg| seg.subsegments.iter())
+                .any(|subseg| matches!(subseg, Subsegment::Unknown { .. }));
+            assert!(has_unknown_subsegment, "song has no Subsegment::Unknown to test");
+
+            // Round-trip through RON
+            let ron = ron::ser::to_string(&bgm).expect("serialize error");
+            let bgm: Bgm = ron::de::from_str(&ron).expect("deserialize error");
+
+            // Encode the Bgm
+            let mut encoded = Cursor::new(Vec::new());
+            bgm.encode(&mut encoded).unwrap();
+            let encoded = encoded.into_inner();
+
+            assert!(encoded == original, "RON round-trip did not match original");
+        }
+    };
+}
+
 /*
 /// Each test generated by this macro tests for the following property:
 ///
@@ -327,7 +366,51 @@ fn shared_subsegment_tracks_ptr() {
 }
 
 #[test]
-#[ignore] // TEMP: bgm::en not yet implemented
+fn truncated() {
+    let original = include_bytes!("bin/Koopa_Village_13.bin");
+
+    for &len in &[0, 0x0A, 0x20, original.len() / 4, original.len() / 2, original.len() * 3 / 4] {
+        match Bgm::from_bytes(&original[..len]) {
+            Err(pm64::bgm::de::Error::UnexpectedEof { .. }) => {}
+            result => panic!("cut at {:#X}: expected end-of-file error, got {:?}", len, result.map(|_| ())),
+        }
+    }
+}
+
+/// [Segment::len_secs] should agree with how long the [Sequencer] takes to play a segment once through. The sequencer
+/// fades tempos all at once, so they only roughly agree for songs with fades.
+#[test]
+fn lengths() {
+    let songs: [&[u8]; 2] = [include_bytes!("bin/Koopa_Village_13.bin"), include_bytes!("bin/Goomba_Village_01.bin")];
+
+    for original in &songs {
+        let bgm = Bgm::from_bytes(original).unwrap();
+        let segment = bgm.segments[0].as_ref().unwrap();
+        let len_secs = segment.len_secs(&bgm.track_lists);
+
+        let mut sequencer = Sequencer::new(bgm.clone(), 0);
+        let mut events = Vec::new();
+        let mut bpm = DEFAULT_TEMPO;
+        let mut played_secs = 0.0;
+
+        for _ in 0..segment.len_time(&bgm.track_lists) {
+            sequencer.tick(&mut events);
+
+            for event in events.drain(..) {
+                if let SeqEvent::Tempo(tempo) = event {
+                    bpm = tempo;
+                }
+            }
+
+            played_secs += 60.0 / (bpm as f64 * TICKS_PER_BEAT as f64);
+        }
+
+        assert!(len_secs > 0.0);
+        assert!((len_secs - played_secs).abs() < played_secs * 0.05, "{} != {}", len_secs, played_secs);
+    }
+}
+
+#[test]
 fn sbn() {
     let original = include_bytes!("bin/sbn.bin");
     let sbn = Sbn::from_bytes(original).unwrap();
@@ -347,3 +430,56 @@ fn sbn() {
 
     assert!(sbn.as_bytes().unwrap() == original);
 }
+
+#[cfg(feature = "serde")]
+mod ron_round_trip {
+    use super::*;
+
+    #[test]
+    fn shared_track_lists_preserved() {
+        let mut bgm = Bgm::new();
+        bgm.add_segment().unwrap();
+        bgm.add_segment().unwrap();
+
+        // Both variations play the same track list
+        let segment = bgm.segments[0].as_mut().unwrap();
+        let track_list = segment.add_tracks_subsegment(&mut bgm.track_lists, 0x10);
+        bgm.track_lists.get_mut(&track_list).unwrap().tracks[1].commands = CommandSeq::from(vec![
+            Command::Note { pitch: 0x90, velocity: 100, length: 48 },
+            Command::Delay(48),
+            Command::End,
+        ]);
+        bgm.segments[1].as_mut().unwrap().subsegments.push(Subsegment::Tracks {
+            flags: 0x10,
+            track_list,
+        });
+
+        let ron = ron::ser::to_string(&bgm).expect("serialize error");
+        assert_eq!(ron.matches("tracks:").count(), 1, "track list was serialized more than once");
+
+        let from_ron: Bgm = ron::de::from_str(&ron).expect("deserialize error");
+        assert_eq!(from_ron.track_lists.len(), 1);
+        assert_eq!(from_ron, bgm);
+
+        // Sharing survives encoding too: the track list is written once, and both variations point to it
+        let decoded = Bgm::from_bytes(&from_ron.as_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.track_lists.len(), 1);
+    }
+
+    #[test]
+    fn unknown_subsegments_preserved() {
+        // Trojan Bowser doesn't match (see above), so compare the decoded structures instead.
+        let original = include_bytes!("bin/Trojan_Bowser_09.bin");
+        let bgm = Bgm::decode(&mut Cursor::new(original)).expect("decode error");
+
+        let ron = ron::ser::to_string(&bgm).expect("serialize error");
+        let from_ron: Bgm = ron::de::from_str(&ron).expect("deserialize error");
+
+        assert_eq!(bgm.segments, from_ron.segments);
+        assert_eq!(bgm.track_lists, from_ron.track_lists);
+    }
+
+    test_ron_matching!(Goomba_Village_01);
+    test_ron_matching!(Pleasant_Path_11);
+    test_ron_matching!(Koopa_Bros_Fortress_14);
+}